@@ -15,12 +15,19 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program. If not, see <http://www.gnu.org/licenses/>.
 
-use anyhow::Result;
+use anyhow::{anyhow, bail, Result};
 use duckdb::types::Value;
 use pgrx::*;
+use std::collections::{BTreeMap, HashMap};
+use supabase_wrappers::prelude::options_to_hashmap;
 
 use crate::duckdb::connection;
+use crate::duckdb::csv::CsvOption;
+use crate::duckdb::json::JsonOption;
 use crate::duckdb::utils;
+use crate::fdw::base::check_file_access;
+use crate::fdw::handler::FdwHandler;
+use crate::fdw::trigger::construct_column_definitions;
 
 type SniffCsvRow = (
     Option<String>,
@@ -36,6 +43,15 @@ type SniffCsvRow = (
     Option<String>,
 );
 
+type CsvRejectsRow = (
+    Option<String>,
+    Option<i64>,
+    Option<String>,
+    Option<String>,
+    Option<String>,
+    Option<String>,
+);
+
 #[allow(clippy::type_complexity)]
 #[pg_extern]
 pub fn sniff_csv(
@@ -62,6 +78,11 @@ pub fn sniff_csv(
 
 #[inline]
 fn sniff_csv_impl(files: &str, sample_size: Option<i64>) -> Result<Vec<SniffCsvRow>> {
+    check_file_access(
+        &HashMap::from([(CsvOption::Files.as_ref().to_string(), files.to_string())]),
+        unsafe { pg_sys::GetUserId() },
+    )?;
+
     let schema_str = vec![
         Some(utils::format_csv(files)),
         sample_size.map(|s| s.to_string()),
@@ -93,3 +114,314 @@ fn sniff_csv_impl(files: &str, sample_size: Option<i64>) -> Result<Vec<SniffCsvR
         .map(|row| row.unwrap())
         .collect::<Vec<SniffCsvRow>>())
 }
+
+/// Creates a CSV foreign table with the dialect and columns detected by sniff_csv,
+/// and returns the executed DDL. The detected options are pinned and auto detection is turned off,
+/// so changes to the files are reported as errors instead of changing how they are parsed.
+#[pg_extern]
+pub fn create_csv_foreign_table(
+    name: &str,
+    server: &str,
+    files: &str,
+    sample_size: default!(Option<i64>, "NULL"),
+) -> String {
+    create_csv_foreign_table_impl(name, server, files, sample_size).unwrap_or_else(|e| {
+        panic!("{}", e);
+    })
+}
+
+#[inline]
+fn create_csv_foreign_table_impl(
+    name: &str,
+    server: &str,
+    files: &str,
+    sample_size: Option<i64>,
+) -> Result<String> {
+    let mut table_options =
+        HashMap::from([(CsvOption::Files.as_ref().to_string(), files.to_string())]);
+    check_file_access(&table_options, unsafe { pg_sys::GetUserId() })?;
+
+    let sniff_options = vec![
+        Some(utils::format_csv(files)),
+        sample_size.map(|sample_size| format!("sample_size = {sample_size}")),
+    ]
+    .into_iter()
+    .flatten()
+    .collect::<Vec<String>>()
+    .join(", ");
+
+    let conn = unsafe { &*connection::get_global_connection().get() };
+    let (delim, quote, escape, new_line, skip, header, dateformat, timestampformat, columns) = conn
+        .query_row(
+            &format!(
+                "SELECT Delimiter, Quote, Escape, NewLineDelimiter, SkipRows, HasHeader, DateFormat, TimestampFormat, to_json(Columns)::VARCHAR \
+                 FROM sniff_csv({sniff_options})"
+            ),
+            [],
+            |row| {
+                Ok((
+                    row.get::<_, Option<String>>(0)?,
+                    row.get::<_, Option<String>>(1)?,
+                    row.get::<_, Option<String>>(2)?,
+                    row.get::<_, Option<String>>(3)?,
+                    row.get::<_, Option<i64>>(4)?,
+                    row.get::<_, Option<bool>>(5)?,
+                    row.get::<_, Option<String>>(6)?,
+                    row.get::<_, Option<String>>(7)?,
+                    row.get::<_, Option<String>>(8)?,
+                ))
+            },
+        )?;
+
+    let columns = serde_json::from_str::<Vec<serde_json::Value>>(&columns.unwrap_or_default())
+        .map_err(|e| anyhow!("could not read the columns detected in {files}: {e}"))?
+        .into_iter()
+        .filter_map(|column| {
+            Some((
+                column.get("name")?.as_str()?.to_string(),
+                column.get("type")?.as_str()?.to_string(),
+            ))
+        })
+        .collect::<Vec<(String, String)>>();
+    if columns.is_empty() {
+        bail!("no columns were detected in {}", files);
+    }
+
+    // DuckDB types are pinned with the columns option, so that the column types can't drift
+    let duckdb_columns = columns
+        .iter()
+        .map(|(column_name, duckdb_type)| {
+            format!(
+                "'{}': '{}'",
+                column_name.replace('\'', "''"),
+                duckdb_type.replace('\'', "''")
+            )
+        })
+        .collect::<Vec<String>>()
+        .join(", ");
+
+    table_options.insert(
+        CsvOption::AutoDetect.as_ref().to_string(),
+        "false".to_string(),
+    );
+    table_options.insert(
+        CsvOption::Columns.as_ref().to_string(),
+        format!("{{{duckdb_columns}}}"),
+    );
+    // Column definitions keep the sniffed names as they are, so the table has to match them the same way
+    table_options.insert(
+        CsvOption::PreserveCasing.as_ref().to_string(),
+        "true".to_string(),
+    );
+    for (option, value) in [
+        (CsvOption::Delim, delim),
+        (CsvOption::Quote, quote),
+        (CsvOption::Escape, escape),
+        (CsvOption::NewLine, new_line),
+        (CsvOption::Skip, skip.map(|skip| skip.to_string())),
+        (CsvOption::Header, header.map(|header| header.to_string())),
+        (CsvOption::Dateformat, dateformat),
+        (CsvOption::Timestampformat, timestampformat),
+    ] {
+        if let Some(value) = value.filter(|value| !value.is_empty()) {
+            table_options.insert(option.as_ref().to_string(), value);
+        }
+    }
+
+    let column_definitions = construct_column_definitions(&columns, true, None, &HashMap::new())?;
+    let options = BTreeMap::from_iter(table_options.iter())
+        .into_iter()
+        .map(|(key, value)| format!("{} {}", key, spi::quote_literal(value)))
+        .collect::<Vec<String>>()
+        .join(", ");
+
+    let ddl = format!(
+        "CREATE FOREIGN TABLE {} ({}) SERVER {} OPTIONS ({})",
+        qualified_name(name)?,
+        column_definitions.join(", "),
+        spi::quote_identifier(server),
+        options
+    );
+    Spi::run(&ddl)?;
+
+    Ok(ddl)
+}
+
+// Accepts the same names as CREATE FOREIGN TABLE, optionally qualified and quoted
+fn qualified_name(name: &str) -> Result<String> {
+    Ok(Spi::get_one_with_args::<String>(
+        "SELECT string_agg(quote_ident(part), '.' ORDER BY position) FROM unnest(parse_ident($1)) WITH ORDINALITY AS name(part, position)",
+        vec![(PgBuiltInOids::TEXTOID.oid(), name.into_datum())],
+    )?
+    .ok_or_else(|| anyhow!("invalid table name {}", name))?)
+}
+
+/// Returns the rows that could not be parsed by the last scan of a CSV table in this session.
+/// JSON files are validated when the function is called, because read_json can't store its rejects,
+/// so only records that are not valid JSON are returned for them.
+#[allow(clippy::type_complexity)]
+#[pg_extern]
+pub fn csv_rejects(
+    relation: PgRelation,
+) -> iter::TableIterator<
+    'static,
+    (
+        name!(file_path, Option<String>),
+        name!(line, Option<i64>),
+        name!(column_name, Option<String>),
+        name!(error_type, Option<String>),
+        name!(raw_line, Option<String>),
+        name!(error_message, Option<String>),
+    ),
+> {
+    let rows = csv_rejects_impl(relation).unwrap_or_else(|e| {
+        panic!("{}", e);
+    });
+    iter::TableIterator::new(rows)
+}
+
+#[inline]
+fn csv_rejects_impl(relation: PgRelation) -> Result<Vec<CsvRejectsRow>> {
+    // Rejected rows are table data
+    let acl_result = unsafe {
+        pg_sys::pg_class_aclcheck(
+            relation.oid(),
+            pg_sys::GetUserId(),
+            pg_sys::ACL_SELECT as pg_sys::AclMode,
+        )
+    };
+    if acl_result != pg_sys::AclResult::ACLCHECK_OK {
+        bail!("permission denied for table {}", relation.name());
+    }
+
+    if !relation.is_foreign_table() {
+        bail!("relation {} is not a foreign table", relation.name());
+    }
+
+    let foreign_table = unsafe { pg_sys::GetForeignTable(relation.oid()) };
+    let table_options = unsafe { options_to_hashmap((*foreign_table).options)? };
+    let store_rejects = table_options
+        .get(CsvOption::StoreRejects.as_ref())
+        .is_some_and(|option| option.eq_ignore_ascii_case("true"));
+
+    let query = match FdwHandler::from(foreign_table) {
+        FdwHandler::Csv if store_rejects => {
+            check_file_access(&table_options, unsafe { (*relation.rd_rel).relowner })?;
+            csv_rejects_query(&table_options)?
+        }
+        FdwHandler::Json if store_rejects => {
+            check_file_access(&table_options, unsafe { (*relation.rd_rel).relowner })?;
+            Some(json_rejects_query(&table_options)?)
+        }
+        FdwHandler::Csv | FdwHandler::Json => {
+            bail!(
+                "table {} does not have the store_rejects option set",
+                relation.name()
+            )
+        }
+        _ => bail!("relation {} is not a csv or json table", relation.name()),
+    };
+
+    let Some(query) = query else {
+        return Ok(vec![]);
+    };
+
+    let conn = unsafe { &*connection::get_global_connection().get() };
+    let mut stmt = conn.prepare(&query)?;
+
+    Ok(stmt
+        .query_map([], |row| {
+            Ok((
+                row.get::<_, Option<String>>(0)?,
+                row.get::<_, Option<i64>>(1)?,
+                row.get::<_, Option<String>>(2)?,
+                row.get::<_, Option<String>>(3)?,
+                row.get::<_, Option<String>>(4)?,
+                row.get::<_, Option<String>>(5)?,
+            ))
+        })?
+        .map(|row| row.unwrap())
+        .collect::<Vec<CsvRejectsRow>>())
+}
+
+// DuckDB stores the rejects of read_csv in temporary tables, which don't exist before the first scan.
+// Tables with the default names share them, so only the rejects of this table's files are returned.
+fn csv_rejects_query(table_options: &HashMap<String, String>) -> Result<Option<String>> {
+    let rejects_table = table_options
+        .get(CsvOption::RejectsTable.as_ref())
+        .map_or("reject_errors", |option| option.as_str());
+    let rejects_scan = table_options
+        .get(CsvOption::RejectsScan.as_ref())
+        .map_or("reject_scans", |option| option.as_str());
+    let files = table_options
+        .get(CsvOption::Files.as_ref())
+        .ok_or_else(|| anyhow!("files option is required"))?;
+
+    let conn = unsafe { &*connection::get_global_connection().get() };
+    let tables: i64 = conn.query_row(
+        "SELECT COUNT(*) FROM duckdb_tables() WHERE temporary AND table_name IN (?, ?)",
+        [rejects_table, rejects_scan],
+        |row| row.get(0),
+    )?;
+    if tables < 2 {
+        return Ok(None);
+    }
+
+    // Files can be globs, which are expanded the same way read_csv expands them
+    let table_files = files
+        .split(',')
+        .map(|file| {
+            format!(
+                "SELECT file FROM glob('{}')",
+                file.trim().replace('\'', "''")
+            )
+        })
+        .collect::<Vec<String>>()
+        .join(" UNION ALL ");
+
+    Ok(Some(format!(
+        "SELECT scans.file_path, errors.line::BIGINT, errors.column_name, errors.error_type::VARCHAR, errors.csv_line, errors.error_message \
+         FROM {} errors JOIN {} scans ON errors.scan_id = scans.scan_id AND errors.file_id = scans.file_id \
+         WHERE scans.file_path IN ({table_files}) \
+         ORDER BY scans.file_path, errors.line",
+        quote_identifier(rejects_table),
+        quote_identifier(rejects_scan)
+    )))
+}
+
+#[inline]
+fn quote_identifier(identifier: &str) -> String {
+    format!("\"{}\"", identifier.replace('"', "\"\""))
+}
+
+fn json_rejects_query(table_options: &HashMap<String, String>) -> Result<String> {
+    let files = utils::format_csv(
+        table_options
+            .get(JsonOption::Files.as_ref())
+            .ok_or_else(|| anyhow!("files option is required"))?,
+    );
+    let limit = table_options
+        .get(JsonOption::RejectsLimit.as_ref())
+        .and_then(|option| option.parse::<i64>().ok())
+        .filter(|limit| *limit > 0)
+        .map(|limit| format!(" LIMIT {limit}"))
+        .unwrap_or_default();
+    let newline_delimited = table_options
+        .get(JsonOption::Format.as_ref())
+        .is_some_and(|option| option.eq_ignore_ascii_case("newline_delimited"));
+
+    // Each line of newline-delimited files is a record, other files are validated as a whole
+    Ok(match newline_delimited {
+        true => format!(
+            "SELECT filename, line, NULL, 'INVALID JSON', raw_line, 'invalid JSON record' \
+             FROM (SELECT filename, unnest(range(1, len(lines) + 1)) AS line, unnest(lines) AS raw_line \
+             FROM (SELECT filename, string_split(content, chr(10)) AS lines FROM read_text({files}))) \
+             WHERE trim(raw_line) <> '' AND NOT json_valid(raw_line) \
+             ORDER BY filename, line{limit}"
+        ),
+        false => format!(
+            "SELECT filename, NULL::BIGINT, NULL, 'INVALID JSON', NULL, 'invalid JSON file' \
+             FROM read_text({files}) WHERE NOT json_valid(content) ORDER BY filename{limit}"
+        ),
+    })
+}
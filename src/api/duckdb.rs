@@ -1,7 +1,11 @@
-use anyhow::Result;
+use anyhow::{anyhow, bail, Result};
 use pgrx::*;
+use std::sync::Arc;
 
 use crate::duckdb::connection;
+use crate::fdw::base::has_privs_of_role;
+use crate::schema::converter::ColumnConverter;
+use crate::GUCS;
 
 type DuckdbSettingsRow = (
     Option<String>,
@@ -25,9 +29,41 @@ type DuckdbExtensionsRow = (
 
 #[pg_extern]
 pub fn duckdb_execute(query: &str) {
+    // Runs any DuckDB SQL, so it is gated like duckdb_query
+    check_duckdb_query_role().unwrap_or_else(|err| panic!("{err}"));
     connection::execute(query, []).unwrap_or_else(|err| panic!("error executing query: {err:?}"));
 }
 
+#[pg_extern(sql = "
+    CREATE FUNCTION duckdb_query(query TEXT)
+    RETURNS SETOF record
+    STRICT
+    LANGUAGE c
+    AS 'MODULE_PATHNAME', '@FUNCTION_NAME@';
+")]
+fn duckdb_query(fcinfo: pg_sys::FunctionCallInfo) {
+    unsafe {
+        duckdb_query_impl(fcinfo, false).unwrap_or_else(|e| {
+            panic!("{}", e);
+        });
+    }
+}
+
+#[pg_extern(sql = "
+    CREATE FUNCTION duckdb_query_json(query TEXT)
+    RETURNS SETOF jsonb
+    STRICT
+    LANGUAGE c
+    AS 'MODULE_PATHNAME', '@FUNCTION_NAME@';
+")]
+fn duckdb_query_json(fcinfo: pg_sys::FunctionCallInfo) {
+    unsafe {
+        duckdb_query_impl(fcinfo, true).unwrap_or_else(|e| {
+            panic!("{}", e);
+        });
+    }
+}
+
 #[allow(clippy::type_complexity)]
 #[pg_extern]
 pub fn duckdb_settings() -> iter::TableIterator<
@@ -122,3 +158,157 @@ fn duckdb_extensions_impl() -> Result<Vec<DuckdbExtensionsRow>> {
         .map(|row| row.unwrap())
         .collect::<Vec<DuckdbExtensionsRow>>())
 }
+
+/// Installs a DuckDB extension from a .duckdb_extension file or a local extension repository, and loads it.
+/// Extensions are installed in the data directory, so every session can load them afterwards.
+#[pg_extern]
+pub fn duckdb_install_extension(name: &str, path: &str) {
+    if !unsafe { pg_sys::superuser() } {
+        panic!("permission denied: only superusers can install DuckDB extensions");
+    }
+
+    connection::install_extension(name, path).unwrap_or_else(|e| {
+        panic!("error installing duckdb extension {name}: {e}");
+    });
+}
+
+#[inline]
+unsafe fn duckdb_query_impl(fcinfo: pg_sys::FunctionCallInfo, as_json: bool) -> Result<()> {
+    check_duckdb_query_role()?;
+
+    let query: String = pg_getarg(fcinfo, 0).ok_or_else(|| anyhow!("query cannot be null"))?;
+    materialize_duckdb_query(fcinfo, query, as_json)
+}
+
+/// Runs the query in DuckDB and materializes the result into a tuplestore.
+/// Without as_json, output types come from the column definition list of the call,
+/// otherwise every row is returned as a single jsonb object.
+pub(crate) unsafe fn materialize_duckdb_query(
+    fcinfo: pg_sys::FunctionCallInfo,
+    query: String,
+    as_json: bool,
+) -> Result<()> {
+    let rsinfo = (*fcinfo).resultinfo as *mut pg_sys::ReturnSetInfo;
+    if rsinfo.is_null()
+        || !is_a(
+            rsinfo as *mut pg_sys::Node,
+            pg_sys::NodeTag::T_ReturnSetInfo,
+        )
+        || (*rsinfo).allowedModes & pg_sys::SetFunctionReturnMode::SFRM_Materialize as i32 == 0
+    {
+        bail!("set-valued function called in context that cannot accept a set");
+    }
+
+    let per_query_context = (*(*rsinfo).econtext).ecxt_per_query_memory;
+    let old_context = pg_sys::MemoryContextSwitchTo(per_query_context);
+
+    let tupdesc = match as_json {
+        true => {
+            let tupdesc = pg_sys::CreateTemplateTupleDesc(1);
+            pg_sys::TupleDescInitEntry(
+                tupdesc,
+                1,
+                c"duckdb_query_json".as_ptr(),
+                pg_sys::JSONBOID,
+                -1,
+                0,
+            );
+            tupdesc
+        }
+        false => {
+            let mut tupdesc: pg_sys::TupleDesc = std::ptr::null_mut();
+            if pg_sys::get_call_result_type(fcinfo, std::ptr::null_mut(), &mut tupdesc)
+                != pg_sys::TypeFuncClass::TYPEFUNC_COMPOSITE
+            {
+                pg_sys::MemoryContextSwitchTo(old_context);
+                bail!("a column definition list is required, e.g. SELECT * FROM duckdb_query('...') AS (name text)");
+            }
+            pg_sys::CreateTupleDescCopy(tupdesc)
+        }
+    };
+    let tupstore = pg_sys::tuplestore_begin_heap(true, false, pg_sys::work_mem);
+
+    (*rsinfo).returnMode = pg_sys::SetFunctionReturnMode::SFRM_Materialize;
+    (*rsinfo).setResult = tupstore;
+    (*rsinfo).setDesc = tupdesc;
+
+    pg_sys::MemoryContextSwitchTo(old_context);
+
+    // Selecting the subquery alias turns each row into a struct, which converts to a jsonb object
+    let query = match as_json {
+        true => format!("SELECT q FROM ({}) q", query.trim().trim_end_matches(';')),
+        false => query,
+    };
+
+    // Use a statement of our own rather than the global one, which may belong to a foreign scan in the same query
+    let conn = &*connection::get_global_connection().get();
+    let mut statement = conn.prepare(&query)?;
+    let tuple_desc = PgTupleDesc::from_pg_unchecked(tupdesc);
+    let natts = tuple_desc.len();
+    let mut row_context = PgMemoryContexts::new("duckdb_query rows");
+    let names = tuple_desc
+        .iter()
+        .map(|attribute| Arc::<str>::from(attribute.name()))
+        .collect::<Vec<_>>();
+
+    for batch in statement.query_arrow([])? {
+        if batch.num_columns() != natts {
+            bail!(
+                "query returned {} columns, but the column definition list has {}",
+                batch.num_columns(),
+                natts
+            );
+        }
+
+        let converters = tuple_desc
+            .iter()
+            .zip(&names)
+            .enumerate()
+            .map(|(col_index, (attribute, name))| {
+                ColumnConverter::new(batch.column(col_index), attribute.atttypid, name)
+            })
+            .collect::<Vec<_>>();
+
+        // Converted values only need to live until they are copied into the tuplestore
+        row_context.switch_to(|_| -> Result<()> {
+            let mut values = vec![pg_sys::Datum::from(0); natts];
+            let mut nulls = vec![false; natts];
+
+            for row_index in 0..batch.num_rows() {
+                for (col_index, converter) in converters.iter().enumerate() {
+                    match converter.datum(row_index)? {
+                        Some(datum) => {
+                            values[col_index] = datum;
+                            nulls[col_index] = false;
+                        }
+                        None => nulls[col_index] = true,
+                    }
+                }
+
+                pg_sys::tuplestore_putvalues(
+                    tupstore,
+                    tupdesc,
+                    values.as_mut_ptr(),
+                    nulls.as_mut_ptr(),
+                );
+            }
+
+            Ok(())
+        })?;
+        row_context.reset();
+    }
+
+    Ok(())
+}
+
+#[inline]
+fn check_duckdb_query_role() -> Result<()> {
+    if unsafe { pg_sys::superuser() } {
+        return Ok(());
+    }
+
+    match GUCS.duckdb_query_role.get() {
+        Some(role) if has_privs_of_role(unsafe { pg_sys::GetUserId() }, role) => Ok(()),
+        _ => bail!("permission denied: only superusers and members of thdb.duckdb_query_role can run DuckDB queries"),
+    }
+}
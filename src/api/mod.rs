@@ -18,3 +18,7 @@
 mod csv;
 mod duckdb;
 mod parquet;
+mod read;
+mod result_cache;
+mod schema;
+mod stats;
@@ -113,6 +113,7 @@ fn parquet_schema_impl(relation: PgRelation) -> Result<Vec<ParquetSchemaRow>> {
         table_options.clone(),
         user_mapping_options,
         handler,
+        unsafe { (*relation.rd_rel).relowner },
     )?;
 
     let files = utils::format_csv(
@@ -163,6 +164,7 @@ fn parquet_describe_impl(relation: PgRelation) -> Result<Vec<ParquetDescribeRow>
         table_options.clone(),
         user_mapping_options,
         handler,
+        unsafe { (*relation.rd_rel).relowner },
     )?;
 
     let files = utils::format_csv(
@@ -0,0 +1,112 @@
+// Copyright (c) 2023-2025 Retake, Inc.
+//
+// This file is part of ParadeDB - Postgres for Search and Analytics
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <http://www.gnu.org/licenses/>.
+use anyhow::{anyhow, Result};
+use pgrx::*;
+
+use super::duckdb::materialize_duckdb_query;
+use crate::fdw::handler::FdwHandler;
+use crate::fdw::read::{prepare_read_function, read_function_options, read_function_select};
+
+extension_sql!(
+    r#"
+    CREATE SCHEMA IF NOT EXISTS th_dbdm;
+    "#,
+    name = "th_dbdm_schema"
+);
+
+#[pg_extern(
+    sql = "
+    CREATE FUNCTION th_dbdm.read_parquet(files TEXT, options JSONB DEFAULT '{}', server TEXT DEFAULT NULL)
+    RETURNS SETOF record
+    LANGUAGE c
+    AS 'MODULE_PATHNAME', '@FUNCTION_NAME@';
+",
+    requires = ["th_dbdm_schema"]
+)]
+fn read_parquet(fcinfo: pg_sys::FunctionCallInfo) {
+    unsafe {
+        read_function_impl(fcinfo, FdwHandler::Parquet).unwrap_or_else(|e| {
+            panic!("{}", e);
+        });
+    }
+}
+
+#[pg_extern(
+    sql = "
+    CREATE FUNCTION th_dbdm.read_csv(files TEXT, options JSONB DEFAULT '{}', server TEXT DEFAULT NULL)
+    RETURNS SETOF record
+    LANGUAGE c
+    AS 'MODULE_PATHNAME', '@FUNCTION_NAME@';
+",
+    requires = ["th_dbdm_schema"]
+)]
+fn read_csv(fcinfo: pg_sys::FunctionCallInfo) {
+    unsafe {
+        read_function_impl(fcinfo, FdwHandler::Csv).unwrap_or_else(|e| {
+            panic!("{}", e);
+        });
+    }
+}
+
+#[pg_extern(
+    sql = "
+    CREATE FUNCTION th_dbdm.read_json(files TEXT, options JSONB DEFAULT '{}', server TEXT DEFAULT NULL)
+    RETURNS SETOF record
+    LANGUAGE c
+    AS 'MODULE_PATHNAME', '@FUNCTION_NAME@';
+",
+    requires = ["th_dbdm_schema"]
+)]
+fn read_json(fcinfo: pg_sys::FunctionCallInfo) {
+    unsafe {
+        read_function_impl(fcinfo, FdwHandler::Json).unwrap_or_else(|e| {
+            panic!("{}", e);
+        });
+    }
+}
+
+#[pg_extern(
+    sql = "
+    CREATE FUNCTION th_dbdm.read_delta(files TEXT, options JSONB DEFAULT '{}', server TEXT DEFAULT NULL)
+    RETURNS SETOF record
+    LANGUAGE c
+    AS 'MODULE_PATHNAME', '@FUNCTION_NAME@';
+",
+    requires = ["th_dbdm_schema"]
+)]
+fn read_delta(fcinfo: pg_sys::FunctionCallInfo) {
+    unsafe {
+        read_function_impl(fcinfo, FdwHandler::Delta).unwrap_or_else(|e| {
+            panic!("{}", e);
+        });
+    }
+}
+
+// Queries that only read from these functions are pushed down by the executor hook,
+// so this runs when they are combined with Postgres tables or when pushdown falls back
+#[inline]
+unsafe fn read_function_impl(fcinfo: pg_sys::FunctionCallInfo, handler: FdwHandler) -> Result<()> {
+    let files: String = pg_getarg(fcinfo, 0).ok_or_else(|| anyhow!("files cannot be null"))?;
+    let options: Option<JsonB> = pg_getarg(fcinfo, 1);
+    let server: Option<String> = pg_getarg(fcinfo, 2);
+
+    let table_options =
+        read_function_options(handler, &files, options.as_ref().map(|options| &options.0))?;
+    prepare_read_function(handler, &table_options, server.as_deref())?;
+
+    materialize_duckdb_query(fcinfo, read_function_select(handler, table_options)?, false)
+}
@@ -0,0 +1,76 @@
+// Copyright (c) 2023-2025 Retake, Inc.
+//
+// This file is part of ParadeDB - Postgres for Search and Analytics
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <http://www.gnu.org/licenses/>.
+
+use pgrx::*;
+
+use crate::result_cache;
+
+extension_sql!(
+    r#"
+    CREATE VIEW th_dbdm_result_cache AS
+    SELECT key, query, rows, bytes, to_timestamp(created) AS created, to_timestamp(expires) AS expires
+    FROM th_dbdm_result_cache_internal();
+    "#,
+    name = "th_dbdm_result_cache",
+    requires = [th_dbdm_result_cache_internal]
+);
+
+#[allow(clippy::type_complexity)]
+#[pg_extern]
+pub fn th_dbdm_result_cache_internal() -> iter::TableIterator<
+    'static,
+    (
+        name!(key, String),
+        name!(query, String),
+        name!(rows, i64),
+        name!(bytes, i64),
+        name!(created, f64),
+        name!(expires, f64),
+    ),
+> {
+    check_superuser();
+    let entries = result_cache::entries().unwrap_or_else(|e| {
+        panic!("{}", e);
+    });
+    iter::TableIterator::new(entries.into_iter().map(|entry| {
+        (
+            entry.key,
+            entry.query,
+            entry.rows,
+            entry.bytes,
+            entry.created,
+            entry.expires,
+        )
+    }))
+}
+
+/// Removes the cached results of a query, compared with its whitespace collapsed, or every result
+#[pg_extern]
+pub fn th_dbdm_result_cache_invalidate(query: default!(Option<&str>, "NULL")) -> i64 {
+    check_superuser();
+    result_cache::invalidate(query).unwrap_or_else(|e| {
+        panic!("{}", e);
+    })
+}
+
+// Cached results hold the rows of every role that ran a query, so only superusers can see or drop them
+#[inline]
+fn check_superuser() {
+    if !unsafe { pg_sys::superuser() } {
+        panic!("permission denied: only superusers can access the result cache");
+    }
+}
@@ -0,0 +1,93 @@
+// Copyright (c) 2023-2025 Retake, Inc.
+//
+// This file is part of ParadeDB - Postgres for Search and Analytics
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <http://www.gnu.org/licenses/>.
+
+use anyhow::{anyhow, bail, Result};
+use pgrx::*;
+
+use crate::fdw::drift::{self, RefreshMode};
+
+type SchemaDiffRow = (String, String, Option<String>, Option<String>);
+
+#[allow(clippy::type_complexity)]
+#[pg_extern]
+pub fn foreign_table_schema_diff(
+    relation: PgRelation,
+) -> iter::TableIterator<
+    'static,
+    (
+        name!(column_name, String),
+        name!(status, String),
+        name!(postgres_type, Option<String>),
+        name!(duckdb_type, Option<String>),
+    ),
+> {
+    let rows = foreign_table_schema_diff_impl(relation).unwrap_or_else(|e| {
+        panic!("{}", e);
+    });
+    iter::TableIterator::new(rows)
+}
+
+#[pg_extern]
+pub fn refresh_foreign_table_schema(
+    relation: PgRelation,
+    mode: default!(&str, "'additive'"),
+) -> Option<String> {
+    refresh_foreign_table_schema_impl(relation, mode).unwrap_or_else(|e| {
+        panic!("{}", e);
+    })
+}
+
+#[inline]
+fn foreign_table_schema_diff_impl(relation: PgRelation) -> Result<Vec<SchemaDiffRow>> {
+    check_select_privilege(&relation)?;
+    Ok(drift::foreign_table_schema_diff(&relation)?
+        .into_iter()
+        .map(|column| {
+            (
+                column.column_name,
+                column.status.as_ref().to_string(),
+                column.postgres_type,
+                column.duckdb_type,
+            )
+        })
+        .collect())
+}
+
+#[inline]
+fn refresh_foreign_table_schema_impl(relation: PgRelation, mode: &str) -> Result<Option<String>> {
+    let mode = RefreshMode::from_name(mode)
+        .ok_or_else(|| anyhow!("invalid refresh mode {}, expected additive or all", mode))?;
+    check_select_privilege(&relation)?;
+    drift::refresh_foreign_table_schema(&relation, mode)
+}
+
+// Diffing reads the files of the table, which is table data
+#[inline]
+fn check_select_privilege(relation: &PgRelation) -> Result<()> {
+    let acl_result = unsafe {
+        pg_sys::pg_class_aclcheck(
+            relation.oid(),
+            pg_sys::GetUserId(),
+            pg_sys::ACL_SELECT as pg_sys::AclMode,
+        )
+    };
+    if acl_result != pg_sys::AclResult::ACLCHECK_OK {
+        bail!("permission denied for table {}", relation.name());
+    }
+
+    Ok(())
+}
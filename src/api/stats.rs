@@ -0,0 +1,122 @@
+// Copyright (c) 2023-2025 Retake, Inc.
+//
+// This file is part of ParadeDB - Postgres for Search and Analytics
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <http://www.gnu.org/licenses/>.
+
+use anyhow::{bail, Result};
+use pgrx::*;
+
+use crate::fdw::base::has_privs_of_role;
+use crate::stats;
+
+type StatQueriesRow = (
+    i64,
+    String,
+    i64,
+    i64,
+    i64,
+    i64,
+    Option<String>,
+    f64,
+    f64,
+    i64,
+    i64,
+);
+
+extension_sql!(
+    r#"
+    CREATE VIEW th_dbdm_stat_queries AS
+    SELECT * FROM th_dbdm_stat_queries_internal();
+    "#,
+    name = "th_dbdm_stat_queries",
+    requires = [th_dbdm_stat_queries_internal]
+);
+
+#[allow(clippy::type_complexity)]
+#[pg_extern]
+pub fn th_dbdm_stat_queries_internal() -> iter::TableIterator<
+    'static,
+    (
+        name!(queryid, i64),
+        name!(query, String),
+        name!(calls, i64),
+        name!(pushdown_calls, i64),
+        name!(fallback_calls, i64),
+        name!(fdw_scan_calls, i64),
+        name!(last_fallback_error, Option<String>),
+        name!(total_duckdb_time_ms, f64),
+        name!(mean_duckdb_time_ms, f64),
+        name!(rows, i64),
+        name!(bytes_read, i64),
+    ),
+> {
+    let rows = th_dbdm_stat_queries_impl().unwrap_or_else(|e| {
+        panic!("{}", e);
+    });
+    iter::TableIterator::new(rows)
+}
+
+#[pg_extern]
+pub fn th_dbdm_stat_reset() {
+    th_dbdm_stat_reset_impl().unwrap_or_else(|e| {
+        panic!("{}", e);
+    });
+}
+
+#[inline]
+fn th_dbdm_stat_queries_impl() -> Result<Vec<StatQueriesRow>> {
+    if !stats::is_enabled() {
+        bail!("th_dbdm query statistics require th_dbdm to be loaded via shared_preload_libraries");
+    }
+
+    // The statistics hold the query text of every role, like pg_stat_statements
+    if !has_privs_of_role(unsafe { pg_sys::GetUserId() }, c"pg_read_all_stats") {
+        bail!("permission denied: only superusers and roles with privileges of pg_read_all_stats can read th_dbdm query statistics");
+    }
+
+    let store = stats::QUERY_STATS.share();
+    Ok(store
+        .entries()
+        .map(|entry| {
+            (
+                entry.queryid(),
+                entry.query(),
+                entry.calls(),
+                entry.pushdown_calls(),
+                entry.fallback_calls(),
+                entry.fdw_scan_calls(),
+                entry.last_fallback_error(),
+                entry.total_duckdb_time(),
+                entry.mean_duckdb_time(),
+                entry.rows(),
+                entry.bytes_read(),
+            )
+        })
+        .collect())
+}
+
+#[inline]
+fn th_dbdm_stat_reset_impl() -> Result<()> {
+    if !stats::is_enabled() {
+        bail!("th_dbdm query statistics require th_dbdm to be loaded via shared_preload_libraries");
+    }
+
+    if !unsafe { pg_sys::superuser() } {
+        bail!("permission denied: only superusers can reset th_dbdm query statistics");
+    }
+
+    stats::QUERY_STATS.exclusive().reset();
+    Ok(())
+}
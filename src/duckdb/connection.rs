@@ -15,7 +15,7 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program. If not, see <http://www.gnu.org/licenses/>.
 
-use anyhow::{anyhow, Result};
+use anyhow::{anyhow, bail, Result};
 use duckdb::arrow::array::RecordBatch;
 use duckdb::{Connection, Params, Statement};
 use signal_hook::consts::signal::*;
@@ -27,6 +27,7 @@ use std::sync::Once;
 use std::thread;
 
 use super::{csv, delta, iceberg, json, parquet, secret, spatial};
+use crate::GUCS;
 
 // Global mutable static variables
 static mut GLOBAL_CONNECTION: Option<UnsafeCell<Connection>> = None;
@@ -42,11 +43,18 @@ fn init_globals() {
     let _ =
         set_duckdb_extension_directory(&conn).expect("failed to set duckdb extension directory");
 
-    // duckdb-rs stopped bundling in httpfs, so we need to load it ourselves
-    conn.execute("INSTALL httpfs", [])
-        .expect("failed to install httpfs");
-    conn.execute("LOAD httpfs", [])
-        .expect("failed to load httpfs");
+    // Servers without internet access install extensions from thdb.extension_repository instead
+    if let Err(err) = set_extension_repository(&conn) {
+        pgrx::log!("failed to set duckdb extension repository: {}", err);
+    }
+
+    // duckdb-rs stopped bundling in httpfs, which is preloaded by default. An extension that is
+    // missing only fails the tables that need it, when they load it.
+    for extension in GUCS.preload_extensions() {
+        if let Err(err) = load_extension_with(&conn, &extension) {
+            pgrx::debug1!("{}", err);
+        }
+    }
 
     unsafe {
         GLOBAL_CONNECTION = Some(UnsafeCell::new(conn));
@@ -64,17 +72,79 @@ fn init_globals() {
     });
 }
 
-fn check_extension_loaded(extension_name: &str) -> Result<bool> {
-    unsafe {
-        let conn = &mut *get_global_connection().get();
-        let mut statement = conn.prepare(format!("SELECT * FROM duckdb_extensions() WHERE extension_name = '{extension_name}' AND installed = true AND loaded = true").as_str())?;
-        match statement.query([])?.next() {
-            Ok(Some(_)) => Ok(true),
-            _ => Ok(false),
-        }
+fn set_extension_repository(conn: &Connection) -> Result<()> {
+    if let Some(repository) = GUCS.extension_repository() {
+        let repository = repository.replace('\'', "''");
+        conn.execute_batch(&format!(
+            "SET custom_extension_repository = '{repository}'; SET autoinstall_extension_repository = '{repository}';"
+        ))?;
+    }
+    Ok(())
+}
+
+fn check_extension_loaded(conn: &Connection, extension_name: &str) -> Result<bool> {
+    let mut statement = conn.prepare(format!("SELECT * FROM duckdb_extensions() WHERE extension_name = '{extension_name}' AND installed = true AND loaded = true").as_str())?;
+    match statement.query([])?.next() {
+        Ok(Some(_)) => Ok(true),
+        _ => Ok(false),
     }
 }
 
+fn check_extension_name(extension_name: &str) -> Result<()> {
+    if extension_name.is_empty()
+        || !extension_name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_')
+    {
+        bail!("invalid duckdb extension name: {extension_name}");
+    }
+    Ok(())
+}
+
+fn load_extension_with(conn: &Connection, extension_name: &str) -> Result<()> {
+    check_extension_name(extension_name)?;
+    if check_extension_loaded(conn, extension_name)? {
+        return Ok(());
+    }
+
+    // Extensions installed earlier, e.g. by duckdb_install_extension, load without a download
+    if conn
+        .execute_batch(&format!("LOAD {extension_name}"))
+        .is_ok()
+    {
+        return Ok(());
+    }
+
+    conn.execute_batch(&format!("INSTALL {extension_name}; LOAD {extension_name};"))
+        .map_err(|err| {
+            anyhow!(
+                "duckdb extension {extension_name} is not available: {err}. Install it with duckdb_install_extension, or set thdb.extension_repository to a directory that holds it"
+            )
+        })
+}
+
+/// Loads a DuckDB extension, installing it from thdb.extension_repository or the DuckDB repository if needed
+pub fn load_extension(extension_name: &str) -> Result<()> {
+    let conn = unsafe { &*get_global_connection().get() };
+    load_extension_with(conn, extension_name)
+}
+
+/// Installs a DuckDB extension from a .duckdb_extension file or a repository directory, and loads it
+pub fn install_extension(extension_name: &str, path: &str) -> Result<()> {
+    check_extension_name(extension_name)?;
+
+    let conn = unsafe { &*get_global_connection().get() };
+    let escaped_path = path.replace('\'', "''");
+    let install = if path.ends_with(".duckdb_extension") || path.ends_with(".duckdb_extension.gz") {
+        format!("FORCE INSTALL '{escaped_path}'")
+    } else {
+        format!("FORCE INSTALL {extension_name} FROM '{escaped_path}'")
+    };
+    conn.execute_batch(&install)?;
+    conn.execute_batch(&format!("LOAD {extension_name}"))?;
+    Ok(())
+}
+
 pub fn get_global_connection() -> &'static UnsafeCell<Connection> {
     INIT.call_once(|| {
         init_globals();
@@ -123,6 +193,8 @@ pub fn create_delta_view(
     schema_name: &str,
     table_options: HashMap<String, String>,
 ) -> Result<usize> {
+    load_extension("delta")?;
+
     let statement = delta::create_view(table_name, schema_name, table_options)?;
     execute(statement.as_str(), [])
 }
@@ -132,15 +204,25 @@ pub fn create_iceberg_view(
     schema_name: &str,
     table_options: HashMap<String, String>,
 ) -> Result<usize> {
-    if !check_extension_loaded("iceberg")? {
-        execute("INSTALL iceberg", [])?;
-        execute("LOAD iceberg", [])?;
-    }
+    load_extension("iceberg")?;
 
     let statement = iceberg::create_view(table_name, schema_name, table_options)?;
     execute(statement.as_str(), [])
 }
 
+/// Id of the current snapshot of an iceberg table, None if the table has no snapshot yet
+pub fn latest_iceberg_snapshot(table_options: &HashMap<String, String>) -> Result<Option<String>> {
+    load_extension("iceberg")?;
+
+    let conn = unsafe { &*get_global_connection().get() };
+    let mut stmt = conn.prepare(&iceberg::latest_snapshot_select(table_options)?)?;
+    let mut rows = stmt.query([])?;
+    Ok(match rows.next()? {
+        Some(row) => Some(row.get::<_, String>(0)?),
+        None => None,
+    })
+}
+
 pub fn create_parquet_view(
     table_name: &str,
     schema_name: &str,
@@ -155,10 +237,7 @@ pub fn create_spatial_view(
     schema_name: &str,
     table_options: HashMap<String, String>,
 ) -> Result<usize> {
-    if !check_extension_loaded("spatial")? {
-        execute("INSTALL spatial", [])?;
-        execute("LOAD spatial", [])?;
-    }
+    load_extension("spatial")?;
 
     let statement = spatial::create_view(table_name, schema_name, table_options)?;
     execute(statement.as_str(), [])
@@ -193,6 +272,13 @@ pub fn create_arrow(sql: &str) -> Result<bool> {
     Ok(true)
 }
 
+/// A new connection to the same database, for statements that run while the global one is busy.
+/// Views, secrets and loaded extensions are shared, session settings are not.
+pub fn clone_connection() -> Result<Connection> {
+    let conn = unsafe { &*get_global_connection().get() };
+    Ok(conn.try_clone()?)
+}
+
 pub fn clear_arrow() {
     unsafe {
         *get_global_statement().get() = None;
@@ -246,6 +332,49 @@ pub fn view_exists(table_name: &str, schema_name: &str) -> Result<bool> {
     }
 }
 
+/// The SQL DuckDB keeps for a view, None if there is no such view
+pub fn view_sql(table_name: &str, schema_name: &str) -> Result<Option<String>> {
+    let conn = unsafe { &*get_global_connection().get() };
+    let mut stmt = conn.prepare(
+        "SELECT sql FROM duckdb_views() WHERE schema_name = ? AND view_name = ? AND NOT internal",
+    )?;
+    let mut rows = stmt.query([schema_name, table_name])?;
+    Ok(match rows.next()? {
+        Some(row) => row.get::<_, Option<String>>(0)?,
+        None => None,
+    })
+}
+
+pub fn glob_files(pattern: &str) -> Result<Vec<String>> {
+    let conn = unsafe { &*get_global_connection().get() };
+    let mut stmt = conn.prepare(&format!(
+        "SELECT file FROM glob('{}')",
+        pattern.replace('\'', "''")
+    ))?;
+
+    Ok(stmt
+        .query_map([], |row| row.get::<_, String>(0))?
+        .collect::<Result<Vec<String>, _>>()?)
+}
+
+/// Name, size and modification time of every file matching the patterns, which change whenever a file is
+/// rewritten. read_blob only reads the contents of the files when they are selected.
+pub fn file_versions(patterns: &[&str]) -> Result<Vec<String>> {
+    let conn = unsafe { &*get_global_connection().get() };
+    let patterns = patterns
+        .iter()
+        .map(|pattern| format!("'{}'", pattern.replace('\'', "''")))
+        .collect::<Vec<String>>()
+        .join(", ");
+    let mut stmt = conn.prepare(&format!(
+        "SELECT filename || ':' || size || ':' || CAST(last_modified AS VARCHAR) FROM read_blob([{patterns}]) ORDER BY filename"
+    ))?;
+
+    Ok(stmt
+        .query_map([], |row| row.get::<_, String>(0))?
+        .collect::<Result<Vec<String>, _>>()?)
+}
+
 pub fn get_available_schemas() -> Result<Vec<String>> {
     let conn = unsafe { &*get_global_connection().get() };
     let mut stmt = conn.prepare("select DISTINCT(nspname) from pg_namespace;")?;
@@ -268,6 +397,15 @@ pub fn set_search_path(search_path: Vec<String>) -> Result<()> {
     Ok(())
 }
 
+pub fn set_timezone(timezone: &str) -> Result<()> {
+    execute(
+        format!("SET TimeZone = '{}'", timezone.replace('\'', "''")).as_str(),
+        [],
+    )?;
+
+    Ok(())
+}
+
 pub fn set_duckdb_extension_directory(conn: &Connection) -> Result<usize> {
     let data_dir = unsafe {
         CStr::from_ptr(pgrx::pg_sys::DataDir)
@@ -298,3 +436,16 @@ pub fn execute_explain(query: &str) -> Result<String> {
 
     Ok(rows.join(""))
 }
+
+pub fn execute_explain_json(query: &str, analyze: bool) -> Result<serde_json::Value> {
+    let explain_query = if analyze {
+        format!("EXPLAIN (ANALYZE, FORMAT JSON) {query}")
+    } else {
+        format!("EXPLAIN (FORMAT JSON) {query}")
+    };
+    let profile = execute_explain(&explain_query)?;
+
+    // Keep the raw output if DuckDB ever returns something that is not valid JSON,
+    // rather than failing the whole EXPLAIN
+    Ok(serde_json::from_str(&profile).unwrap_or(serde_json::Value::String(profile)))
+}
@@ -53,11 +53,17 @@ pub enum CsvOption {
     Parallel,
     PreserveCasing,
     Quote,
+    RejectsLimit,
+    RejectsScan,
+    RejectsTable,
     SampleSize,
     Select,
     Sep,
     Skip,
+    StoreRejects,
     Timestampformat,
+    Timezone,
+    TypeOverrides,
     Types,
     UnionByName,
 }
@@ -92,11 +98,17 @@ impl OptionValidator for CsvOption {
             Self::Parallel => false,
             Self::PreserveCasing => false,
             Self::Quote => false,
+            Self::RejectsLimit => false,
+            Self::RejectsScan => false,
+            Self::RejectsTable => false,
             Self::SampleSize => false,
             Self::Select => false,
             Self::Sep => false,
             Self::Skip => false,
+            Self::StoreRejects => false,
             Self::Timestampformat => false,
+            Self::Timezone => false,
+            Self::TypeOverrides => false,
             Self::Types => false,
             Self::UnionByName => false,
         }
@@ -108,6 +120,13 @@ pub fn create_view(
     schema_name: &str,
     table_options: HashMap<String, String>,
 ) -> Result<String> {
+    Ok(format!(
+        "CREATE VIEW IF NOT EXISTS {schema_name}.{table_name} AS {}",
+        create_select(table_options)?
+    ))
+}
+
+pub fn create_select(table_options: HashMap<String, String>) -> Result<String> {
     let files = Some(utils::format_csv(
         table_options
             .get(CsvOption::Files.as_ref())
@@ -214,6 +233,18 @@ pub fn create_view(
         .get(CsvOption::Quote.as_ref())
         .map(|option| format!("quote = '{option}'"));
 
+    let rejects_limit = table_options
+        .get(CsvOption::RejectsLimit.as_ref())
+        .map(|option| format!("rejects_limit = {option}"));
+
+    let rejects_scan = table_options
+        .get(CsvOption::RejectsScan.as_ref())
+        .map(|option| format!("rejects_scan = '{option}'"));
+
+    let rejects_table = table_options
+        .get(CsvOption::RejectsTable.as_ref())
+        .map(|option| format!("rejects_table = '{option}'"));
+
     let sample_size = table_options
         .get(CsvOption::SampleSize.as_ref())
         .map(|option| format!("sample_size = {option}"));
@@ -226,6 +257,10 @@ pub fn create_view(
         .get(CsvOption::Skip.as_ref())
         .map(|option| format!("skip = {option}"));
 
+    let store_rejects = table_options
+        .get(CsvOption::StoreRejects.as_ref())
+        .map(|option| format!("store_rejects = {option}"));
+
     let timestampformat = table_options
         .get(CsvOption::Timestampformat.as_ref())
         .map(|option| format!("timestampformat = '{option}'"));
@@ -265,9 +300,13 @@ pub fn create_view(
         nullstr,
         parallel,
         quote,
+        rejects_limit,
+        rejects_scan,
+        rejects_table,
         sample_size,
         sep,
         skip,
+        store_rejects,
         timestampformat,
         types,
         union_by_name,
@@ -282,7 +321,7 @@ pub fn create_view(
         .get(CsvOption::Select.as_ref())
         .unwrap_or(&default_select);
 
-    Ok(format!("CREATE VIEW IF NOT EXISTS {schema_name}.{table_name} AS SELECT {select} FROM read_csv({create_csv_str})"))
+    Ok(format!("SELECT {select} FROM read_csv({create_csv_str})"))
 }
 
 #[cfg(test)]
@@ -451,4 +490,35 @@ mod tests {
             Err(e) => assert!(e.to_string().contains("file.csv")),
         }
     }
+
+    #[test]
+    fn test_create_csv_view_with_rejects() {
+        let table_options = HashMap::from([
+            (
+                CsvOption::Files.as_ref().to_string(),
+                "/data/file.csv".to_string(),
+            ),
+            (
+                CsvOption::StoreRejects.as_ref().to_string(),
+                "true".to_string(),
+            ),
+            (
+                CsvOption::RejectsTable.as_ref().to_string(),
+                "file_errors".to_string(),
+            ),
+            (
+                CsvOption::RejectsScan.as_ref().to_string(),
+                "file_scans".to_string(),
+            ),
+            (
+                CsvOption::RejectsLimit.as_ref().to_string(),
+                "100".to_string(),
+            ),
+        ]);
+
+        let expected = "SELECT * FROM read_csv('/data/file.csv', rejects_limit = 100, rejects_scan = 'file_scans', rejects_table = 'file_errors', store_rejects = true)";
+        let actual = create_select(table_options).unwrap();
+
+        assert_eq!(expected, actual);
+    }
 }
@@ -26,6 +26,8 @@ pub enum DeltaOption {
     Files,
     PreserveCasing,
     Select,
+    Timezone,
+    TypeOverrides,
 }
 
 impl OptionValidator for DeltaOption {
@@ -34,6 +36,8 @@ impl OptionValidator for DeltaOption {
             Self::Files => true,
             Self::PreserveCasing => false,
             Self::Select => false,
+            Self::Timezone => false,
+            Self::TypeOverrides => false,
         }
     }
 }
@@ -43,6 +47,13 @@ pub fn create_view(
     schema_name: &str,
     table_options: HashMap<String, String>,
 ) -> Result<String> {
+    Ok(format!(
+        "CREATE VIEW IF NOT EXISTS {schema_name}.{table_name} AS {}",
+        create_select(table_options)?
+    ))
+}
+
+pub fn create_select(table_options: HashMap<String, String>) -> Result<String> {
     let files = format!(
         "'{}'",
         table_options
@@ -55,11 +66,30 @@ pub fn create_view(
         .get(DeltaOption::Select.as_ref())
         .unwrap_or(&default_select);
 
+    Ok(format!("SELECT {select} FROM delta_scan({files})"))
+}
+
+/// Attaches the table as a database that keeps reading the version that was current when it was attached
+pub fn attach_snapshot(table_options: &HashMap<String, String>, alias: &str) -> Result<String> {
+    let files = table_options
+        .get(DeltaOption::Files.as_ref())
+        .ok_or_else(|| anyhow!("files option is required"))?;
+
     Ok(format!(
-        "CREATE VIEW IF NOT EXISTS {schema_name}.{table_name} AS SELECT {select} FROM delta_scan({files})"
+        "ATTACH IF NOT EXISTS '{files}' AS {alias} (TYPE delta, PIN_SNAPSHOT)"
     ))
 }
 
+/// The select of the view, reading a table attached with attach_snapshot
+pub fn create_snapshot_select(table_options: &HashMap<String, String>, alias: &str) -> String {
+    let select = table_options
+        .get(DeltaOption::Select.as_ref())
+        .map(String::as_str)
+        .unwrap_or("*");
+
+    format!("SELECT {select} FROM {alias}")
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -86,4 +116,24 @@ mod tests {
             Err(e) => assert!(e.to_string().contains("/data/delta")),
         }
     }
+
+    #[test]
+    fn test_delta_snapshot() {
+        let table_options = HashMap::from([
+            (
+                DeltaOption::Files.as_ref().to_string(),
+                "/data/delta".to_string(),
+            ),
+            (DeltaOption::Select.as_ref().to_string(), "id".to_string()),
+        ]);
+
+        assert_eq!(
+            attach_snapshot(&table_options, "delta_snapshot_16384").unwrap(),
+            "ATTACH IF NOT EXISTS '/data/delta' AS delta_snapshot_16384 (TYPE delta, PIN_SNAPSHOT)"
+        );
+        assert_eq!(
+            create_snapshot_select(&table_options, "delta_snapshot_16384"),
+            "SELECT id FROM delta_snapshot_16384"
+        );
+    }
 }
@@ -30,6 +30,8 @@ pub enum IcebergOption {
     Files,
     PreserveCasing,
     Select,
+    Timezone,
+    TypeOverrides,
 }
 
 impl OptionValidator for IcebergOption {
@@ -41,6 +43,8 @@ impl OptionValidator for IcebergOption {
             Self::Files => true,
             Self::PreserveCasing => false,
             Self::Select => false,
+            Self::Timezone => false,
+            Self::TypeOverrides => false,
         }
     }
 }
@@ -50,30 +54,38 @@ pub fn create_view(
     schema_name: &str,
     table_options: HashMap<String, String>,
 ) -> Result<String> {
-    let files = Some(format!(
-        "'{}'",
-        table_options
-            .get(IcebergOption::Files.as_ref())
-            .ok_or_else(|| anyhow!("files option is required"))?
-    ));
+    Ok(format!(
+        "CREATE VIEW IF NOT EXISTS {schema_name}.{table_name} AS {}",
+        create_select(table_options, None)?
+    ))
+}
+
+/// The select of the view, reading the given snapshot instead of the latest one if there is one
+pub fn create_select(
+    table_options: HashMap<String, String>,
+    snapshot_id: Option<&str>,
+) -> Result<String> {
+    let files = Some(files(&table_options)?);
 
     let allow_moved_paths = table_options
         .get(IcebergOption::AllowMovedPaths.as_ref())
         .map(|option| format!("allow_moved_paths = {option}"));
 
-    let metadata_compression_codec = table_options
-        .get(IcebergOption::MetadataCompressionCodec.as_ref())
-        .map(|option| format!("metadata_compression_codec = '{option}'"));
+    let metadata_compression_codec = metadata_compression_codec(&table_options);
 
     let skip_schema_inference = table_options
         .get(IcebergOption::SkipSchemaInference.as_ref())
         .map(|option| format!("skip_schema_inference = {option}"));
 
+    let snapshot_from_id =
+        snapshot_id.map(|snapshot_id| format!("snapshot_from_id = {snapshot_id}"));
+
     let create_iceberg_str = [
         files,
         allow_moved_paths,
         metadata_compression_codec,
         skip_schema_inference,
+        snapshot_from_id,
     ]
     .into_iter()
     .flatten()
@@ -85,7 +97,40 @@ pub fn create_view(
         .get(IcebergOption::Select.as_ref())
         .unwrap_or(&default_select);
 
-    Ok(format!("CREATE VIEW IF NOT EXISTS {schema_name}.{table_name} AS SELECT {select} FROM iceberg_scan({create_iceberg_str})"))
+    Ok(format!(
+        "SELECT {select} FROM iceberg_scan({create_iceberg_str})"
+    ))
+}
+
+/// Query for the id of the current snapshot of the table, the one with the highest sequence number
+pub fn latest_snapshot_select(table_options: &HashMap<String, String>) -> Result<String> {
+    let arguments = [
+        Some(files(table_options)?),
+        metadata_compression_codec(table_options),
+    ]
+    .into_iter()
+    .flatten()
+    .collect::<Vec<String>>()
+    .join(", ");
+
+    Ok(format!(
+        "SELECT CAST(snapshot_id AS VARCHAR) FROM iceberg_snapshots({arguments}) ORDER BY sequence_number DESC LIMIT 1"
+    ))
+}
+
+fn files(table_options: &HashMap<String, String>) -> Result<String> {
+    Ok(format!(
+        "'{}'",
+        table_options
+            .get(IcebergOption::Files.as_ref())
+            .ok_or_else(|| anyhow!("files option is required"))?
+    ))
+}
+
+fn metadata_compression_codec(table_options: &HashMap<String, String>) -> Option<String> {
+    table_options
+        .get(IcebergOption::MetadataCompressionCodec.as_ref())
+        .map(|option| format!("metadata_compression_codec = '{option}'"))
 }
 
 #[cfg(test)]
@@ -117,4 +162,27 @@ mod tests {
             Err(e) => assert!(e.to_string().contains("/data/iceberg")),
         }
     }
+
+    #[test]
+    fn test_create_pinned_iceberg_select() {
+        let table_options = HashMap::from([
+            (
+                IcebergOption::Files.as_ref().to_string(),
+                "/data/iceberg".to_string(),
+            ),
+            (
+                IcebergOption::MetadataCompressionCodec.as_ref().to_string(),
+                "gzip".to_string(),
+            ),
+        ]);
+
+        assert_eq!(
+            create_select(table_options.clone(), Some("7635660646343998149")).unwrap(),
+            "SELECT * FROM iceberg_scan('/data/iceberg', metadata_compression_codec = 'gzip', snapshot_from_id = 7635660646343998149)"
+        );
+        assert_eq!(
+            latest_snapshot_select(&table_options).unwrap(),
+            "SELECT CAST(snapshot_id AS VARCHAR) FROM iceberg_snapshots('/data/iceberg', metadata_compression_codec = 'gzip') ORDER BY sequence_number DESC LIMIT 1"
+        );
+    }
 }
@@ -15,7 +15,7 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program. If not, see <http://www.gnu.org/licenses/>.
 
-use anyhow::{anyhow, Result};
+use anyhow::{anyhow, bail, Result};
 use std::collections::HashMap;
 use strum::{AsRefStr, Display, EnumIter};
 
@@ -39,9 +39,13 @@ pub enum JsonOption {
     MaximumDepth,
     MaximumObjectSize,
     Records,
+    RejectsLimit,
     SampleSize,
     Select,
+    StoreRejects,
     Timestampformat,
+    Timezone,
+    TypeOverrides,
     UnionByName,
 }
 
@@ -56,12 +60,21 @@ pub fn create_view(
     schema_name: &str,
     table_options: HashMap<String, String>,
 ) -> Result<String> {
+    Ok(format!(
+        "CREATE VIEW IF NOT EXISTS {schema_name}.{table_name} AS {}",
+        create_select(table_options)?
+    ))
+}
+
+pub fn create_select(table_options: HashMap<String, String>) -> Result<String> {
     let files = Some(utils::format_csv(
         table_options
             .get(JsonOption::Files.as_ref())
             .ok_or_else(|| anyhow!("files option is required"))?,
     ));
 
+    let store_rejects = store_rejects(&table_options)?;
+
     let create_json_str = vec![
         files,
         extract_option(JsonOption::AutoDetect, &table_options, false),
@@ -72,11 +85,11 @@ pub fn create_view(
         extract_option(JsonOption::Filename, &table_options, false),
         extract_option(JsonOption::Format, &table_options, true),
         extract_option(JsonOption::HivePartitioning, &table_options, false),
-        extract_option(JsonOption::IgnoreErrors, &table_options, false),
+        ignore_errors(&table_options, store_rejects),
         extract_option(JsonOption::MaximumDepth, &table_options, false),
         extract_option(JsonOption::MaximumObjectSize, &table_options, false),
         extract_option(JsonOption::Records, &table_options, false),
-        extract_option(JsonOption::SampleSize, &table_options, false),
+        sample_size(&table_options, store_rejects),
         extract_option(JsonOption::Timestampformat, &table_options, true),
         extract_option(JsonOption::UnionByName, &table_options, false),
     ]
@@ -90,7 +103,47 @@ pub fn create_view(
         .get(JsonOption::Select.as_ref())
         .unwrap_or(&default_select);
 
-    Ok(format!("CREATE VIEW IF NOT EXISTS {schema_name}.{table_name} AS SELECT {select} FROM read_json({create_json_str})"))
+    Ok(format!("SELECT {select} FROM read_json({create_json_str})"))
+}
+
+// read_json can't store rejected records, so they are skipped while scanning
+// and found again by validating the files when they are requested. Only records that
+// are not valid JSON are found that way, so the options that make valid records fail
+// to convert, and be skipped without being reported, are rejected.
+fn store_rejects(table_options: &HashMap<String, String>) -> Result<bool> {
+    let is_set = |option: JsonOption, value: &str| {
+        table_options
+            .get(option.as_ref())
+            .is_some_and(|option| option.eq_ignore_ascii_case(value))
+    };
+
+    if !is_set(JsonOption::StoreRejects, "true") {
+        return Ok(false);
+    }
+
+    if table_options.contains_key(JsonOption::Columns.as_ref())
+        || is_set(JsonOption::AutoDetect, "false")
+    {
+        bail!("store_rejects can't be combined with columns or auto_detect 'false': records that don't fit the given types are skipped without being reported");
+    }
+    if table_options.contains_key(JsonOption::SampleSize.as_ref())
+        && !is_set(JsonOption::SampleSize, "-1")
+    {
+        bail!("store_rejects requires sample_size '-1': records that don't fit the types detected from the sample are skipped without being reported");
+    }
+
+    Ok(true)
+}
+
+fn ignore_errors(table_options: &HashMap<String, String>, store_rejects: bool) -> Option<String> {
+    extract_option(JsonOption::IgnoreErrors, table_options, false)
+        .or_else(|| store_rejects.then(|| format!("{} = true", JsonOption::IgnoreErrors)))
+}
+
+// Types detected from every record fit every valid record
+fn sample_size(table_options: &HashMap<String, String>, store_rejects: bool) -> Option<String> {
+    extract_option(JsonOption::SampleSize, table_options, false)
+        .or_else(|| store_rejects.then(|| format!("{} = -1", JsonOption::SampleSize)))
 }
 
 fn extract_option(
@@ -185,4 +238,29 @@ mod tests {
             Err(e) => assert!(e.to_string().contains("file1.json")),
         }
     }
+
+    #[test]
+    fn test_create_json_view_store_rejects() {
+        let table_options = HashMap::from([
+            (
+                JsonOption::Files.to_string(),
+                "/data/file1.json".to_string(),
+            ),
+            (JsonOption::StoreRejects.to_string(), "true".to_string()),
+        ]);
+
+        let expected =
+            "SELECT * FROM read_json('/data/file1.json', ignore_errors = true, sample_size = -1)";
+        assert_eq!(create_select(table_options.clone()).unwrap(), expected);
+
+        for (option, value) in [
+            (JsonOption::Columns, "{'key1': 'INTEGER'}"),
+            (JsonOption::AutoDetect, "false"),
+            (JsonOption::SampleSize, "100"),
+        ] {
+            let mut table_options = table_options.clone();
+            table_options.insert(option.to_string(), value.to_string());
+            assert!(create_select(table_options).is_err());
+        }
+    }
 }
@@ -34,6 +34,8 @@ pub enum ParquetOption {
     HiveTypes,
     HiveTypesAutocast,
     PreserveCasing,
+    Timezone,
+    TypeOverrides,
     UnionByName,
     Select,
     // TODO: EncryptionConfig
@@ -51,6 +53,8 @@ impl OptionValidator for ParquetOption {
             Self::HiveTypesAutocast => false,
             Self::PreserveCasing => false,
             Self::Select => false,
+            Self::Timezone => false,
+            Self::TypeOverrides => false,
             Self::UnionByName => false,
         }
     }
@@ -61,6 +65,13 @@ pub fn create_view(
     schema_name: &str,
     table_options: HashMap<String, String>,
 ) -> Result<String> {
+    Ok(format!(
+        "CREATE VIEW IF NOT EXISTS {schema_name}.{table_name} AS {}",
+        create_select(table_options)?
+    ))
+}
+
+pub fn create_select(table_options: HashMap<String, String>) -> Result<String> {
     let files = Some(utils::format_csv(
         table_options
             .get(ParquetOption::Files.as_ref())
@@ -115,7 +126,9 @@ pub fn create_view(
         .get(ParquetOption::Select.as_ref())
         .unwrap_or(&default_select);
 
-    Ok(format!("CREATE VIEW IF NOT EXISTS {schema_name}.{table_name} AS SELECT {select} FROM read_parquet({create_parquet_str})"))
+    Ok(format!(
+        "SELECT {select} FROM read_parquet({create_parquet_str})"
+    ))
 }
 
 #[cfg(test)]
@@ -36,6 +36,7 @@ pub enum SpatialOption {
     SiblingFiles,
     SpatialFilterBox,
     KeepWkb,
+    TypeOverrides,
 }
 
 impl OptionValidator for SpatialOption {
@@ -50,6 +51,7 @@ impl OptionValidator for SpatialOption {
             Self::SiblingFiles => false,
             Self::SpatialFilterBox => false,
             Self::KeepWkb => false,
+            Self::TypeOverrides => false,
         }
     }
 }
@@ -67,6 +69,8 @@ pub fn create_view(
         .filter_map(|param| {
             let value = table_options.get(param.as_ref())?;
             Some(match param {
+                // Only read by auto schema creation, not by st_read
+                SpatialOption::TypeOverrides => return None,
                 SpatialOption::Files => format!("'{}'", value),
                 _ => format!("{}={}", param.as_ref(), value),
             })
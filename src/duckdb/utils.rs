@@ -29,3 +29,71 @@ pub fn format_csv(csv_str: &str) -> String {
         ),
     }
 }
+
+/// Paths without a URL scheme, or with the file:// scheme, are read from the local filesystem
+pub fn is_local_path(path: &str) -> bool {
+    match path.split_once("://") {
+        Some((scheme, _)) => scheme.eq_ignore_ascii_case("file"),
+        None => true,
+    }
+}
+
+/// Resolves `.` and `..` lexically, so that a path can't escape an allowed directory
+pub fn normalize_local_path(path: &str) -> String {
+    let path = path.strip_prefix("file://").unwrap_or(path);
+    let mut components: Vec<&str> = vec![];
+
+    for component in path.split('/') {
+        match component {
+            "" | "." => {}
+            ".." => {
+                components.pop();
+            }
+            component => components.push(component),
+        }
+    }
+
+    match path.starts_with('/') {
+        true => format!("/{}", components.join("/")),
+        false => components.join("/"),
+    }
+}
+
+pub fn is_path_allowed(path: &str, allowed_directories: &[String]) -> bool {
+    let path = normalize_local_path(path);
+    allowed_directories
+        .iter()
+        .filter(|directory| is_local_path(directory))
+        .map(|directory| normalize_local_path(directory))
+        .any(|directory| {
+            path == directory
+                || directory == "/"
+                || path.starts_with(format!("{directory}/").as_str())
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_local_path() {
+        assert!(is_local_path("/data/file.parquet"));
+        assert!(is_local_path("data/*.csv"));
+        assert!(is_local_path("file:///data/file.parquet"));
+        assert!(!is_local_path("s3://bucket/file.parquet"));
+        assert!(!is_local_path("https://example.com/file.csv"));
+    }
+
+    #[test]
+    fn test_is_path_allowed() {
+        let allowed = vec!["/srv/data/".to_string(), "s3://bucket/".to_string()];
+        assert!(is_path_allowed("/srv/data/trips.parquet", &allowed));
+        assert!(is_path_allowed("/srv/data/**/*.parquet", &allowed));
+        assert!(is_path_allowed("file:///srv/data/a.csv", &allowed));
+        assert!(!is_path_allowed("/srv/data/../../etc/passwd", &allowed));
+        assert!(!is_path_allowed("/srv/database/file.parquet", &allowed));
+        assert!(!is_path_allowed("srv/data/file.parquet", &allowed));
+        assert!(!is_path_allowed("/etc/passwd", &allowed));
+    }
+}
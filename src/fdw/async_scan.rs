@@ -0,0 +1,380 @@
+// Copyright (c) 2023-2025 Retake, Inc.
+//
+// This file is part of ParadeDB - Postgres for Search and Analytics
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <http://www.gnu.org/licenses/>.
+
+use anyhow::{anyhow, Result};
+use duckdb::arrow::array::RecordBatch;
+use duckdb::InterruptHandle;
+use pgrx::*;
+use std::cell::{Cell as StdCell, RefCell};
+use std::collections::HashMap;
+use std::io::{Read, Write};
+#[cfg(not(feature = "pg13"))]
+use std::os::fd::AsRawFd;
+use std::os::unix::net::UnixStream;
+use std::rc::Rc;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, SyncSender, TryRecvError};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use crate::duckdb::connection;
+
+#[cfg(not(feature = "pg13"))]
+use crate::GUCS;
+
+// Foreign scans under an async Append (Postgres 14 and later) run their statement on a connection of
+// their own, in a thread that queues the batches and writes a byte to a socket for each of them. The
+// Append waits on the sockets of all its scans at once and takes rows from whichever is ready, so the
+// DuckDB statements of a UNION ALL run side by side instead of one after the other.
+
+// Batches a scan reads ahead of the rows returned to Postgres
+const READ_AHEAD_BATCHES: usize = 4;
+
+// How long a blocking read waits before checking for a query cancel
+const RECV_INTERVAL: Duration = Duration::from_millis(100);
+
+// How often a stopped statement is interrupted again until its thread is done
+const STOP_INTERVAL: Duration = Duration::from_millis(10);
+
+thread_local! {
+    // ForeignScanState whose BeginForeignScan is being called, when its plan is async capable
+    static REQUESTED: StdCell<Option<usize>> = const { StdCell::new(None) };
+    // Running statements, by ForeignScanState. Dropping one disconnects its thread.
+    static ASYNC_SCANS: RefCell<HashMap<usize, AsyncScan>> = RefCell::new(HashMap::new());
+    static NEXT_ID: StdCell<u64> = const { StdCell::new(0) };
+    static XACT_CALLBACK_REGISTERED: StdCell<bool> = const { StdCell::new(false) };
+    static SUBXACT_CALLBACK_REGISTERED: StdCell<bool> = const { StdCell::new(false) };
+}
+
+struct AsyncScan {
+    id: u64,
+    receiver: Receiver<Result<RecordBatch>>,
+    // Batch received while checking whether the scan is ready, not yet handed to the scan
+    buffered: Option<Result<RecordBatch>>,
+    finished: bool,
+    waker: UnixStream,
+    // Whether the scan still has rows of its current batch, which it returns without waiting
+    has_rows: Rc<StdCell<bool>>,
+    interrupt: Arc<InterruptHandle>,
+    thread: Option<JoinHandle<()>>,
+    // Statements are stopped when the subtransaction that started them aborts
+    subxact: pg_sys::SubTransactionId,
+}
+
+impl Drop for AsyncScan {
+    // The thread returns once its statement is interrupted or its next batch can't be sent.
+    // A statement that had not started yet when interrupted is interrupted again.
+    fn drop(&mut self) {
+        let (_, disconnected) = mpsc::sync_channel(0);
+        drop(std::mem::replace(&mut self.receiver, disconnected));
+
+        if let Some(handle) = self.thread.take() {
+            while !handle.is_finished() {
+                self.interrupt.interrupt();
+                thread::sleep(STOP_INTERVAL);
+            }
+            let _ = handle.join();
+        }
+    }
+}
+
+impl AsyncScan {
+    fn is_ready(&mut self) -> bool {
+        if self.has_rows.get() || self.buffered.is_some() || self.finished {
+            return true;
+        }
+
+        // The thread queues a batch before writing its byte, so a batch queued after the first
+        // check has its byte drained here and is found by the second one
+        for drain in [false, true] {
+            if drain {
+                let mut bytes = [0; 64];
+                while matches!((&self.waker).read(&mut bytes), Ok(read) if read > 0) {}
+            }
+            match self.receiver.try_recv() {
+                Ok(message) => {
+                    self.buffered = Some(message);
+                    return true;
+                }
+                Err(TryRecvError::Disconnected) => {
+                    self.finished = true;
+                    return true;
+                }
+                Err(TryRecvError::Empty) => {}
+            }
+        }
+        false
+    }
+}
+
+/// The statement of a foreign scan running on its own connection, as seen by the scan.
+/// Dropping it stops the statement.
+pub struct AsyncScanHandle {
+    node: usize,
+    id: u64,
+    has_rows: Rc<StdCell<bool>>,
+}
+
+impl AsyncScanHandle {
+    /// Waits for the next batch of the statement, None once it is done
+    pub fn next_batch(&self) -> Result<Option<RecordBatch>> {
+        loop {
+            let received = ASYNC_SCANS.with(|scans| {
+                let mut scans = scans.borrow_mut();
+                let scan = scans
+                    .get_mut(&self.node)
+                    .filter(|scan| scan.id == self.id)
+                    .ok_or_else(|| anyhow!("async foreign scan is no longer running"))?;
+                if let Some(message) = scan.buffered.take() {
+                    return message.map(Some).map(Some);
+                }
+                if scan.finished {
+                    return Ok(Some(None));
+                }
+                match scan.receiver.recv_timeout(RECV_INTERVAL) {
+                    Ok(message) => message.map(Some).map(Some),
+                    Err(RecvTimeoutError::Disconnected) => {
+                        scan.finished = true;
+                        Ok(Some(None))
+                    }
+                    Err(RecvTimeoutError::Timeout) => Ok(None),
+                }
+            })?;
+
+            match received {
+                Some(batch) => return Ok(batch),
+                None => check_for_interrupts!(),
+            }
+        }
+    }
+
+    pub fn set_has_rows(&self, has_rows: bool) {
+        self.has_rows.set(has_rows);
+    }
+}
+
+impl Drop for AsyncScanHandle {
+    fn drop(&mut self) {
+        ASYNC_SCANS.with(|scans| {
+            let mut scans = scans.borrow_mut();
+            if scans.get(&self.node).is_some_and(|scan| scan.id == self.id) {
+                scans.remove(&self.node);
+            }
+        });
+    }
+}
+
+/// Runs BeginForeignScan, letting the scan start its statement right away if the plan is async capable
+#[cfg(not(feature = "pg13"))]
+pub(super) unsafe fn begin_foreign_scan<R>(
+    node: *mut pg_sys::ForeignScanState,
+    eflags: i32,
+    begin: impl FnOnce() -> R,
+) -> R {
+    let plan = (*node).ss.ps.plan;
+    let requested = (*plan).async_capable
+        && !(*plan).parallel_aware
+        && eflags & pg_sys::EXEC_FLAG_EXPLAIN_ONLY as i32 == 0;
+
+    // A scan that errors out before it takes the request leaves it to the abort callbacks
+    if requested {
+        register_callbacks();
+    }
+    REQUESTED.with(|current| current.set(requested.then_some(node as usize)));
+    let result = begin();
+    REQUESTED.with(|current| current.set(None));
+    result
+}
+
+/// Starts the statement of the scan being begun on its own connection, if the scan is async
+pub fn start_requested(sql: &str) -> Result<Option<AsyncScanHandle>> {
+    let Some(node) = REQUESTED.with(|current| current.take()) else {
+        return Ok(None);
+    };
+
+    let conn = connection::clone_connection()?;
+    let (sender, receiver) = mpsc::sync_channel(READ_AHEAD_BATCHES);
+    let (waker, thread_waker) = UnixStream::pair()?;
+    waker.set_nonblocking(true)?;
+
+    let interrupt = conn.interrupt_handle();
+    let sql = sql.to_string();
+    let thread = thread::spawn(move || {
+        if let Err(err) = read_batches(&conn, &sql, &sender, &thread_waker) {
+            let _ = sender.send(Err(err));
+        }
+        // Disconnecting the channel is what tells the scan that the statement is done
+        drop(sender);
+        let _ = (&thread_waker).write_all(&[1]);
+    });
+
+    let id = NEXT_ID.with(|next| next.replace(next.get() + 1));
+    let has_rows = Rc::new(StdCell::new(false));
+    ASYNC_SCANS.with(|scans| {
+        scans.borrow_mut().insert(
+            node,
+            AsyncScan {
+                id,
+                receiver,
+                buffered: None,
+                finished: false,
+                waker,
+                has_rows: has_rows.clone(),
+                interrupt,
+                thread: Some(thread),
+                subxact: unsafe { pg_sys::GetCurrentSubTransactionId() },
+            },
+        )
+    });
+    register_callbacks();
+
+    Ok(Some(AsyncScanHandle { node, id, has_rows }))
+}
+
+// Scans that errored out never drop their handle, so whatever is left is stopped with the transaction
+// or the subtransaction that started it
+fn register_callbacks() {
+    if !XACT_CALLBACK_REGISTERED.with(|registered| registered.replace(true)) {
+        for event in [PgXactCallbackEvent::Commit, PgXactCallbackEvent::Abort] {
+            register_xact_callback(event, clear_async_scans);
+        }
+    }
+    if !SUBXACT_CALLBACK_REGISTERED.with(|registered| registered.replace(true)) {
+        unsafe { pg_sys::RegisterSubXactCallback(Some(abort_subxact_scans), std::ptr::null_mut()) };
+    }
+}
+
+// Runs in the thread of the scan, so it must not call into Postgres
+fn read_batches(
+    conn: &duckdb::Connection,
+    sql: &str,
+    sender: &SyncSender<Result<RecordBatch>>,
+    mut waker: &UnixStream,
+) -> Result<()> {
+    let mut statement = conn.prepare(sql)?;
+    for batch in statement.query_arrow([])? {
+        // The scan is gone once the channel is disconnected
+        if sender.send(Ok(batch)).is_err() {
+            return Ok(());
+        }
+        let _ = waker.write_all(&[1]);
+    }
+    Ok(())
+}
+
+fn clear_async_scans() {
+    let scans = ASYNC_SCANS.with(|scans| std::mem::take(&mut *scans.borrow_mut()));
+    drop(scans);
+    REQUESTED.with(|current| current.set(None));
+    XACT_CALLBACK_REGISTERED.with(|registered| registered.set(false));
+}
+
+#[pg_guard]
+unsafe extern "C" fn abort_subxact_scans(
+    event: pg_sys::SubXactEvent::Type,
+    subxact: pg_sys::SubTransactionId,
+    _parent_subxact: pg_sys::SubTransactionId,
+    _arg: *mut std::ffi::c_void,
+) {
+    if event != pg_sys::SubXactEvent::SUBXACT_EVENT_ABORT_SUB {
+        return;
+    }
+
+    let aborted = ASYNC_SCANS.with(|scans| {
+        let mut scans = scans.borrow_mut();
+        let nodes = scans
+            .iter()
+            .filter(|(_, scan)| scan.subxact == subxact)
+            .map(|(node, _)| *node)
+            .collect::<Vec<usize>>();
+        nodes
+            .into_iter()
+            .filter_map(|node| scans.remove(&node))
+            .collect::<Vec<AsyncScan>>()
+    });
+    drop(aborted);
+    REQUESTED.with(|current| current.set(None));
+}
+
+#[cfg(not(feature = "pg13"))]
+#[pg_guard]
+pub(super) unsafe extern "C" fn is_foreign_path_async_capable(
+    _path: *mut pg_sys::ForeignPath,
+) -> bool {
+    GUCS.enable_async_foreign_scan.get()
+}
+
+#[cfg(not(feature = "pg13"))]
+#[pg_guard]
+pub(super) unsafe extern "C" fn foreign_async_request(areq: *mut pg_sys::AsyncRequest) {
+    produce_tuple(areq);
+}
+
+#[cfg(not(feature = "pg13"))]
+#[pg_guard]
+pub(super) unsafe extern "C" fn foreign_async_configure_wait(areq: *mut pg_sys::AsyncRequest) {
+    let node = (*areq).requestee as usize;
+    let Some(fd) =
+        ASYNC_SCANS.with(|scans| scans.borrow().get(&node).map(|scan| scan.waker.as_raw_fd()))
+    else {
+        return;
+    };
+
+    let append = (*areq).requestor as *mut pg_sys::AppendState;
+    pg_sys::AddWaitEventToSet(
+        (*append).as_eventset,
+        pg_sys::WL_SOCKET_READABLE,
+        fd,
+        std::ptr::null_mut(),
+        areq as *mut std::ffi::c_void,
+    );
+}
+
+#[cfg(not(feature = "pg13"))]
+#[pg_guard]
+pub(super) unsafe extern "C" fn foreign_async_notify(areq: *mut pg_sys::AsyncRequest) {
+    produce_tuple(areq);
+}
+
+/// Returns the next row of the scan if it can do so without waiting, otherwise leaves the request pending
+/// until its socket is readable. Scans without a statement of their own, such as those of parallel
+/// workers or rescans, always answer right away.
+#[cfg(not(feature = "pg13"))]
+unsafe fn produce_tuple(areq: *mut pg_sys::AsyncRequest) {
+    let node = (*areq).requestee;
+    let ready = ASYNC_SCANS.with(|scans| {
+        scans
+            .borrow_mut()
+            .get_mut(&(node as usize))
+            .map(|scan| scan.is_ready())
+            .unwrap_or(true)
+    });
+
+    // The same as ExecAsyncRequestPending and ExecAsyncRequestDone
+    if !ready {
+        (*areq).callback_pending = true;
+        (*areq).request_complete = false;
+        (*areq).result = std::ptr::null_mut();
+        return;
+    }
+
+    let exec_proc_node = (*node)
+        .ExecProcNodeReal
+        .expect("ExecProcNodeReal is not set");
+    (*areq).request_complete = true;
+    (*areq).result = exec_proc_node(node);
+}
@@ -19,35 +19,167 @@ use anyhow::{anyhow, bail, Result};
 use duckdb::arrow::array::RecordBatch;
 use pgrx::*;
 use std::collections::HashMap;
+use std::ffi::CStr;
+use std::sync::Arc;
+use std::time::Instant;
 use strum::IntoEnumIterator;
 use supabase_wrappers::prelude::*;
 use thiserror::Error;
 
+use super::async_scan::{self, AsyncScanHandle};
+use super::drift::check_schema_drift;
 use super::handler::FdwHandler;
-use crate::duckdb::connection;
-use crate::schema::cell::*;
+use super::parallel::{current_parallel_scan, ParallelScan};
+use super::snapshot::pin_snapshot;
+use super::spatial_filter::apply_spatial_filter;
+use crate::duckdb::parquet::{self, ParquetOption};
+use crate::duckdb::{connection, utils};
+use crate::schema::converter::ColumnConverter;
+use crate::schema::postgis::{postgis_scan_target, postgis_type};
+use crate::stats;
+use crate::GUCS;
 
 #[cfg(debug_assertions)]
 use crate::DEBUG_GUCS;
 
-const DEFAULT_SECRET: &str = "default_secret";
+pub(crate) const DEFAULT_SECRET: &str = "default_secret";
+
+// Table option with the time zone of timestamps without one, e.g. Europe/Berlin
+pub(crate) const TIMEZONE_OPTION: &str = "timezone";
+
+// Table options that can point DuckDB at local files
+const FILE_OPTIONS: [&str; 2] = ["files", "sibling_files"];
+
+/// State of a foreign scan. The scan loop borrows it instead of cloning the batch and targets
+/// out of getters, so reading a row allocates nothing beyond the Datums themselves.
+#[derive(Default)]
+pub struct ScanState {
+    batch: Option<RecordBatch>,
+    batch_index: usize,
+    // Target names are interned once per scan and shared by the converters of every batch
+    columns: Vec<(Arc<str>, pg_sys::Oid)>,
+    converters: Vec<ColumnConverter>,
+    started: bool,
+    sql: Option<String>,
+    timezone: Option<Arc<str>>,
+    // Set for tables whose files can be read one at a time by parallel workers
+    file_scan: Option<FileScan>,
+    parallel: Option<ParallelScan>,
+    // Set for scans under an async Append, whose statement runs on a connection of its own
+    async_scan: Option<AsyncScanHandle>,
+}
+
+/// The scan statement of a parquet table, split around the view so it can read a single file instead
+struct FileScan {
+    select: String,
+    suffix: String,
+    table_name: String,
+    table_options: HashMap<String, String>,
+}
+
+impl FileScan {
+    fn sql(&self, file: &str) -> Result<String> {
+        let mut table_options = self.table_options.clone();
+        table_options.insert(ParquetOption::Files.as_ref().to_string(), file.to_string());
+        Ok(format!(
+            "{} ({}) AS {}{}",
+            self.select,
+            parquet::create_select(table_options)?,
+            self.table_name,
+            self.suffix
+        ))
+    }
+}
+
+impl ScanState {
+    pub fn new(table_options: &HashMap<String, String>) -> Self {
+        Self {
+            timezone: table_options
+                .get(TIMEZONE_OPTION)
+                .map(|timezone| Arc::from(timezone.as_str())),
+            ..Default::default()
+        }
+    }
+
+    pub fn sql(&self) -> Option<&str> {
+        self.sql.as_deref()
+    }
+
+    fn set_columns(&mut self, columns: &[Column]) {
+        self.columns = columns
+            .iter()
+            .map(|column| (Arc::from(column.name.as_str()), column.type_oid))
+            .collect();
+    }
+
+    fn set_batch(&mut self, batch: RecordBatch) {
+        let disable_converters = GUCS.disable_column_converters.get();
+
+        self.converters.clear();
+        self.converters.extend(self.columns.iter().enumerate().map(
+            |(column_index, (name, oid))| {
+                let column = batch.column(column_index);
+                match disable_converters {
+                    true => ColumnConverter::cell(column, *oid, name),
+                    false => ColumnConverter::new(column, *oid, name),
+                }
+                .in_timezone(self.timezone.as_ref())
+            },
+        ));
+        self.batch = Some(batch);
+        self.batch_index = 0;
+    }
+
+    /// Returns the statement to read next: the whole scan once, or the next unclaimed file of a split scan
+    fn next_query(&mut self) -> Result<Option<String>> {
+        if !self.started {
+            self.started = true;
+            self.parallel = current_parallel_scan();
+            if self.parallel.is_none() {
+                let sql = self
+                    .sql()
+                    .ok_or_else(|| anyhow!("sql statement was not cached"))?;
+                return Ok(Some(sql.to_string()));
+            }
+        }
+
+        let Some(parallel) = &self.parallel else {
+            return Ok(None);
+        };
+        let file_scan = self
+            .file_scan
+            .as_ref()
+            .ok_or_else(|| anyhow!("foreign scan cannot be split by file"))?;
+        parallel
+            .claim_file()
+            .map(|file| file_scan.sql(&file))
+            .transpose()
+    }
+
+    fn restart(&mut self) {
+        connection::clear_arrow();
+        self.batch = None;
+        self.batch_index = 0;
+        self.converters.clear();
+        self.started = false;
+        self.parallel = None;
+        // Rescans read through the global connection
+        self.async_scan = None;
+    }
+
+    fn has_rows(&self) -> bool {
+        self.batch
+            .as_ref()
+            .is_some_and(|batch| self.batch_index < batch.num_rows())
+    }
+}
 
 pub trait BaseFdw {
     // Getter methods
-    fn get_current_batch(&self) -> Option<RecordBatch>;
-    fn get_current_batch_index(&self) -> usize;
-    fn get_scan_started(&self) -> bool;
-    fn get_sql(&self) -> Option<String>;
-    fn get_target_columns(&self) -> Vec<Column>;
+    fn get_scan_state(&self) -> &ScanState;
+    fn get_scan_state_mut(&mut self) -> &mut ScanState;
     fn get_user_mapping_options(&self) -> HashMap<String, String>;
 
-    // Setter methods
-    fn set_current_batch(&mut self, batch: Option<RecordBatch>);
-    fn set_current_batch_index(&mut self, idx: usize);
-    fn set_scan_started(&mut self);
-    fn set_sql(&mut self, statement: Option<String>);
-    fn set_target_columns(&mut self, columns: &[Column]);
-
     async fn begin_scan_impl(
         &mut self,
         quals: &[Qual],
@@ -66,42 +198,72 @@ pub trait BaseFdw {
         let table_name = pg_relation.name();
 
         // Cache target columns
-        self.set_target_columns(columns);
+        self.get_scan_state_mut().set_columns(columns);
 
         // Register view with DuckDB
         let user_mapping_options = self.get_user_mapping_options();
         let foreign_table = unsafe { pg_sys::GetForeignTable(pg_relation.oid()) };
-        let table_options = unsafe { options_to_hashmap((*foreign_table).options)? };
+        let mut table_options = unsafe { options_to_hashmap((*foreign_table).options)? };
         let handler = FdwHandler::from(foreign_table);
+        if GUCS.schema_drift_check.get() {
+            check_schema_drift(&pg_relation)?;
+        }
+        let file_options = (handler == FdwHandler::Parquet).then(|| table_options.clone());
+        let spatial_where_clauses = match handler {
+            FdwHandler::Spatial => {
+                apply_spatial_filter(table_oid, schema_name, table_name, &mut table_options)?
+            }
+            _ => vec![],
+        };
         register_duckdb_view(
             table_name,
             schema_name,
             table_options,
             user_mapping_options,
             handler,
+            unsafe { (*pg_relation.rd_rel).relowner },
         )?;
+        pin_snapshot(&pg_relation)?;
 
         // Construct SQL scan statement
         let targets = if columns.is_empty() {
             "*".to_string()
         } else {
+            let tuple_desc = pg_relation.tuple_desc();
             columns
                 .iter()
-                .map(|c| c.name.clone())
+                .map(|c| match postgis_type(c.type_oid) {
+                    Some(_) => {
+                        let typmod = tuple_desc
+                            .iter()
+                            .find(|attribute| attribute.name() == c.name)
+                            .map(|attribute| attribute.atttypmod)
+                            .unwrap_or(-1);
+                        postgis_scan_target(&c.name, typmod)
+                    }
+                    // Arrow times have no offset, so timetz is read in its text form, e.g. 12:34:56+02.
+                    // Times without an offset are read as UTC, like pushed down queries read them.
+                    None if c.type_oid == pg_sys::TIMETZOID => {
+                        format!("CAST(CAST({} AS TIMETZ) AS VARCHAR) AS {}", c.name, c.name)
+                    }
+                    None => c.name.clone(),
+                })
                 .collect::<Vec<String>>()
                 .join(", ")
         };
 
-        let mut sql = format!("SELECT {targets} FROM {schema_name}.{table_name}");
+        let select = format!("SELECT {targets} FROM");
+        let mut suffix = String::new();
 
-        if !quals.is_empty() {
+        if !quals.is_empty() || !spatial_where_clauses.is_empty() {
             let mut formatter = DuckDbFormatter::new();
             let where_clauses = quals
                 .iter()
                 .map(|x| x.deparse_with_fmt(&mut formatter))
+                .chain(spatial_where_clauses)
                 .collect::<Vec<String>>()
                 .join(" AND ");
-            sql.push_str(&format!(" WHERE {}", where_clauses));
+            suffix.push_str(&format!(" WHERE {}", where_clauses));
         }
 
         if !sorts.is_empty() {
@@ -110,15 +272,24 @@ pub trait BaseFdw {
                 .map(|sort| sort.deparse())
                 .collect::<Vec<String>>()
                 .join(", ");
-            sql.push_str(&format!(" ORDER BY {}", order_by));
+            suffix.push_str(&format!(" ORDER BY {}", order_by));
         }
 
         if let Some(limit) = limit {
             let real_limit = limit.offset + limit.count;
-            sql.push_str(&format!(" LIMIT {}", real_limit));
+            suffix.push_str(&format!(" LIMIT {}", real_limit));
         }
 
-        self.set_sql(Some(sql));
+        let sql = format!("{select} {schema_name}.{table_name}{suffix}");
+        let state = self.get_scan_state_mut();
+        state.async_scan = async_scan::start_requested(&sql)?;
+        state.sql = Some(sql);
+        state.file_scan = file_options.map(|table_options| FileScan {
+            select,
+            suffix,
+            table_name: table_name.to_string(),
+            table_options,
+        });
         Ok(())
     }
 
@@ -128,64 +299,84 @@ pub trait BaseFdw {
             error!("FDW is disabled. This may indicate that the executor hook did not execute as expected.")
         }
 
-        if !self.get_scan_started() {
-            self.set_scan_started();
-            let sql = self
-                .get_sql()
-                .ok_or_else(|| anyhow!("sql statement was not cached"))?;
-            connection::create_arrow(sql.as_str())?;
-        }
+        let state = self.get_scan_state_mut();
+        while !state.has_rows() {
+            if let Some(async_scan) = &state.async_scan {
+                let start = Instant::now();
+                let next_batch = async_scan.next_batch()?;
+
+                if let Some(sql) = state.sql() {
+                    let (rows, bytes) = stats::batches_size(&next_batch);
+                    stats::record_fdw_scan(sql, !state.started, start.elapsed(), rows, bytes);
+                }
+                state.started = true;
+
+                match next_batch {
+                    Some(batch) => {
+                        state.set_batch(batch);
+                        continue;
+                    }
+                    None => return Ok(None),
+                }
+            }
 
-        if self.get_current_batch().is_none()
-            || self.get_current_batch_index()
-                >= self
-                    .get_current_batch()
-                    .as_ref()
-                    .ok_or_else(|| anyhow!("current batch not found"))?
-                    .num_rows()
-        {
-            self.set_current_batch_index(0);
-            let next_batch = connection::get_next_batch()?;
-
-            if next_batch.is_none() {
-                return Ok(None);
+            if state.started {
+                let start = Instant::now();
+                let next_batch = connection::get_next_batch()?;
+
+                if let Some(sql) = state.sql() {
+                    let (rows, bytes) = stats::batches_size(&next_batch);
+                    stats::record_fdw_scan(sql, false, start.elapsed(), rows, bytes);
+                }
+
+                if let Some(batch) = next_batch {
+                    state.set_batch(batch);
+                    continue;
+                }
             }
 
-            self.set_current_batch(next_batch);
+            // Split scans read the next file once the previous one is done
+            let first_query = !state.started;
+            let Some(query) = state.next_query()? else {
+                return Ok(None);
+            };
+            let start = Instant::now();
+            connection::create_arrow(query.as_str())?;
+            if let Some(sql) = state.sql() {
+                stats::record_fdw_scan(sql, first_query, start.elapsed(), 0, 0);
+            }
         }
 
-        let current_batch_binding = self.get_current_batch();
-        let current_batch = current_batch_binding
-            .as_ref()
-            .ok_or_else(|| anyhow!("current batch not found"))?;
-        let current_batch_index = self.get_current_batch_index();
-
-        for (column_index, target_column) in
-            self.get_target_columns().clone().into_iter().enumerate()
-        {
-            let batch_column = current_batch.column(column_index);
-            let cell = batch_column.get_cell(
-                current_batch_index,
-                target_column.type_oid,
-                target_column.name.as_str(),
-            )?;
-            row.push(target_column.name.as_str(), cell);
+        // supabase-wrappers asks for one row per call and matches cells to the targets by position,
+        // so the names are never read and are left empty, which doesn't allocate
+        for converter in &state.converters {
+            row.cols.push(String::new());
+            row.cells.push(converter.cell_value(state.batch_index)?);
         }
 
-        self.set_current_batch_index(current_batch_index + 1);
+        state.batch_index += 1;
+        if let Some(async_scan) = &state.async_scan {
+            async_scan.set_has_rows(state.has_rows());
+        }
 
         Ok(Some(()))
     }
 
+    fn re_scan_impl(&mut self) {
+        self.get_scan_state_mut().restart();
+    }
+
     fn end_scan_impl(&mut self) {
         connection::clear_arrow();
+        self.get_scan_state_mut().async_scan = None;
     }
 
     fn explain_impl(&self) -> Result<Option<Vec<(String, String)>>> {
         let sql = self
-            .get_sql()
+            .get_scan_state()
+            .sql()
             .ok_or_else(|| anyhow!("sql statement was not cached"))?;
-        Ok(Some(vec![("DuckDB Scan".to_string(), sql)]))
+        Ok(Some(vec![("DuckDB Scan".to_string(), sql.to_string())]))
     }
 }
 
@@ -213,13 +404,87 @@ pub fn validate_options(opt_list: Vec<Option<String>>, valid_options: Vec<String
     Ok(())
 }
 
+/// Local paths can only be read by roles with the privileges of pg_read_server_files,
+/// and only inside thdb.allowed_directories when it is set
+pub fn check_file_access(table_options: &HashMap<String, String>, role: pg_sys::Oid) -> Result<()> {
+    let local_paths = FILE_OPTIONS
+        .iter()
+        .filter_map(|option| table_options.get(*option))
+        .flat_map(|files| files.split(','))
+        .map(|path| path.trim())
+        .filter(|path| !path.is_empty() && utils::is_local_path(path))
+        .collect::<Vec<&str>>();
+
+    let Some(path) = local_paths.first() else {
+        return Ok(());
+    };
+
+    if !has_privs_of_role(role, c"pg_read_server_files") {
+        bail!(
+            "permission denied to read local file {}: only superusers and roles with privileges of pg_read_server_files can read local files",
+            path
+        );
+    }
+
+    let allowed_directories = GUCS.allowed_directories();
+    if allowed_directories.is_empty() {
+        return Ok(());
+    }
+
+    for path in local_paths {
+        if !utils::is_path_allowed(path, &allowed_directories) {
+            bail!(
+                "local file {} is outside of thdb.allowed_directories ({})",
+                path,
+                allowed_directories.join(", ")
+            );
+        }
+    }
+
+    Ok(())
+}
+
+/// Used by the validators, where the role setting the options is the current user
+pub fn validate_file_access(opt_list: &[Option<String>]) -> Result<()> {
+    let table_options = opt_list
+        .iter()
+        .flatten()
+        .filter_map(|opt| opt.split_once('='))
+        .map(|(key, value)| (key.to_string(), value.to_string()))
+        .collect::<HashMap<String, String>>();
+
+    check_file_access(&table_options, unsafe { pg_sys::GetUserId() })
+}
+
+/// Roles that don't exist grant nothing
+pub(crate) fn has_privs_of_role(role: pg_sys::Oid, role_name: &CStr) -> bool {
+    unsafe {
+        let target_role = pg_sys::get_role_oid(role_name.as_ptr(), true);
+        target_role != pg_sys::InvalidOid && pg_sys::has_privs_of_role(role, target_role)
+    }
+}
+
 pub fn register_duckdb_view(
     table_name: &str,
     schema_name: &str,
     table_options: HashMap<String, String>,
     user_mapping_options: HashMap<String, String>,
     handler: FdwHandler,
+    owner: pg_sys::Oid,
 ) -> Result<()> {
+    // Checked on every registration, because privileges and allowed directories can change after the table was created
+    check_file_access(&table_options, owner)?;
+
+    // httpfs may not be preloaded, and only tables with remote files need it
+    let has_remote_files = FILE_OPTIONS
+        .iter()
+        .filter_map(|option| table_options.get(*option))
+        .flat_map(|files| files.split(','))
+        .any(|path| !utils::is_local_path(path.trim()));
+    if has_remote_files {
+        connection::load_extension("httpfs")?;
+    }
+
     if !user_mapping_options.is_empty() {
         connection::create_secret(DEFAULT_SECRET, user_mapping_options)?;
     }
@@ -0,0 +1,382 @@
+// Copyright (c) 2023-2025 Retake, Inc.
+//
+// This file is part of ParadeDB - Postgres for Search and Analytics
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <http://www.gnu.org/licenses/>.
+
+use anyhow::{bail, Result};
+use pgrx::*;
+use std::ffi::CStr;
+use strum::AsRefStr;
+use supabase_wrappers::prelude::{options_to_hashmap, user_mapping_options};
+
+use super::base::register_duckdb_view;
+use super::handler::FdwHandler;
+use super::trigger::{
+    column_pg_type, construct_column_definitions, describe_duckdb_view, postgis_column_types,
+    type_override, type_overrides,
+};
+use crate::duckdb::connection;
+use crate::schema::duckdb_type::DuckDbType;
+use crate::schema::postgis::postgis_type;
+
+#[derive(AsRefStr, Clone, Copy, Debug, PartialEq)]
+#[strum(serialize_all = "snake_case")]
+pub enum ColumnStatus {
+    // in the files, but not in the foreign table
+    Added,
+    // in the foreign table, but not in the files
+    Dropped,
+    // in both, with a type the foreign table can't read
+    TypeChanged,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ColumnDiff {
+    pub column_name: String,
+    pub status: ColumnStatus,
+    pub postgres_type: Option<String>,
+    pub duckdb_type: Option<String>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RefreshMode {
+    // only add the new columns
+    Additive,
+    // also drop the removed columns and retype the changed ones
+    All,
+}
+
+impl RefreshMode {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            "additive" => Some(Self::Additive),
+            "all" => Some(Self::All),
+            _ => None,
+        }
+    }
+}
+
+struct PostgresColumn {
+    name: String,
+    type_oid: pg_sys::Oid,
+    type_name: String,
+}
+
+/// Compares the columns of a foreign table with the columns DuckDB currently reads from its files
+pub fn foreign_table_schema_diff(relation: &PgRelation) -> Result<Vec<ColumnDiff>> {
+    let schema_name = relation.namespace();
+    let table_name = relation.name();
+    let foreign_table = unsafe { pg_sys::GetForeignTable(relation.oid()) };
+    let handler = FdwHandler::from(foreign_table);
+    if handler == FdwHandler::Other {
+        bail!("relation {} is not a DuckDB foreign table", table_name);
+    }
+
+    // DuckDB views keep the columns they were bound with, so the view is recreated to read the files again
+    let foreign_server = unsafe { pg_sys::GetForeignServer((*foreign_table).serverid) };
+    let user_mapping_options = unsafe { user_mapping_options(foreign_server) };
+    let table_options = unsafe { options_to_hashmap((*foreign_table).options)? };
+    let type_overrides = type_overrides(&table_options)?;
+    connection::execute(
+        format!("DROP VIEW IF EXISTS {schema_name}.{table_name}").as_str(),
+        [],
+    )?;
+    register_duckdb_view(
+        table_name,
+        schema_name,
+        table_options,
+        user_mapping_options,
+        handler,
+        unsafe { (*relation.rd_rel).relowner },
+    )?;
+    let duckdb_columns = describe_duckdb_view(schema_name, table_name)?;
+
+    let postgres_columns = relation
+        .tuple_desc()
+        .iter()
+        .filter(|attribute| !attribute.is_dropped())
+        .map(|attribute| PostgresColumn {
+            name: attribute.name().to_string(),
+            type_oid: attribute.atttypid,
+            type_name: format_type(attribute.atttypid, attribute.atttypmod),
+        })
+        .collect::<Vec<PostgresColumn>>();
+
+    let mut diff = vec![];
+    let mut matched = vec![];
+
+    for (column_name, duckdb_type) in duckdb_columns.iter() {
+        // Unquoted Postgres names are lowercase, while DuckDB keeps the case of the files
+        let postgres_column = postgres_columns
+            .iter()
+            .find(|column| &column.name == column_name)
+            .or_else(|| {
+                postgres_columns
+                    .iter()
+                    .find(|column| column.name.eq_ignore_ascii_case(column_name))
+            });
+
+        match postgres_column {
+            None => diff.push(ColumnDiff {
+                column_name: column_name.clone(),
+                status: ColumnStatus::Added,
+                postgres_type: None,
+                duckdb_type: Some(duckdb_type.clone()),
+            }),
+            Some(column) => {
+                matched.push(column.name.clone());
+                // Overridden columns are read through the input function of their type
+                if type_override(&type_overrides, column_name).is_none()
+                    && !is_compatible_type(column, column_name, duckdb_type)?
+                {
+                    diff.push(ColumnDiff {
+                        column_name: column.name.clone(),
+                        status: ColumnStatus::TypeChanged,
+                        postgres_type: Some(column.type_name.clone()),
+                        duckdb_type: Some(duckdb_type.clone()),
+                    });
+                }
+            }
+        }
+    }
+
+    for column in postgres_columns.iter() {
+        if !matched.contains(&column.name) {
+            diff.push(ColumnDiff {
+                column_name: column.name.clone(),
+                status: ColumnStatus::Dropped,
+                postgres_type: Some(column.type_name.clone()),
+                duckdb_type: None,
+            });
+        }
+    }
+
+    Ok(diff)
+}
+
+/// Alters the foreign table to match its files, and returns the executed statement
+pub fn refresh_foreign_table_schema(
+    relation: &PgRelation,
+    mode: RefreshMode,
+) -> Result<Option<String>> {
+    let diff = foreign_table_schema_diff(relation)?;
+
+    let foreign_table = unsafe { pg_sys::GetForeignTable(relation.oid()) };
+    let table_options = unsafe { options_to_hashmap((*foreign_table).options)? };
+    let preserve_casing = table_options
+        .get("preserve_casing")
+        .is_some_and(|s| s.eq_ignore_ascii_case("true"));
+    let geometry_types = postgis_column_types(FdwHandler::from(foreign_table), &table_options)?;
+    let type_overrides = type_overrides(&table_options)?;
+
+    let mut actions = vec![];
+    for column in diff.iter() {
+        match (column.status, mode, column.duckdb_type.as_ref()) {
+            (ColumnStatus::Added, _, Some(duckdb_type)) => {
+                let definition = construct_column_definitions(
+                    &[(column.column_name.clone(), duckdb_type.clone())],
+                    preserve_casing,
+                    geometry_types.as_ref(),
+                    &type_overrides,
+                )?;
+                actions.extend(
+                    definition
+                        .into_iter()
+                        .map(|definition| format!("ADD COLUMN {definition}")),
+                );
+            }
+            (ColumnStatus::Dropped, RefreshMode::All, _) => actions.push(format!(
+                "DROP COLUMN {}",
+                spi::quote_identifier(&column.column_name)
+            )),
+            (ColumnStatus::TypeChanged, RefreshMode::All, Some(duckdb_type)) => {
+                actions.push(format!(
+                    "ALTER COLUMN {} TYPE {}",
+                    spi::quote_identifier(&column.column_name),
+                    column_pg_type(
+                        &column.column_name,
+                        duckdb_type,
+                        geometry_types.as_ref(),
+                        &type_overrides
+                    )?
+                ))
+            }
+            _ => {}
+        }
+    }
+
+    if actions.is_empty() {
+        return Ok(None);
+    }
+
+    let statement = format!(
+        "ALTER FOREIGN TABLE {}.{} {}",
+        spi::quote_identifier(relation.namespace()),
+        spi::quote_identifier(relation.name()),
+        actions.join(", ")
+    );
+    Spi::run(&statement)?;
+
+    Ok(Some(statement))
+}
+
+/// Fails a scan whose files dropped or retyped columns of the foreign table.
+/// New columns in the files don't affect the scan, and are left to refresh_foreign_table_schema.
+pub fn check_schema_drift(relation: &PgRelation) -> Result<()> {
+    let changes = foreign_table_schema_diff(relation)?
+        .into_iter()
+        .filter_map(|column| match column.status {
+            ColumnStatus::Added => None,
+            ColumnStatus::Dropped => Some(format!(
+                "column {} is no longer in the files",
+                column.column_name
+            )),
+            ColumnStatus::TypeChanged => Some(format!(
+                "column {} is {} in the files but {} in the table",
+                column.column_name,
+                column.duckdb_type.unwrap_or_default(),
+                column.postgres_type.unwrap_or_default()
+            )),
+        })
+        .collect::<Vec<String>>();
+
+    if !changes.is_empty() {
+        bail!(
+            "the schema of foreign table {} has drifted from its files: {}. Run SELECT refresh_foreign_table_schema('{}', 'all') to update the table",
+            relation.name(),
+            changes.join(", "),
+            relation.name()
+        );
+    }
+
+    Ok(())
+}
+
+// Columns can be declared with a different type than the one inferred, as long as get_cell can read
+// the values into it, e.g. a TIMESTAMP into timestamptz or an INTEGER into bigint or numeric
+fn is_compatible_type(
+    column: &PostgresColumn,
+    column_name: &str,
+    duckdb_type: &str,
+) -> Result<bool> {
+    if postgis_type(column.type_oid).is_some() {
+        return Ok(duckdb_type == "GEOMETRY" || duckdb_type == "WKB_BLOB");
+    }
+
+    // Types without a Postgres equivalent must have been declared by hand
+    let Ok(parsed_type) = DuckDbType::parse(duckdb_type) else {
+        return Ok(true);
+    };
+    let Ok(expected_type) = parsed_type.to_postgres(column_name) else {
+        return Ok(true);
+    };
+
+    // Integers are only read into types that hold all of their values
+    let integer_width = match parsed_type {
+        DuckDbType::TinyInt | DuckDbType::UTinyInt | DuckDbType::SmallInt => Some(16),
+        DuckDbType::USmallInt | DuckDbType::Integer => Some(32),
+        DuckDbType::UInteger | DuckDbType::BigInt => Some(64),
+        DuckDbType::UBigInt | DuckDbType::HugeInt | DuckDbType::UHugeInt => Some(128),
+        _ => None,
+    };
+
+    Ok(match column.type_oid {
+        // Text is read through the input function of any type
+        _ if matches!(parsed_type, DuckDbType::Varchar | DuckDbType::Json) => true,
+        pg_sys::BOOLOID => parsed_type == DuckDbType::Boolean,
+        pg_sys::INT2OID => integer_width.is_some_and(|width| width <= 16),
+        pg_sys::INT4OID => integer_width.is_some_and(|width| width <= 32),
+        pg_sys::INT8OID => integer_width.is_some_and(|width| width <= 64),
+        pg_sys::FLOAT4OID => {
+            parsed_type == DuckDbType::Float || integer_width.is_some_and(|width| width <= 16)
+        }
+        pg_sys::FLOAT8OID => {
+            matches!(parsed_type, DuckDbType::Float | DuckDbType::Double)
+                || integer_width.is_some_and(|width| width <= 32)
+        }
+        pg_sys::NUMERICOID => {
+            integer_width.is_some()
+                || matches!(
+                    parsed_type,
+                    DuckDbType::Float | DuckDbType::Double | DuckDbType::Decimal(_)
+                )
+        }
+        pg_sys::TEXTOID | pg_sys::VARCHAROID | pg_sys::BPCHAROID | pg_sys::NAMEOID => matches!(
+            parsed_type,
+            DuckDbType::Blob
+                | DuckDbType::Geometry
+                | DuckDbType::WkbBlob
+                | DuckDbType::BigInt
+                | DuckDbType::UBigInt
+                | DuckDbType::HugeInt
+                | DuckDbType::UHugeInt
+                | DuckDbType::Decimal(_)
+        ),
+        pg_sys::BYTEAOID => matches!(
+            parsed_type,
+            DuckDbType::Blob | DuckDbType::Geometry | DuckDbType::WkbBlob
+        ),
+        pg_sys::DATEOID => parsed_type == DuckDbType::Date,
+        pg_sys::TIMESTAMPOID | pg_sys::TIMESTAMPTZOID => matches!(
+            parsed_type,
+            DuckDbType::Date | DuckDbType::Timestamp | DuckDbType::TimestampTz
+        ),
+        pg_sys::TIMEOID => parsed_type == DuckDbType::Time,
+        pg_sys::TIMETZOID => matches!(parsed_type, DuckDbType::Time | DuckDbType::TimeTz),
+        pg_sys::INTERVALOID => parsed_type == DuckDbType::Interval,
+        pg_sys::UUIDOID => parsed_type == DuckDbType::Uuid,
+        pg_sys::JSONOID | pg_sys::JSONBOID => matches!(
+            parsed_type,
+            DuckDbType::Struct(_) | DuckDbType::List(_) | DuckDbType::Array(_, _)
+        ),
+        // Lists are only read into arrays of the inferred element type
+        pg_sys::BOOLARRAYOID
+        | pg_sys::TEXTARRAYOID
+        | pg_sys::VARCHARARRAYOID
+        | pg_sys::BPCHARARRAYOID
+        | pg_sys::INT2ARRAYOID
+        | pg_sys::INT4ARRAYOID
+        | pg_sys::INT8ARRAYOID
+        | pg_sys::FLOAT4ARRAYOID
+        | pg_sys::FLOAT8ARRAYOID => {
+            let Some(expected_oid) = Spi::get_one_with_args::<pg_sys::Oid>(
+                "SELECT to_regtype($1)::oid",
+                vec![(PgBuiltInOids::TEXTOID.oid(), expected_type.into_datum())],
+            )?
+            else {
+                return Ok(true);
+            };
+            expected_oid == column.type_oid
+                || (is_string_array_type(expected_oid) && is_string_array_type(column.type_oid))
+        }
+        // Other types are read through their input function from the text form of the values
+        _ => true,
+    })
+}
+
+fn is_string_array_type(type_oid: pg_sys::Oid) -> bool {
+    matches!(
+        type_oid,
+        pg_sys::TEXTARRAYOID | pg_sys::VARCHARARRAYOID | pg_sys::BPCHARARRAYOID
+    )
+}
+
+fn format_type(type_oid: pg_sys::Oid, typmod: i32) -> String {
+    unsafe {
+        CStr::from_ptr(pg_sys::format_type_with_typemod(type_oid, typmod))
+            .to_string_lossy()
+            .into_owned()
+    }
+}
@@ -17,7 +17,7 @@
 
 use pgrx::*;
 
-#[derive(PartialEq)]
+#[derive(Clone, Copy, PartialEq)]
 pub enum FdwHandler {
     Csv,
     Json,
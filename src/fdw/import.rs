@@ -0,0 +1,310 @@
+// Copyright (c) 2023-2025 Retake, Inc.
+//
+// This file is part of ParadeDB - Postgres for Search and Analytics
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <http://www.gnu.org/licenses/>.
+use anyhow::{bail, Result};
+use pgrx::*;
+use std::collections::{BTreeMap, HashMap};
+use std::ffi::CString;
+use supabase_wrappers::prelude::*;
+
+use super::base::{check_file_access, register_duckdb_view, DEFAULT_SECRET};
+use super::handler::FdwHandler;
+use super::trigger::{
+    construct_column_definitions, describe_duckdb_view, postgis_column_types, type_overrides,
+    DUCKDB_RESERVED_NAMES,
+};
+use crate::duckdb::connection;
+
+// Files that belong to the same table
+struct ImportTable {
+    files: Vec<String>,
+    glob: Option<String>,
+    hive_partitioned: bool,
+}
+
+/// Builds one CREATE FOREIGN TABLE statement per top-level directory or file under the remote schema,
+/// which is a local or object store prefix, e.g. IMPORT FOREIGN SCHEMA "s3://bucket/warehouse/"
+pub fn import_foreign_schema_impl(
+    stmt: ImportForeignSchemaStmt,
+    user_mapping_options: HashMap<String, String>,
+    handler: FdwHandler,
+) -> Result<Vec<String>> {
+    let prefix = match stmt.remote_schema.ends_with('/') {
+        true => stmt.remote_schema.clone(),
+        false => format!("{}/", stmt.remote_schema),
+    };
+
+    // Listing a local directory is as sensitive as reading the files in it
+    check_file_access(
+        &HashMap::from([("files".to_string(), prefix.clone())]),
+        unsafe { pg_sys::GetUserId() },
+    )?;
+
+    if !user_mapping_options.is_empty() {
+        connection::create_secret(DEFAULT_SECRET, user_mapping_options.clone())?;
+    }
+
+    let tables = match handler {
+        FdwHandler::Delta => table_directories(&prefix, "_delta_log")?,
+        FdwHandler::Iceberg => table_directories(&prefix, "metadata")?,
+        FdwHandler::Parquet => group_files(&prefix, &[".parquet"], true)?,
+        FdwHandler::Csv => group_files(&prefix, &[".csv", ".tsv", ".csv.gz", ".tsv.gz"], true)?,
+        FdwHandler::Json => group_files(
+            &prefix,
+            &[".json", ".jsonl", ".ndjson", ".json.gz", ".jsonl.gz"],
+            true,
+        )?,
+        // st_read reads a single file, so every file becomes its own table
+        FdwHandler::Spatial => group_files(
+            &prefix,
+            &[".geojson", ".gpkg", ".shp", ".fgb", ".kml", ".gml"],
+            false,
+        )?,
+        FdwHandler::Other => bail!("got unexpected fdw_handler"),
+    };
+
+    let tables = tables
+        .into_iter()
+        .filter(|(table_name, _)| {
+            let is_listed = stmt.table_list.contains(table_name);
+            match stmt.list_type {
+                ImportSchemaType::FdwImportSchemaAll => true,
+                ImportSchemaType::FdwImportSchemaLimitTo => is_listed,
+                ImportSchemaType::FdwImportSchemaExcept => !is_listed,
+            }
+        })
+        .filter(|(table_name, _)| {
+            let is_reserved = DUCKDB_RESERVED_NAMES.contains(&table_name.as_str());
+            if is_reserved {
+                warning!(
+                    "skipping {} because the table name is reserved by DuckDB",
+                    table_name
+                );
+            }
+            !is_reserved
+        })
+        .collect::<Vec<(String, ImportTable)>>();
+
+    // The DuckDB view of an existing table has the same name, so nothing is registered until every
+    // name is known to be free
+    for (table_name, _) in &tables {
+        if relation_exists(&stmt.local_schema, table_name)? {
+            bail!(
+                "relation \"{}.{}\" already exists",
+                stmt.local_schema,
+                table_name
+            );
+        }
+    }
+
+    let mut statements = vec![];
+
+    for (table_name, table) in tables {
+        // Options given to IMPORT FOREIGN SCHEMA apply to every table
+        let mut table_options = stmt.options.clone();
+        table_options.insert(
+            "files".to_string(),
+            table.glob.clone().unwrap_or_else(|| table.files.join(", ")),
+        );
+        if table.hive_partitioned {
+            table_options
+                .entry("hive_partitioning".to_string())
+                .or_insert_with(|| "true".to_string());
+        }
+
+        // Infer the columns from a DuckDB view, like the auto schema trigger does for CREATE FOREIGN TABLE
+        connection::execute(
+            format!(
+                "DROP VIEW IF EXISTS {}.{}",
+                spi::quote_identifier(&stmt.local_schema),
+                spi::quote_identifier(&table_name)
+            )
+            .as_str(),
+            [],
+        )?;
+        register_duckdb_view(
+            &table_name,
+            &stmt.local_schema,
+            table_options.clone(),
+            user_mapping_options.clone(),
+            handler,
+            unsafe { pg_sys::GetUserId() },
+        )?;
+
+        let preserve_casing = table_options
+            .get("preserve_casing")
+            .is_some_and(|s| s.eq_ignore_ascii_case("true"));
+        let geometry_types = postgis_column_types(handler, &table_options)?;
+        let columns = construct_column_definitions(
+            &describe_duckdb_view(&stmt.local_schema, &table_name)?,
+            preserve_casing,
+            geometry_types.as_ref(),
+            &type_overrides(&table_options)?,
+        )?;
+
+        let options = BTreeMap::from_iter(table_options.iter())
+            .into_iter()
+            .map(|(key, value)| format!("{} '{}'", key, value.replace('\'', "''")))
+            .collect::<Vec<String>>()
+            .join(", ");
+
+        statements.push(format!(
+            "CREATE FOREIGN TABLE {}.{} ({}) SERVER {} OPTIONS ({})",
+            spi::quote_identifier(&stmt.local_schema),
+            spi::quote_identifier(&table_name),
+            columns.join(", "),
+            spi::quote_identifier(&stmt.server_name),
+            options
+        ));
+    }
+
+    Ok(statements)
+}
+
+fn relation_exists(schema_name: &str, table_name: &str) -> Result<bool> {
+    let schema_name = CString::new(schema_name)?;
+    let table_name = CString::new(table_name)?;
+    unsafe {
+        let namespace = pg_sys::get_namespace_oid(schema_name.as_ptr(), false);
+        Ok(pg_sys::get_relname_relid(table_name.as_ptr(), namespace) != pg_sys::InvalidOid)
+    }
+}
+
+// Different names can turn into the same table name, e.g. a-b.parquet and a_b.parquet, and one table
+// would silently replace the other
+fn claim_table_name(
+    sources: &mut HashMap<String, String>,
+    source: &str,
+    table_name: String,
+) -> Result<String> {
+    match sources.get(&table_name) {
+        Some(claimed) if claimed != source => {
+            bail!("{claimed} and {source} would both be imported as table {table_name}")
+        }
+        _ => {
+            sources.insert(table_name.clone(), source.to_string());
+            Ok(table_name)
+        }
+    }
+}
+
+// Tables such as Delta and Iceberg are directories recognized by a marker subdirectory
+fn table_directories(prefix: &str, marker: &str) -> Result<BTreeMap<String, ImportTable>> {
+    let mut tables = BTreeMap::new();
+    let mut sources = HashMap::new();
+
+    for file in connection::glob_files(&format!("{prefix}**/*"))? {
+        let relative_path = file.strip_prefix(prefix).unwrap_or(&file);
+        let parts: Vec<&str> = relative_path.split('/').collect();
+
+        if parts.len() > 2 && parts[1] == marker {
+            tables
+                .entry(claim_table_name(
+                    &mut sources,
+                    parts[0],
+                    table_name(parts[0]),
+                )?)
+                .or_insert_with(|| ImportTable {
+                    files: vec![format!("{prefix}{}", parts[0])],
+                    glob: None,
+                    hive_partitioned: false,
+                });
+        }
+    }
+
+    Ok(tables)
+}
+
+// Files directly under the prefix become a table each. Files in a top-level directory are grouped
+// into one table, which is hive partitioned if a nested directory is named like key=value.
+fn group_files(
+    prefix: &str,
+    extensions: &[&str],
+    group_directories: bool,
+) -> Result<BTreeMap<String, ImportTable>> {
+    let mut tables: BTreeMap<String, ImportTable> = BTreeMap::new();
+    let mut sources = HashMap::new();
+
+    for file in connection::glob_files(&format!("{prefix}**/*"))? {
+        let Some(extension) = extensions
+            .iter()
+            .find(|extension| file.to_lowercase().ends_with(*extension))
+        else {
+            continue;
+        };
+
+        let relative_path = file.strip_prefix(prefix).unwrap_or(&file).to_string();
+        let parts: Vec<&str> = relative_path.split('/').collect();
+
+        if parts.len() == 1 || !group_directories {
+            let stem = &relative_path[..relative_path.len() - extension.len()];
+            tables.insert(
+                claim_table_name(&mut sources, &relative_path, table_name(stem))?,
+                ImportTable {
+                    files: vec![file.clone()],
+                    glob: None,
+                    hive_partitioned: false,
+                },
+            );
+            continue;
+        }
+
+        let directory = format!("{}/", parts[0]);
+        let table = tables
+            .entry(claim_table_name(
+                &mut sources,
+                &directory,
+                table_name(parts[0]),
+            )?)
+            .or_insert_with(|| ImportTable {
+                files: vec![],
+                glob: Some(format!("{prefix}{}/**/*{extension}", parts[0])),
+                hive_partitioned: false,
+            });
+
+        // Mixed extensions can't be matched by one glob, so the files are listed instead
+        if !table
+            .glob
+            .as_ref()
+            .is_some_and(|glob| glob.ends_with(*extension))
+        {
+            table.glob = None;
+        }
+        table.hive_partitioned |= parts[1..parts.len() - 1]
+            .iter()
+            .any(|part| part.contains('='));
+        table.files.push(file.clone());
+    }
+
+    Ok(tables)
+}
+
+// Turns a file or directory name into an unquoted Postgres identifier
+fn table_name(name: &str) -> String {
+    let name: String = name
+        .to_lowercase()
+        .chars()
+        .map(|c| match c.is_ascii_alphanumeric() {
+            true => c,
+            false => '_',
+        })
+        .collect();
+
+    match name.starts_with(|c: char| c.is_ascii_digit()) {
+        true => format!("_{name}"),
+        false => name,
+    }
+}
@@ -15,12 +15,19 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program. If not, see <http://www.gnu.org/licenses/>.
 
+pub mod async_scan;
 pub mod base;
 pub mod csv;
 pub mod delta;
+pub mod drift;
 pub mod handler;
 pub mod iceberg;
+pub mod import;
 pub mod json;
+pub mod parallel;
 pub mod parquet;
+pub mod read;
+pub mod snapshot;
 pub mod spatial;
+pub mod spatial_filter;
 pub mod trigger;
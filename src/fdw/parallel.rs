@@ -0,0 +1,685 @@
+// Copyright (c) 2023-2025 Retake, Inc.
+//
+// This file is part of ParadeDB - Postgres for Search and Analytics
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <http://www.gnu.org/licenses/>.
+
+use anyhow::{anyhow, Result};
+use async_std::task;
+use pgrx::*;
+use std::any::TypeId;
+use std::cell::{Cell as StdCell, RefCell};
+use std::collections::{HashMap, HashSet};
+use std::ffi::c_void;
+use std::ptr::NonNull;
+use std::sync::atomic::{AtomicU32, Ordering};
+use supabase_wrappers::prelude::*;
+
+#[cfg(not(feature = "pg13"))]
+use super::async_scan;
+use super::base::{register_duckdb_view, BaseFdw, BaseFdwError};
+use super::csv::CsvFdw;
+use super::delta::DeltaFdw;
+use super::handler::FdwHandler;
+use super::iceberg::IcebergFdw;
+use super::json::JsonFdw;
+use super::parquet::ParquetFdw;
+use super::spatial::SpatialFdw;
+use crate::duckdb::{connection, parquet::ParquetOption};
+use crate::GUCS;
+
+// supabase-wrappers hands its planner state to the executor as a pointer in the plan, which
+// parallel workers can't follow, so scans in workers are run by the callbacks of this module.
+// Parquet scans can also be split: the leader expands the files of the table into dynamic
+// shared memory, and every process of the Gather claims the next file when it is done with one.
+// The same callbacks let the scans of an async Append start their statements up front (see async_scan).
+
+static mut PREV_GET_RELATION_INFO_HOOK: pg_sys::get_relation_info_hook_type = None;
+static mut PREV_SET_REL_PATHLIST_HOOK: pg_sys::set_rel_pathlist_hook_type = None;
+
+thread_local! {
+    // Callbacks of supabase-wrappers replaced in the FdwRoutine, by FDW type
+    static WRAPPERS_CALLBACKS: RefCell<HashMap<TypeId, WrappersCallbacks>> = RefCell::new(HashMap::new());
+    // Files expanded by the leader between estimating and initializing the shared memory of a scan
+    static PENDING_FILES: RefCell<HashMap<usize, Vec<String>>> = RefCell::new(HashMap::new());
+    // Shared file lists of the parallel aware scans of this process, by ForeignScanState
+    static PARALLEL_SCANS: RefCell<HashMap<usize, RegisteredScan>> = RefCell::new(HashMap::new());
+    static CURRENT_SCAN: StdCell<usize> = const { StdCell::new(0) };
+    // Number of files of the parquet tables planned by the current statement, by PlannerGlobal and relation
+    static PLANNED_FILES: RefCell<PlannedFiles> = RefCell::new(PlannedFiles::default());
+    static XACT_CALLBACK_REGISTERED: StdCell<bool> = const { StdCell::new(false) };
+}
+
+#[derive(Clone, Copy)]
+struct WrappersCallbacks {
+    begin: pg_sys::BeginForeignScan_function,
+    iterate: pg_sys::IterateForeignScan_function,
+    rescan: pg_sys::ReScanForeignScan_function,
+    end: pg_sys::EndForeignScan_function,
+}
+
+#[derive(Default)]
+struct PlannedFiles {
+    statement_start: pg_sys::TimestampTz,
+    counts: HashMap<(usize, pg_sys::Oid), usize>,
+}
+
+struct RegisteredScan {
+    shared: NonNull<SharedFiles>,
+    // Shared memory is released when the subtransaction that set it up aborts
+    subxact: pg_sys::SubTransactionId,
+}
+
+/// Header of the shared memory of a split scan, followed by the offsets and bytes of the paths
+#[repr(C)]
+struct SharedFiles {
+    next_file: AtomicU32,
+    nfiles: u32,
+}
+
+impl SharedFiles {
+    fn size(files: &[String]) -> usize {
+        std::mem::size_of::<Self>()
+            + std::mem::size_of::<u32>() * (files.len() + 1)
+            + files.iter().map(|file| file.len()).sum::<usize>()
+    }
+
+    unsafe fn write(coordinate: *mut c_void, files: &[String]) {
+        let shared = coordinate as *mut Self;
+        (*shared).next_file = AtomicU32::new(0);
+        (*shared).nfiles = files.len() as u32;
+
+        let offsets = shared.add(1) as *mut u32;
+        let bytes = offsets.add(files.len() + 1) as *mut u8;
+        let mut offset = 0;
+        for (index, file) in files.iter().enumerate() {
+            *offsets.add(index) = offset as u32;
+            std::ptr::copy_nonoverlapping(file.as_ptr(), bytes.add(offset), file.len());
+            offset += file.len();
+        }
+        *offsets.add(files.len()) = offset as u32;
+    }
+
+    unsafe fn file(&self, index: usize) -> String {
+        let offsets = (self as *const Self).add(1) as *const u32;
+        let bytes = offsets.add(self.nfiles as usize + 1) as *const u8;
+        let start = *offsets.add(index) as usize;
+        let end = *offsets.add(index + 1) as usize;
+        String::from_utf8_lossy(std::slice::from_raw_parts(bytes.add(start), end - start))
+            .into_owned()
+    }
+}
+
+/// The files of a split scan, shared by the processes of a Gather
+pub struct ParallelScan {
+    shared: NonNull<SharedFiles>,
+}
+
+impl ParallelScan {
+    /// Returns the next file that no other process has claimed
+    pub fn claim_file(&self) -> Option<String> {
+        let shared = unsafe { self.shared.as_ref() };
+        let index = shared.next_file.fetch_add(1, Ordering::SeqCst);
+        (index < shared.nfiles).then(|| unsafe { shared.file(index as usize) })
+    }
+}
+
+/// The shared files of the scan being iterated, if it is split among parallel workers
+pub fn current_parallel_scan() -> Option<ParallelScan> {
+    let node = CURRENT_SCAN.with(|current| current.get());
+    let subxact = unsafe { pg_sys::GetCurrentSubTransactionId() };
+    PARALLEL_SCANS.with(|scans| {
+        scans
+            .borrow()
+            .get(&node)
+            .filter(|scan| scan.subxact == subxact)
+            .map(|scan| ParallelScan {
+                shared: scan.shared,
+            })
+    })
+}
+
+pub fn init() {
+    unsafe {
+        PREV_GET_RELATION_INFO_HOOK = pg_sys::get_relation_info_hook;
+        pg_sys::get_relation_info_hook = Some(get_relation_info);
+        PREV_SET_REL_PATHLIST_HOOK = pg_sys::set_rel_pathlist_hook;
+        pg_sys::set_rel_pathlist_hook = Some(set_rel_pathlist);
+    }
+}
+
+fn is_parallel_worker() -> bool {
+    unsafe { pg_sys::ParallelWorkerNumber >= 0 }
+}
+
+/// Planner rels get their own copy of the FdwRoutine, so the planner callbacks are set on that copy
+#[pg_guard]
+unsafe extern "C" fn get_relation_info(
+    root: *mut pg_sys::PlannerInfo,
+    relation_object_id: pg_sys::Oid,
+    inhparent: bool,
+    rel: *mut pg_sys::RelOptInfo,
+) {
+    if let Some(prev_hook) = PREV_GET_RELATION_INFO_HOOK {
+        prev_hook(root, relation_object_id, inhparent, rel);
+    }
+
+    if (*rel).fdwroutine.is_null() || (*rel).serverid == pg_sys::InvalidOid {
+        return;
+    }
+
+    let foreign_server = pg_sys::GetForeignServer((*rel).serverid);
+    if FdwHandler::from(foreign_server) != FdwHandler::Other {
+        (*(*rel).fdwroutine).IsForeignScanParallelSafe = Some(is_foreign_scan_parallel_safe);
+        #[cfg(not(feature = "pg13"))]
+        {
+            (*(*rel).fdwroutine).IsForeignPathAsyncCapable =
+                Some(async_scan::is_foreign_path_async_capable);
+        }
+    }
+}
+
+#[pg_guard]
+unsafe extern "C" fn is_foreign_scan_parallel_safe(
+    _root: *mut pg_sys::PlannerInfo,
+    _rel: *mut pg_sys::RelOptInfo,
+    _rte: *mut pg_sys::RangeTblEntry,
+) -> bool {
+    GUCS.enable_parallel_foreign_scan.get()
+}
+
+/// Adds a partial path to parquet tables with more than one file, so the planner can split them among workers
+#[pg_guard]
+unsafe extern "C" fn set_rel_pathlist(
+    root: *mut pg_sys::PlannerInfo,
+    rel: *mut pg_sys::RelOptInfo,
+    rti: pg_sys::Index,
+    rte: *mut pg_sys::RangeTblEntry,
+) {
+    if let Some(prev_hook) = PREV_SET_REL_PATHLIST_HOOK {
+        prev_hook(root, rel, rti, rte);
+    }
+
+    if !(*rel).consider_parallel
+        || (*rel).reloptkind != pg_sys::RelOptKind::RELOPT_BASEREL
+        || (*rte).relkind != pg_sys::RELKIND_FOREIGN_TABLE as core::ffi::c_char
+        || pg_sys::max_parallel_workers_per_gather <= 0
+        || !GUCS.enable_parallel_foreign_scan.get()
+    {
+        return;
+    }
+
+    let Some(path) = PgList::<pg_sys::Path>::from_pg((*rel).pathlist)
+        .iter_ptr()
+        .find(|path| (**path).pathtype == pg_sys::NodeTag::T_ForeignScan)
+    else {
+        return;
+    };
+
+    // Planning goes on without a partial path when the files can't be listed
+    let nfiles = match planned_file_count(root, (*rte).relid) {
+        Ok(Some(nfiles)) => nfiles,
+        _ => return,
+    };
+    if nfiles < 2 {
+        return;
+    }
+
+    let workers = match (*rel).rel_parallel_workers {
+        -1 => (nfiles - 1).min(pg_sys::max_parallel_workers_per_gather as usize) as i32,
+        workers => workers,
+    };
+    if workers <= 0 {
+        return;
+    }
+
+    // Each process reads whole files, so the run cost of the scan is divided like the one of a parallel seq scan
+    let partial_path =
+        pg_sys::palloc(std::mem::size_of::<pg_sys::ForeignPath>()) as *mut pg_sys::ForeignPath;
+    std::ptr::copy_nonoverlapping(path as *mut pg_sys::ForeignPath, partial_path, 1);
+    let divisor = parallel_divisor(workers);
+    let run_cost = (*path).total_cost - (*path).startup_cost;
+    (*partial_path).path.parallel_aware = true;
+    (*partial_path).path.parallel_safe = true;
+    (*partial_path).path.parallel_workers = workers;
+    (*partial_path).path.pathkeys = std::ptr::null_mut();
+    (*partial_path).path.rows = ((*path).rows / divisor).ceil();
+    (*partial_path).path.total_cost = (*path).startup_cost + run_cost / divisor;
+
+    pg_sys::add_partial_path(rel, partial_path as *mut pg_sys::Path);
+}
+
+/// Share of the work done by each worker, the same as the one the planner uses for parallel sequential scans
+fn parallel_divisor(workers: i32) -> f64 {
+    let mut divisor = workers as f64;
+    if unsafe { pg_sys::parallel_leader_participation } {
+        let leader_contribution = 1.0 - 0.3 * workers as f64;
+        if leader_contribution > 0.0 {
+            divisor += leader_contribution;
+        }
+    }
+    divisor
+}
+
+/// Number of files of a parquet table, listed once per relation for all the planning of a statement.
+/// Files are only listed when the table has a glob pattern, which is the costly part.
+unsafe fn planned_file_count(
+    root: *mut pg_sys::PlannerInfo,
+    relid: pg_sys::Oid,
+) -> Result<Option<usize>> {
+    let foreign_table = pg_sys::GetForeignTable(relid);
+    let Some(patterns) = parquet_patterns(foreign_table)? else {
+        return Ok(None);
+    };
+    if !patterns.iter().any(|pattern| is_glob_pattern(pattern)) {
+        return Ok(Some(patterns.iter().collect::<HashSet<_>>().len()));
+    }
+
+    let statement_start = pg_sys::GetCurrentStatementStartTimestamp();
+    let key = ((*root).glob as usize, relid);
+    let cached = PLANNED_FILES.with(|planned| {
+        let mut planned = planned.borrow_mut();
+        if planned.statement_start != statement_start {
+            planned.statement_start = statement_start;
+            planned.counts.clear();
+        }
+        planned.counts.get(&key).copied()
+    });
+    if let Some(nfiles) = cached {
+        return Ok(Some(nfiles));
+    }
+
+    let nfiles = parquet_files(relid)?.map_or(0, |files| files.len());
+    PLANNED_FILES.with(|planned| planned.borrow_mut().counts.insert(key, nfiles));
+    Ok(Some(nfiles))
+}
+
+/// The files option of a parquet table that can be read one file at a time, None for other tables
+unsafe fn parquet_patterns(
+    foreign_table: *mut pg_sys::ForeignTable,
+) -> Result<Option<Vec<String>>> {
+    if FdwHandler::from(foreign_table) != FdwHandler::Parquet {
+        return Ok(None);
+    }
+    let table_options = options_to_hashmap((*foreign_table).options)?;
+
+    // Files read with union_by_name only have the columns of the table together
+    let union_by_name = table_options
+        .get(ParquetOption::UnionByName.as_ref())
+        .is_some_and(|option| option.eq_ignore_ascii_case("true"));
+    if union_by_name {
+        return Ok(None);
+    }
+
+    Ok(Some(
+        table_options
+            .get(ParquetOption::Files.as_ref())
+            .ok_or_else(|| anyhow!("files option is required"))?
+            .split(',')
+            .map(|pattern| pattern.trim())
+            .filter(|pattern| !pattern.is_empty())
+            .map(|pattern| pattern.to_string())
+            .collect(),
+    ))
+}
+
+fn is_glob_pattern(pattern: &str) -> bool {
+    pattern.contains(['*', '?', '['])
+}
+
+/// Files of a parquet table that can be read one at a time, None for other tables
+unsafe fn parquet_files(relid: pg_sys::Oid) -> Result<Option<Vec<String>>> {
+    let foreign_table = pg_sys::GetForeignTable(relid);
+    let Some(patterns) = parquet_patterns(foreign_table)? else {
+        return Ok(None);
+    };
+    let handler = FdwHandler::from(foreign_table);
+    let table_options = options_to_hashmap((*foreign_table).options)?;
+
+    // The view registers the secret that remote files are listed with
+    let relation = PgRelation::open(relid);
+    let foreign_server = pg_sys::GetForeignServer((*foreign_table).serverid);
+    register_duckdb_view(
+        relation.name(),
+        relation.namespace(),
+        table_options.clone(),
+        user_mapping_options(foreign_server),
+        handler,
+        (*relation.rd_rel).relowner,
+    )?;
+
+    let mut files = vec![];
+    for pattern in patterns {
+        // Patterns that match nothing are kept, so that reading them fails as it does without workers
+        match connection::glob_files(&pattern)? {
+            matched if matched.is_empty() => files.push(pattern),
+            matched => files.extend(matched),
+        }
+    }
+    // Files matched by several patterns are read once
+    let mut seen = HashSet::new();
+    files.retain(|file| seen.insert(file.clone()));
+
+    Ok(Some(files))
+}
+
+/// Sets the callbacks of this module on the cached FdwRoutine of the foreign tables in the plan,
+/// which the foreign scans copy when the executor starts
+pub fn install_scan_callbacks(query_desc: &PgBox<pg_sys::QueryDesc>) {
+    unsafe {
+        let planned_stmt = query_desc.plannedstmt;
+        if planned_stmt.is_null() {
+            return;
+        }
+
+        for rte in PgList::<pg_sys::RangeTblEntry>::from_pg((*planned_stmt).rtable).iter_ptr() {
+            if (*rte).rtekind != pg_sys::RTEKind::RTE_RELATION
+                || (*rte).relkind != pg_sys::RELKIND_FOREIGN_TABLE as core::ffi::c_char
+            {
+                continue;
+            }
+
+            let relation = PgRelation::open((*rte).relid);
+            let foreign_table = pg_sys::GetForeignTable((*rte).relid);
+            let routine = pg_sys::GetFdwRoutineForRelation(relation.as_ptr(), false);
+            match FdwHandler::from(foreign_table) {
+                FdwHandler::Csv => install::<CsvFdw>(routine),
+                FdwHandler::Delta => install::<DeltaFdw>(routine),
+                FdwHandler::Iceberg => install::<IcebergFdw>(routine),
+                FdwHandler::Json => install::<JsonFdw>(routine),
+                FdwHandler::Parquet => install::<ParquetFdw>(routine),
+                FdwHandler::Spatial => install::<SpatialFdw>(routine),
+                FdwHandler::Other => {}
+            }
+        }
+    }
+}
+
+unsafe fn install<W: BaseFdw + ForeignDataWrapper<BaseFdwError> + 'static>(
+    routine: *mut pg_sys::FdwRoutine,
+) {
+    // supabase-wrappers leaves the parallel callbacks unset
+    if (*routine).InitializeWorkerForeignScan.is_some() {
+        return;
+    }
+
+    WRAPPERS_CALLBACKS.with(|callbacks| {
+        callbacks
+            .borrow_mut()
+            .entry(TypeId::of::<W>())
+            .or_insert(WrappersCallbacks {
+                begin: (*routine).BeginForeignScan,
+                iterate: (*routine).IterateForeignScan,
+                rescan: (*routine).ReScanForeignScan,
+                end: (*routine).EndForeignScan,
+            });
+    });
+
+    (*routine).BeginForeignScan = Some(begin_foreign_scan::<W>);
+    (*routine).IterateForeignScan = Some(iterate_foreign_scan::<W>);
+    (*routine).ReScanForeignScan = Some(rescan_foreign_scan::<W>);
+    (*routine).EndForeignScan = Some(end_foreign_scan::<W>);
+    (*routine).IsForeignScanParallelSafe = Some(is_foreign_scan_parallel_safe);
+    (*routine).EstimateDSMForeignScan = Some(estimate_dsm_foreign_scan);
+    (*routine).InitializeDSMForeignScan = Some(initialize_dsm_foreign_scan);
+    (*routine).ReInitializeDSMForeignScan = Some(reinitialize_dsm_foreign_scan);
+    (*routine).InitializeWorkerForeignScan = Some(initialize_worker_foreign_scan);
+    (*routine).ShutdownForeignScan = Some(shutdown_foreign_scan);
+
+    #[cfg(not(feature = "pg13"))]
+    {
+        (*routine).IsForeignPathAsyncCapable = Some(async_scan::is_foreign_path_async_capable);
+        (*routine).ForeignAsyncRequest = Some(async_scan::foreign_async_request);
+        (*routine).ForeignAsyncConfigureWait = Some(async_scan::foreign_async_configure_wait);
+        (*routine).ForeignAsyncNotify = Some(async_scan::foreign_async_notify);
+    }
+}
+
+fn wrappers_callbacks<W: 'static>() -> WrappersCallbacks {
+    WRAPPERS_CALLBACKS
+        .with(|callbacks| callbacks.borrow().get(&TypeId::of::<W>()).copied())
+        .expect("foreign scan callbacks were not installed")
+}
+
+fn with_current_scan<R>(node: *mut pg_sys::ForeignScanState, f: impl FnOnce() -> R) -> R {
+    let previous = CURRENT_SCAN.with(|current| current.replace(node as usize));
+    let result = f();
+    CURRENT_SCAN.with(|current| current.set(previous));
+    result
+}
+
+/// A foreign scan run by a parallel worker, over every column of the table and without pushed down quals,
+/// which the plan checks again anyway
+struct WorkerScan<W> {
+    fdw: W,
+    columns: Vec<Column>,
+    row: Row,
+}
+
+impl<W: BaseFdw + ForeignDataWrapper<BaseFdwError>> WorkerScan<W> {
+    unsafe fn begin(node: *mut pg_sys::ForeignScanState) -> Result<Self> {
+        let relation = (*node).ss.ss_currentRelation;
+        let relid = (*relation).rd_id;
+        let foreign_table = pg_sys::GetForeignTable(relid);
+        let foreign_server = pg_sys::GetForeignServer((*foreign_table).serverid);
+
+        let mut fdw = W::new(
+            options_to_hashmap((*foreign_table).options)?,
+            options_to_hashmap((*foreign_server).options)?,
+            user_mapping_options(foreign_server),
+        )?;
+
+        let columns = PgTupleDesc::from_pg_unchecked((*relation).rd_att)
+            .iter()
+            .filter(|attribute| !attribute.is_dropped())
+            .map(|attribute| Column {
+                name: attribute.name().to_string(),
+                num: attribute.attnum as usize,
+                type_oid: attribute.atttypid,
+            })
+            .collect::<Vec<_>>();
+
+        let options = HashMap::from([(OPTS_TABLE_KEY.to_string(), relid.as_u32().to_string())]);
+        task::block_on(fdw.begin_scan_impl(&[], &columns, &[], &None, options))?;
+
+        Ok(Self {
+            fdw,
+            columns,
+            row: Row::new(),
+        })
+    }
+
+    unsafe fn iterate(&mut self, slot: *mut pg_sys::TupleTableSlot) -> Result<bool> {
+        self.row.cols.clear();
+        self.row.cells.clear();
+        if task::block_on(self.fdw.iter_scan_impl(&mut self.row))?.is_none() {
+            return Ok(false);
+        }
+
+        let natts = (*(*slot).tts_tupleDescriptor).natts as usize;
+        for index in 0..natts {
+            *(*slot).tts_isnull.add(index) = true;
+        }
+        for (column, cell) in self.columns.iter().zip(self.row.cells.iter_mut()) {
+            if let Some(datum) = cell.take().and_then(|cell| cell.into_datum()) {
+                *(*slot).tts_values.add(column.num - 1) = datum;
+                *(*slot).tts_isnull.add(column.num - 1) = false;
+            }
+        }
+
+        pg_sys::ExecStoreVirtualTuple(slot);
+        Ok(true)
+    }
+}
+
+#[pg_guard]
+unsafe extern "C" fn begin_foreign_scan<W: BaseFdw + ForeignDataWrapper<BaseFdwError> + 'static>(
+    node: *mut pg_sys::ForeignScanState,
+    eflags: i32,
+) {
+    if !is_parallel_worker() {
+        if let Some(begin) = wrappers_callbacks::<W>().begin {
+            #[cfg(not(feature = "pg13"))]
+            async_scan::begin_foreign_scan(node, eflags, || begin(node, eflags));
+            #[cfg(feature = "pg13")]
+            begin(node, eflags);
+        }
+        return;
+    }
+
+    let scan = WorkerScan::<W>::begin(node).unwrap_or_else(|err| panic!("{}", err));
+    (*node).fdw_state = Box::into_raw(Box::new(scan)) as *mut c_void;
+}
+
+#[pg_guard]
+unsafe extern "C" fn iterate_foreign_scan<
+    W: BaseFdw + ForeignDataWrapper<BaseFdwError> + 'static,
+>(
+    node: *mut pg_sys::ForeignScanState,
+) -> *mut pg_sys::TupleTableSlot {
+    if !is_parallel_worker() {
+        let iterate = wrappers_callbacks::<W>()
+            .iterate
+            .expect("IterateForeignScan is not set");
+        return with_current_scan(node, || iterate(node));
+    }
+
+    let slot = (*node).ss.ss_ScanTupleSlot;
+    pg_sys::ExecClearTuple(slot);
+
+    let scan = &mut *((*node).fdw_state as *mut WorkerScan<W>);
+    with_current_scan(node, || scan.iterate(slot)).unwrap_or_else(|err| panic!("{}", err));
+    slot
+}
+
+#[pg_guard]
+unsafe extern "C" fn rescan_foreign_scan<
+    W: BaseFdw + ForeignDataWrapper<BaseFdwError> + 'static,
+>(
+    node: *mut pg_sys::ForeignScanState,
+) {
+    if !is_parallel_worker() {
+        if let Some(rescan) = wrappers_callbacks::<W>().rescan {
+            rescan(node);
+        }
+        return;
+    }
+
+    let scan = &mut *((*node).fdw_state as *mut WorkerScan<W>);
+    scan.fdw.re_scan_impl();
+}
+
+#[pg_guard]
+unsafe extern "C" fn end_foreign_scan<W: BaseFdw + ForeignDataWrapper<BaseFdwError> + 'static>(
+    node: *mut pg_sys::ForeignScanState,
+) {
+    PARALLEL_SCANS.with(|scans| scans.borrow_mut().remove(&(node as usize)));
+
+    if !is_parallel_worker() {
+        if let Some(end) = wrappers_callbacks::<W>().end {
+            end(node);
+        }
+        return;
+    }
+
+    if !(*node).fdw_state.is_null() {
+        let mut scan = Box::from_raw((*node).fdw_state as *mut WorkerScan<W>);
+        scan.fdw.end_scan_impl();
+        (*node).fdw_state = std::ptr::null_mut();
+    }
+}
+
+#[pg_guard]
+unsafe extern "C" fn estimate_dsm_foreign_scan(
+    node: *mut pg_sys::ForeignScanState,
+    _pcxt: *mut pg_sys::ParallelContext,
+) -> pg_sys::Size {
+    let relid = (*(*node).ss.ss_currentRelation).rd_id;
+    let files = parquet_files(relid)
+        .unwrap_or_else(|err| panic!("{}", err))
+        .unwrap_or_default();
+    let size = SharedFiles::size(&files);
+
+    PENDING_FILES.with(|pending| pending.borrow_mut().insert(node as usize, files));
+    size
+}
+
+#[pg_guard]
+unsafe extern "C" fn initialize_dsm_foreign_scan(
+    node: *mut pg_sys::ForeignScanState,
+    _pcxt: *mut pg_sys::ParallelContext,
+    coordinate: *mut c_void,
+) {
+    let files = PENDING_FILES
+        .with(|pending| pending.borrow_mut().remove(&(node as usize)))
+        .unwrap_or_default();
+    SharedFiles::write(coordinate, &files);
+    register_parallel_scan(node, coordinate);
+}
+
+#[pg_guard]
+unsafe extern "C" fn reinitialize_dsm_foreign_scan(
+    node: *mut pg_sys::ForeignScanState,
+    _pcxt: *mut pg_sys::ParallelContext,
+    coordinate: *mut c_void,
+) {
+    (*(coordinate as *mut SharedFiles))
+        .next_file
+        .store(0, Ordering::SeqCst);
+    register_parallel_scan(node, coordinate);
+}
+
+#[pg_guard]
+unsafe extern "C" fn initialize_worker_foreign_scan(
+    node: *mut pg_sys::ForeignScanState,
+    _toc: *mut pg_sys::shm_toc,
+    coordinate: *mut c_void,
+) {
+    register_parallel_scan(node, coordinate);
+}
+
+#[pg_guard]
+unsafe extern "C" fn shutdown_foreign_scan(node: *mut pg_sys::ForeignScanState) {
+    PARALLEL_SCANS.with(|scans| scans.borrow_mut().remove(&(node as usize)));
+}
+
+unsafe fn register_parallel_scan(node: *mut pg_sys::ForeignScanState, coordinate: *mut c_void) {
+    let Some(shared) = NonNull::new(coordinate as *mut SharedFiles) else {
+        return;
+    };
+
+    PARALLEL_SCANS.with(|scans| {
+        scans.borrow_mut().insert(
+            node as usize,
+            RegisteredScan {
+                shared,
+                subxact: pg_sys::GetCurrentSubTransactionId(),
+            },
+        )
+    });
+
+    // Scans that errored out never reach shutdown, so whatever is left is forgotten with the transaction
+    if !XACT_CALLBACK_REGISTERED.with(|registered| registered.replace(true)) {
+        for event in [PgXactCallbackEvent::Commit, PgXactCallbackEvent::Abort] {
+            register_xact_callback(event, clear_parallel_scans);
+        }
+    }
+}
+
+fn clear_parallel_scans() {
+    PARALLEL_SCANS.with(|scans| scans.borrow_mut().clear());
+    PENDING_FILES.with(|pending| pending.borrow_mut().clear());
+    CURRENT_SCAN.with(|current| current.set(0));
+    XACT_CALLBACK_REGISTERED.with(|registered| registered.set(false));
+}
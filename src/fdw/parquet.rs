@@ -17,12 +17,13 @@
 
 use anyhow::Result;
 use async_std::task;
-use duckdb::arrow::array::RecordBatch;
 use pgrx::*;
 use std::collections::HashMap;
 use supabase_wrappers::prelude::*;
 
 use super::base::*;
+use super::handler::FdwHandler;
+use super::import::import_foreign_schema_impl;
 use crate::duckdb::{parquet::ParquetOption, secret::UserMappingOptions};
 
 #[wrappers_fdw(
@@ -31,72 +32,32 @@ use crate::duckdb::{parquet::ParquetOption, secret::UserMappingOptions};
     error_type = "BaseFdwError"
 )]
 pub(crate) struct ParquetFdw {
-    current_batch: Option<RecordBatch>,
-    current_batch_index: usize,
-    scan_started: bool,
-    sql: Option<String>,
-    target_columns: Vec<Column>,
+    scan_state: ScanState,
     user_mapping_options: HashMap<String, String>,
 }
 
 impl BaseFdw for ParquetFdw {
-    fn get_current_batch(&self) -> Option<RecordBatch> {
-        self.current_batch.clone()
+    fn get_scan_state(&self) -> &ScanState {
+        &self.scan_state
     }
 
-    fn get_current_batch_index(&self) -> usize {
-        self.current_batch_index
-    }
-
-    fn get_scan_started(&self) -> bool {
-        self.scan_started
-    }
-
-    fn get_sql(&self) -> Option<String> {
-        self.sql.clone()
-    }
-
-    fn get_target_columns(&self) -> Vec<Column> {
-        self.target_columns.clone()
+    fn get_scan_state_mut(&mut self) -> &mut ScanState {
+        &mut self.scan_state
     }
 
     fn get_user_mapping_options(&self) -> HashMap<String, String> {
         self.user_mapping_options.clone()
     }
-
-    fn set_current_batch(&mut self, batch: Option<RecordBatch>) {
-        self.current_batch = batch;
-    }
-
-    fn set_current_batch_index(&mut self, index: usize) {
-        self.current_batch_index = index;
-    }
-
-    fn set_scan_started(&mut self) {
-        self.scan_started = true;
-    }
-
-    fn set_sql(&mut self, sql: Option<String>) {
-        self.sql = sql;
-    }
-
-    fn set_target_columns(&mut self, columns: &[Column]) {
-        self.target_columns = columns.to_vec();
-    }
 }
 
 impl ForeignDataWrapper<BaseFdwError> for ParquetFdw {
     fn new(
-        _table_options: HashMap<String, String>,
+        table_options: HashMap<String, String>,
         _server_options: HashMap<String, String>,
         user_mapping_options: HashMap<String, String>,
     ) -> Result<Self, BaseFdwError> {
         Ok(Self {
-            current_batch: None,
-            current_batch_index: 0,
-            scan_started: false,
-            sql: None,
-            target_columns: Vec::new(),
+            scan_state: ScanState::new(&table_options),
             user_mapping_options,
         })
     }
@@ -110,6 +71,7 @@ impl ForeignDataWrapper<BaseFdwError> for ParquetFdw {
                 FOREIGN_DATA_WRAPPER_RELATION_ID => {}
                 FOREIGN_SERVER_RELATION_ID => {}
                 FOREIGN_TABLE_RELATION_ID => {
+                    validate_file_access(&opt_list)?;
                     validate_mapping_option::<ParquetOption>(opt_list)?;
                 }
                 USER_MAPPING_RELATION_ID => {
@@ -138,6 +100,11 @@ impl ForeignDataWrapper<BaseFdwError> for ParquetFdw {
         Ok(task::block_on(self.iter_scan_impl(row))?)
     }
 
+    fn re_scan(&mut self) -> Result<(), BaseFdwError> {
+        self.re_scan_impl();
+        Ok(())
+    }
+
     fn end_scan(&mut self) -> Result<(), BaseFdwError> {
         self.end_scan_impl();
         Ok(())
@@ -146,4 +113,15 @@ impl ForeignDataWrapper<BaseFdwError> for ParquetFdw {
     fn explain(&self) -> Result<Option<Vec<(String, String)>>, BaseFdwError> {
         Ok(self.explain_impl()?)
     }
+
+    fn import_foreign_schema(
+        &mut self,
+        stmt: ImportForeignSchemaStmt,
+    ) -> Result<Vec<String>, BaseFdwError> {
+        Ok(import_foreign_schema_impl(
+            stmt,
+            self.get_user_mapping_options(),
+            FdwHandler::Parquet,
+        )?)
+    }
 }
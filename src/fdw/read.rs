@@ -0,0 +1,252 @@
+// Copyright (c) 2023-2025 Retake, Inc.
+//
+// This file is part of ParadeDB - Postgres for Search and Analytics
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <http://www.gnu.org/licenses/>.
+use anyhow::{bail, Result};
+use pgrx::*;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::ffi::CString;
+use supabase_wrappers::prelude::*;
+
+use super::base::{check_file_access, validate_mapping_option, DEFAULT_SECRET};
+use super::handler::FdwHandler;
+use crate::duckdb::{connection, csv, delta, json, parquet};
+
+// The table functions live in their own schema so they don't shadow the DuckDB functions they call
+pub const READ_FUNCTION_SCHEMA: &str = "th_dbdm";
+
+pub fn read_function_handler(function_name: &str) -> Option<FdwHandler> {
+    match function_name {
+        "read_parquet" => Some(FdwHandler::Parquet),
+        "read_csv" => Some(FdwHandler::Csv),
+        "read_json" => Some(FdwHandler::Json),
+        "read_delta" => Some(FdwHandler::Delta),
+        _ => None,
+    }
+}
+
+/// Turns the jsonb options into the table options a foreign table would have,
+/// e.g. {"hive_partitioning": true} becomes hive_partitioning 'true'.
+/// The options of a foreign table are set by its owner, but these come from whoever calls the function,
+/// so every value is checked before it is pasted into the DuckDB SQL.
+pub fn read_function_options(
+    handler: FdwHandler,
+    files: &str,
+    options: Option<&Value>,
+) -> Result<HashMap<String, String>> {
+    if files.contains('\'') {
+        bail!("files cannot contain single quotes");
+    }
+    let mut table_options = HashMap::from([("files".to_string(), files.to_string())]);
+
+    match options {
+        None | Some(Value::Null) => {}
+        Some(Value::Object(options)) => {
+            for (key, value) in options {
+                let value = match value {
+                    Value::String(value) => value.clone(),
+                    Value::Bool(_) | Value::Number(_) => value.to_string(),
+                    _ => bail!("option {key} must be a string, a number or a boolean"),
+                };
+                let value = if key == "select" {
+                    quote_select_list(&value)?
+                } else if is_quoted_option(handler, key) {
+                    if value.contains('\'') {
+                        bail!("option {key} cannot contain single quotes");
+                    }
+                    value
+                } else {
+                    if !is_plain_value(&value) {
+                        bail!(
+                            "option {key} must be a plain value such as true or 100, got {value}"
+                        );
+                    }
+                    value
+                };
+                table_options.insert(key.clone(), value);
+            }
+        }
+        Some(_) => bail!("options must be a json object"),
+    }
+
+    Ok(table_options)
+}
+
+// Options that the create_select functions put between single quotes. Any other option is pasted
+// into the SQL as it is, e.g. columns and hive_types, which take DuckDB struct literals.
+fn is_quoted_option(handler: FdwHandler, key: &str) -> bool {
+    match handler {
+        FdwHandler::Csv => matches!(
+            key,
+            "auto_type_candidates"
+                | "compression"
+                | "dateformat"
+                | "decimal_separator"
+                | "delim"
+                | "escape"
+                | "force_not_null"
+                | "names"
+                | "new_line"
+                | "nullstr"
+                | "quote"
+                | "rejects_scan"
+                | "rejects_table"
+                | "sep"
+                | "timestampformat"
+                | "types"
+        ),
+        FdwHandler::Json => matches!(
+            key,
+            "compression" | "dateformat" | "format" | "timestampformat"
+        ),
+        _ => false,
+    }
+}
+
+// Booleans, numbers and keywords such as auto, which can't be turned into an expression
+fn is_plain_value(value: &str) -> bool {
+    !value.is_empty()
+        && value
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '.' | '-' | '+'))
+}
+
+// The select option of a read_* call can only list columns, which are quoted as identifiers
+fn quote_select_list(select: &str) -> Result<String> {
+    if select.trim() == "*" {
+        return Ok("*".to_string());
+    }
+
+    select
+        .split(',')
+        .map(|column| match column.trim() {
+            "" => bail!("option select must be * or a list of column names"),
+            column => Ok(format!("\"{}\"", column.replace('"', "\"\""))),
+        })
+        .collect::<Result<Vec<String>>>()
+        .map(|columns| columns.join(", "))
+}
+
+pub fn read_function_select(
+    handler: FdwHandler,
+    table_options: HashMap<String, String>,
+) -> Result<String> {
+    match handler {
+        FdwHandler::Parquet => parquet::create_select(table_options),
+        FdwHandler::Csv => csv::create_select(table_options),
+        FdwHandler::Json => json::create_select(table_options),
+        FdwHandler::Delta => delta::create_select(table_options),
+        _ => bail!("got unexpected fdw_handler"),
+    }
+}
+
+/// Applies the same checks as a foreign table with these options, and creates the DuckDB secret
+/// from the current user's mapping on the server
+pub fn prepare_read_function(
+    handler: FdwHandler,
+    table_options: &HashMap<String, String>,
+    server: Option<&str>,
+) -> Result<()> {
+    let opt_list = table_options
+        .iter()
+        .map(|(key, value)| Some(format!("{key}={value}")))
+        .collect::<Vec<Option<String>>>();
+
+    match handler {
+        FdwHandler::Parquet => validate_mapping_option::<parquet::ParquetOption>(opt_list)?,
+        FdwHandler::Csv => validate_mapping_option::<csv::CsvOption>(opt_list)?,
+        FdwHandler::Json => validate_mapping_option::<json::JsonOption>(opt_list)?,
+        FdwHandler::Delta => validate_mapping_option::<delta::DeltaOption>(opt_list)?,
+        _ => bail!("got unexpected fdw_handler"),
+    }
+
+    check_file_access(table_options, unsafe { pg_sys::GetUserId() })?;
+
+    let Some(server) = server else {
+        return Ok(());
+    };
+
+    let server_name = CString::new(server)?;
+    let foreign_server = unsafe { pg_sys::GetForeignServerByName(server_name.as_ptr(), false) };
+
+    #[cfg(any(feature = "pg13", feature = "pg14", feature = "pg15"))]
+    let acl_result = unsafe {
+        pg_sys::pg_foreign_server_aclcheck(
+            (*foreign_server).serverid,
+            pg_sys::GetUserId(),
+            pg_sys::ACL_USAGE as pg_sys::AclMode,
+        )
+    };
+
+    #[cfg(any(feature = "pg16", feature = "pg17"))]
+    let acl_result = unsafe {
+        pg_sys::object_aclcheck(
+            FOREIGN_SERVER_RELATION_ID,
+            (*foreign_server).serverid,
+            pg_sys::GetUserId(),
+            pg_sys::ACL_USAGE as pg_sys::AclMode,
+        )
+    };
+
+    if acl_result != pg_sys::AclResult::ACLCHECK_OK {
+        bail!("permission denied for foreign server {}", server);
+    }
+
+    let user_mapping_options = unsafe { user_mapping_options(foreign_server) };
+    if !user_mapping_options.is_empty() {
+        connection::create_secret(DEFAULT_SECRET, user_mapping_options)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_read_function_options_quote_values() {
+        let options = read_function_options(
+            FdwHandler::Csv,
+            "/data/file.csv",
+            Some(&json!({"select": "a, \"b\"", "delim": "|", "header": true, "skip": 2})),
+        )
+        .unwrap();
+        assert_eq!(options["select"], r#""a", """b""""#);
+        assert_eq!(options["delim"], "|");
+        assert_eq!(options["header"], "true");
+        assert_eq!(options["skip"], "2");
+    }
+
+    #[test]
+    fn test_read_function_options_reject_sql() {
+        for options in [
+            json!({"select": "* FROM read_csv('/etc/passwd') --"}),
+            json!({"select": "a,,b"}),
+            json!({"header": "(SELECT 1)"}),
+            json!({"columns": "{'a': 'INT'}"}),
+            json!({"delim": "', x = '"}),
+            json!({"names": ["a"]}),
+        ] {
+            assert!(
+                read_function_options(FdwHandler::Csv, "/data/file.csv", Some(&options)).is_err(),
+                "{options}"
+            );
+        }
+        assert!(read_function_options(FdwHandler::Parquet, "/x') --", None).is_err());
+    }
+}
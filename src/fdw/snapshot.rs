@@ -0,0 +1,190 @@
+// Copyright (c) 2023-2025 Retake, Inc.
+//
+// This file is part of ParadeDB - Postgres for Search and Analytics
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <http://www.gnu.org/licenses/>.
+
+use anyhow::{anyhow, Result};
+use pgrx::*;
+use std::cell::{Cell as StdCell, RefCell};
+use std::collections::HashMap;
+use supabase_wrappers::prelude::*;
+
+use super::handler::FdwHandler;
+use crate::duckdb::{connection, delta, iceberg};
+use crate::GUCS;
+
+// delta_scan and iceberg_scan read the latest version of a table every time they run. In transactions
+// that read from a single snapshot, the DuckDB view of a lake table is pointed at the version that was
+// current when the transaction first read it, and put back when the transaction ends. Both foreign scans
+// and pushed down queries read through the view, so they see the same version.
+
+thread_local! {
+    // Lake tables pinned by the current transaction, by relid
+    static PINNED_TABLES: RefCell<HashMap<pg_sys::Oid, PinnedTable>> = RefCell::new(HashMap::new());
+    static XACT_CALLBACK_REGISTERED: StdCell<bool> = const { StdCell::new(false) };
+}
+
+struct PinnedTable {
+    schema_name: String,
+    table_name: String,
+    handler: FdwHandler,
+    table_options: HashMap<String, String>,
+    select: String,
+    // Found in the SQL of the view as long as it reads the pinned version: the snapshot id or the
+    // name the Delta table is attached as
+    marker: String,
+    attached: Option<String>,
+}
+
+impl PinnedTable {
+    fn is_applied(&self) -> Result<bool> {
+        Ok(connection::view_sql(&self.table_name, &self.schema_name)?
+            .is_some_and(|sql| sql.contains(&self.marker)))
+    }
+
+    fn apply(&self) -> Result<()> {
+        replace_view(&self.schema_name, &self.table_name, &self.select)
+    }
+
+    fn release(self) -> Result<()> {
+        // Views recreated by ALTER FOREIGN TABLE in the meantime already read the latest version
+        if self.is_applied()? {
+            let select = match self.handler {
+                FdwHandler::Delta => delta::create_select(self.table_options.clone())?,
+                _ => iceberg::create_select(self.table_options.clone(), None)?,
+            };
+            replace_view(&self.schema_name, &self.table_name, &select)?;
+        }
+
+        if let Some(attached) = &self.attached {
+            connection::execute(&format!("DETACH DATABASE IF EXISTS {attached}"), [])?;
+        }
+        Ok(())
+    }
+}
+
+/// Points the view of a Delta or Iceberg table at its current version, the first time a REPEATABLE READ
+/// or SERIALIZABLE transaction reads it. Tables without a registered view are left alone.
+pub fn pin_snapshot(relation: &PgRelation) -> Result<()> {
+    let uses_xact_snapshot = unsafe { pg_sys::XactIsoLevel >= pg_sys::XACT_REPEATABLE_READ as i32 };
+    if !GUCS.pin_lake_snapshots.get() || !uses_xact_snapshot || !relation.is_foreign_table() {
+        return Ok(());
+    }
+
+    if !connection::view_exists(relation.name(), relation.namespace())? {
+        return Ok(());
+    }
+
+    let relid = relation.oid();
+    let applied = PINNED_TABLES.with(|pinned| {
+        pinned
+            .borrow()
+            .get(&relid)
+            .map(|table| {
+                table
+                    .is_applied()
+                    .map(|applied| (table.select.clone(), applied))
+            })
+            .transpose()
+    })?;
+
+    // Checking the schema for drift recreates the view, so it is pointed at the pinned version again
+    if let Some((select, applied)) = applied {
+        if !applied {
+            replace_view(relation.namespace(), relation.name(), &select)?;
+        }
+        return Ok(());
+    }
+
+    let foreign_table = unsafe { pg_sys::GetForeignTable(relid) };
+    let handler = FdwHandler::from(foreign_table);
+    let table_options = unsafe { options_to_hashmap((*foreign_table).options)? };
+
+    let (select, marker, attached) = match handler {
+        FdwHandler::Delta => {
+            let alias = format!("delta_snapshot_{}", relid.as_u32());
+            connection::execute(&delta::attach_snapshot(&table_options, &alias)?, []).map_err(
+                |err| {
+                    anyhow!(
+                        "failed to pin the snapshot of {}, set thdb.pin_lake_snapshots to false if the delta extension does not support PIN_SNAPSHOT: {err}",
+                        relation.name()
+                    )
+                },
+            )?;
+            (
+                delta::create_snapshot_select(&table_options, &alias),
+                alias.clone(),
+                Some(alias),
+            )
+        }
+        FdwHandler::Iceberg => {
+            // Tables without a snapshot have nothing to read yet, and are read as they are
+            let Some(snapshot_id) = connection::latest_iceberg_snapshot(&table_options)? else {
+                return Ok(());
+            };
+            (
+                iceberg::create_select(table_options.clone(), Some(&snapshot_id))?,
+                snapshot_id,
+                None,
+            )
+        }
+        _ => return Ok(()),
+    };
+
+    let table = PinnedTable {
+        schema_name: relation.namespace().to_string(),
+        table_name: relation.name().to_string(),
+        handler,
+        table_options,
+        select,
+        marker,
+        attached,
+    };
+    // Recorded even if the view can't be replaced, so the attached table is released with the transaction
+    let result = table.apply();
+    PINNED_TABLES.with(|pinned| pinned.borrow_mut().insert(relid, table));
+
+    if !XACT_CALLBACK_REGISTERED.with(|registered| registered.replace(true)) {
+        for event in [PgXactCallbackEvent::Commit, PgXactCallbackEvent::Abort] {
+            register_xact_callback(event, release_snapshots);
+        }
+    }
+
+    result
+}
+
+/// Whether the table reads a version pinned by the current transaction
+pub fn is_pinned(relid: pg_sys::Oid) -> bool {
+    PINNED_TABLES.with(|pinned| pinned.borrow().contains_key(&relid))
+}
+
+fn replace_view(schema_name: &str, table_name: &str, select: &str) -> Result<()> {
+    connection::execute(
+        &format!("CREATE OR REPLACE VIEW {schema_name}.{table_name} AS {select}"),
+        [],
+    )?;
+    Ok(())
+}
+
+fn release_snapshots() {
+    let pinned = PINNED_TABLES.with(|pinned| std::mem::take(&mut *pinned.borrow_mut()));
+    for table in pinned.into_values() {
+        let name = format!("{}.{}", table.schema_name, table.table_name);
+        if let Err(err) = table.release() {
+            warning!("failed to release the pinned snapshot of {}: {}", name, err);
+        }
+    }
+    XACT_CALLBACK_REGISTERED.with(|registered| registered.set(false));
+}
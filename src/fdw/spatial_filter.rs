@@ -0,0 +1,458 @@
+// Copyright (c) 2023-2025 Retake, Inc.
+//
+// This file is part of ParadeDB - Postgres for Search and Analytics
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <http://www.gnu.org/licenses/>.
+
+use anyhow::{anyhow, Result};
+use pgrx::*;
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::ffi::CStr;
+use std::panic::AssertUnwindSafe;
+
+use super::handler::FdwHandler;
+use crate::duckdb::{connection, spatial::SpatialOption};
+use crate::schema::postgis::{postgis_type, PostgisType};
+
+// PostGIS predicates are not supported by the qual extraction of the wrappers,
+// so they are read from the plan when the executor starts, and picked up by begin_scan
+thread_local! {
+    static SPATIAL_FILTERS: RefCell<HashMap<pg_sys::Oid, SpatialFilter>> = RefCell::new(HashMap::new());
+    static FILTERED_VIEWS: RefCell<HashSet<pg_sys::Oid>> = RefCell::new(HashSet::new());
+}
+
+/// Spatial predicates of a spatial foreign table that can be evaluated by st_read
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SpatialFilter {
+    // min_x, min_y, max_x, max_y
+    bbox: Option<[f64; 4]>,
+    // Geometry column and hex encoded WKB of the geometry it intersects
+    intersects: Vec<(String, String)>,
+}
+
+impl SpatialFilter {
+    fn add_bbox(&mut self, bbox: [f64; 4]) {
+        // Features whose extent overlaps two boxes don't necessarily overlap their intersection,
+        // so only the most selective box is used
+        let area = |[min_x, min_y, max_x, max_y]: [f64; 4]| (max_x - min_x) * (max_y - min_y);
+        if self.bbox.map_or(true, |current| area(bbox) < area(current)) {
+            self.bbox = Some(bbox);
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.bbox.is_none() && self.intersects.is_empty()
+    }
+
+    pub fn spatial_filter_box(&self) -> Option<String> {
+        self.bbox.map(|[min_x, min_y, max_x, max_y]| {
+            format!(
+                "{{'min_x': {min_x}, 'min_y': {min_y}, 'max_x': {max_x}, 'max_y': {max_y}}}::BOX_2D"
+            )
+        })
+    }
+
+    pub fn where_clauses(&self) -> Vec<String> {
+        self.intersects
+            .iter()
+            .map(|(column, wkb)| format!("ST_Intersects({column}, ST_GeomFromHEXWKB('{wkb}'))"))
+            .collect()
+    }
+}
+
+/// Finds the && and ST_Intersects predicates of the spatial foreign scans in the plan.
+/// This runs for every query and the filters are only an optimization, so a scan whose filter can't be
+/// worked out, e.g. because PostGIS is missing, is read without one.
+/// Returns the filters of the executor that was starting already, e.g. the one running the SPI
+/// query, which are put back with [`restore_spatial_filters`].
+pub fn collect_spatial_filters(
+    query_desc: &PgBox<pg_sys::QueryDesc>,
+) -> HashMap<pg_sys::Oid, SpatialFilter> {
+    let mut filters = HashMap::new();
+
+    unsafe {
+        let planned_stmt = query_desc.plannedstmt;
+        if !planned_stmt.is_null() && has_foreign_table((*planned_stmt).rtable) {
+            let mut scans = vec![];
+            collect_foreign_scans((*planned_stmt).planTree, &mut scans);
+            for subplan in PgList::<pg_sys::Plan>::from_pg((*planned_stmt).subplans).iter_ptr() {
+                collect_foreign_scans(subplan, &mut scans);
+            }
+
+            let mut scanned = HashSet::new();
+            let mut shared = HashSet::new();
+            for scan in scans {
+                let Some(relid) = spatial_scan_relid(scan, (*planned_stmt).rtable) else {
+                    continue;
+                };
+
+                // DuckDB views are shared by every scan of a table, so they can only be filtered for a single scan
+                if !scanned.insert(relid) {
+                    shared.insert(relid);
+                    continue;
+                }
+
+                // Subtransactions can't be started in parallel mode
+                let quals = (*scan).scan.plan.qual;
+                if quals.is_null() || pg_sys::IsInParallelMode() {
+                    continue;
+                }
+
+                match in_subtransaction(|| spatial_filter(relid, quals, query_desc.params)) {
+                    Ok(filter) if !filter.is_empty() => {
+                        filters.insert(relid, filter);
+                    }
+                    Ok(_) => {}
+                    Err(err) => debug1!("skipping the spatial filter of a foreign scan: {}", err),
+                }
+            }
+            filters.retain(|relid, _| !shared.contains(relid));
+        }
+    }
+
+    SPATIAL_FILTERS
+        .with(|spatial_filters| std::mem::replace(&mut *spatial_filters.borrow_mut(), filters))
+}
+
+// The lookups run SPI and read the syscache, which raise Postgres ERRORs. Those are caught in an
+// internal subtransaction that is rolled back, like PL/pgSQL does, so the transaction stays usable.
+unsafe fn in_subtransaction<T>(f: impl FnOnce() -> Result<T>) -> Result<T> {
+    let memory_context = pg_sys::CurrentMemoryContext;
+    let resource_owner = pg_sys::CurrentResourceOwner;
+
+    pg_sys::BeginInternalSubTransaction(std::ptr::null());
+    pg_sys::MemoryContextSwitchTo(memory_context);
+
+    PgTryBuilder::new(AssertUnwindSafe(|| {
+        let result = f();
+        pg_sys::ReleaseCurrentSubTransaction();
+        pg_sys::MemoryContextSwitchTo(memory_context);
+        pg_sys::CurrentResourceOwner = resource_owner;
+        result
+    }))
+    .catch_others(|err| {
+        pg_sys::MemoryContextSwitchTo(memory_context);
+        pg_sys::RollbackAndReleaseCurrentSubTransaction();
+        pg_sys::MemoryContextSwitchTo(memory_context);
+        pg_sys::CurrentResourceOwner = resource_owner;
+        Err(anyhow!("{:?}", err))
+    })
+    .execute()
+}
+
+// Plans without a foreign table are not walked at all
+unsafe fn has_foreign_table(rtable: *mut pg_sys::List) -> bool {
+    PgList::<pg_sys::RangeTblEntry>::from_pg(rtable)
+        .iter_ptr()
+        .any(|rte| {
+            (*rte).rtekind == pg_sys::RTEKind::RTE_RELATION
+                && (*rte).relkind == pg_sys::RELKIND_FOREIGN_TABLE as std::ffi::c_char
+        })
+}
+
+pub fn restore_spatial_filters(filters: HashMap<pg_sys::Oid, SpatialFilter>) {
+    SPATIAL_FILTERS.with(|spatial_filters| *spatial_filters.borrow_mut() = filters);
+}
+
+/// Applies the spatial filter found for the table to its DuckDB view, and returns the
+/// conditions to add to the scan. The view is recreated without a filter on the next scan.
+pub fn apply_spatial_filter(
+    table_oid: pg_sys::Oid,
+    schema_name: &str,
+    table_name: &str,
+    table_options: &mut HashMap<String, String>,
+) -> Result<Vec<String>> {
+    let filter = SPATIAL_FILTERS
+        .with(|spatial_filters| spatial_filters.borrow_mut().remove(&table_oid))
+        .unwrap_or_default();
+
+    // A filter set in the table options is kept
+    let spatial_filter_box = filter.spatial_filter_box().filter(|_| {
+        !table_options.contains_key(SpatialOption::SpatialFilterBox.as_ref())
+            && !table_options.contains_key(SpatialOption::SpatialFilter.as_ref())
+    });
+
+    let was_filtered =
+        FILTERED_VIEWS.with(|filtered_views| filtered_views.borrow_mut().remove(&table_oid));
+    if was_filtered || spatial_filter_box.is_some() {
+        connection::execute(
+            format!("DROP VIEW IF EXISTS {schema_name}.{table_name}").as_str(),
+            [],
+        )?;
+    }
+
+    if let Some(spatial_filter_box) = spatial_filter_box {
+        table_options.insert(
+            SpatialOption::SpatialFilterBox.as_ref().to_string(),
+            spatial_filter_box,
+        );
+        FILTERED_VIEWS.with(|filtered_views| filtered_views.borrow_mut().insert(table_oid));
+    }
+
+    Ok(filter.where_clauses())
+}
+
+unsafe fn collect_foreign_scans(
+    plan: *mut pg_sys::Plan,
+    scans: &mut Vec<*mut pg_sys::ForeignScan>,
+) {
+    if plan.is_null() {
+        return;
+    }
+
+    match (*plan).type_ {
+        pg_sys::NodeTag::T_ForeignScan => scans.push(plan as *mut pg_sys::ForeignScan),
+        pg_sys::NodeTag::T_Append => {
+            let append = plan as *mut pg_sys::Append;
+            for subplan in PgList::<pg_sys::Plan>::from_pg((*append).appendplans).iter_ptr() {
+                collect_foreign_scans(subplan, scans);
+            }
+        }
+        pg_sys::NodeTag::T_MergeAppend => {
+            let merge_append = plan as *mut pg_sys::MergeAppend;
+            for subplan in PgList::<pg_sys::Plan>::from_pg((*merge_append).mergeplans).iter_ptr() {
+                collect_foreign_scans(subplan, scans);
+            }
+        }
+        pg_sys::NodeTag::T_SubqueryScan => {
+            collect_foreign_scans((*(plan as *mut pg_sys::SubqueryScan)).subplan, scans);
+        }
+        _ => {}
+    }
+
+    collect_foreign_scans((*plan).lefttree, scans);
+    collect_foreign_scans((*plan).righttree, scans);
+}
+
+unsafe fn spatial_scan_relid(
+    scan: *mut pg_sys::ForeignScan,
+    rtable: *mut pg_sys::List,
+) -> Option<pg_sys::Oid> {
+    let scanrelid = (*scan).scan.scanrelid as i32;
+    if scanrelid <= 0 {
+        return None;
+    }
+
+    let rte = PgList::<pg_sys::RangeTblEntry>::from_pg(rtable).get_ptr(scanrelid as usize - 1)?;
+    if (*rte).rtekind != pg_sys::RTEKind::RTE_RELATION {
+        return None;
+    }
+
+    let foreign_table = pg_sys::GetForeignTable((*rte).relid);
+    match FdwHandler::from(foreign_table) {
+        FdwHandler::Spatial => Some((*rte).relid),
+        _ => None,
+    }
+}
+
+unsafe fn spatial_filter(
+    relid: pg_sys::Oid,
+    quals: *mut pg_sys::List,
+    params: pg_sys::ParamListInfo,
+) -> Result<SpatialFilter> {
+    let mut filter = SpatialFilter::default();
+
+    for qual in PgList::<pg_sys::Node>::from_pg(quals).iter_ptr() {
+        let (is_intersects, args) = if is_a(qual, pg_sys::NodeTag::T_OpExpr) {
+            let op_expr = qual as *mut pg_sys::OpExpr;
+            let name = pg_sys::get_opname((*op_expr).opno);
+            if name.is_null() || CStr::from_ptr(name).to_bytes() != b"&&" {
+                continue;
+            }
+            (false, (*op_expr).args)
+        } else if is_a(qual, pg_sys::NodeTag::T_FuncExpr) {
+            let func_expr = qual as *mut pg_sys::FuncExpr;
+            let name = pg_sys::get_func_name((*func_expr).funcid);
+            if name.is_null()
+                || !CStr::from_ptr(name)
+                    .to_bytes()
+                    .eq_ignore_ascii_case(b"st_intersects")
+            {
+                continue;
+            }
+            (true, (*func_expr).args)
+        } else {
+            continue;
+        };
+
+        let args = PgList::<pg_sys::Node>::from_pg(args);
+        let (Some(left), Some(right), 2) = (args.get_ptr(0), args.get_ptr(1), args.len()) else {
+            continue;
+        };
+
+        // Both predicates are symmetric
+        let (column, value) = match (scan_column(relid, left), scan_column(relid, right)) {
+            (Some(column), None) => (column, right),
+            (None, Some(column)) => (column, left),
+            _ => continue,
+        };
+
+        let Some((geometry_oid, geometry)) = geometry_value(value, params) else {
+            continue;
+        };
+        let Some((bbox, wkb)) = geometry_bbox(geometry_oid, geometry)? else {
+            continue;
+        };
+
+        filter.add_bbox(bbox);
+        if is_intersects {
+            filter.intersects.push((column, wkb));
+        }
+    }
+
+    Ok(filter)
+}
+
+// Returns the column name of a scanned geometry column, which may be cast from the raw DuckDB geometry
+unsafe fn scan_column(relid: pg_sys::Oid, node: *mut pg_sys::Node) -> Option<String> {
+    let var = if is_a(node, pg_sys::NodeTag::T_Var) {
+        let var = node as *mut pg_sys::Var;
+        if postgis_type((*var).vartype) != Some(PostgisType::Geometry) {
+            return None;
+        }
+        var
+    } else if is_a(node, pg_sys::NodeTag::T_FuncExpr) {
+        let func_expr = node as *mut pg_sys::FuncExpr;
+        let args = PgList::<pg_sys::Node>::from_pg((*func_expr).args);
+        match args.get_ptr(0) {
+            Some(arg)
+                if (*func_expr).funcformat == pg_sys::CoercionForm::COERCE_IMPLICIT_CAST
+                    && args.len() == 1
+                    && is_a(arg, pg_sys::NodeTag::T_Var)
+                    && postgis_type((*func_expr).funcresulttype) == Some(PostgisType::Geometry) =>
+            {
+                arg as *mut pg_sys::Var
+            }
+            _ => return None,
+        }
+    } else {
+        return None;
+    };
+
+    if (*var).varattno <= 0 || (*var).varlevelsup != 0 {
+        return None;
+    }
+
+    let name = pg_sys::get_attname(relid, (*var).varattno, true);
+    if name.is_null() {
+        return None;
+    }
+    Some(CStr::from_ptr(name).to_string_lossy().into_owned())
+}
+
+// Constants are folded by the planner, e.g. ST_MakeEnvelope(...), and parameters are known when the executor starts
+unsafe fn geometry_value(
+    node: *mut pg_sys::Node,
+    params: pg_sys::ParamListInfo,
+) -> Option<(pg_sys::Oid, pg_sys::Datum)> {
+    if is_a(node, pg_sys::NodeTag::T_Const) {
+        let constant = node as *mut pg_sys::Const;
+        if (*constant).constisnull
+            || postgis_type((*constant).consttype) != Some(PostgisType::Geometry)
+        {
+            return None;
+        }
+        return Some(((*constant).consttype, (*constant).constvalue));
+    }
+
+    if !is_a(node, pg_sys::NodeTag::T_Param) || params.is_null() {
+        return None;
+    }
+
+    let param = node as *mut pg_sys::Param;
+    if (*param).paramkind != pg_sys::ParamKind::PARAM_EXTERN
+        || postgis_type((*param).paramtype) != Some(PostgisType::Geometry)
+        || (*param).paramid < 1
+        || (*param).paramid > (*params).numParams
+    {
+        return None;
+    }
+
+    let mut workspace = pg_sys::ParamExternData::default();
+    let value = match (*params).paramFetch {
+        Some(param_fetch) => param_fetch(params, (*param).paramid, false, &mut workspace),
+        None => (*params)
+            .params
+            .as_mut_ptr()
+            .add((*param).paramid as usize - 1),
+    };
+    if value.is_null() || (*value).isnull {
+        return None;
+    }
+    Some(((*param).paramtype, (*value).value))
+}
+
+// The extent and WKB of a geometry are read with PostGIS
+fn geometry_bbox(
+    geometry_oid: pg_sys::Oid,
+    geometry: pg_sys::Datum,
+) -> Result<Option<([f64; 4], String)>> {
+    Ok(Spi::connect(|client| {
+        let row = client
+            .select(
+                "SELECT ST_XMin(g), ST_YMin(g), ST_XMax(g), ST_YMax(g), encode(ST_AsBinary(g), 'hex') \
+                 FROM (SELECT $1 AS g) geometry",
+                Some(1),
+                Some(vec![(PgOid::from(geometry_oid), Some(geometry))]),
+            )?
+            .first();
+
+        // Empty geometries have no extent
+        match (
+            row.get::<f64>(1)?,
+            row.get::<f64>(2)?,
+            row.get::<f64>(3)?,
+            row.get::<f64>(4)?,
+            row.get::<String>(5)?,
+        ) {
+            (Some(min_x), Some(min_y), Some(max_x), Some(max_y), Some(wkb)) => {
+                Ok::<_, spi::Error>(Some(([min_x, min_y, max_x, max_y], wkb)))
+            }
+            _ => Ok(None),
+        }
+    })?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_spatial_filter_box() {
+        let mut filter = SpatialFilter::default();
+        assert!(filter.is_empty());
+        assert_eq!(filter.spatial_filter_box(), None);
+
+        filter.add_bbox([0.0, 0.0, 10.0, 10.0]);
+        filter.add_bbox([1.0, 1.0, 2.5, 2.0]);
+        filter.add_bbox([-5.0, -5.0, 5.0, 5.0]);
+        assert_eq!(
+            filter.spatial_filter_box().unwrap(),
+            "{'min_x': 1, 'min_y': 1, 'max_x': 2.5, 'max_y': 2}::BOX_2D"
+        );
+    }
+
+    #[test]
+    fn test_spatial_filter_where_clauses() {
+        let filter = SpatialFilter {
+            bbox: None,
+            intersects: vec![("geom".to_string(), "0101000000".to_string())],
+        };
+        assert_eq!(
+            filter.where_clauses(),
+            vec!["ST_Intersects(geom, ST_GeomFromHEXWKB('0101000000'))"]
+        );
+    }
+}
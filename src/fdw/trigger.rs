@@ -17,12 +17,17 @@
 
 use anyhow::{bail, Result};
 use pgrx::*;
-use std::ffi::CStr;
+use std::collections::HashMap;
+use std::ffi::{CStr, CString};
 use supabase_wrappers::prelude::{options_to_hashmap, user_mapping_options};
 
 use super::base::register_duckdb_view;
 use crate::duckdb::connection;
 use crate::fdw::handler::FdwHandler;
+use crate::schema::duckdb_type::DuckDbType;
+use crate::schema::postgis::{postgis_column_type, postgis_installed};
+
+pub(crate) const TYPE_OVERRIDES_OPTION: &str = "type_overrides";
 
 extension_sql!(
     r#"
@@ -52,7 +57,7 @@ fn auto_create_schema_hook(fcinfo: pg_sys::FunctionCallInfo) {
 // Foreign tables should not be created with these names
 // because they conflict with built-in DuckDB tables
 // https://duckdb.org/docs/guides/meta/duckdb_environment#meta-table-functions
-const DUCKDB_RESERVED_NAMES: [&str; 16] = [
+pub(crate) const DUCKDB_RESERVED_NAMES: [&str; 16] = [
     "duckdb_columns",
     "duckdb_constraints",
     "duckdb_databases",
@@ -153,6 +158,7 @@ unsafe fn auto_create_schema_impl(fcinfo: pg_sys::FunctionCallInfo) -> Result<()
         table_options.clone(),
         user_mapping_options,
         handler,
+        pg_sys::GetUserId(),
     )?;
 
     // If the table already has columns, no need for auto schema creation
@@ -165,16 +171,7 @@ unsafe fn auto_create_schema_impl(fcinfo: pg_sys::FunctionCallInfo) -> Result<()
     pg_sys::RelationClose(relation);
 
     // Get DuckDB schema
-    let conn = unsafe { &*connection::get_global_connection().get() };
-    let query = format!("DESCRIBE {schema_name}.{table_name}");
-    let mut stmt = conn.prepare(&query)?;
-
-    let schema_rows = stmt
-        .query_map([], |row| {
-            Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
-        })?
-        .map(|row| row.unwrap())
-        .collect::<Vec<(String, String)>>();
+    let schema_rows = describe_duckdb_view(schema_name, table_name)?;
 
     if schema_rows.is_empty() {
         return Ok(());
@@ -184,82 +181,150 @@ unsafe fn auto_create_schema_impl(fcinfo: pg_sys::FunctionCallInfo) -> Result<()
     let preserve_casing = table_options
         .get("preserve_casing")
         .is_some_and(|s| s.eq_ignore_ascii_case("true"));
-    let alter_table_statement =
-        construct_alter_table_statement(schema_name, table_name, schema_rows, preserve_casing);
+    let geometry_types = postgis_column_types(handler, &table_options)?;
+    let type_overrides = type_overrides(&table_options)?;
+    let alter_table_statement = construct_alter_table_statement(
+        schema_name,
+        table_name,
+        schema_rows,
+        preserve_casing,
+        &geometry_types,
+        &type_overrides,
+    );
     Spi::run(alter_table_statement.as_str())?;
 
     Ok(())
 }
 
-#[inline]
-fn duckdb_type_to_pg(column_name: &str, duckdb_type: &str) -> Result<String> {
-    if duckdb_type == "INVALID" {
-        bail!("Column '{}' has an invalid DuckDB type", column_name);
+/// Returns the column names and DuckDB types of a registered DuckDB view
+pub(crate) fn describe_duckdb_view(
+    schema_name: &str,
+    table_name: &str,
+) -> Result<Vec<(String, String)>> {
+    let conn = unsafe { &*connection::get_global_connection().get() };
+    let query = format!("DESCRIBE {schema_name}.{table_name}");
+    let mut stmt = conn.prepare(&query)?;
+
+    Ok(stmt
+        .query_map([], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+        })?
+        .map(|row| row.unwrap())
+        .collect::<Vec<(String, String)>>())
+}
+
+/// Returns the PostGIS column type of each geometry column, e.g. geometry(MultiPolygon, 4326).
+/// The geometry type and SRID come from the layer metadata of spatial files.
+/// Without PostGIS, geometry columns keep their DuckDB mapping and no types are returned.
+pub(crate) fn postgis_column_types(
+    handler: FdwHandler,
+    table_options: &HashMap<String, String>,
+) -> Result<Option<HashMap<String, String>>> {
+    if !postgis_installed() {
+        return Ok(None);
     }
 
-    if duckdb_type.starts_with("MAP") {
-        bail!(
-            "Column '{}' has type MAP, which is not supported",
-            column_name
-        );
+    let mut geometry_types = HashMap::new();
+    let Some(files) = table_options.get("files") else {
+        return Ok(Some(geometry_types));
+    };
+    if handler != FdwHandler::Spatial {
+        return Ok(Some(geometry_types));
     }
 
-    if duckdb_type.starts_with("ENUM") {
-        bail!(
-            "Column '{}' has type ENUM, which is not supported",
-            column_name
-        );
+    // The first layer is read by st_read unless a layer is named
+    let layer = match table_options.get("layer") {
+        Some(layer) => format!(
+            "list_filter(layers, layer -> layer.name = '{}')[1]",
+            layer.replace('\'', "''")
+        ),
+        None => "layers[1]".to_string(),
+    };
+    let query = format!(
+        "SELECT field.name, field.type, field.crs.auth_name, field.crs.auth_code \
+         FROM (SELECT unnest({layer}.geometry_fields) AS field FROM st_read_meta('{}'))",
+        files.replace('\'', "''")
+    );
+
+    let conn = unsafe { &*connection::get_global_connection().get() };
+    let mut stmt = conn.prepare(&query)?;
+    let mut rows = stmt.query([])?;
+    while let Some(row) = rows.next()? {
+        let name = row.get::<_, Option<String>>(0)?.unwrap_or_default();
+        let geometry_type = row.get::<_, Option<String>>(1)?.unwrap_or_default();
+        let auth_name = row.get::<_, Option<String>>(2)?;
+        let auth_code = row.get::<_, Option<String>>(3)?;
+
+        // Only EPSG codes are SRIDs in the spatial_ref_sys table of PostGIS
+        let srid = match auth_name.as_deref() {
+            Some(auth_name) if auth_name.eq_ignore_ascii_case("EPSG") => {
+                auth_code.and_then(|code| code.parse::<i32>().ok())
+            }
+            _ => None,
+        };
+
+        // GDAL leaves the geometry field unnamed for most formats, and st_read names it geom
+        let name = match name.is_empty() {
+            true => "geom".to_string(),
+            false => name,
+        };
+        geometry_types.insert(name, postgis_column_type(&geometry_type, srid));
     }
 
-    if duckdb_type.starts_with("UNION") {
-        bail!(
-            "Column '{}' has type UNION, which is not supported",
-            column_name
-        );
+    Ok(Some(geometry_types))
+}
+
+#[inline]
+pub(crate) fn duckdb_type_to_pg(column_name: &str, duckdb_type: &str) -> Result<String> {
+    DuckDbType::parse(duckdb_type)?.to_postgres(column_name)
+}
+
+/// Parses the type_overrides table option, a JSON object from column names to Postgres types,
+/// e.g. {"id": "uuid", "payload": "jsonb"}. The types are returned as Postgres formats them,
+/// since they end up in ALTER TABLE statements.
+pub(crate) fn type_overrides(
+    table_options: &HashMap<String, String>,
+) -> Result<HashMap<String, String>> {
+    let Some(overrides) = table_options.get(TYPE_OVERRIDES_OPTION) else {
+        return Ok(HashMap::new());
+    };
+
+    match serde_json::from_str::<HashMap<String, String>>(overrides) {
+        Ok(overrides) => overrides
+            .into_iter()
+            .map(|(column_name, pg_type)| Ok((column_name, format_type_name(&pg_type)?)))
+            .collect(),
+        Err(err) => bail!(
+            "{} must be a JSON object of column names to Postgres types: {}",
+            TYPE_OVERRIDES_OPTION,
+            err
+        ),
     }
+}
 
-    if duckdb_type.starts_with("BIT") {
-        bail!(
-            "Column '{}' has type BIT, which is not supported",
-            column_name
+// parseTypeString only accepts a single type name, and raises an error for anything else
+fn format_type_name(pg_type: &str) -> Result<String> {
+    let type_name = CString::new(pg_type)?;
+    let mut type_oid = pg_sys::InvalidOid;
+    let mut typmod = -1;
+
+    unsafe {
+        #[cfg(any(feature = "pg13", feature = "pg14", feature = "pg15"))]
+        pg_sys::parseTypeString(type_name.as_ptr(), &mut type_oid, &mut typmod, false);
+        #[cfg(not(any(feature = "pg13", feature = "pg14", feature = "pg15")))]
+        pg_sys::parseTypeString(
+            type_name.as_ptr(),
+            &mut type_oid,
+            &mut typmod,
+            std::ptr::null_mut(),
         );
-    }
 
-    let mut postgres_type = duckdb_type
-        .replace("TINYINT", "SMALLINT")
-        .replace("UTINYINT", "SMALLINT")
-        .replace("USMALLINT", "INTEGER")
-        .replace("UINTEGER", "BIGINT")
-        .replace("UBIGINT", "NUMERIC")
-        .replace("HUGEINT", "NUMERIC")
-        .replace("UHUGEINT", "NUMERIC")
-        .replace("BLOB", "TEXT")
-        .replace("DOUBLE", "DOUBLE PRECISION")
-        .replace("TIMESTAMP_S", "TIMESTAMP")
-        .replace("TIMESTAMP_MS", "TIMESTAMP")
-        .replace("TIMESTAMP_NS", "TIMESTAMP")
-        .replace("TIME WITH TIME ZONE", "TIME");
-
-    // DuckDB spatial types conversions to PostgreSQL
-    // DuckDB-Spatial abstracts all geometric types as the GEOMETRY data type when
-    // running the st_read function, which is stored as a BLOB. Therefore, we can
-    // treat it the same way we handle BLOB data. Later, if users want to manipulate
-    // the geometry using PostGIS or other extensions, it should still be compatible.
-    postgres_type = postgres_type
-        .replace("GEOMETRY", "TEXT")
-        .replace("WKB_BLOB", "TEXT")
-        .replace("POINT_2D", "POINT")
-        .replace("POINT_3D", "POINT")
-        .replace("POINT_4D", "POINT")
-        .replace("BOX_2D", "BOX")
-        .replace("POLYGON_2D", "POLYGON")
-        .replace("LINESTRING_2D", "LINE");
-
-    if postgres_type.starts_with("STRUCT") {
-        postgres_type = "JSONB".to_string();
+        Ok(
+            CStr::from_ptr(pg_sys::format_type_with_typemod(type_oid, typmod))
+                .to_str()?
+                .to_string(),
+        )
     }
-
-    Ok(postgres_type)
 }
 
 #[inline]
@@ -268,12 +333,39 @@ fn construct_alter_table_statement(
     table_name: &str,
     columns: Vec<(String, String)>,
     preserve_casing: bool,
+    geometry_types: &Option<HashMap<String, String>>,
+    type_overrides: &HashMap<String, String>,
 ) -> String {
-    let column_definitions: Vec<String> = columns
+    let column_definitions: Vec<String> = construct_column_definitions(
+        &columns,
+        preserve_casing,
+        geometry_types.as_ref(),
+        type_overrides,
+    )
+    .expect("failed to convert DuckDB type")
+    .into_iter()
+    .map(|column_definition| format!("ADD COLUMN {}", column_definition))
+    .collect();
+
+    format!(
+        "ALTER TABLE {}.{} {}",
+        spi::quote_identifier(schema_name),
+        spi::quote_identifier(table_name),
+        column_definitions.join(", ")
+    )
+}
+
+/// Builds the Postgres column definitions for columns described by DuckDB
+pub(crate) fn construct_column_definitions(
+    columns: &[(String, String)],
+    preserve_casing: bool,
+    geometry_types: Option<&HashMap<String, String>>,
+    type_overrides: &HashMap<String, String>,
+) -> Result<Vec<String>> {
+    columns
         .iter()
         .map(|(column_name, duckdb_type)| {
-            let pg_type =
-                duckdb_type_to_pg(column_name, duckdb_type).expect("failed to convert DuckDB type");
+            let pg_type = column_pg_type(column_name, duckdb_type, geometry_types, type_overrides)?;
 
             let column_name = if preserve_casing {
                 spi::quote_identifier(column_name)
@@ -281,14 +373,41 @@ fn construct_alter_table_statement(
                 column_name.to_string()
             };
 
-            format!("ADD COLUMN {} {}", column_name, pg_type)
+            Ok(format!("{} {}", column_name, pg_type))
         })
-        .collect();
+        .collect()
+}
 
-    format!(
-        "ALTER TABLE {}.{} {}",
-        spi::quote_identifier(schema_name),
-        spi::quote_identifier(table_name),
-        column_definitions.join(", ")
-    )
+/// Returns the Postgres type of a column described by DuckDB. Overridden types win over the inferred
+/// ones. With PostGIS installed, geometries are read into geometry columns instead of text.
+pub(crate) fn column_pg_type(
+    column_name: &str,
+    duckdb_type: &str,
+    geometry_types: Option<&HashMap<String, String>>,
+    type_overrides: &HashMap<String, String>,
+) -> Result<String> {
+    match (type_override(type_overrides, column_name), geometry_types) {
+        (Some(pg_type), _) => Ok(pg_type.clone()),
+        (None, Some(geometry_types)) if duckdb_type == "GEOMETRY" || duckdb_type == "WKB_BLOB" => {
+            Ok(geometry_types
+                .get(column_name)
+                .cloned()
+                .unwrap_or_else(|| "geometry".to_string()))
+        }
+        _ => duckdb_type_to_pg(column_name, duckdb_type),
+    }
+}
+
+/// Returns the overridden Postgres type of a column. Names are matched exactly first, since
+/// DuckDB keeps the casing of the files, and then case-insensitively.
+pub(crate) fn type_override<'a>(
+    type_overrides: &'a HashMap<String, String>,
+    column_name: &str,
+) -> Option<&'a String> {
+    type_overrides.get(column_name).or_else(|| {
+        type_overrides
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(column_name))
+            .map(|(_, pg_type)| pg_type)
+    })
 }
@@ -0,0 +1,258 @@
+// Copyright (c) 2023-2025 Retake, Inc.
+//
+// This file is part of ParadeDB - Postgres for Search and Analytics
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <http://www.gnu.org/licenses/>.
+use pgrx::{GucContext, GucFlags, GucRegistry, GucSetting, PostgresGucEnum};
+use std::ffi::CStr;
+
+#[derive(PostgresGucEnum, Clone, Copy, PartialEq, Debug)]
+pub enum FallbackPolicy {
+    // emit a WARNING and run the query through Postgres
+    Warn,
+    // fail the query with the DuckDB error
+    Error,
+    // run the query through Postgres without reporting anything
+    Silent,
+    // write the DuckDB error to the server log and run the query through Postgres
+    Log,
+}
+
+impl FallbackPolicy {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            "warn" => Some(Self::Warn),
+            "error" => Some(Self::Error),
+            "silent" => Some(Self::Silent),
+            "log" => Some(Self::Log),
+            _ => None,
+        }
+    }
+}
+
+pub struct GucSettings {
+    // what to do when DuckDB cannot execute a pushed down query
+    pub fallback_policy: GucSetting<FallbackPolicy>,
+
+    // comma separated directory prefixes that foreign tables may read local files from
+    pub allowed_directories: GucSetting<Option<&'static CStr>>,
+
+    // role whose members may run arbitrary DuckDB SQL through duckdb_query
+    pub duckdb_query_role: GucSetting<Option<&'static CStr>>,
+
+    // local directory DuckDB installs its extensions from instead of the internet
+    pub extension_repository: GucSetting<Option<&'static CStr>>,
+
+    // comma separated DuckDB extensions loaded when the connection of a session is opened
+    pub preload_extensions: GucSetting<Option<&'static CStr>>,
+
+    // compare the columns of a foreign table with its files before each scan
+    pub schema_drift_check: GucSetting<bool>,
+
+    // let parallel plans scan foreign tables in workers, splitting parquet files among them
+    pub enable_parallel_foreign_scan: GucSetting<bool>,
+
+    // let Append run the foreign scans of its subplans concurrently
+    pub enable_async_foreign_scan: GucSetting<bool>,
+
+    // read one version of each Delta and Iceberg table for the whole of a REPEATABLE READ transaction
+    pub pin_lake_snapshots: GucSetting<bool>,
+
+    // keep the results of pushed down queries, and how long and how many megabytes of them
+    pub result_cache: GucSetting<bool>,
+    pub result_cache_ttl: GucSetting<i32>,
+    pub result_cache_size: GucSetting<i32>,
+
+    // read every column through get_cell, to compare release builds with the column converters
+    pub disable_column_converters: GucSetting<bool>,
+}
+
+impl GucSettings {
+    pub const fn new() -> Self {
+        Self {
+            fallback_policy: GucSetting::<FallbackPolicy>::new(FallbackPolicy::Warn),
+            allowed_directories: GucSetting::<Option<&'static CStr>>::new(None),
+            duckdb_query_role: GucSetting::<Option<&'static CStr>>::new(None),
+            extension_repository: GucSetting::<Option<&'static CStr>>::new(None),
+            preload_extensions: GucSetting::<Option<&'static CStr>>::new(Some(c"httpfs")),
+            schema_drift_check: GucSetting::<bool>::new(false),
+            enable_parallel_foreign_scan: GucSetting::<bool>::new(true),
+            enable_async_foreign_scan: GucSetting::<bool>::new(true),
+            pin_lake_snapshots: GucSetting::<bool>::new(false),
+            result_cache: GucSetting::<bool>::new(false),
+            result_cache_ttl: GucSetting::<i32>::new(300),
+            result_cache_size: GucSetting::<i32>::new(1024),
+            disable_column_converters: GucSetting::<bool>::new(false),
+        }
+    }
+
+    pub fn init(&self) {
+        GucRegistry::define_enum_guc(
+            "thdb.fallback_policy",
+            "Action taken when DuckDB cannot execute a pushed down query.",
+            "One of warn, error, silent or log. Every mode except error re-runs the query through Postgres.",
+            &self.fallback_policy,
+            GucContext::Userset,
+            GucFlags::default(),
+        );
+
+        GucRegistry::define_string_guc(
+            "thdb.allowed_directories",
+            "Directory prefixes that DuckDB is allowed to read local files from.",
+            "Comma separated list. When set, the local files of foreign tables and read_* calls must be inside these prefixes. Remote files are not affected.",
+            &self.allowed_directories,
+            GucContext::Suset,
+            GucFlags::default(),
+        );
+
+        GucRegistry::define_string_guc(
+            "thdb.duckdb_query_role",
+            "Role allowed to run DuckDB SQL with duckdb_query and duckdb_query_json.",
+            "Members of this role can read any file or bucket DuckDB can reach. When unset, only superusers can run these functions.",
+            &self.duckdb_query_role,
+            GucContext::Suset,
+            GucFlags::default(),
+        );
+
+        GucRegistry::define_string_guc(
+            "thdb.extension_repository",
+            "Local directory that DuckDB extensions are installed from.",
+            "A directory laid out like a DuckDB extension repository, for servers that can't download extensions. When unset, extensions are downloaded from the DuckDB repository. Read when the DuckDB connection of a session is opened.",
+            &self.extension_repository,
+            GucContext::Suset,
+            GucFlags::default(),
+        );
+
+        GucRegistry::define_string_guc(
+            "thdb.preload_extensions",
+            "DuckDB extensions loaded when the DuckDB connection of a session is opened.",
+            "Comma separated list. Extensions that can't be loaded are logged at DEBUG1, and the tables that need them fail when they are read. Other extensions are loaded on first use.",
+            &self.preload_extensions,
+            GucContext::Suset,
+            GucFlags::default(),
+        );
+
+        GucRegistry::define_bool_guc(
+            "thdb.schema_drift_check",
+            "Check foreign tables for schema drift before each scan.",
+            "Scans fail with the list of changes when the files dropped or retyped a column of the foreign table. The files are described again on every scan.",
+            &self.schema_drift_check,
+            GucContext::Userset,
+            GucFlags::default(),
+        );
+
+        GucRegistry::define_bool_guc(
+            "thdb.enable_parallel_foreign_scan",
+            "Allow foreign scans in parallel plans.",
+            "Workers run their own DuckDB scan. Parquet tables with more than one file get a partial path, and every process reads the files it claims next. Tables read with union_by_name are not split.",
+            &self.enable_parallel_foreign_scan,
+            GucContext::Userset,
+            GucFlags::default(),
+        );
+
+        GucRegistry::define_bool_guc(
+            "thdb.enable_async_foreign_scan",
+            "Allow foreign scans to run asynchronously under Append.",
+            "Every scan of a UNION ALL or partitioned table starts its DuckDB statement on its own connection when the executor starts, and Append returns rows from whichever is ready. Requires Postgres 14 or later and enable_async_append.",
+            &self.enable_async_foreign_scan,
+            GucContext::Userset,
+            GucFlags::default(),
+        );
+
+        GucRegistry::define_bool_guc(
+            "thdb.pin_lake_snapshots",
+            "Read the same version of Delta and Iceberg tables for the whole transaction.",
+            "In REPEATABLE READ and SERIALIZABLE transactions, the version of a lake table that is current when the transaction first reads it is read by every later scan and pushed down query. READ COMMITTED transactions always read the latest version. Delta tables are pinned by attaching them with PIN_SNAPSHOT, which needs a release of the DuckDB delta extension that supports it.",
+            &self.pin_lake_snapshots,
+            GucContext::Userset,
+            GucFlags::default(),
+        );
+
+        GucRegistry::define_bool_guc(
+            "thdb.result_cache",
+            "Reuse the results of pushed down queries.",
+            "Results are stored as Arrow IPC files in th_dbdm/result_cache under the data directory, keyed by the query, the session settings it depends on and the versions of the files it reads. Queries that call volatile functions are not cached.",
+            &self.result_cache,
+            GucContext::Userset,
+            GucFlags::default(),
+        );
+
+        GucRegistry::define_int_guc(
+            "thdb.result_cache_ttl",
+            "How long a cached result is reused.",
+            "Results older than this are removed the next time they are looked up.",
+            &self.result_cache_ttl,
+            1,
+            i32::MAX,
+            GucContext::Suset,
+            GucFlags::UNIT_S,
+        );
+
+        GucRegistry::define_int_guc(
+            "thdb.result_cache_size",
+            "Size of the result cache.",
+            "The oldest results are removed once the cache grows past this size. Results larger than the whole cache are not stored.",
+            &self.result_cache_size,
+            0,
+            i32::MAX,
+            GucContext::Suset,
+            GucFlags::UNIT_MB,
+        );
+
+        GucRegistry::define_bool_guc(
+            "thdb.disable_column_converters",
+            "Read query results without the column converters.",
+            "Every value is converted through get_cell, which matches the type pair for each value. Only meant to measure the converters against it.",
+            &self.disable_column_converters,
+            GucContext::Suset,
+            GucFlags::default(),
+        );
+    }
+
+    pub fn allowed_directories(&self) -> Vec<String> {
+        split_list(&self.allowed_directories)
+    }
+
+    pub fn extension_repository(&self) -> Option<String> {
+        self.extension_repository
+            .get()
+            .and_then(|value| value.to_str().ok())
+            .map(|value| value.trim().to_string())
+            .filter(|value| !value.is_empty())
+    }
+
+    pub fn preload_extensions(&self) -> Vec<String> {
+        split_list(&self.preload_extensions)
+    }
+}
+
+fn split_list(setting: &GucSetting<Option<&'static CStr>>) -> Vec<String> {
+    setting
+        .get()
+        .and_then(|value| value.to_str().ok())
+        .map(|value| {
+            value
+                .split(',')
+                .map(|item| item.trim().to_string())
+                .filter(|item| !item.is_empty())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+impl Default for GucSettings {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,976 @@
+// Copyright (c) 2023-2025 Retake, Inc.
+//
+// This file is part of ParadeDB - Postgres for Search and Analytics
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <http://www.gnu.org/licenses/>.
+use std::ops::ControlFlow;
+
+use sqlparser::ast::{
+    BinaryOperator, CastKind, CreateTable, DataType, Expr, Function, FunctionArg, FunctionArgExpr,
+    FunctionArgumentList, FunctionArguments, Ident, Insert, ObjectName, Query, SetExpr, Statement,
+    TableFactor, TimezoneInfo, UnaryOperator, Value, VisitMut, VisitorMut,
+};
+use sqlparser::dialect::{DuckDbDialect, PostgreSqlDialect};
+use sqlparser::parser::Parser;
+use thiserror::Error;
+
+use crate::fdw::handler::FdwHandler;
+use crate::fdw::read::{
+    read_function_handler, read_function_options, read_function_select, READ_FUNCTION_SCHEMA,
+};
+
+// Functions with the same semantics but a different name in DuckDB
+const RENAMED_FUNCTIONS: [(&str, &str); 7] = [
+    ("btrim", "trim"),
+    ("char_length", "length"),
+    ("character_length", "length"),
+    ("clock_timestamp", "get_current_timestamp"),
+    ("every", "bool_and"),
+    ("json_build_object", "json_object"),
+    ("jsonb_build_object", "json_object"),
+];
+
+// Functions that depend on Postgres state and have no DuckDB equivalent
+const POSTGRES_ONLY_FUNCTIONS: [&str; 9] = [
+    "nextval",
+    "currval",
+    "setval",
+    "lastval",
+    "set_config",
+    "txid_current",
+    "regexp_matches",
+    "has_table_privilege",
+    "has_column_privilege",
+];
+
+// Catalog types that only exist in Postgres
+const POSTGRES_ONLY_TYPES: [&str; 9] = [
+    "oid",
+    "regclass",
+    "regproc",
+    "regprocedure",
+    "regtype",
+    "regoper",
+    "regoperator",
+    "regnamespace",
+    "regrole",
+];
+
+// Postgres to_char patterns and their strftime equivalent, longest patterns first
+const TO_CHAR_PATTERNS: [(&str, &str, bool); 20] = [
+    ("HH24", "H", true),
+    ("HH12", "I", true),
+    ("YYYY", "Y", false),
+    ("Month", "B", false),
+    ("DDD", "j", true),
+    ("Mon", "b", false),
+    ("Day", "A", false),
+    ("HH", "I", true),
+    ("MI", "M", true),
+    ("SS", "S", true),
+    ("MS", "g", false),
+    ("US", "f", false),
+    ("YY", "y", false),
+    ("MM", "m", true),
+    ("DD", "d", true),
+    ("Dy", "a", false),
+    ("AM", "p", false),
+    ("PM", "p", false),
+    ("TZ", "Z", false),
+    ("OF", "z", false),
+];
+
+/// Values of the Postgres session that DuckDB cannot know about.
+/// Role and time functions are replaced with these values so both execution paths agree.
+#[derive(Clone, Debug, Default)]
+pub struct SessionContext {
+    pub current_user: Option<String>,
+    pub session_user: Option<String>,
+    // UTC timestamps, e.g. 2024-01-01 00:00:00.000000+00
+    pub transaction_timestamp: Option<String>,
+    pub statement_timestamp: Option<String>,
+    // Set when DateStyle or IntervalStyle would make text output differ from DuckDB
+    pub text_style: Option<String>,
+}
+
+#[derive(Error, Debug, PartialEq)]
+pub enum TranslateError {
+    #[error("query could not be parsed: {0}")]
+    Parse(String),
+
+    #[error("query cannot be translated to DuckDB: {0}")]
+    Unsupported(String),
+}
+
+/// Rewrites Postgres-only functions, casts and operators into their DuckDB equivalents.
+/// The original query text is returned untouched if nothing needed to be rewritten.
+pub fn translate_query(query: &str, context: &SessionContext) -> Result<String, TranslateError> {
+    let mut statements = Parser::parse_sql(&PostgreSqlDialect {}, query)
+        .map_err(|err| TranslateError::Parse(err.to_string()))?;
+
+    if statements.len() != 1 {
+        return Ok(query.to_string());
+    }
+
+    let mut translator = DuckDbTranslator {
+        changed: false,
+        context,
+    };
+    let statement: &mut Statement = &mut statements[0];
+
+    if let ControlFlow::Break(reason) = statement.visit(&mut translator) {
+        return Err(TranslateError::Unsupported(reason));
+    }
+
+    if translator.changed {
+        Ok(statement.to_string())
+    } else {
+        Ok(query.to_string())
+    }
+}
+
+/// Returns the SELECT that feeds a CREATE TABLE AS, CREATE MATERIALIZED VIEW or SELECT INTO statement.
+pub fn into_rel_source_query(query: &str) -> Option<String> {
+    let mut statements = Parser::parse_sql(&PostgreSqlDialect {}, query).ok()?;
+    if statements.len() != 1 {
+        return None;
+    }
+
+    match statements.remove(0) {
+        Statement::CreateTable(CreateTable {
+            query: Some(source),
+            ..
+        }) => Some(source.to_string()),
+        Statement::CreateView {
+            materialized: true,
+            query: source,
+            ..
+        } => Some(source.to_string()),
+        Statement::Query(mut source) => match source.body.as_mut() {
+            SetExpr::Select(select) if select.into.is_some() => {
+                select.into = None;
+                Some(source.to_string())
+            }
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Returns the target column names and the SELECT of an INSERT ... SELECT statement.
+/// Statements with ON CONFLICT or RETURNING need the Postgres executor and are not returned.
+pub fn insert_source_query(query: &str) -> Option<(Vec<String>, String)> {
+    let mut statements = Parser::parse_sql(&PostgreSqlDialect {}, query).ok()?;
+    if statements.len() != 1 {
+        return None;
+    }
+
+    match statements.remove(0) {
+        Statement::Insert(Insert {
+            columns,
+            source: Some(source),
+            on: None,
+            returning: None,
+            ..
+        }) if !matches!(source.body.as_ref(), SetExpr::Values(_)) => {
+            // Unquoted identifiers are folded to lower case by Postgres
+            let columns = columns
+                .into_iter()
+                .map(|column| match column.quote_style {
+                    Some(_) => column.value,
+                    None => column.value.to_lowercase(),
+                })
+                .collect();
+            Some((columns, source.to_string()))
+        }
+        _ => None,
+    }
+}
+
+struct DuckDbTranslator<'a> {
+    changed: bool,
+    context: &'a SessionContext,
+}
+
+impl VisitorMut for DuckDbTranslator<'_> {
+    type Break = String;
+
+    fn post_visit_table_factor(&mut self, table_factor: &mut TableFactor) -> ControlFlow<String> {
+        let mut derived = None;
+
+        if let TableFactor::Table {
+            name,
+            args: Some(table_args),
+            alias,
+            ..
+        } = table_factor
+        {
+            self.changed |= strip_pg_catalog(name);
+
+            if function_name(name) == "generate_series" {
+                self.changed |= fix_generate_series_args(&mut table_args.args);
+            }
+
+            // th_dbdm.read_* calls become the DuckDB query they would run, keeping the alias without column types
+            if let Some(handler) = read_function(name) {
+                let mut alias = alias.clone();
+                if let Some(alias) = alias.as_mut() {
+                    alias
+                        .columns
+                        .iter_mut()
+                        .for_each(|column| column.data_type = None);
+                }
+                derived = Some(TableFactor::Derived {
+                    lateral: false,
+                    subquery: read_function_subquery(handler, &table_args.args)?,
+                    alias,
+                });
+            }
+        }
+
+        if let Some(derived) = derived {
+            *table_factor = derived;
+            self.changed = true;
+        }
+
+        ControlFlow::Continue(())
+    }
+
+    fn post_visit_expr(&mut self, expr: &mut Expr) -> ControlFlow<String> {
+        let translated = match expr {
+            Expr::Function(function) => translate_function(function, self.context)?,
+            Expr::Cast { data_type, .. } => {
+                if let (true, Some(style)) = (is_text_type(data_type), &self.context.text_style) {
+                    return ControlFlow::Break(format!(
+                        "casting to text with {style} does not match DuckDB output"
+                    ));
+                }
+                self.changed |= translate_cast(data_type)?;
+                None
+            }
+            Expr::BinaryOp { left, op, right } => translate_binary_op(left, op, right),
+            Expr::ILike {
+                any: true, pattern, ..
+            } => match array_elements(pattern) {
+                Some(patterns) => Some(expand_ilike_any(expr, patterns)),
+                None => {
+                    return ControlFlow::Break(
+                        "ILIKE ANY is only supported with an array literal".to_string(),
+                    )
+                }
+            },
+            _ => None,
+        };
+
+        if let Some(translated) = translated {
+            *expr = translated;
+            self.changed = true;
+        }
+
+        ControlFlow::Continue(())
+    }
+}
+
+fn translate_function(
+    function: &mut Function,
+    context: &SessionContext,
+) -> ControlFlow<String, Option<Expr>> {
+    let stripped = strip_pg_catalog(&mut function.name);
+    let name = function_name(&function.name);
+
+    if let Some(expr) = session_value(&name, context) {
+        return ControlFlow::Continue(Some(expr));
+    }
+
+    if POSTGRES_ONLY_FUNCTIONS.contains(&name.as_str())
+        || (name.starts_with("pg_") && name != "pg_typeof")
+    {
+        return ControlFlow::Break(format!("function {name} depends on the Postgres catalog"));
+    }
+
+    if let Some((_, duckdb_name)) = RENAMED_FUNCTIONS.iter().find(|(pg, _)| *pg == name) {
+        function.name = ObjectName(vec![Ident::new(*duckdb_name)]);
+        return ControlFlow::Continue(Some(Expr::Function(function.clone())));
+    }
+
+    let args = function_args(function);
+    let translated = match (name.as_str(), args.as_slice()) {
+        ("to_char", [value, Expr::Value(Value::SingleQuotedString(format))]) => {
+            let format = translate_to_char_format(format)?;
+            Some(function_call(
+                "strftime",
+                vec![
+                    value.clone(),
+                    Expr::Value(Value::SingleQuotedString(format)),
+                ],
+            ))
+        }
+        ("to_char", _) => {
+            return ControlFlow::Break("to_char requires a constant format".to_string());
+        }
+        ("age", [value]) => Some(function_call(
+            "age",
+            vec![
+                cast(
+                    function_call("today", vec![]),
+                    DataType::Timestamp(None, TimezoneInfo::None),
+                ),
+                value.clone(),
+            ],
+        )),
+        ("array_length", [array, Expr::Value(Value::Number(dimension, _))]) => {
+            if dimension != "1" {
+                return ControlFlow::Break(
+                    "array_length is only supported for the first dimension".to_string(),
+                );
+            }
+            Some(function_call("len", vec![array.clone()]))
+        }
+        ("string_to_array", [value, delimiter]) => Some(function_call(
+            "string_split",
+            vec![value.clone(), delimiter.clone()],
+        )),
+        ("string_to_array", _) => {
+            return ControlFlow::Break(
+                "string_to_array with a null string argument is not supported".to_string(),
+            );
+        }
+        ("generate_series", _) => {
+            // DuckDB returns a list from generate_series outside of the FROM clause
+            if let FunctionArguments::List(list) = &mut function.args {
+                fix_generate_series_args(&mut list.args);
+            }
+            Some(function_call(
+                "unnest",
+                vec![Expr::Function(function.clone())],
+            ))
+        }
+        ("percentile_cont" | "percentile_disc" | "mode", _) if function.filter.is_some() => {
+            Some(translate_filtered_ordered_set(function, &name, &args)?)
+        }
+        _ => None,
+    };
+
+    match translated {
+        Some(expr) => ControlFlow::Continue(Some(expr)),
+        None if stripped => ControlFlow::Continue(Some(Expr::Function(function.clone()))),
+        None => ControlFlow::Continue(None),
+    }
+}
+
+fn session_value(name: &str, context: &SessionContext) -> Option<Expr> {
+    let string = |value: &Option<String>| {
+        value
+            .as_ref()
+            .map(|value| Expr::Value(Value::SingleQuotedString(value.clone())))
+    };
+    let timestamptz = |value: &Option<String>| {
+        string(value).map(|value| cast(value, DataType::Timestamp(None, TimezoneInfo::Tz)))
+    };
+
+    // The DuckDB TimeZone is synced with Postgres, so local values are derived from the same instant
+    match name {
+        "current_user" | "current_role" | "user" => string(&context.current_user),
+        "session_user" => string(&context.session_user),
+        "now" | "current_timestamp" | "transaction_timestamp" => {
+            timestamptz(&context.transaction_timestamp)
+        }
+        "statement_timestamp" => timestamptz(&context.statement_timestamp),
+        "current_date" => {
+            timestamptz(&context.transaction_timestamp).map(|value| cast(value, DataType::Date))
+        }
+        "localtimestamp" => timestamptz(&context.transaction_timestamp)
+            .map(|value| cast(value, DataType::Timestamp(None, TimezoneInfo::None))),
+        _ => None,
+    }
+}
+
+// DuckDB rejects FILTER on ordered-set aggregates, so the filter is folded into the
+// aggregated value instead. Aggregates skip the NULLs produced for filtered out rows.
+fn translate_filtered_ordered_set(
+    function: &Function,
+    name: &str,
+    args: &[Expr],
+) -> ControlFlow<String, Expr> {
+    let [order_by] = function.within_group.as_slice() else {
+        return ControlFlow::Break(format!("{name} requires a single WITHIN GROUP column"));
+    };
+    if order_by.asc == Some(false) {
+        return ControlFlow::Break(format!(
+            "{name} with a descending WITHIN GROUP order is not supported"
+        ));
+    }
+
+    let Some(filter) = function.filter.clone() else {
+        return ControlFlow::Break(format!("{name} requires a FILTER clause"));
+    };
+    let value = Expr::Case {
+        operand: None,
+        conditions: vec![*filter],
+        results: vec![order_by.expr.clone()],
+        else_result: None,
+    };
+
+    let expr = match (name, args) {
+        ("percentile_cont", [fraction]) => {
+            function_call("quantile_cont", vec![value, fraction.clone()])
+        }
+        ("percentile_disc", [fraction]) => {
+            function_call("quantile_disc", vec![value, fraction.clone()])
+        }
+        ("mode", []) => function_call("mode", vec![value]),
+        _ => return ControlFlow::Break(format!("unexpected arguments to {name}")),
+    };
+
+    ControlFlow::Continue(expr)
+}
+
+// Returns whether the data type was rewritten in place
+fn translate_cast(data_type: &mut DataType) -> ControlFlow<String, bool> {
+    match data_type {
+        DataType::Regclass => {
+            ControlFlow::Break("cast to regclass depends on the Postgres catalog".to_string())
+        }
+        DataType::JSONB => {
+            *data_type = DataType::JSON;
+            ControlFlow::Continue(true)
+        }
+        DataType::Custom(name, modifiers) => {
+            let stripped = strip_pg_catalog(name);
+            let type_name = function_name(name);
+
+            if POSTGRES_ONLY_TYPES.contains(&type_name.as_str()) {
+                return ControlFlow::Break(format!(
+                    "cast to {type_name} depends on the Postgres catalog"
+                ));
+            }
+
+            if type_name == "name" && modifiers.is_empty() {
+                *data_type = DataType::Varchar(None);
+                return ControlFlow::Continue(true);
+            }
+
+            ControlFlow::Continue(stripped)
+        }
+        _ => ControlFlow::Continue(false),
+    }
+}
+
+fn translate_binary_op(left: &Expr, op: &BinaryOperator, right: &Expr) -> Option<Expr> {
+    // Postgres regex operators match anywhere in the string, DuckDB's ~ matches the full string
+    let (case_insensitive, negated) = match op {
+        BinaryOperator::PGRegexMatch => (false, false),
+        BinaryOperator::PGRegexIMatch => (true, false),
+        BinaryOperator::PGRegexNotMatch => (false, true),
+        BinaryOperator::PGRegexNotIMatch => (true, true),
+        _ => return None,
+    };
+
+    let mut args = vec![left.clone(), right.clone()];
+    if case_insensitive {
+        args.push(Expr::Value(Value::SingleQuotedString("i".to_string())));
+    }
+
+    let matches = function_call("regexp_matches", args);
+    if negated {
+        Some(Expr::UnaryOp {
+            op: UnaryOperator::Not,
+            expr: Box::new(matches),
+        })
+    } else {
+        Some(matches)
+    }
+}
+
+fn expand_ilike_any(expr: &Expr, patterns: Vec<Expr>) -> Expr {
+    let mut conditions = patterns.into_iter().map(|pattern| {
+        let mut condition = expr.clone();
+        if let Expr::ILike {
+            any, pattern: p, ..
+        } = &mut condition
+        {
+            *any = false;
+            *p = Box::new(pattern);
+        }
+        condition
+    });
+
+    let first = conditions
+        .next()
+        .unwrap_or(Expr::Value(Value::Boolean(false)));
+    let disjunction = conditions.fold(first, |acc, condition| Expr::BinaryOp {
+        left: Box::new(acc),
+        op: BinaryOperator::Or,
+        right: Box::new(condition),
+    });
+
+    Expr::Nested(Box::new(disjunction))
+}
+
+fn array_elements(expr: &Expr) -> Option<Vec<Expr>> {
+    match expr {
+        Expr::Nested(inner) => array_elements(inner),
+        Expr::Array(array) => Some(array.elem.clone()),
+        Expr::Cast { expr, .. } => array_elements(expr),
+        // Postgres array literals, e.g. '{foo%,bar%}'
+        Expr::Value(Value::SingleQuotedString(literal)) => {
+            let inner = literal.strip_prefix('{')?.strip_suffix('}')?;
+            Some(
+                inner
+                    .split(',')
+                    .map(|element| {
+                        Expr::Value(Value::SingleQuotedString(
+                            element.trim().trim_matches('"').to_string(),
+                        ))
+                    })
+                    .collect(),
+            )
+        }
+        _ => None,
+    }
+}
+
+// generate_series over dates or untyped literals needs explicit timestamp and interval types in DuckDB
+fn fix_generate_series_args(args: &mut [FunctionArg]) -> bool {
+    let [start, stop, step] = args else {
+        return false;
+    };
+
+    let (is_interval_step, untyped_step) = match unnamed_arg_expr(step) {
+        Some(Expr::Value(Value::SingleQuotedString(_))) => (true, true),
+        Some(Expr::Interval(_))
+        | Some(Expr::Cast {
+            data_type: DataType::Interval,
+            ..
+        }) => (true, false),
+        _ => (false, false),
+    };
+
+    if !is_interval_step {
+        return false;
+    }
+
+    if untyped_step {
+        if let Some(expr) = unnamed_arg_expr_mut(step) {
+            *expr = cast(expr.clone(), DataType::Interval);
+        }
+    }
+
+    for bound in [start, stop] {
+        if let Some(expr) = unnamed_arg_expr_mut(bound) {
+            let needs_cast = matches!(
+                expr,
+                Expr::Value(Value::SingleQuotedString(_))
+                    | Expr::Cast {
+                        data_type: DataType::Date,
+                        ..
+                    }
+            );
+            if needs_cast {
+                *expr = cast(expr.clone(), DataType::Timestamp(None, TimezoneInfo::None));
+            }
+        }
+    }
+
+    true
+}
+
+pub fn translate_to_char_format(format: &str) -> ControlFlow<String, String> {
+    let mut translated = String::new();
+    let mut rest = format;
+
+    while let Some(c) = rest.chars().next() {
+        // Quoted text is copied as is
+        if c == '"' {
+            let end = rest[1..].find('"').map(|i| i + 1).unwrap_or(rest.len());
+            translated.push_str(&rest[1..end].replace('%', "%%"));
+            rest = rest.get(end + 1..).unwrap_or_default();
+            continue;
+        }
+
+        let (fill_mode, candidate) = match rest.strip_prefix("FM") {
+            Some(stripped) => (true, stripped),
+            None => (false, rest),
+        };
+
+        if let Some((pattern, specifier, numeric)) = TO_CHAR_PATTERNS
+            .iter()
+            .find(|(pattern, _, _)| candidate.starts_with(pattern))
+        {
+            translated.push('%');
+            if fill_mode && *numeric {
+                translated.push('-');
+            }
+            translated.push_str(specifier);
+            rest = &candidate[pattern.len()..];
+            continue;
+        }
+
+        if c == '9' || c == '0' {
+            return ControlFlow::Break(
+                "to_char with a numeric format is not supported".to_string(),
+            );
+        }
+        if c.is_ascii_alphabetic() {
+            return ControlFlow::Break(format!("to_char format {format} is not supported"));
+        }
+
+        if c == '%' {
+            translated.push('%');
+        }
+        translated.push(c);
+        rest = &rest[c.len_utf8()..];
+    }
+
+    ControlFlow::Continue(translated)
+}
+
+fn is_text_type(data_type: &DataType) -> bool {
+    match data_type {
+        DataType::Text | DataType::Varchar(_) | DataType::String(_) => true,
+        DataType::Custom(name, _) => function_name(name) == "text",
+        _ => false,
+    }
+}
+
+fn read_function(name: &ObjectName) -> Option<FdwHandler> {
+    match name.0.as_slice() {
+        [schema, function] if schema.value.eq_ignore_ascii_case(READ_FUNCTION_SCHEMA) => {
+            read_function_handler(function.value.to_lowercase().as_str())
+        }
+        _ => None,
+    }
+}
+
+fn read_function_subquery(
+    handler: FdwHandler,
+    args: &[FunctionArg],
+) -> ControlFlow<String, Box<Query>> {
+    // files, options and server, in the order of the function signature
+    let mut values: [Option<String>; 3] = [None, None, None];
+
+    for (position, arg) in args.iter().enumerate() {
+        let (index, expr) = match arg {
+            FunctionArg::Unnamed(FunctionArgExpr::Expr(expr)) => (position, expr),
+            FunctionArg::Named {
+                name,
+                arg: FunctionArgExpr::Expr(expr),
+                ..
+            } => match name.value.to_lowercase().as_str() {
+                "files" => (0, expr),
+                "options" => (1, expr),
+                "server" => (2, expr),
+                other => return ControlFlow::Break(format!("unknown argument {other}")),
+            },
+            _ => return ControlFlow::Break("unsupported read function argument".to_string()),
+        };
+
+        match (values.get_mut(index), string_literal(expr)) {
+            (Some(value), Some(literal)) => *value = literal,
+            (None, _) => return ControlFlow::Break("too many read function arguments".to_string()),
+            (_, None) => {
+                return ControlFlow::Break("read function arguments must be constants".to_string())
+            }
+        }
+    }
+
+    let [Some(files), options, _] = values else {
+        return ControlFlow::Break("files cannot be null".to_string());
+    };
+
+    let select = options
+        .map(|options| serde_json::from_str::<serde_json::Value>(&options))
+        .transpose()
+        .map_err(anyhow::Error::from)
+        .and_then(|options| read_function_options(handler, &files, options.as_ref()))
+        .and_then(|table_options| read_function_select(handler, table_options));
+    let select = match select {
+        Ok(select) => select,
+        Err(err) => return ControlFlow::Break(err.to_string()),
+    };
+
+    match Parser::parse_sql(&DuckDbDialect {}, &select).as_deref() {
+        Ok([Statement::Query(query)]) => ControlFlow::Continue(query.clone()),
+        _ => ControlFlow::Break(format!("could not parse {select}")),
+    }
+}
+
+// Some(None) is a NULL literal, and None is anything that isn't a constant string
+fn string_literal(expr: &Expr) -> Option<Option<String>> {
+    match expr {
+        Expr::Value(Value::SingleQuotedString(value)) => Some(Some(value.clone())),
+        Expr::Value(Value::DollarQuotedString(value)) => Some(Some(value.value.clone())),
+        Expr::Value(Value::Null) => Some(None),
+        Expr::Cast { expr, .. } | Expr::Nested(expr) => string_literal(expr),
+        _ => None,
+    }
+}
+
+fn strip_pg_catalog(name: &mut ObjectName) -> bool {
+    if name.0.len() == 2 && name.0[0].value.eq_ignore_ascii_case("pg_catalog") {
+        name.0.remove(0);
+        true
+    } else {
+        false
+    }
+}
+
+fn function_name(name: &ObjectName) -> String {
+    name.0
+        .last()
+        .map(|ident| ident.value.to_lowercase())
+        .unwrap_or_default()
+}
+
+fn function_args(function: &Function) -> Vec<Expr> {
+    match &function.args {
+        FunctionArguments::List(list) => list
+            .args
+            .iter()
+            .filter_map(|arg| unnamed_arg_expr(arg).cloned())
+            .collect(),
+        _ => vec![],
+    }
+}
+
+fn unnamed_arg_expr(arg: &FunctionArg) -> Option<&Expr> {
+    match arg {
+        FunctionArg::Unnamed(FunctionArgExpr::Expr(expr)) => Some(expr),
+        _ => None,
+    }
+}
+
+fn unnamed_arg_expr_mut(arg: &mut FunctionArg) -> Option<&mut Expr> {
+    match arg {
+        FunctionArg::Unnamed(FunctionArgExpr::Expr(expr)) => Some(expr),
+        _ => None,
+    }
+}
+
+fn function_call(name: &str, args: Vec<Expr>) -> Expr {
+    Expr::Function(Function {
+        name: ObjectName(vec![Ident::new(name)]),
+        parameters: FunctionArguments::None,
+        args: FunctionArguments::List(FunctionArgumentList {
+            duplicate_treatment: None,
+            args: args
+                .into_iter()
+                .map(|arg| FunctionArg::Unnamed(FunctionArgExpr::Expr(arg)))
+                .collect(),
+            clauses: vec![],
+        }),
+        filter: None,
+        null_treatment: None,
+        over: None,
+        within_group: vec![],
+    })
+}
+
+fn cast(expr: Expr, data_type: DataType) -> Expr {
+    Expr::Cast {
+        kind: CastKind::Cast,
+        expr: Box::new(expr),
+        data_type,
+        format: None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use duckdb::Connection;
+
+    fn assert_runs_in_duckdb(query: &str) {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch("CREATE TABLE t (s VARCHAR, ts TIMESTAMP, v DOUBLE)")
+            .unwrap();
+        let translated = translate_query(query, &SessionContext::default()).unwrap();
+        conn.prepare(&translated)
+            .unwrap_or_else(|err| panic!("{translated}: {err}"));
+    }
+
+    #[test]
+    fn test_untouched_query() {
+        let query = "SELECT  s FROM t WHERE v > 1";
+        assert_eq!(
+            translate_query(query, &SessionContext::default()).unwrap(),
+            query
+        );
+    }
+
+    #[test]
+    fn test_to_char_format() {
+        assert_eq!(
+            translate_to_char_format("YYYY-MM-DD HH24:MI:SS"),
+            ControlFlow::Continue("%Y-%m-%d %H:%M:%S".to_string())
+        );
+        assert_eq!(
+            translate_to_char_format("FMDD Mon YYYY \"at\" HH12 AM"),
+            ControlFlow::Continue("%-d %b %Y at %I %p".to_string())
+        );
+        assert!(translate_to_char_format("999D99").is_break());
+        assert!(translate_to_char_format("IYYY-IW").is_break());
+    }
+
+    #[test]
+    fn test_translate_functions() {
+        assert_runs_in_duckdb("SELECT to_char(ts, 'YYYY-MM-DD') FROM t");
+        assert_runs_in_duckdb("SELECT age(ts) FROM t");
+        assert_runs_in_duckdb("SELECT pg_catalog.upper(s), btrim(s) FROM t");
+        assert_runs_in_duckdb("SELECT array_length(string_to_array(s, ','), 1) FROM t");
+        assert_runs_in_duckdb(
+            "SELECT * FROM generate_series('2024-01-01'::date, '2024-01-31'::date, '1 day')",
+        );
+        assert_runs_in_duckdb(
+            "SELECT percentile_cont(0.5) WITHIN GROUP (ORDER BY v) FILTER (WHERE v > 0) FROM t",
+        );
+    }
+
+    #[test]
+    fn test_translate_operators() {
+        assert_runs_in_duckdb("SELECT * FROM t WHERE s ~ '^a' AND s !~* 'B$'");
+        assert_runs_in_duckdb("SELECT * FROM t WHERE s ILIKE ANY (ARRAY['a%', 'b%'])");
+        assert_runs_in_duckdb("SELECT s::pg_catalog.text, '{}'::jsonb FROM t");
+    }
+
+    #[test]
+    fn test_untranslatable() {
+        assert_eq!(
+            translate_query("SELECT 't'::regclass", &SessionContext::default()),
+            Err(TranslateError::Unsupported(
+                "cast to regclass depends on the Postgres catalog".to_string()
+            ))
+        );
+        assert_eq!(
+            translate_query(
+                "SELECT s FROM t WHERE pg_backend_pid() > 0",
+                &SessionContext::default()
+            ),
+            Err(TranslateError::Unsupported(
+                "function pg_backend_pid depends on the Postgres catalog".to_string()
+            ))
+        );
+        assert!(matches!(
+            translate_query(
+                "SELECT to_char(v, '999D99') FROM t",
+                &SessionContext::default()
+            ),
+            Err(TranslateError::Unsupported(_))
+        ));
+    }
+
+    #[test]
+    fn test_source_queries() {
+        assert_eq!(
+            into_rel_source_query("CREATE TABLE s AS SELECT a, count(*) FROM t GROUP BY a"),
+            Some("SELECT a, count(*) FROM t GROUP BY a".to_string())
+        );
+        assert_eq!(
+            into_rel_source_query("SELECT a INTO s FROM t"),
+            Some("SELECT a FROM t".to_string())
+        );
+        assert_eq!(into_rel_source_query("SELECT a FROM t"), None);
+
+        assert_eq!(
+            insert_source_query("INSERT INTO s (A, \"B\") SELECT a, b FROM t"),
+            Some((
+                vec!["a".to_string(), "B".to_string()],
+                "SELECT a, b FROM t".to_string()
+            ))
+        );
+        assert_eq!(insert_source_query("INSERT INTO s VALUES (1)"), None);
+        assert_eq!(
+            insert_source_query("INSERT INTO s SELECT a FROM t RETURNING a"),
+            None
+        );
+    }
+
+    #[test]
+    fn test_session_values() {
+        let context = SessionContext {
+            current_user: Some("alice".to_string()),
+            session_user: Some("bob".to_string()),
+            transaction_timestamp: Some("2024-03-10 12:00:00.000000+00".to_string()),
+            statement_timestamp: Some("2024-03-10 12:00:01.000000+00".to_string()),
+            text_style: None,
+        };
+
+        assert_eq!(
+            translate_query("SELECT current_user, session_user", &context).unwrap(),
+            "SELECT 'alice', 'bob'"
+        );
+
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch("SET TimeZone = 'Asia/Shanghai'")
+            .unwrap();
+        let translated = translate_query(
+            "SELECT current_date::text, date_part('hour', localtimestamp)::int",
+            &context,
+        )
+        .unwrap();
+        let (date, hour): (String, i32) = conn
+            .query_row(&translated, [], |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap();
+        assert_eq!(date, "2024-03-10");
+        assert_eq!(hour, 20);
+    }
+
+    #[test]
+    fn test_text_style() {
+        let context = SessionContext {
+            text_style: Some("DateStyle SQL, DMY".to_string()),
+            ..Default::default()
+        };
+
+        assert!(matches!(
+            translate_query("SELECT s::text FROM t", &context),
+            Err(TranslateError::Unsupported(_))
+        ));
+        assert!(translate_query("SELECT s FROM t", &context).is_ok());
+    }
+
+    #[test]
+    fn test_read_functions() {
+        let translated = translate_query(
+            r#"SELECT COUNT(*) FROM th_dbdm.read_parquet('/data/trips.parquet', '{"hive_partitioning": true}'::jsonb, 's3_server') AS t(n int)"#,
+            &SessionContext::default(),
+        )
+        .unwrap();
+        assert!(!translated.contains("th_dbdm"), "{translated}");
+        assert!(
+            translated.contains(
+                "(SELECT * FROM read_parquet('/data/trips.parquet', hive_partitioning = true)) AS t (n)"
+            ),
+            "{translated}"
+        );
+
+        let translated = translate_query(
+            "SELECT * FROM th_dbdm.read_csv(files => '/data/a.csv, /data/b.csv') AS t(a text)",
+            &SessionContext::default(),
+        )
+        .unwrap();
+        assert!(
+            translated.contains("read_csv(['/data/a.csv', '/data/b.csv'])"),
+            "{translated}"
+        );
+
+        assert!(matches!(
+            translate_query(
+                "SELECT * FROM th_dbdm.read_parquet(s) AS t(a text), t2",
+                &SessionContext::default()
+            ),
+            Err(TranslateError::Unsupported(_))
+        ));
+    }
+}
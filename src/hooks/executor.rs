@@ -18,9 +18,18 @@
 use anyhow::Result;
 use pgrx::*;
 use std::ffi::CStr;
+use std::time::Instant;
 
 use crate::duckdb::connection;
+use crate::fdw::drift::check_schema_drift;
+use crate::fdw::read::prepare_read_function;
+use crate::fdw::snapshot::pin_snapshot;
+use crate::result_cache;
+use crate::stats;
+use crate::GUCS;
 
+use super::dialect::{into_rel_source_query, translate_query, TranslateError};
+use super::insert::insert_select;
 use super::query::*;
 
 #[cfg(debug_assertions)]
@@ -46,32 +55,136 @@ pub async fn executor_run(
         return Ok(());
     }
 
+    // Workers run a part of a plan the leader chose not to push down
+    if unsafe { pg_sys::ParallelWorkerNumber } >= 0 {
+        prev_hook(query_desc, direction, count, execute_once);
+        return Ok(());
+    }
+
     let ps = query_desc.plannedstmt;
     let rtable = unsafe { (*ps).rtable };
     let query = get_current_query(ps, unsafe { CStr::from_ptr(query_desc.sourceText) })?;
+
+    if !rtable.is_null() && query_desc.operation == pg_sys::CmdType::CMD_INSERT {
+        if !insert_select(&query_desc, query.as_str())? {
+            prev_hook(query_desc, direction, count, execute_once);
+        }
+        return Ok(());
+    }
+
     let query_relations = get_query_relations(unsafe { (*ps).rtable });
-    let is_duckdb_query = is_duckdb_query(&query_relations);
+    let read_function_calls = get_read_function_calls(unsafe { (*ps).rtable })?;
+    // Queries that read only from th_dbdm.read_* functions have no foreign tables
+    let is_duckdb_query = match query_relations.is_empty() {
+        true => !read_function_calls.is_empty(),
+        false => is_duckdb_query(&query_relations),
+    };
+
+    // CREATE TABLE AS and SELECT INTO hand the result to the new table through an IntoRel receiver
+    let is_into_rel = unsafe { (*query_desc.dest).mydest } == pg_sys::CommandDest::DestIntoRel;
 
     if rtable.is_null()
         || query_desc.operation != pg_sys::CmdType::CMD_SELECT
         || !is_duckdb_query
+        || has_postgis_attributes(query_desc.tupDesc)
+        || reads_postgis_columns(ps)
+        || query_relations.iter().any(has_timezone_option)
         // Tech Debt: Find a less hacky way to let COPY/CREATE go through
         || query.to_lowercase().starts_with("copy")
-        || query.to_lowercase().starts_with("create")
+        || (query.to_lowercase().starts_with("create") && !is_into_rel)
         || query.to_lowercase().starts_with("prepare")
     {
         prev_hook(query_desc, direction, count, execute_once);
         return Ok(());
     }
 
+    check_relations_file_access(&query_relations)?;
+    // Pushed down queries skip the foreign scan, so the drift check of begin_scan is repeated here
+    if GUCS.schema_drift_check.get() {
+        for relation in query_relations
+            .iter()
+            .filter(|relation| relation.is_foreign_table())
+        {
+            check_schema_drift(relation)?;
+        }
+    }
+    for relation in query_relations.iter() {
+        pin_snapshot(relation)?;
+    }
+    for call in read_function_calls.iter() {
+        prepare_read_function(call.handler, &call.table_options, call.server.as_deref())?;
+    }
+
+    // Only the SELECT that feeds the new table is executed in DuckDB
+    let query = if is_into_rel {
+        match into_rel_source_query(query.as_str()) {
+            Some(source_query) => source_query,
+            None => {
+                prev_hook(query_desc, direction, count, execute_once);
+                return Ok(());
+            }
+        }
+    } else {
+        query
+    };
+
     // Set DuckDB search path according search path in Postgres
     // Make sure it could find unqualified relations.
     set_search_path_by_pg()?;
 
-    match connection::create_arrow(query.as_str()) {
+    let queryid = stats::fingerprint(unsafe { (*ps).queryId }, query.as_str());
+    let start = Instant::now();
+
+    // Rewrite Postgres-only syntax into DuckDB syntax.
+    // Queries that cannot be parsed are handed to DuckDB as is.
+    // Sync TimeZone and role-dependent values, so results match running the query in Postgres
+    let context = match set_session_context_by_pg() {
+        Ok(context) => context,
+        Err(err) => {
+            stats::record_fallback(queryid, query.as_str(), &err.to_string());
+            fallback_warning!(query.as_str(), &err.to_string());
+            prev_hook(query_desc, direction, count, execute_once);
+            return Ok(());
+        }
+    };
+
+    let duckdb_query = match translate_query(query.as_str(), &context) {
+        Ok(translated) => translated,
+        Err(TranslateError::Parse(_)) => query.clone(),
+        Err(err) => {
+            stats::record_fallback(queryid, query.as_str(), &err.to_string());
+            fallback_warning!(query.as_str(), &err.to_string());
+            prev_hook(query_desc, direction, count, execute_once);
+            return Ok(());
+        }
+    };
+
+    let cache_key = match result_cache::is_enabled() {
+        true => result_cache_key(
+            query.as_str(),
+            duckdb_query.as_str(),
+            &query_relations,
+            &read_function_calls,
+            &context,
+        ),
+        false => None,
+    };
+    // Results that can't be read are run again, and replaced
+    if let Some(batches) = cache_key
+        .as_ref()
+        .and_then(|key| result_cache::get(key).ok().flatten())
+    {
+        let (rows, bytes) = stats::batches_size(&batches);
+        stats::record_pushdown(queryid, query.as_str(), start.elapsed(), rows, bytes);
+        write_batches_to_slots(query_desc, batches)?;
+        return Ok(());
+    }
+
+    match connection::create_arrow(duckdb_query.as_str()) {
         Err(err) => {
             connection::clear_arrow();
-            fallback_warning!(err.to_string());
+            stats::record_fallback(queryid, query.as_str(), &err.to_string());
+            fallback_warning!(query.as_str(), &err.to_string());
             prev_hook(query_desc, direction, count, execute_once);
             return Ok(());
         }
@@ -83,10 +196,20 @@ pub async fn executor_run(
     }
 
     match connection::get_batches() {
-        Ok(batches) => write_batches_to_slots(query_desc, batches)?,
+        Ok(batches) => {
+            let (rows, bytes) = stats::batches_size(&batches);
+            stats::record_pushdown(queryid, query.as_str(), start.elapsed(), rows, bytes);
+            if let Some(key) = &cache_key {
+                if let Err(err) = result_cache::put(key, &batches) {
+                    warning!("failed to cache the result of the query: {}", err);
+                }
+            }
+            write_batches_to_slots(query_desc, batches)?
+        }
         Err(err) => {
             connection::clear_arrow();
-            fallback_warning!(err.to_string());
+            stats::record_fallback(queryid, query.as_str(), &err.to_string());
+            fallback_warning!(query.as_str(), &err.to_string());
             prev_hook(query_desc, direction, count, execute_once);
             return Ok(());
         }
@@ -0,0 +1,208 @@
+// Copyright (c) 2023-2025 Retake, Inc.
+//
+// This file is part of ParadeDB - Postgres for Search and Analytics
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <http://www.gnu.org/licenses/>.
+use anyhow::{bail, Result};
+use pgrx::*;
+use std::ffi::CStr;
+use std::time::Instant;
+
+use super::dialect::{insert_source_query, translate_query, TranslateError};
+use super::query::*;
+use crate::duckdb::connection;
+use crate::stats;
+
+/// Runs the SELECT side of an INSERT ... SELECT in DuckDB and bulk inserts the result into the heap table.
+/// Returns false if the statement must be executed by Postgres instead.
+pub fn insert_select<T: WhoAllocated>(
+    query_desc: &PgBox<pg_sys::QueryDesc, T>,
+    query: &str,
+) -> Result<bool> {
+    let ps = query_desc.plannedstmt;
+    if unsafe { (*ps).hasReturning } {
+        return Ok(false);
+    }
+
+    let Some((columns, source_query)) = insert_source_query(query) else {
+        return Ok(false);
+    };
+
+    // The target is the only relation that is not a DuckDB foreign table
+    let (targets, sources): (Vec<PgRelation>, Vec<PgRelation>) =
+        get_query_relations(unsafe { (*ps).rtable })
+            .into_iter()
+            .partition(|relation| !relation.is_foreign_table());
+    let [target] = targets.as_slice() else {
+        return Ok(false);
+    };
+    if !is_duckdb_query(&sources)
+        || !supports_bulk_insert(target)
+        || has_postgis_attributes(target.rd_att)
+        || sources
+            .iter()
+            .any(|relation| has_postgis_attributes(relation.rd_att))
+        || sources.iter().any(has_timezone_option)
+    {
+        return Ok(false);
+    }
+
+    // Decided before anything runs in DuckDB, so that Postgres doesn't read the source a second time
+    let width = match columns.len() {
+        0 => select_width(unsafe { (*ps).rtable }),
+        len => Some(len),
+    };
+    let Some(attributes) = width.and_then(|width| target_attributes(target, &columns, width))
+    else {
+        return Ok(false);
+    };
+
+    check_relations_file_access(&sources)?;
+    set_search_path_by_pg()?;
+
+    let queryid = stats::fingerprint(unsafe { (*ps).queryId }, query);
+    let start = Instant::now();
+
+    let context = match set_session_context_by_pg() {
+        Ok(context) => context,
+        Err(err) => {
+            stats::record_fallback(queryid, query, &err.to_string());
+            fallback_warning!(query, &err.to_string());
+            return Ok(false);
+        }
+    };
+
+    let duckdb_query = match translate_query(source_query.as_str(), &context) {
+        Ok(translated) => translated,
+        Err(TranslateError::Parse(_)) => source_query,
+        Err(err) => {
+            stats::record_fallback(queryid, query, &err.to_string());
+            fallback_warning!(query, &err.to_string());
+            return Ok(false);
+        }
+    };
+
+    if let Err(err) = connection::create_arrow(duckdb_query.as_str()) {
+        connection::clear_arrow();
+        stats::record_fallback(queryid, query, &err.to_string());
+        fallback_warning!(query, &err.to_string());
+        return Ok(false);
+    }
+
+    let batches = match connection::get_batches() {
+        Ok(batches) => batches,
+        Err(err) => {
+            connection::clear_arrow();
+            stats::record_fallback(queryid, query, &err.to_string());
+            fallback_warning!(query, &err.to_string());
+            return Ok(false);
+        }
+    };
+
+    if let Some(batch) = batches
+        .iter()
+        .find(|batch| batch.num_columns() != attributes.len())
+    {
+        connection::clear_arrow();
+        bail!(
+            "DuckDB returned {} columns for the {} columns of the INSERT",
+            batch.num_columns(),
+            attributes.len()
+        );
+    }
+
+    let (rows, bytes) = stats::batches_size(&batches);
+    stats::record_pushdown(queryid, query, start.elapsed(), rows, bytes);
+
+    let processed =
+        write_batches_to_heap(target.as_ptr(), query_desc.estate, &attributes, batches)?;
+    unsafe { (*query_desc.estate).es_processed = processed };
+
+    connection::clear_arrow();
+    Ok(true)
+}
+
+// Triggers, row level security and partition routing all need the Postgres insert path
+fn supports_bulk_insert(relation: &PgRelation) -> bool {
+    let rd_rel = unsafe { &*relation.rd_rel };
+    rd_rel.relkind == pg_sys::RELKIND_RELATION as std::ffi::c_char
+        && !rd_rel.relhastriggers
+        && !rd_rel.relrowsecurity
+}
+
+/// Number of columns of the SELECT, which the parser adds to the range table as the *SELECT* subquery
+fn select_width(rtable: *mut pg_sys::List) -> Option<usize> {
+    unsafe {
+        PgList::<pg_sys::RangeTblEntry>::from_pg(rtable)
+            .iter_ptr()
+            .find(|rte| {
+                (**rte).rtekind == pg_sys::RTEKind::RTE_SUBQUERY
+                    && !(**rte).eref.is_null()
+                    && CStr::from_ptr((*(**rte).eref).aliasname).to_bytes() == b"*SELECT*"
+            })
+            .map(|rte| PgList::<pg_sys::Node>::from_pg((*(**rte).eref).colnames).len())
+    }
+}
+
+/// Maps each of the `width` selected columns to an attribute of the target table,
+/// following the INSERT column list or the table's column order.
+/// Cells are converted to the type of their attribute but not coerced to its typmod or domain,
+/// so such attributes are left to Postgres. NOT NULL and CHECK constraints are checked by the bulk insert.
+fn target_attributes(
+    relation: &PgRelation,
+    columns: &[String],
+    width: usize,
+) -> Option<Vec<usize>> {
+    let tuple_desc = relation.tuple_desc();
+    let live_attributes: Vec<(usize, &pg_sys::FormData_pg_attribute)> = tuple_desc
+        .iter()
+        .enumerate()
+        .filter(|(_, attribute)| !attribute.attisdropped)
+        .collect();
+
+    let attributes: Vec<usize> = if columns.is_empty() {
+        live_attributes
+            .iter()
+            .take(width)
+            .map(|(index, _)| *index)
+            .collect()
+    } else {
+        columns
+            .iter()
+            .map(|column| {
+                live_attributes
+                    .iter()
+                    .find(|(_, attribute)| attribute.name() == column)
+                    .map(|(index, _)| *index)
+            })
+            .collect::<Option<Vec<usize>>>()?
+    };
+
+    if attributes.len() != width {
+        return None;
+    }
+
+    let supported = live_attributes.iter().all(|(index, attribute)| {
+        if attributes.contains(index) {
+            attribute.attgenerated == 0
+                && attribute.atttypmod < 0
+                && unsafe { pg_sys::get_typtype(attribute.atttypid) }
+                    != pg_sys::TYPTYPE_DOMAIN as std::ffi::c_char
+        } else {
+            !attribute.atthasdef && attribute.attidentity == 0 && attribute.attgenerated == 0
+        }
+    });
+
+    supported.then_some(attributes)
+}
@@ -17,12 +17,16 @@
 
 #[macro_use]
 mod query;
+mod dialect;
 mod executor;
+mod insert;
 mod utility;
 
 use async_std::task::block_on;
 use pgrx::*;
 
+use crate::fdw::{parallel, spatial_filter};
+
 pub struct ExtensionHook;
 
 #[allow(deprecated)]
@@ -54,6 +58,23 @@ impl hooks::PgHooks for ExtensionHook {
         HookResult::new(())
     }
 
+    fn executor_start(
+        &mut self,
+        query_desc: PgBox<pg_sys::QueryDesc>,
+        eflags: i32,
+        prev_hook: fn(query_desc: PgBox<pg_sys::QueryDesc>, eflags: i32) -> HookResult<()>,
+    ) -> HookResult<()> {
+        // Foreign scans begin while the executor starts, and read the spatial filters of their table
+        // An executor started by SPI while another one starts must not take away the filters of the outer one
+        let outer_filters = spatial_filter::collect_spatial_filters(&query_desc);
+        // Parallel workers start their executor too, and run their share of the foreign scans with these callbacks
+        parallel::install_scan_callbacks(&query_desc);
+        prev_hook(query_desc, eflags);
+        spatial_filter::restore_spatial_filters(outer_filters);
+
+        HookResult::new(())
+    }
+
     fn process_utility_hook(
         &mut self,
         pstmt: PgBox<pg_sys::PlannedStmt>,
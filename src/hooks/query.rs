@@ -18,19 +18,123 @@
 use anyhow::{anyhow, Result};
 use duckdb::arrow::array::RecordBatch;
 use pgrx::*;
-use std::ffi::CStr;
+use std::collections::HashMap;
+use std::ffi::{CStr, CString};
 use std::str::Utf8Error;
+use std::sync::Arc;
+use supabase_wrappers::prelude::options_to_hashmap;
 
+use super::dialect::SessionContext;
 use crate::duckdb::connection;
+use crate::fdw::base::{check_file_access, TIMEZONE_OPTION};
 use crate::fdw::handler::FdwHandler;
+use crate::fdw::read::{read_function_handler, read_function_options, READ_FUNCTION_SCHEMA};
+use crate::fdw::snapshot::is_pinned;
+use crate::gucs::FallbackPolicy;
+use crate::result_cache::{self, CacheKey};
 use crate::schema::cell::*;
+use crate::schema::converter::ColumnConverter;
+use crate::schema::postgis::postgis_type;
+use crate::GUCS;
 
 macro_rules! fallback_warning {
-    ($msg:expr) => {
-        warning!("This query was not fully pushed down to DuckDB because DuckDB returned an error. Query times may be impacted.\n{}", $msg);
+    ($query:expr, $msg:expr) => {
+        crate::hooks::query::report_fallback($query, $msg);
     };
 }
 
+const FALLBACK_POLICY_HINT: &str = "thdb.fallback_policy";
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FallbackReason {
+    UnsupportedFunction,
+    UnsupportedType,
+    BinderError,
+    IoError,
+    Untranslatable,
+    Other,
+}
+
+impl FallbackReason {
+    // Categorizes on the error class prefix DuckDB puts in front of its messages
+    pub fn from_error(msg: &str) -> Self {
+        let lower = msg.to_lowercase();
+
+        if lower.contains("cannot be translated to duckdb") {
+            Self::Untranslatable
+        } else if (lower.contains("catalog error") && lower.contains("function"))
+            || lower.contains("no function matches")
+        {
+            Self::UnsupportedFunction
+        } else if lower.contains("conversion error")
+            || lower.contains("not implemented error")
+            || lower.contains("unsupported type")
+            || lower.contains("data type")
+        {
+            Self::UnsupportedType
+        } else if lower.contains("binder error") || lower.contains("catalog error") {
+            Self::BinderError
+        } else if lower.contains("io error") || lower.contains("http error") {
+            Self::IoError
+        } else {
+            Self::Other
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::UnsupportedFunction => "unsupported function",
+            Self::UnsupportedType => "unsupported type",
+            Self::BinderError => "binder error",
+            Self::IoError => "IO error",
+            Self::Untranslatable => "untranslatable",
+            Self::Other => "other",
+        }
+    }
+}
+
+/// Reads a per-query override written as a comment, e.g. `/* thdb.fallback_policy=error */`
+pub fn fallback_policy_hint(query: &str) -> Option<FallbackPolicy> {
+    let mut rest = query;
+
+    while let Some(start) = rest.find("/*") {
+        let after_start = &rest[start + 2..];
+        let end = after_start.find("*/")?;
+        let comment = &after_start[..end];
+
+        if let Some(index) = comment.find(FALLBACK_POLICY_HINT) {
+            let value = comment[index + FALLBACK_POLICY_HINT.len()..]
+                .trim_start()
+                .strip_prefix('=')?
+                .trim_start()
+                .trim_matches(|c| c == '\'' || c == '"');
+            let name = value
+                .split(|c: char| !c.is_ascii_alphabetic())
+                .next()
+                .unwrap_or_default();
+            return FallbackPolicy::from_name(name);
+        }
+
+        rest = &after_start[end + 2..];
+    }
+
+    None
+}
+
+/// Applies the fallback policy after DuckDB failed to execute `query`.
+/// Returns only if the query should be re-run through Postgres.
+pub fn report_fallback(query: &str, msg: &str) {
+    let policy = fallback_policy_hint(query).unwrap_or_else(|| GUCS.fallback_policy.get());
+    let reason = FallbackReason::from_error(msg);
+
+    match policy {
+        FallbackPolicy::Warn => warning!("This query was not fully pushed down to DuckDB because DuckDB returned an error ({}). Query times may be impacted.\n{}", reason.as_str(), msg),
+        FallbackPolicy::Log => log!("This query was not fully pushed down to DuckDB because DuckDB returned an error ({}).\n{}", reason.as_str(), msg),
+        FallbackPolicy::Silent => {}
+        FallbackPolicy::Error => error!("DuckDB failed to execute this query ({}) and thdb.fallback_policy is error.\n{}", reason.as_str(), msg),
+    }
+}
+
 pub fn get_current_query(
     planned_stmt: *mut pg_sys::PlannedStmt,
     query_string: &CStr,
@@ -78,6 +182,94 @@ pub fn get_query_relations(rtable: *mut pg_sys::List) -> Vec<PgRelation> {
     relations
 }
 
+/// A call to one of the th_dbdm.read_* table functions, with the arguments folded into constants by the planner
+pub struct ReadFunctionCall {
+    pub handler: FdwHandler,
+    pub table_options: HashMap<String, String>,
+    pub server: Option<String>,
+}
+
+pub fn get_read_function_calls(rtable: *mut pg_sys::List) -> Result<Vec<ReadFunctionCall>> {
+    let mut calls = Vec::new();
+
+    unsafe {
+        if rtable.is_null() {
+            return Ok(calls);
+        }
+
+        let elements = (*rtable).elements;
+
+        for i in 0..(*rtable).length {
+            let rte = (*elements.offset(i as isize)).ptr_value as *mut pg_sys::RangeTblEntry;
+
+            if (*rte).rtekind != pg_sys::RTEKind::RTE_FUNCTION || (*rte).functions.is_null() {
+                continue;
+            }
+
+            let functions = (*rte).functions;
+            for j in 0..(*functions).length {
+                let rtfunc = (*(*functions).elements.offset(j as isize)).ptr_value
+                    as *mut pg_sys::RangeTblFunction;
+                if let Some(call) = read_function_call((*rtfunc).funcexpr)? {
+                    calls.push(call);
+                }
+            }
+        }
+    }
+
+    Ok(calls)
+}
+
+unsafe fn read_function_call(funcexpr: *mut pg_sys::Node) -> Result<Option<ReadFunctionCall>> {
+    if !is_a(funcexpr, pg_sys::NodeTag::T_FuncExpr) {
+        return Ok(None);
+    }
+
+    let funcexpr = funcexpr as *mut pg_sys::FuncExpr;
+    let namespace = pg_sys::get_namespace_name(pg_sys::get_func_namespace((*funcexpr).funcid));
+    let name = pg_sys::get_func_name((*funcexpr).funcid);
+    if namespace.is_null()
+        || name.is_null()
+        || CStr::from_ptr(namespace).to_str()? != READ_FUNCTION_SCHEMA
+    {
+        return Ok(None);
+    }
+
+    let Some(handler) = read_function_handler(CStr::from_ptr(name).to_str()?) else {
+        return Ok(None);
+    };
+
+    // Arguments that are not constants can't be known before execution
+    let args = PgList::<pg_sys::Node>::from_pg((*funcexpr).args);
+    let mut values = vec![];
+    for arg in args.iter_ptr() {
+        if !is_a(arg, pg_sys::NodeTag::T_Const) {
+            return Ok(None);
+        }
+        values.push(arg as *mut pg_sys::Const);
+    }
+
+    let [files, options, server] = values.as_slice() else {
+        return Ok(None);
+    };
+
+    let Some(files) = String::from_datum((**files).constvalue, (**files).constisnull) else {
+        return Ok(None);
+    };
+    let options = JsonB::from_datum((**options).constvalue, (**options).constisnull);
+    let server = String::from_datum((**server).constvalue, (**server).constisnull);
+
+    Ok(Some(ReadFunctionCall {
+        handler,
+        table_options: read_function_options(
+            handler,
+            &files,
+            options.as_ref().map(|options| &options.0),
+        )?,
+        server,
+    }))
+}
+
 pub fn set_search_path_by_pg() -> Result<()> {
     let mut search_path = get_postgres_search_path();
     let duckdb_schemas = connection::get_available_schemas()?;
@@ -91,6 +283,135 @@ pub fn set_search_path_by_pg() -> Result<()> {
     Ok(())
 }
 
+/// Applies the Postgres TimeZone to DuckDB and collects the session values the query translation needs.
+pub fn set_session_context_by_pg() -> Result<SessionContext> {
+    let timezone = get_config_option("TimeZone")?;
+    connection::set_timezone(&duckdb_timezone(&timezone))?;
+
+    // Text output of dates and intervals is only identical for the default styles
+    let date_style = get_config_option("DateStyle")?;
+    let interval_style = get_config_option("IntervalStyle")?;
+    let text_style = if !date_style.starts_with("ISO") {
+        Some(format!("DateStyle {date_style}"))
+    } else if interval_style != "postgres" {
+        Some(format!("IntervalStyle {interval_style}"))
+    } else {
+        None
+    };
+
+    Ok(SessionContext {
+        current_user: Some(get_user_name(unsafe { pg_sys::GetUserId() })?),
+        session_user: Some(get_user_name(unsafe { pg_sys::GetSessionUserId() })?),
+        transaction_timestamp: Some(timestamptz_to_utc_string(unsafe {
+            pg_sys::GetCurrentTransactionStartTimestamp()
+        })?),
+        statement_timestamp: Some(timestamptz_to_utc_string(unsafe {
+            pg_sys::GetCurrentStatementStartTimestamp()
+        })?),
+        text_style,
+    })
+}
+
+/// Key of the result of a pushed down query in the result cache. None when the result can't be reused:
+/// the query calls a volatile function, reads a version pinned by the transaction, or reads files whose
+/// versions can't be listed.
+pub fn result_cache_key(
+    query: &str,
+    duckdb_query: &str,
+    relations: &[PgRelation],
+    read_function_calls: &[ReadFunctionCall],
+    context: &SessionContext,
+) -> Option<CacheKey> {
+    if !result_cache::is_cacheable(duckdb_query)
+        || relations.iter().any(|relation| is_pinned(relation.oid()))
+    {
+        return None;
+    }
+
+    let session = vec![
+        get_postgres_search_path().join(","),
+        get_config_option("TimeZone").ok()?,
+        context.current_user.clone().unwrap_or_default(),
+        context.text_style.clone().unwrap_or_default(),
+    ];
+
+    // Options of foreign tables aren't part of the query, unlike the arguments of read functions
+    let mut sources = vec![];
+    for relation in relations {
+        let foreign_table = unsafe { pg_sys::GetForeignTable(relation.oid()) };
+        let table_options = unsafe { options_to_hashmap((*foreign_table).options) }.ok()?;
+        let mut options = table_options
+            .iter()
+            .map(|(key, value)| format!("{key}={value}"))
+            .collect::<Vec<String>>();
+        options.sort();
+
+        let version =
+            result_cache::source_version(FdwHandler::from(foreign_table), &table_options).ok()?;
+        sources.push(format!(
+            "{} {} {}",
+            relation.oid().as_u32(),
+            options.join(","),
+            version
+        ));
+    }
+    for call in read_function_calls {
+        let version = result_cache::source_version(call.handler, &call.table_options).ok()?;
+        sources.push(format!(
+            "{} {}",
+            call.server.as_deref().unwrap_or_default(),
+            version
+        ));
+    }
+
+    Some(CacheKey::new(query, duckdb_query, &session, &sources))
+}
+
+fn get_config_option(name: &str) -> Result<String> {
+    let name = CString::new(name)?;
+    let value = unsafe { pg_sys::GetConfigOption(name.as_ptr(), false, false) };
+    if value.is_null() {
+        return Err(anyhow!("setting {:?} not found", name));
+    }
+    Ok(unsafe { CStr::from_ptr(value) }.to_str()?.to_string())
+}
+
+fn get_user_name(user_id: pg_sys::Oid) -> Result<String> {
+    let name = unsafe { pg_sys::GetUserNameFromId(user_id, false) };
+    Ok(unsafe { CStr::from_ptr(name) }.to_str()?.to_string())
+}
+
+fn timestamptz_to_utc_string(timestamp: pg_sys::TimestampTz) -> Result<String> {
+    // Postgres timestamps count microseconds from 2000-01-01
+    const POSTGRES_EPOCH_MICROS: i64 = 946_684_800_000_000;
+
+    let datetime = chrono::DateTime::from_timestamp_micros(timestamp + POSTGRES_EPOCH_MICROS)
+        .ok_or_else(|| anyhow!("timestamp {timestamp} is out of range"))?;
+    Ok(datetime.format("%Y-%m-%d %H:%M:%S%.6f+00").to_string())
+}
+
+/// Postgres displays numeric offsets as POSIX zones, e.g. SET TIME ZONE 8 shows as <+08>-08.
+/// POSIX offsets are inverted, so they are converted to the GMT+08:00 form ICU understands.
+pub fn duckdb_timezone(timezone: &str) -> String {
+    let Some(offset) = timezone
+        .strip_prefix('<')
+        .and_then(|rest| rest.split_once('>'))
+        .map(|(_, offset)| offset)
+    else {
+        return timezone.to_string();
+    };
+
+    let (sign, offset) = match offset.strip_prefix('-') {
+        Some(offset) => ('+', offset),
+        None => ('-', offset.strip_prefix('+').unwrap_or(offset)),
+    };
+    let mut parts = offset.split(':');
+    let hours = parts.next().unwrap_or("0");
+    let minutes = parts.next().unwrap_or("00");
+
+    format!("GMT{sign}{:0>2}:{:0>2}", hours, minutes)
+}
+
 fn get_postgres_search_path() -> Vec<String> {
     let active_schemas =
         unsafe { PgList::<pg_sys::Oid>::from_pg(pg_sys::fetch_search_path(false)) };
@@ -130,6 +451,99 @@ pub fn is_duckdb_query(relations: &[PgRelation]) -> bool {
         })
 }
 
+/// Pushed down queries read the DuckDB views directly, so the file access checks done when
+/// a view is registered are repeated for every foreign table in the query
+pub fn check_relations_file_access(relations: &[PgRelation]) -> Result<()> {
+    for pg_relation in relations
+        .iter()
+        .filter(|relation| relation.is_foreign_table())
+    {
+        let foreign_table = unsafe { pg_sys::GetForeignTable(pg_relation.oid()) };
+        let table_options = unsafe { options_to_hashmap((*foreign_table).options)? };
+        check_file_access(&table_options, unsafe { (*pg_relation.rd_rel).relowner })?;
+    }
+
+    Ok(())
+}
+
+/// DuckDB only converts geometries to WKB in foreign scans, and its spatial functions
+/// differ from PostGIS, so queries that touch PostGIS values are left to the foreign scan
+pub fn has_postgis_attributes(tupdesc: pg_sys::TupleDesc) -> bool {
+    let tuple_desc = unsafe { PgTupleDesc::from_pg_unchecked(tupdesc) };
+    tuple_desc
+        .iter()
+        .any(|attribute| postgis_type(attribute.atttypid).is_some())
+}
+
+/// Like has_postgis_attributes, but only the columns the query reads from each table are checked,
+/// so a table with a PostGIS column is pushed down as long as that column is not used
+pub fn reads_postgis_columns(planned_stmt: *mut pg_sys::PlannedStmt) -> bool {
+    unsafe {
+        PgList::<pg_sys::RangeTblEntry>::from_pg((*planned_stmt).rtable)
+            .iter_ptr()
+            .filter(|rte| (**rte).rtekind == pg_sys::RTEKind::RTE_RELATION)
+            .any(|rte| {
+                let relid = (*rte).relid;
+                let attnos = match selected_columns(planned_stmt, rte) {
+                    Some(attnos) if !attnos.contains(&0) => attnos,
+                    // Whole row references, and children of inheritance trees, are checked for every column
+                    _ => {
+                        let relation = PgRelation::with_lock(relid, pg_sys::NoLock as i32);
+                        return has_postgis_attributes(relation.rd_att);
+                    }
+                };
+                attnos
+                    .into_iter()
+                    .filter(|attno| *attno > 0)
+                    .any(|attno| postgis_type(pg_sys::get_atttype(relid, attno)).is_some())
+            })
+    }
+}
+
+// The columns checked for SELECT privilege are the ones the query reads, in the target list or elsewhere
+#[cfg_attr(
+    any(feature = "pg13", feature = "pg14", feature = "pg15"),
+    allow(unused_variables)
+)]
+unsafe fn selected_columns(
+    planned_stmt: *mut pg_sys::PlannedStmt,
+    rte: *mut pg_sys::RangeTblEntry,
+) -> Option<Vec<pg_sys::AttrNumber>> {
+    #[cfg(any(feature = "pg13", feature = "pg14", feature = "pg15"))]
+    let selected_cols = (*rte).selectedCols;
+    #[cfg(not(any(feature = "pg13", feature = "pg14", feature = "pg15")))]
+    let selected_cols = {
+        // Children of inheritance trees have no permission info of their own
+        if (*rte).perminfoindex == 0 {
+            return None;
+        }
+        let perminfo = PgList::<pg_sys::RTEPermissionInfo>::from_pg((*planned_stmt).permInfos)
+            .get_ptr((*rte).perminfoindex as usize - 1)?;
+        (*perminfo).selectedCols
+    };
+
+    // An empty set, e.g. for COUNT(*), is a null pointer
+    let mut attnos = vec![];
+    let mut member = pg_sys::bms_next_member(selected_cols, -1);
+    while member >= 0 {
+        attnos.push((member + pg_sys::FirstLowInvalidHeapAttributeNumber) as pg_sys::AttrNumber);
+        member = pg_sys::bms_next_member(selected_cols, member);
+    }
+    Some(attnos)
+}
+
+/// Timestamps of foreign tables with the timezone option are read in that zone by the foreign scan,
+/// which DuckDB doesn't know about when it runs the whole query
+pub fn has_timezone_option(relation: &PgRelation) -> bool {
+    if !relation.is_foreign_table() {
+        return false;
+    }
+
+    let foreign_table = unsafe { pg_sys::GetForeignTable(relation.oid()) };
+    unsafe { options_to_hashmap((*foreign_table).options) }
+        .is_ok_and(|options| options.contains_key(TIMEZONE_OPTION))
+}
+
 #[inline]
 pub fn write_batches_to_slots<T: WhoAllocated>(
     query_desc: PgBox<pg_sys::QueryDesc, T>,
@@ -151,39 +565,64 @@ pub fn write_batches_to_slots<T: WhoAllocated>(
             .receiveSlot
             .ok_or_else(|| anyhow!("receiveSlot not found"))?;
 
-        for batch in batches.iter_mut() {
-            for row_index in 0..batch.num_rows() {
-                let tuple_table_slot =
-                    pg_sys::MakeTupleTableSlot(query_desc.tupDesc, &pg_sys::TTSOpsVirtual);
+        let tuple_table_slot =
+            pg_sys::MakeTupleTableSlot(query_desc.tupDesc, &pg_sys::TTSOpsVirtual);
+        let mut row_context = PgMemoryContexts::new("write_batches_to_slots");
+
+        let disable_converters = GUCS.disable_column_converters.get();
 
-                pg_sys::ExecStoreVirtualTuple(tuple_table_slot);
+        let names = tuple_desc
+            .iter()
+            .map(|attribute| Arc::<str>::from(attribute.name()))
+            .collect::<Vec<_>>();
 
-                for (col_index, _) in tuple_desc.iter().enumerate() {
-                    let attribute = tuple_desc
-                        .get(col_index)
-                        .ok_or_else(|| anyhow!("attribute at {col_index} not found in tupdesc"))?;
+        for batch in batches.iter_mut() {
+            let converters = tuple_desc
+                .iter()
+                .zip(&names)
+                .enumerate()
+                .map(|(col_index, (attribute, name))| {
                     let column = batch.column(col_index);
-                    let tts_value = (*tuple_table_slot).tts_values.add(col_index);
-                    let tts_isnull = (*tuple_table_slot).tts_isnull.add(col_index);
+                    match disable_converters {
+                        true => ColumnConverter::cell(column, attribute.atttypid, name),
+                        false => ColumnConverter::new(column, attribute.atttypid, name),
+                    }
+                })
+                .collect::<Vec<_>>();
 
-                    match column.get_cell(row_index, attribute.atttypid, attribute.name())? {
-                        Some(cell) => {
-                            if let Some(datum) = cell.into_datum() {
+            for row_index in 0..batch.num_rows() {
+                pg_sys::ExecClearTuple(tuple_table_slot);
+
+                // Values are allocated per row and freed once the destination has consumed the slot
+                row_context.switch_to(|_| -> Result<()> {
+                    for (col_index, converter) in converters.iter().enumerate() {
+                        let tts_value = (*tuple_table_slot).tts_values.add(col_index);
+                        let tts_isnull = (*tuple_table_slot).tts_isnull.add(col_index);
+
+                        match converter.datum(row_index)? {
+                            Some(datum) => {
                                 *tts_value = datum;
+                                *tts_isnull = false;
                             }
-                        }
-                        None => {
-                            *tts_isnull = true;
-                        }
-                    };
-                }
+                            None => {
+                                *tts_value = pg_sys::Datum::from(0);
+                                *tts_isnull = true;
+                            }
+                        };
+                    }
+
+                    pg_sys::ExecStoreVirtualTuple(tuple_table_slot);
+                    receive(tuple_table_slot, dest);
+                    Ok(())
+                })?;
 
-                receive(tuple_table_slot, dest);
                 (*estate).es_processed += 1;
-                pg_sys::ExecDropSingleTupleTableSlot(tuple_table_slot);
+                row_context.reset();
             }
         }
 
+        pg_sys::ExecDropSingleTupleTableSlot(tuple_table_slot);
+
         let shutdown = (*dest)
             .rShutdown
             .ok_or_else(|| anyhow!("rShutdown not found"))?;
@@ -192,3 +631,196 @@ pub fn write_batches_to_slots<T: WhoAllocated>(
 
     Ok(())
 }
+
+/// Writes DuckDB batches into a heap table through the table AM's multi insert, the same path COPY uses.
+/// `attributes` holds the tuple descriptor index each batch column is written to; other columns are NULL.
+pub fn write_batches_to_heap(
+    relation: pg_sys::Relation,
+    estate: *mut pg_sys::EState,
+    attributes: &[usize],
+    batches: Vec<RecordBatch>,
+) -> Result<u64> {
+    unsafe {
+        let tuple_desc = PgTupleDesc::from_pg_unchecked((*relation).rd_att);
+        let has_constraints = !(*(*relation).rd_att).constr.is_null();
+        let table_am = (*relation).rd_tableam;
+        let multi_insert = (*table_am)
+            .multi_insert
+            .ok_or_else(|| anyhow!("table access method does not support multi insert"))?;
+
+        let result_rel_info = pg_sys::palloc0(std::mem::size_of::<pg_sys::ResultRelInfo>())
+            as *mut pg_sys::ResultRelInfo;
+        pg_sys::InitResultRelInfo(result_rel_info, relation, 1, std::ptr::null_mut(), 0);
+        pg_sys::ExecOpenIndices(result_rel_info, false);
+        #[cfg(feature = "pg13")]
+        {
+            (*estate).es_result_relation_info = result_rel_info;
+        }
+
+        let command_id = (*estate).es_output_cid;
+        let bistate = pg_sys::GetBulkInsertState();
+        let mut processed = 0;
+
+        let columns = attributes
+            .iter()
+            .map(|att_index| {
+                tuple_desc
+                    .get(*att_index)
+                    .map(|attribute| (attribute.atttypid, Arc::<str>::from(attribute.name())))
+                    .ok_or_else(|| anyhow!("attribute at {att_index} not found in tupdesc"))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        for batch in batches.iter() {
+            let mut slots = Vec::with_capacity(batch.num_rows());
+            let converters = columns
+                .iter()
+                .enumerate()
+                .map(|(col_index, (oid, name))| {
+                    ColumnConverter::new(batch.column(col_index), *oid, name)
+                })
+                .collect::<Vec<_>>();
+
+            for row_index in 0..batch.num_rows() {
+                let slot = pg_sys::table_slot_create(relation, std::ptr::null_mut());
+
+                for att_index in 0..tuple_desc.len() {
+                    *(*slot).tts_values.add(att_index) = pg_sys::Datum::from(0);
+                    *(*slot).tts_isnull.add(att_index) = true;
+                }
+
+                for (converter, att_index) in converters.iter().zip(attributes) {
+                    if let Some(datum) = converter.datum(row_index)? {
+                        *(*slot).tts_values.add(*att_index) = datum;
+                        *(*slot).tts_isnull.add(*att_index) = false;
+                    }
+                }
+
+                pg_sys::ExecStoreVirtualTuple(slot);
+                if has_constraints {
+                    pg_sys::ExecConstraints(result_rel_info, slot, estate);
+                }
+                slots.push(slot);
+            }
+
+            if slots.is_empty() {
+                continue;
+            }
+
+            multi_insert(
+                relation,
+                slots.as_mut_ptr(),
+                slots.len() as i32,
+                command_id,
+                0,
+                bistate,
+            );
+
+            if (*result_rel_info).ri_NumIndices > 0 {
+                for slot in slots.iter() {
+                    #[cfg(feature = "pg13")]
+                    pg_sys::ExecInsertIndexTuples(
+                        *slot,
+                        estate,
+                        false,
+                        std::ptr::null_mut(),
+                        std::ptr::null_mut(),
+                    );
+                    #[cfg(any(feature = "pg14", feature = "pg15"))]
+                    pg_sys::ExecInsertIndexTuples(
+                        result_rel_info,
+                        *slot,
+                        estate,
+                        false,
+                        false,
+                        std::ptr::null_mut(),
+                        std::ptr::null_mut(),
+                    );
+                    #[cfg(any(feature = "pg16", feature = "pg17"))]
+                    pg_sys::ExecInsertIndexTuples(
+                        result_rel_info,
+                        *slot,
+                        estate,
+                        false,
+                        false,
+                        std::ptr::null_mut(),
+                        std::ptr::null_mut(),
+                        false,
+                    );
+                }
+            }
+
+            processed += slots.len() as u64;
+            for slot in slots {
+                pg_sys::ExecDropSingleTupleTableSlot(slot);
+            }
+        }
+
+        if let Some(finish_bulk_insert) = (*table_am).finish_bulk_insert {
+            finish_bulk_insert(relation, 0);
+        }
+        pg_sys::FreeBulkInsertState(bistate);
+        pg_sys::ExecCloseIndices(result_rel_info);
+
+        Ok(processed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_duckdb_timezone() {
+        assert_eq!(duckdb_timezone("Asia/Shanghai"), "Asia/Shanghai");
+        assert_eq!(duckdb_timezone("<+08>-08"), "GMT+08:00");
+        assert_eq!(duckdb_timezone("<-03:30>+03:30"), "GMT-03:30");
+    }
+
+    #[test]
+    fn test_fallback_policy_hint() {
+        assert_eq!(
+            fallback_policy_hint("/* thdb.fallback_policy=error */ SELECT * FROM t"),
+            Some(FallbackPolicy::Error)
+        );
+        assert_eq!(
+            fallback_policy_hint("SELECT * FROM t /*+ thdb.fallback_policy = 'silent' */"),
+            Some(FallbackPolicy::Silent)
+        );
+        assert_eq!(
+            fallback_policy_hint("/* other */ SELECT 1 /* thdb.fallback_policy=Log */"),
+            Some(FallbackPolicy::Log)
+        );
+        assert_eq!(fallback_policy_hint("SELECT * FROM t"), None);
+        assert_eq!(
+            fallback_policy_hint("/* thdb.fallback_policy=unknown */ SELECT 1"),
+            None
+        );
+    }
+
+    #[test]
+    fn test_fallback_reason_from_error() {
+        assert_eq!(
+            FallbackReason::from_error(
+                "Catalog Error: Scalar Function with name pg_backend_pid does not exist!"
+            ),
+            FallbackReason::UnsupportedFunction
+        );
+        assert_eq!(
+            FallbackReason::from_error("Binder Error: Referenced column \"x\" not found"),
+            FallbackReason::BinderError
+        );
+        assert_eq!(
+            FallbackReason::from_error("Conversion Error: Could not convert string"),
+            FallbackReason::UnsupportedType
+        );
+        assert_eq!(
+            FallbackReason::from_error("IO Error: No files found that match the pattern"),
+            FallbackReason::IoError
+        );
+        assert_eq!(
+            FallbackReason::from_error("Parser Error: syntax error"),
+            FallbackReason::Other
+        );
+    }
+}
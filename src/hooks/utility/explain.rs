@@ -18,8 +18,9 @@
 use std::ffi::{CStr, CString};
 use std::time::Instant;
 
-use anyhow::Result;
-use pgrx::{error, pg_sys};
+use anyhow::{bail, Result};
+use pgrx::{error, pg_sys, PgList};
+use serde_json::{json, Map, Value};
 
 use super::parse_query_from_utility_stmt;
 use crate::{
@@ -31,8 +32,20 @@ enum Style {
     Postgres,
     Duckdb,
 }
+
+#[derive(PartialEq)]
+enum Format {
+    Text,
+    Json,
+    Yaml,
+    Xml,
+}
+
 struct ExplainState {
     analyze: bool,
+    verbose: bool,
+    summary: Option<bool>,
+    format: Format,
     style: Style,
 }
 
@@ -51,24 +64,65 @@ pub fn explain_query(
     }
 
     let state = parse_explain_options(unsafe { (*stmt).options });
+    let output_columns = get_output_columns(query);
     let query = parse_query_from_utility_stmt(query_string)?;
 
     let output = match state.style {
-        Style::Postgres => {
+        Style::Postgres if state.format == Format::Text => {
             let mut output = format!("DuckDB Scan: {}\n", query);
+            if state.verbose {
+                output += &format!("  Output: {}\n", output_columns.join(", "));
+            }
             if state.analyze {
                 let start_time = Instant::now();
                 set_search_path_by_pg()?;
                 connection::execute(&query, [])?;
                 let duration = start_time.elapsed();
-                output += &format!(
-                    "Execution Time: {:.3} ms\n",
-                    duration.as_micros() as f64 / 1_000.0
-                );
+                if state.summary.unwrap_or(true) {
+                    output += &format!(
+                        "Execution Time: {:.3} ms\n",
+                        duration.as_micros() as f64 / 1_000.0
+                    );
+                }
             }
             output
         }
+        Style::Postgres => {
+            set_search_path_by_pg()?;
+            let start_time = Instant::now();
+            let profile = connection::execute_explain_json(&query, state.analyze)?;
+            let duration = start_time.elapsed();
+
+            let mut plan = Map::new();
+            plan.insert("Node Type".to_string(), json!("DuckDB Scan"));
+            plan.insert("Query".to_string(), json!(query));
+            if state.verbose {
+                plan.insert("Output".to_string(), json!(output_columns));
+            }
+            plan.insert("DuckDB Plan".to_string(), profile);
+
+            let mut root = Map::new();
+            root.insert("Plan".to_string(), Value::Object(plan));
+            if state.analyze && state.summary.unwrap_or(true) {
+                root.insert(
+                    "Execution Time".to_string(),
+                    json!(duration.as_micros() as f64 / 1_000.0),
+                );
+            }
+
+            let explain = Value::Array(vec![Value::Object(root)]);
+            match state.format {
+                Format::Json => serde_json::to_string_pretty(&explain)?,
+                Format::Yaml => format_yaml(&explain, 0),
+                Format::Xml => format_xml(&explain),
+                Format::Text => unreachable!("text format is handled above"),
+            }
+        }
         Style::Duckdb => {
+            if state.format != Format::Text {
+                bail!("STYLE duckdb only supports FORMAT TEXT");
+            }
+
             set_search_path_by_pg()?;
             let explain_query = if state.analyze {
                 format!("EXPLAIN ANALYZE {query}")
@@ -88,7 +142,17 @@ pub fn explain_query(
 
         let output_cstr = CString::new(output)?;
 
-        pg_sys::do_text_output_multiline(tstate, output_cstr.as_ptr());
+        // Like Postgres, only the text format is split into one row per line.
+        // The other formats are emitted as a single document.
+        if state.format == Format::Text {
+            pg_sys::do_text_output_multiline(tstate, output_cstr.as_ptr());
+        } else {
+            let mut values = [pg_sys::Datum::from(pg_sys::cstring_to_text(
+                output_cstr.as_ptr(),
+            ))];
+            let mut isnull = [false];
+            pg_sys::do_tup_output(tstate, values.as_mut_ptr(), isnull.as_mut_ptr());
+        }
         pg_sys::end_tup_output(tstate);
     }
 
@@ -98,6 +162,9 @@ pub fn explain_query(
 fn parse_explain_options(options: *const pg_sys::List) -> ExplainState {
     let mut explain_state = ExplainState {
         analyze: false,
+        verbose: false,
+        summary: None,
+        format: Format::Text,
         style: Style::Postgres,
     };
 
@@ -121,6 +188,31 @@ fn parse_explain_options(options: *const pg_sys::List) -> ExplainState {
                 "analyze" => {
                     explain_state.analyze = pg_sys::defGetBoolean(opt);
                 }
+                "verbose" => {
+                    explain_state.verbose = pg_sys::defGetBoolean(opt);
+                }
+                "summary" => {
+                    explain_state.summary = Some(pg_sys::defGetBoolean(opt));
+                }
+                // These options only affect Postgres plan nodes, which a pushed down query
+                // does not have. They are accepted so that tools which always send them keep working.
+                "costs" | "buffers" | "wal" | "timing" | "settings" | "generic_plan" | "memory"
+                | "serialize" => {}
+                "format" => {
+                    let format = match CStr::from_ptr(pg_sys::defGetString(opt)).to_str() {
+                        Ok(format) => format,
+                        Err(e) => {
+                            error!("failed to parse FORMAT option: {e}");
+                        }
+                    };
+
+                    explain_state.format = match parse_explain_format(format) {
+                        Some(f) => f,
+                        None => {
+                            error!("unrecognized value for EXPLAIN option \"format\": \"{format}\"")
+                        }
+                    };
+                }
                 "style" => {
                     let style = match CStr::from_ptr(pg_sys::defGetString(opt)).to_str() {
                         Ok(style) => style,
@@ -153,3 +245,149 @@ fn parse_explain_style(style: &str) -> Option<Style> {
         _ => None,
     }
 }
+
+fn parse_explain_format(format: &str) -> Option<Format> {
+    match format {
+        "text" => Some(Format::Text),
+        "json" => Some(Format::Json),
+        "yaml" => Some(Format::Yaml),
+        "xml" => Some(Format::Xml),
+        _ => None,
+    }
+}
+
+fn get_output_columns(query: *mut pg_sys::Query) -> Vec<String> {
+    let target_list = unsafe { PgList::<pg_sys::TargetEntry>::from_pg((*query).targetList) };
+
+    target_list
+        .iter_ptr()
+        .filter(|entry| unsafe { !(**entry).resjunk && !(**entry).resname.is_null() })
+        .map(|entry| {
+            unsafe { CStr::from_ptr((*entry).resname) }
+                .to_string_lossy()
+                .into_owned()
+        })
+        .collect()
+}
+
+// Mirrors the YAML layout produced by Postgres' own EXPLAIN (FORMAT YAML)
+fn format_yaml(value: &Value, indent: usize) -> String {
+    let padding = " ".repeat(indent);
+
+    match value {
+        Value::Array(items) if !items.is_empty() => items
+            .iter()
+            .map(|item| match item {
+                Value::Object(_) | Value::Array(_) => {
+                    let nested = format_yaml(item, indent + 2);
+                    format!("{padding}- {}", nested.trim_start())
+                }
+                scalar => format!("{padding}- {}", format_yaml_scalar(scalar)),
+            })
+            .collect::<Vec<String>>()
+            .join("\n"),
+        Value::Object(map) if !map.is_empty() => map
+            .iter()
+            .map(|(key, item)| match item {
+                Value::Object(map) if !map.is_empty() => {
+                    format!("{padding}{key}: \n{}", format_yaml(item, indent + 2))
+                }
+                Value::Array(items) if !items.is_empty() => {
+                    format!("{padding}{key}: \n{}", format_yaml(item, indent + 2))
+                }
+                scalar => format!("{padding}{key}: {}", format_yaml_scalar(scalar)),
+            })
+            .collect::<Vec<String>>()
+            .join("\n"),
+        scalar => format!("{padding}{}", format_yaml_scalar(scalar)),
+    }
+}
+
+fn format_yaml_scalar(value: &Value) -> String {
+    match value {
+        Value::Array(_) => "[]".to_string(),
+        Value::Object(_) => "{}".to_string(),
+        Value::Null => "null".to_string(),
+        scalar => scalar.to_string(),
+    }
+}
+
+// Mirrors the XML layout produced by Postgres' own EXPLAIN (FORMAT XML)
+fn format_xml(value: &Value) -> String {
+    let mut output = String::from("<explain xmlns=\"http://www.postgresql.org/2009/explain\">\n");
+    if let Value::Array(items) = value {
+        for item in items {
+            output += &format!("  <Query>\n{}  </Query>\n", format_xml_node(item, 4));
+        }
+    }
+    output + "</explain>"
+}
+
+fn format_xml_node(value: &Value, indent: usize) -> String {
+    let padding = " ".repeat(indent);
+
+    match value {
+        Value::Object(map) => map
+            .iter()
+            .map(|(key, item)| {
+                let tag = key.replace(' ', "-");
+                match item {
+                    Value::Object(_) | Value::Array(_) => format!(
+                        "{padding}<{tag}>\n{}{padding}</{tag}>\n",
+                        format_xml_node(item, indent + 2)
+                    ),
+                    scalar => format!("{padding}<{tag}>{}</{tag}>\n", format_xml_scalar(scalar)),
+                }
+            })
+            .collect(),
+        Value::Array(items) => items
+            .iter()
+            .map(|item| {
+                format!(
+                    "{padding}<Item>\n{}{padding}</Item>\n",
+                    format_xml_node(item, indent + 2)
+                )
+            })
+            .collect(),
+        scalar => format!("{padding}{}\n", format_xml_scalar(scalar)),
+    }
+}
+
+fn format_xml_scalar(value: &Value) -> String {
+    let raw = match value {
+        Value::String(s) => s.clone(),
+        Value::Null => String::new(),
+        other => other.to_string(),
+    };
+
+    raw.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_yaml() {
+        let explain = json!([{
+            "Plan": {
+                "Node Type": "DuckDB Scan",
+                "Output": ["a", "b"],
+            },
+            "Execution Time": 1.5,
+        }]);
+
+        let expected = "- Plan: \n    Node Type: \"DuckDB Scan\"\n    Output: \n      - \"a\"\n      - \"b\"\n  Execution Time: 1.5";
+        assert_eq!(format_yaml(&explain, 0), expected);
+    }
+
+    #[test]
+    fn test_format_xml() {
+        let explain = json!([{ "Plan": { "Node Type": "DuckDB Scan", "Query": "SELECT 1 < 2" } }]);
+
+        let expected = "<explain xmlns=\"http://www.postgresql.org/2009/explain\">\n  <Query>\n    <Plan>\n      <Node-Type>DuckDB Scan</Node-Type>\n      <Query>SELECT 1 &lt; 2</Query>\n    </Plan>\n  </Query>\n</explain>";
+        assert_eq!(format_xml(&explain), expected);
+    }
+}
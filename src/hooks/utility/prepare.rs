@@ -17,19 +17,23 @@
 
 use std::ffi::CStr;
 use std::ptr::null_mut;
+use std::time::Instant;
 
 use anyhow::Result;
-use pgrx::{error, pg_sys, pgbox, warning, PgBox};
+use pgrx::{error, pg_sys, pgbox, PgBox};
 
 use crate::duckdb::connection;
+use crate::hooks::dialect::{translate_query, SessionContext, TranslateError};
 use crate::hooks::query::*;
+use crate::stats;
 
 pub fn execute_query<T: pgbox::WhoAllocated>(
     _psate: *mut pg_sys::ParseState,
     stmt: *mut pg_sys::ExecuteStmt,
     query_desc: PgBox<pg_sys::QueryDesc, T>,
 ) -> Result<bool> {
-    unsafe {
+    // Statistics are keyed on the id of the prepared statement, like the executor does for the same query
+    let prepared_query_id = unsafe {
         let prepared_stmt = pg_sys::FetchPreparedStatement((*stmt).name, true);
         let plan_source = (*prepared_stmt).plansource;
 
@@ -71,20 +75,48 @@ pub fn execute_query<T: pgbox::WhoAllocated>(
         // We enforce this by executing the PREPARE statement again.
         set_search_path_by_pg()?;
 
+        // Prepared statements outlive the session values, so only the text style check is kept
+        let context = match set_session_context_by_pg() {
+            Ok(context) => SessionContext {
+                text_style: context.text_style,
+                ..Default::default()
+            },
+            Err(err) => {
+                fallback_warning!(
+                    CStr::from_ptr((*query_desc.as_ptr()).sourceText).to_str()?,
+                    &err.to_string()
+                );
+                return Ok(true);
+            }
+        };
+
         if need_replan {
-            let prepare_stmt = CStr::from_ptr((*plan_source).query_string);
-            if let Err(e) = connection::execute(prepare_stmt.to_str()?, []) {
+            let prepare_stmt = CStr::from_ptr((*plan_source).query_string).to_str()?;
+            let prepare_stmt = match translate_query(prepare_stmt, &context) {
+                Ok(translated) => translated,
+                Err(TranslateError::Parse(_)) => prepare_stmt.to_string(),
+                Err(err) => {
+                    fallback_warning!(prepare_stmt, &err.to_string());
+                    return Ok(true);
+                }
+            };
+            if let Err(e) = connection::execute(prepare_stmt.as_str(), []) {
                 error!("execute prepare replan error: {}", e.to_string());
             }
         }
-    }
 
-    let query = unsafe { CStr::from_ptr((*query_desc.as_ptr()).sourceText) };
+        (*planned_stmt).queryId
+    };
 
-    match connection::create_arrow(query.to_str()?) {
+    let query = unsafe { CStr::from_ptr((*query_desc.as_ptr()).sourceText) }.to_str()?;
+    let queryid = stats::fingerprint(prepared_query_id, query);
+    let start = Instant::now();
+
+    match connection::create_arrow(query) {
         Err(err) => {
             connection::clear_arrow();
-            fallback_warning!(err.to_string());
+            stats::record_fallback(queryid, query, &err.to_string());
+            fallback_warning!(query, &err.to_string());
             return Ok(true);
         }
         Ok(false) => {
@@ -95,10 +127,15 @@ pub fn execute_query<T: pgbox::WhoAllocated>(
     }
 
     match connection::get_batches() {
-        Ok(batches) => write_batches_to_slots(query_desc, batches)?,
+        Ok(batches) => {
+            let (rows, bytes) = stats::batches_size(&batches);
+            stats::record_pushdown(queryid, query, start.elapsed(), rows, bytes);
+            write_batches_to_slots(query_desc, batches)?
+        }
         Err(err) => {
             connection::clear_arrow();
-            fallback_warning!(err.to_string());
+            stats::record_fallback(queryid, query, &err.to_string());
+            fallback_warning!(query, &err.to_string());
             return Ok(true);
         }
     }
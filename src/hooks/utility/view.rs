@@ -19,10 +19,7 @@ use std::ptr::null_mut;
 
 use anyhow::Result;
 
-use pgrx::{
-    pg_sys::{self},
-    warning,
-};
+use pgrx::pg_sys::{self};
 
 use crate::{duckdb::connection::execute, hooks::query::is_duckdb_query};
 
@@ -91,7 +88,7 @@ pub fn view_query(
     // Push down the view creation query to DuckDB
     set_search_path_by_pg()?;
     if let Err(e) = execute(query_string.to_str()?, []) {
-        fallback_warning!(e.to_string());
+        fallback_warning!(query_string.to_str()?, &e.to_string());
     }
 
     Ok(true)
@@ -21,17 +21,23 @@ mod api;
 mod debug_guc;
 mod duckdb;
 mod fdw;
+mod gucs;
 mod hooks;
+mod result_cache;
 mod schema;
+mod stats;
 
 #[cfg(debug_assertions)]
 use crate::debug_guc::DebugGucSettings;
+use crate::gucs::GucSettings;
 use hooks::ExtensionHook;
 use pgrx::*;
 
 #[cfg(debug_assertions)]
 pub static DEBUG_GUCS: DebugGucSettings = DebugGucSettings::new();
 
+pub static GUCS: GucSettings = GucSettings::new();
+
 pg_module_magic!();
 
 static mut EXTENSION_HOOK: ExtensionHook = ExtensionHook;
@@ -44,6 +50,10 @@ pub extern "C" fn _PG_init() {
         register_hook(&mut EXTENSION_HOOK)
     };
 
+    GUCS.init();
+    stats::init();
+    fdw::parallel::init();
+
     #[cfg(debug_assertions)]
     DEBUG_GUCS.init();
 }
@@ -0,0 +1,389 @@
+// Copyright (c) 2023-2025 Retake, Inc.
+//
+// This file is part of ParadeDB - Postgres for Search and Analytics
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <http://www.gnu.org/licenses/>.
+
+use anyhow::{anyhow, bail, Result};
+use arrow_ipc::reader::FileReader;
+use arrow_ipc::writer::FileWriter;
+use duckdb::arrow::array::RecordBatch;
+use duckdb::arrow::datatypes::Schema;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::duckdb::connection;
+use crate::fdw::handler::FdwHandler;
+use crate::GUCS;
+
+// Results of pushed down queries are kept as Arrow IPC files in the data directory, which is the working
+// directory of every backend, so all sessions share them. A file is named after the hash of its key and
+// holds the whole key in its schema metadata, which is compared on every read.
+const CACHE_DIRECTORY: &str = "th_dbdm/result_cache";
+const CACHE_EXTENSION: &str = "arrow";
+
+const KEY_METADATA: &str = "th_dbdm.key";
+const QUERY_METADATA: &str = "th_dbdm.query";
+const ROWS_METADATA: &str = "th_dbdm.rows";
+
+// Functions whose result changes from one call to the next. Timestamps of the transaction and statement
+// are already constants of the translated query, so queries that read them get a new key every time.
+const VOLATILE_FUNCTIONS: [&str; 5] = ["random(", "uuid(", "setseed(", "nextval(", "currval("];
+
+pub struct CacheKey {
+    hash: u64,
+    key: String,
+    query: String,
+}
+
+impl CacheKey {
+    /// The DuckDB query, the session values it depends on, and the versions of the files it reads.
+    /// The query as sent to Postgres is kept to list and invalidate the result.
+    pub fn new(query: &str, duckdb_query: &str, session: &[String], sources: &[String]) -> Self {
+        let duckdb_query = normalize_query(duckdb_query);
+        let key = std::iter::once(duckdb_query.as_str())
+            .chain(session.iter().map(String::as_str))
+            .chain(sources.iter().map(String::as_str))
+            .collect::<Vec<&str>>()
+            .join("\n");
+
+        let mut hasher = DefaultHasher::new();
+        key.hash(&mut hasher);
+        Self {
+            hash: hasher.finish(),
+            key,
+            query: normalize_query(query),
+        }
+    }
+
+    fn path(&self) -> PathBuf {
+        Path::new(CACHE_DIRECTORY).join(format!("{:016x}.{CACHE_EXTENSION}", self.hash))
+    }
+}
+
+pub struct CacheEntry {
+    pub key: String,
+    pub query: String,
+    pub rows: i64,
+    pub bytes: i64,
+    pub created: f64,
+    pub expires: f64,
+}
+
+pub fn is_enabled() -> bool {
+    GUCS.result_cache.get()
+}
+
+/// Whether running the query twice gives the same result, as far as the text of the query tells
+pub fn is_cacheable(query: &str) -> bool {
+    let query = query.to_lowercase();
+    !VOLATILE_FUNCTIONS
+        .iter()
+        .any(|function| query.contains(function))
+}
+
+/// Collapses the whitespace outside of literals and quoted identifiers, and drops the trailing semicolon
+pub fn normalize_query(query: &str) -> String {
+    let mut normalized = String::with_capacity(query.len());
+    let mut quote = None;
+    let mut pending_space = false;
+
+    for c in query.trim().trim_end_matches(';').trim_end().chars() {
+        match quote {
+            Some(open) => {
+                normalized.push(c);
+                if c == open {
+                    quote = None;
+                }
+            }
+            None if c.is_whitespace() => pending_space = true,
+            None => {
+                if pending_space && !normalized.is_empty() {
+                    normalized.push(' ');
+                }
+                pending_space = false;
+                if c == '\'' || c == '"' {
+                    quote = Some(c);
+                }
+                normalized.push(c);
+            }
+        }
+    }
+
+    normalized
+}
+
+/// Version of the data a foreign table or read function reads: the snapshot of an Iceberg table,
+/// the log of a Delta table, or the files themselves
+pub fn source_version(
+    handler: FdwHandler,
+    table_options: &HashMap<String, String>,
+) -> Result<String> {
+    let files = table_options
+        .get("files")
+        .ok_or_else(|| anyhow!("files option is required"))?;
+
+    let versions = match handler {
+        FdwHandler::Iceberg => {
+            return connection::latest_iceberg_snapshot(table_options)
+                .map(|snapshot| snapshot.unwrap_or_default())
+        }
+        FdwHandler::Delta => connection::file_versions(&[&format!(
+            "{}/_delta_log/*.json",
+            files.trim_end_matches('/')
+        )])?,
+        _ => connection::file_versions(
+            &files
+                .split(',')
+                .map(|pattern| pattern.trim())
+                .filter(|pattern| !pattern.is_empty())
+                .collect::<Vec<&str>>(),
+        )?,
+    };
+
+    Ok(versions.join(","))
+}
+
+/// Returns the cached result of the key, unless it has expired
+pub fn get(key: &CacheKey) -> Result<Option<Vec<RecordBatch>>> {
+    let path = key.path();
+    let Ok(metadata) = fs::metadata(&path) else {
+        return Ok(None);
+    };
+
+    if age(&metadata) > ttl() {
+        let _ = fs::remove_file(&path);
+        return Ok(None);
+    }
+
+    let reader = FileReader::try_new(File::open(&path)?, None)?;
+    if reader.schema().metadata().get(KEY_METADATA) != Some(&key.key) {
+        return Ok(None);
+    }
+
+    Ok(Some(reader.collect::<Result<Vec<RecordBatch>, _>>()?))
+}
+
+/// Stores the result of the key, if it fits in thdb.result_cache_size, and evicts the oldest results
+/// until the cache fits again
+pub fn put(key: &CacheKey, batches: &[RecordBatch]) -> Result<()> {
+    // The schema of a result is only known from its batches
+    let Some(first) = batches.first() else {
+        return Ok(());
+    };
+
+    let bytes = batches
+        .iter()
+        .map(|batch| batch.get_array_memory_size() as u64)
+        .sum::<u64>();
+    if bytes > max_size() {
+        return Ok(());
+    }
+
+    let rows = batches.iter().map(|batch| batch.num_rows()).sum::<usize>();
+    let schema = Arc::new(Schema::new_with_metadata(
+        first.schema().fields().clone(),
+        HashMap::from([
+            (KEY_METADATA.to_string(), key.key.clone()),
+            (QUERY_METADATA.to_string(), key.query.clone()),
+            (ROWS_METADATA.to_string(), rows.to_string()),
+        ]),
+    ));
+
+    fs::create_dir_all(CACHE_DIRECTORY)?;
+    let path = key.path();
+    // Written under a name of its own, so other sessions never read a partial file
+    let temp_path = path.with_extension(format!("{}.tmp", std::process::id()));
+    let result = (|| -> Result<()> {
+        let mut writer = FileWriter::try_new(File::create(&temp_path)?, &schema)?;
+        for batch in batches {
+            writer.write(&batch.clone().with_schema(schema.clone())?)?;
+        }
+        writer.finish()?;
+        drop(writer);
+        fs::rename(&temp_path, &path)?;
+        Ok(())
+    })();
+    if result.is_err() {
+        let _ = fs::remove_file(&temp_path);
+    }
+    result?;
+
+    evict(max_size())
+}
+
+/// Every cached result, expired or not
+pub fn entries() -> Result<Vec<CacheEntry>> {
+    let ttl = ttl().as_secs_f64();
+    let mut entries = vec![];
+
+    for (path, metadata) in cache_files()? {
+        // Files removed by another session in the meantime are skipped
+        let Ok(reader) = File::open(&path)
+            .map_err(anyhow::Error::from)
+            .and_then(|file| FileReader::try_new(file, None).map_err(anyhow::Error::from))
+        else {
+            continue;
+        };
+
+        let schema = reader.schema();
+        let metadata_value = |name: &str| schema.metadata().get(name).cloned().unwrap_or_default();
+        let created = modified(&metadata)
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs_f64();
+
+        entries.push(CacheEntry {
+            key: path
+                .file_stem()
+                .map(|stem| stem.to_string_lossy().into_owned())
+                .unwrap_or_default(),
+            query: metadata_value(QUERY_METADATA),
+            rows: metadata_value(ROWS_METADATA).parse().unwrap_or_default(),
+            bytes: metadata.len() as i64,
+            created,
+            expires: created + ttl,
+        });
+    }
+
+    Ok(entries)
+}
+
+/// Removes the cached results of the query, or all of them, and returns how many were removed
+pub fn invalidate(query: Option<&str>) -> Result<i64> {
+    let query = query.map(normalize_query);
+    let mut removed = 0;
+
+    for (path, _) in cache_files()? {
+        if let Some(query) = &query {
+            let matches = File::open(&path)
+                .ok()
+                .and_then(|file| FileReader::try_new(file, None).ok())
+                .is_some_and(|reader| {
+                    reader.schema().metadata().get(QUERY_METADATA) == Some(query)
+                });
+            if !matches {
+                continue;
+            }
+        }
+
+        if fs::remove_file(&path).is_ok() {
+            removed += 1;
+        }
+    }
+
+    Ok(removed)
+}
+
+fn evict(max_size: u64) -> Result<()> {
+    let mut files = cache_files()?;
+    let mut size = files
+        .iter()
+        .map(|(_, metadata)| metadata.len())
+        .sum::<u64>();
+    if size <= max_size {
+        return Ok(());
+    }
+
+    files.sort_by_key(|(_, metadata)| modified(metadata));
+    for (path, metadata) in files {
+        if size <= max_size {
+            break;
+        }
+        if fs::remove_file(&path).is_ok() {
+            size -= metadata.len();
+        }
+    }
+
+    Ok(())
+}
+
+fn cache_files() -> Result<Vec<(PathBuf, fs::Metadata)>> {
+    let directory = match fs::read_dir(CACHE_DIRECTORY) {
+        Ok(directory) => directory,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(vec![]),
+        Err(err) => bail!("failed to read {}: {}", CACHE_DIRECTORY, err),
+    };
+
+    Ok(directory
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == CACHE_EXTENSION))
+        .filter_map(|path| fs::metadata(&path).ok().map(|metadata| (path, metadata)))
+        .collect())
+}
+
+fn modified(metadata: &fs::Metadata) -> SystemTime {
+    metadata.modified().unwrap_or(UNIX_EPOCH)
+}
+
+fn age(metadata: &fs::Metadata) -> Duration {
+    SystemTime::now()
+        .duration_since(modified(metadata))
+        .unwrap_or_default()
+}
+
+fn ttl() -> Duration {
+    Duration::from_secs(GUCS.result_cache_ttl.get().max(0) as u64)
+}
+
+fn max_size() -> u64 {
+    GUCS.result_cache_size.get().max(0) as u64 * 1024 * 1024
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize_query() {
+        assert_eq!(
+            normalize_query("  SELECT  a,\n\tb FROM t WHERE s = 'x  y' ;  "),
+            "SELECT a, b FROM t WHERE s = 'x  y'"
+        );
+        assert_eq!(
+            normalize_query("SELECT \"a  b\"   FROM t"),
+            "SELECT \"a  b\" FROM t"
+        );
+    }
+
+    #[test]
+    fn test_is_cacheable() {
+        assert!(is_cacheable("SELECT COUNT(*) FROM trips"));
+        assert!(!is_cacheable(
+            "SELECT * FROM trips ORDER BY RANDOM() LIMIT 10"
+        ));
+    }
+
+    #[test]
+    fn test_cache_key() {
+        let session = ["public".to_string()];
+        let key = CacheKey::new("SELECT  1;", "SELECT 1", &session, &[]);
+        assert_eq!(key.query, "SELECT 1");
+        assert_eq!(key.key, "SELECT 1\npublic");
+        assert_eq!(
+            key.hash,
+            CacheKey::new("SELECT 1", "SELECT\n1", &session, &[]).hash
+        );
+        assert_ne!(
+            key.hash,
+            CacheKey::new("SELECT 1", "SELECT 1", &["main".to_string()], &[]).hash
+        );
+    }
+}
@@ -17,27 +17,30 @@
 
 use anyhow::{anyhow, bail, Result};
 use duckdb::arrow::array::types::{
-    ArrowTemporalType, Date32Type, Date64Type, Decimal128Type, IntervalDayTimeType,
+    ArrowTemporalType, Date32Type, Date64Type, Decimal128Type, Decimal256Type, IntervalDayTimeType,
     IntervalMonthDayNanoType, IntervalYearMonthType, Time32MillisecondType, Time32SecondType,
     Time64MicrosecondType, Time64NanosecondType, TimestampMicrosecondType,
     TimestampMillisecondType, TimestampNanosecondType, TimestampSecondType, UInt16Type, UInt32Type,
     UInt64Type, UInt8Type,
 };
 use duckdb::arrow::array::{
-    timezone::Tz, Array, ArrayAccessor, ArrayRef, ArrowPrimitiveType, AsArray, BinaryArray,
-    BooleanArray, Decimal128Array, Float16Array, Float32Array, Float64Array, GenericByteArray,
-    Int16Array, Int32Array, Int64Array, Int8Array, LargeBinaryArray, StringArray,
+    Array, ArrayAccessor, ArrayRef, ArrowPrimitiveType, AsArray, BinaryArray, BooleanArray,
+    Float16Array, Float32Array, Float64Array, GenericByteArray, Int16Array, Int32Array, Int64Array,
+    Int8Array, LargeBinaryArray, StringArray,
 };
 use duckdb::arrow::datatypes::{DataType, DecimalType, GenericStringType, IntervalUnit, TimeUnit};
+use duckdb::arrow::util::display::{ArrayFormatter, FormatOptions};
 use pgrx::*;
 use serde_json::{value::Number, Map, Value};
 use std::any::type_name;
+use std::ffi::{c_char, CString};
 use std::fmt::Debug;
 use std::str::FromStr;
 use std::sync::Arc;
 use supabase_wrappers::interface::Cell;
 
 use super::datetime::*;
+use super::postgis::*;
 
 type LargeStringArray = GenericByteArray<GenericStringType<i64>>;
 
@@ -298,9 +301,13 @@ where
                             );
                         }
                     }
-                    DataType::Decimal128(p, s) => {
+                    DataType::Decimal128(_, _) | DataType::Decimal256(_, _) => {
                         let column = downcast_array.column(column_index);
-                        if let Some(value) = column.get_decimal_value::<f64>(index, *p, *s)? {
+                        if let Some(value) = column.get_decimal_value::<f64>(
+                            index,
+                            pg_sys::FLOAT8OID,
+                            column_name,
+                        )? {
                             map.insert(
                                 column_name.to_string(),
                                 Value::Number(Number::from_f64(value).ok_or_else(|| {
@@ -443,23 +450,45 @@ pub trait GetDecimalValue
 where
     Self: Array + AsArray,
 {
-    fn get_decimal_value<N>(&self, index: usize, precision: u8, scale: i8) -> Result<Option<N>>
+    /// Reads a Decimal128 or Decimal256 value. DuckDB exports HUGEINT and UHUGEINT as Decimal128,
+    /// and wide decimals, e.g. from finance exports, can arrive as Decimal256.
+    fn get_numeric_value(&self, index: usize) -> Result<Option<AnyNumeric>> {
+        if self.is_null(index) {
+            return Ok(None);
+        }
+
+        let value = match self.data_type() {
+            DataType::Decimal128(p, s) => Decimal128Type::format_decimal(
+                self.as_primitive::<Decimal128Type>().value(index),
+                *p,
+                *s,
+            ),
+            DataType::Decimal256(p, s) => Decimal256Type::format_decimal(
+                self.as_primitive::<Decimal256Type>().value(index),
+                *p,
+                *s,
+            ),
+            unsupported => bail!("{:?} is not a decimal type", unsupported),
+        };
+
+        Ok(Some(AnyNumeric::from_str(&value)?))
+    }
+
+    fn get_decimal_value<N>(&self, index: usize, oid: pg_sys::Oid, name: &str) -> Result<Option<N>>
     where
-        N: std::marker::Send + std::marker::Sync + TryFrom<AnyNumeric>,
-        <N as TryFrom<pgrx::AnyNumeric>>::Error: Sync + Send + std::error::Error + 'static,
+        N: TryFrom<AnyNumeric>,
     {
-        let downcast_array = self
-            .as_any()
-            .downcast_ref::<Decimal128Array>()
-            .ok_or_else(|| anyhow!("failed to downcast Decimal128 array"))?;
-        match downcast_array.nulls().is_some() && downcast_array.is_null(index) {
-            false => {
-                let value = downcast_array.value(index);
-                let numeric =
-                    AnyNumeric::from_str(&Decimal128Type::format_decimal(value, precision, scale))?;
-                Ok(Some(N::try_from(numeric)?))
-            }
-            true => Ok(None),
+        match self.get_numeric_value(index)? {
+            Some(numeric) => match N::try_from(numeric.clone()) {
+                Ok(value) => Ok(Some(value)),
+                Err(_) => Err(DataTypeError::ValueOutOfRange(
+                    name.to_string(),
+                    numeric.to_string(),
+                    PgOid::from(oid),
+                )
+                .into()),
+            },
+            None => Ok(None),
         }
     }
 }
@@ -583,6 +612,7 @@ where
         &self,
         index: usize,
         tz: Option<Arc<str>>,
+        default_tz: Option<&str>,
     ) -> Result<Option<datum::TimestampWithTimeZone>>
     where
         T: ArrowPrimitiveType<Native = i64> + ArrowTemporalType,
@@ -592,26 +622,64 @@ where
             return Ok(None);
         }
 
-        match tz {
-            Some(tz) => {
-                let datetime = downcast_array
-                    .value_as_datetime_with_tz(index, Tz::from_str(&tz)?)
-                    .ok_or_else(|| anyhow!("failed to convert timestamp to NaiveDateTime"))?;
+        let datetime = downcast_array
+            .value_as_datetime(index)
+            .ok_or_else(|| anyhow!("failed to convert timestamp to NaiveDateTime"))?;
 
-                Ok(Some(datum::TimestampWithTimeZone::try_from(
-                    DateTimeTz::new(datetime, &tz),
-                )?))
-            }
-            None => {
-                let datetime = downcast_array
-                    .value_as_datetime(index)
-                    .ok_or_else(|| anyhow!("failed to convert timestamp to NaiveDateTime"))?;
+        // Arrow stores zoned timestamps as UTC instants, which stay unambiguous across DST changes.
+        // Timestamps without a zone are wall clock times, read in the table or session time zone.
+        let zone = match (tz, default_tz) {
+            (Some(_), _) => "UTC".to_string(),
+            (None, Some(default_tz)) => default_tz.to_string(),
+            (None, None) => session_timezone(),
+        };
 
-                Ok(Some(datum::TimestampWithTimeZone::try_from(DateTimeNoTz(
-                    datetime,
-                ))?))
+        Ok(Some(datum::TimestampWithTimeZone::try_from(
+            DateTimeInZone(datetime, zone),
+        )?))
+    }
+}
+
+pub trait GetTimeTzValue
+where
+    Self: Array + AsArray + GetTextInputValue,
+{
+    fn get_timetz_value(&self, index: usize, oid: pg_sys::Oid, name: &str) -> Result<Option<Cell>> {
+        if self.is_null(index) {
+            return Ok(None);
+        }
+
+        let time = match self.data_type() {
+            // Foreign scans read timetz as text, because Arrow times can't carry the offset
+            DataType::Utf8 | DataType::LargeUtf8 => return self.get_text_input_value(index, oid),
+            DataType::Time64(TimeUnit::Nanosecond) => self
+                .as_primitive::<Time64NanosecondType>()
+                .value_as_time(index),
+            DataType::Time64(TimeUnit::Microsecond) => self
+                .as_primitive::<Time64MicrosecondType>()
+                .value_as_time(index),
+            DataType::Time32(TimeUnit::Millisecond) => self
+                .as_primitive::<Time32MillisecondType>()
+                .value_as_time(index),
+            DataType::Time32(TimeUnit::Second) => {
+                self.as_primitive::<Time32SecondType>().value_as_time(index)
+            }
+            unsupported => {
+                return Err(DataTypeError::DataTypeMismatch(
+                    name.to_string(),
+                    unsupported.clone(),
+                    PgOid::from(oid),
+                )
+                .into())
             }
         }
+        .ok_or_else(|| anyhow!("failed to convert time to NaiveTime"))?;
+
+        // timetz is passed by reference, so Cell::Bytea hands the datum to Postgres unchanged
+        let datum = datum::TimeWithTimeZone::try_from(Time(time))?
+            .into_datum()
+            .ok_or_else(|| anyhow!("failed to convert timetz to datum"))?;
+        Ok(Some(Cell::Bytea(datum.cast_mut_ptr())))
     }
 }
 
@@ -660,6 +728,67 @@ where
     }
 }
 
+pub trait GetPostgisValue
+where
+    Self: Array + AsArray,
+{
+    fn get_postgis_value(
+        &self,
+        index: usize,
+        oid: pg_sys::Oid,
+        name: &str,
+    ) -> Result<Option<Cell>> {
+        if self.is_null(index) {
+            return Ok(None);
+        }
+
+        // WKB arrives as binary, while hex WKB with an SRID prefix arrives as text
+        let value = match self.data_type() {
+            DataType::Binary => wkb_to_hex(self.as_binary::<i32>().value(index)),
+            DataType::LargeBinary => wkb_to_hex(self.as_binary::<i64>().value(index)),
+            DataType::Utf8 => self.as_string::<i32>().value(index).to_string(),
+            DataType::LargeUtf8 => self.as_string::<i64>().value(index).to_string(),
+            unsupported => {
+                return Err(DataTypeError::DataTypeMismatch(
+                    name.to_string(),
+                    unsupported.clone(),
+                    PgOid::from(oid),
+                )
+                .into())
+            }
+        };
+
+        // PostGIS accepts hex encoded WKB optionally prefixed with SRID=<srid>;
+        let datum = input_datum(oid, &value)?;
+        Ok(Some(Cell::Bytea(datum.cast_mut_ptr())))
+    }
+}
+
+pub trait GetTextInputValue
+where
+    Self: Array + AsArray,
+{
+    /// Reads a value through the input function of its Postgres type, for columns declared
+    /// with types that have no Arrow conversion, e.g. with the type_overrides option
+    fn get_text_input_value(&self, index: usize, oid: pg_sys::Oid) -> Result<Option<Cell>> {
+        if self.is_null(index) {
+            return Ok(None);
+        }
+
+        let value = match self.data_type() {
+            DataType::Utf8 => self.as_string::<i32>().value(index).to_string(),
+            DataType::LargeUtf8 => self.as_string::<i64>().value(index).to_string(),
+            _ => ArrayFormatter::try_new(self.slice(index, 1).as_ref(), &FormatOptions::default())?
+                .value(0)
+                .to_string(),
+        };
+
+        // Cell has no variant for arbitrary types, so Cell::Bytea hands the datum to Postgres unchanged
+        let datum = input_datum(oid, &value)?;
+        Ok(Some(Cell::Bytea(datum.cast_mut_ptr())))
+    }
+}
+
 pub trait GetCell
 where
     Self: Array
@@ -679,10 +808,44 @@ where
         + GetTimeValue
         + GetTimestampValue
         + GetTimestampTzValue
+        + GetTimeTzValue
         + GetUIntValue
-        + GetUuidValue,
+        + GetUuidValue
+        + GetPostgisValue
+        + GetTextInputValue,
 {
     fn get_cell(&self, index: usize, oid: pg_sys::Oid, name: &str) -> Result<Option<Cell>> {
+        self.get_cell_in_timezone(index, oid, name, None)
+    }
+
+    /// Like get_cell, but timestamps without a zone are read into timestamptz in the given time zone
+    /// instead of the session TimeZone
+    fn get_cell_in_timezone(
+        &self,
+        index: usize,
+        oid: pg_sys::Oid,
+        name: &str,
+        timezone: Option<&str>,
+    ) -> Result<Option<Cell>> {
+        match self.get_native_cell(index, oid, name, timezone) {
+            // Text is read into any type that accepts it as input, e.g. a VARCHAR column overridden to INTEGER
+            Err(err)
+                if err.downcast_ref::<DataTypeError>().is_some()
+                    && matches!(self.data_type(), DataType::Utf8 | DataType::LargeUtf8) =>
+            {
+                self.get_text_input_value(index, oid)
+            }
+            cell => cell,
+        }
+    }
+
+    fn get_native_cell(
+        &self,
+        index: usize,
+        oid: pg_sys::Oid,
+        name: &str,
+        timezone: Option<&str>,
+    ) -> Result<Option<Cell>> {
         match oid {
             pg_sys::BOOLOID => match self.get_primitive_value::<BooleanArray>(index)? {
                 Some(value) => Ok(Some(Cell::Bool(value))),
@@ -732,15 +895,15 @@ where
                     None => Ok(None),
                 },
                 DataType::UInt16 => match self.get_uint_value::<UInt16Type>(index)? {
-                    Some(value) => Ok(Some(Cell::I16(value as i16))),
+                    Some(value) => Ok(Some(Cell::I16(checked_int(value, oid, name)?))),
                     None => Ok(None),
                 },
                 DataType::UInt32 => match self.get_uint_value::<UInt32Type>(index)? {
-                    Some(value) => Ok(Some(Cell::I16(value as i16))),
+                    Some(value) => Ok(Some(Cell::I16(checked_int(value, oid, name)?))),
                     None => Ok(None),
                 },
                 DataType::UInt64 => match self.get_uint_value::<UInt64Type>(index)? {
-                    Some(value) => Ok(Some(Cell::I16(value as i16))),
+                    Some(value) => Ok(Some(Cell::I16(checked_int(value, oid, name)?))),
                     None => Ok(None),
                 },
                 DataType::Float16 => match self.get_primitive_value::<Float16Array>(index)? {
@@ -755,8 +918,8 @@ where
                     Some(value) => Ok(Some(Cell::I16(value as i16))),
                     None => Ok(None),
                 },
-                DataType::Decimal128(p, s) => {
-                    match self.get_decimal_value::<i16>(index, *p, *s)? {
+                DataType::Decimal128(_, _) | DataType::Decimal256(_, _) => {
+                    match self.get_decimal_value::<i16>(index, oid, name)? {
                         Some(value) => Ok(Some(Cell::I16(value))),
                         None => Ok(None),
                     }
@@ -794,11 +957,11 @@ where
                     None => Ok(None),
                 },
                 DataType::UInt32 => match self.get_uint_value::<UInt32Type>(index)? {
-                    Some(value) => Ok(Some(Cell::I32(value as i32))),
+                    Some(value) => Ok(Some(Cell::I32(checked_int(value, oid, name)?))),
                     None => Ok(None),
                 },
                 DataType::UInt64 => match self.get_uint_value::<UInt64Type>(index)? {
-                    Some(value) => Ok(Some(Cell::I32(value as i32))),
+                    Some(value) => Ok(Some(Cell::I32(checked_int(value, oid, name)?))),
                     None => Ok(None),
                 },
                 DataType::Float16 => match self.get_primitive_value::<Float16Array>(index)? {
@@ -813,8 +976,8 @@ where
                     Some(value) => Ok(Some(Cell::I32(value as i32))),
                     None => Ok(None),
                 },
-                DataType::Decimal128(p, s) => {
-                    match self.get_decimal_value::<i32>(index, *p, *s)? {
+                DataType::Decimal128(_, _) | DataType::Decimal256(_, _) => {
+                    match self.get_decimal_value::<i32>(index, oid, name)? {
                         Some(value) => Ok(Some(Cell::I32(value))),
                         None => Ok(None),
                     }
@@ -856,7 +1019,7 @@ where
                     None => Ok(None),
                 },
                 DataType::UInt64 => match self.get_uint_value::<UInt64Type>(index)? {
-                    Some(value) => Ok(Some(Cell::I64(value as i64))),
+                    Some(value) => Ok(Some(Cell::I64(checked_int(value, oid, name)?))),
                     None => Ok(None),
                 },
                 DataType::Float16 => match self.get_primitive_value::<Float16Array>(index)? {
@@ -871,8 +1034,8 @@ where
                     Some(value) => Ok(Some(Cell::I64(value as i64))),
                     None => Ok(None),
                 },
-                DataType::Decimal128(p, s) => {
-                    match self.get_decimal_value::<i64>(index, *p, *s)? {
+                DataType::Decimal128(_, _) | DataType::Decimal256(_, _) => {
+                    match self.get_decimal_value::<i64>(index, oid, name)? {
                         Some(value) => Ok(Some(Cell::I64(value))),
                         None => Ok(None),
                     }
@@ -929,8 +1092,8 @@ where
                     Some(value) => Ok(Some(Cell::F32(value as f32))),
                     None => Ok(None),
                 },
-                DataType::Decimal128(p, s) => {
-                    match self.get_decimal_value::<f32>(index, *p, *s)? {
+                DataType::Decimal128(_, _) | DataType::Decimal256(_, _) => {
+                    match self.get_decimal_value::<f32>(index, oid, name)? {
                         Some(value) => Ok(Some(Cell::F32(value))),
                         None => Ok(None),
                     }
@@ -987,8 +1150,8 @@ where
                     Some(value) => Ok(Some(Cell::F64(value))),
                     None => Ok(None),
                 },
-                DataType::Decimal128(p, s) => {
-                    match self.get_decimal_value::<f64>(index, *p, *s)? {
+                DataType::Decimal128(_, _) | DataType::Decimal256(_, _) => {
+                    match self.get_decimal_value::<f64>(index, oid, name)? {
                         Some(value) => Ok(Some(Cell::F64(value))),
                         None => Ok(None),
                     }
@@ -1045,11 +1208,9 @@ where
                     Some(value) => Ok(Some(Cell::Numeric(AnyNumeric::try_from(value)?))),
                     None => Ok(None),
                 },
-                DataType::Decimal128(p, s) => {
-                    match self.get_primitive_value::<Decimal128Array>(index)? {
-                        Some(value) => Ok(Some(Cell::Numeric(AnyNumeric::from_str(
-                            &Decimal128Type::format_decimal(value, *p, *s),
-                        )?))),
+                DataType::Decimal128(_, _) | DataType::Decimal256(_, _) => {
+                    match self.get_numeric_value(index)? {
+                        Some(value) => Ok(Some(Cell::Numeric(value))),
                         None => Ok(None),
                     }
                 }
@@ -1082,6 +1243,21 @@ where
                             None => Ok(None),
                         }
                     }
+                    // Wide integers and decimals keep all their digits when read as text
+                    DataType::Int64 => match self.get_primitive_value::<Int64Array>(index)? {
+                        Some(value) => Ok(Some(Cell::String(value.to_string()))),
+                        None => Ok(None),
+                    },
+                    DataType::UInt64 => match self.get_uint_value::<UInt64Type>(index)? {
+                        Some(value) => Ok(Some(Cell::String(value.to_string()))),
+                        None => Ok(None),
+                    },
+                    DataType::Decimal128(_, _) | DataType::Decimal256(_, _) => {
+                        match self.get_numeric_value(index)? {
+                            Some(value) => Ok(Some(Cell::String(value.to_string()))),
+                            None => Ok(None),
+                        }
+                    }
                     unsupported => Err(DataTypeError::DataTypeMismatch(
                         name.to_string(),
                         unsupported.clone(),
@@ -1231,6 +1407,7 @@ where
                 )
                 .into()),
             },
+            pg_sys::TIMETZOID => self.get_timetz_value(index, oid, name),
             pg_sys::TIMESTAMPOID => match self.data_type() {
                 DataType::Timestamp(TimeUnit::Nanosecond, _) => {
                     match self.get_timestamp_value::<TimestampNanosecondType>(index)? {
@@ -1273,31 +1450,41 @@ where
             },
             pg_sys::TIMESTAMPTZOID => match self.data_type() {
                 DataType::Timestamp(TimeUnit::Nanosecond, tz) => {
-                    match self
-                        .get_timestamptz_value::<TimestampNanosecondType>(index, tz.clone())?
-                    {
+                    match self.get_timestamptz_value::<TimestampNanosecondType>(
+                        index,
+                        tz.clone(),
+                        timezone,
+                    )? {
                         Some(value) => Ok(Some(Cell::Timestamptz(value))),
                         None => Ok(None),
                     }
                 }
                 DataType::Timestamp(TimeUnit::Microsecond, tz) => {
-                    match self
-                        .get_timestamptz_value::<TimestampMicrosecondType>(index, tz.clone())?
-                    {
+                    match self.get_timestamptz_value::<TimestampMicrosecondType>(
+                        index,
+                        tz.clone(),
+                        timezone,
+                    )? {
                         Some(value) => Ok(Some(Cell::Timestamptz(value))),
                         None => Ok(None),
                     }
                 }
                 DataType::Timestamp(TimeUnit::Millisecond, tz) => {
-                    match self
-                        .get_timestamptz_value::<TimestampMillisecondType>(index, tz.clone())?
-                    {
+                    match self.get_timestamptz_value::<TimestampMillisecondType>(
+                        index,
+                        tz.clone(),
+                        timezone,
+                    )? {
                         Some(value) => Ok(Some(Cell::Timestamptz(value))),
                         None => Ok(None),
                     }
                 }
                 DataType::Timestamp(TimeUnit::Second, tz) => {
-                    match self.get_timestamptz_value::<TimestampSecondType>(index, tz.clone())? {
+                    match self.get_timestamptz_value::<TimestampSecondType>(
+                        index,
+                        tz.clone(),
+                        timezone,
+                    )? {
                         Some(value) => Ok(Some(Cell::Timestamptz(value))),
                         None => Ok(None),
                     }
@@ -1372,12 +1559,8 @@ where
                     None => Ok(None),
                 }
             }
-            unsupported => Err(DataTypeError::DataTypeMismatch(
-                name.to_string(),
-                self.data_type().clone(),
-                PgOid::from(unsupported),
-            )
-            .into()),
+            // Types without an Arrow conversion, e.g. inet or citext
+            _ => self.get_text_input_value(index, oid),
         }
     }
 }
@@ -1390,6 +1573,8 @@ impl GetDecimalValue for ArrayRef {}
 impl GetIntervalDayTimeValue for ArrayRef {}
 impl GetIntervalMonthDayNanoValue for ArrayRef {}
 impl GetIntervalYearMonthValue for ArrayRef {}
+impl GetPostgisValue for ArrayRef {}
+impl GetTextInputValue for ArrayRef {}
 impl GetListValue for ArrayRef {}
 impl GetPrimitiveValue for ArrayRef {}
 impl GetPrimitiveListValue for ArrayRef {}
@@ -1398,20 +1583,47 @@ impl GetStructValue for ArrayRef {}
 impl GetTimeValue for ArrayRef {}
 impl GetTimestampValue for ArrayRef {}
 impl GetTimestampTzValue for ArrayRef {}
+impl GetTimeTzValue for ArrayRef {}
 impl GetUIntValue for ArrayRef {}
 impl GetUuidValue for ArrayRef {}
 
 #[derive(Debug)]
 pub enum DataTypeError {
     DataTypeMismatch(String, DataType, PgOid),
+    ValueOutOfRange(String, String, PgOid),
 }
 
 impl std::fmt::Display for DataTypeError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             DataTypeError::DataTypeMismatch(arg1, arg2, arg3) => write!(f, "Column {} has Arrow data type {:?} but is mapped to the {:?} type in Postgres, which are incompatible.", arg1, arg2, arg3),
+            DataTypeError::ValueOutOfRange(arg1, arg2, arg3) => write!(f, "Column {} has value {}, which is out of range for the {:?} type in Postgres.", arg1, arg2, arg3),
         }
     }
 }
 
 impl std::error::Error for DataTypeError {}
+
+// Unsigned values above the maximum of the signed Postgres integer types fail instead of wrapping
+fn checked_int<N: TryFrom<u64>>(value: u64, oid: pg_sys::Oid, name: &str) -> Result<N> {
+    N::try_from(value).map_err(|_| {
+        DataTypeError::ValueOutOfRange(name.to_string(), value.to_string(), PgOid::from(oid)).into()
+    })
+}
+
+/// Builds a Postgres value from its text representation with the input function of its type
+pub fn input_datum(oid: pg_sys::Oid, value: &str) -> Result<pg_sys::Datum> {
+    let mut input_function = pg_sys::InvalidOid;
+    let mut io_param = pg_sys::InvalidOid;
+    let value = CString::new(value)?;
+
+    unsafe {
+        pg_sys::getTypeInputInfo(oid, &mut input_function, &mut io_param);
+        Ok(pg_sys::OidInputFunctionCall(
+            input_function,
+            value.as_ptr() as *mut c_char,
+            io_param,
+            -1,
+        ))
+    }
+}
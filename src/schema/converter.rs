@@ -0,0 +1,199 @@
+// Copyright (c) 2023-2025 Retake, Inc.
+//
+// This file is part of ParadeDB - Postgres for Search and Analytics
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <http://www.gnu.org/licenses/>.
+
+use anyhow::Result;
+use duckdb::arrow::array::{
+    Array, ArrayRef, AsArray, BooleanArray, Date32Array, Float32Array, Float64Array, Int16Array,
+    Int32Array, Int64Array, LargeStringArray, StringArray,
+};
+use duckdb::arrow::datatypes::DataType;
+use pgrx::*;
+use std::sync::Arc;
+use supabase_wrappers::interface::Cell;
+
+use super::cell::{DataTypeError, GetCell, GetPostgisValue};
+use super::postgis::postgis_type;
+
+// Days between the Unix epoch and the Postgres epoch, 2000-01-01
+const POSTGRES_EPOCH_DAYS: i32 = 10_957;
+
+// Range of DateADT, from 4714-11-24 BC to 5874897-12-31, in days from the Postgres epoch
+const MIN_DATE_DAYS: i32 = -2_451_545;
+const MAX_DATE_DAYS: i32 = 2_147_483_494 - 2_451_545;
+
+/// Converts the values of one Arrow column into Datums of one Postgres type.
+/// The conversion is chosen and the array downcast once per batch, while get_cell
+/// matches the type pair and downcasts again for every value.
+/// Arrays are held by their shared buffers, so a converter can outlive the batch it was built from.
+pub enum ColumnConverter {
+    Bool(BooleanArray),
+    Int2(Int16Array),
+    Int4(Int32Array),
+    Int8(Int64Array),
+    Int4AsInt8(Int32Array),
+    Float4(Float32Array),
+    Float8(Float64Array),
+    Float4AsFloat8(Float32Array),
+    Text(StringArray),
+    LargeText(LargeStringArray),
+    Date(Date32Array, Arc<str>),
+    // PostGIS types are recognized by name, which is looked up once per column rather than for every value
+    Postgis {
+        array: ArrayRef,
+        oid: pg_sys::Oid,
+        name: Arc<str>,
+    },
+    // Every other pair goes through get_cell and Cell
+    Cell {
+        array: ArrayRef,
+        oid: pg_sys::Oid,
+        name: Arc<str>,
+        timezone: Option<Arc<str>>,
+    },
+}
+
+impl ColumnConverter {
+    pub fn new(array: &ArrayRef, oid: pg_sys::Oid, name: &Arc<str>) -> Self {
+        match (oid, array.data_type()) {
+            (pg_sys::BOOLOID, DataType::Boolean) => Self::Bool(array.as_boolean().clone()),
+            (pg_sys::INT2OID, DataType::Int16) => Self::Int2(array.as_primitive().clone()),
+            (pg_sys::INT4OID, DataType::Int32) => Self::Int4(array.as_primitive().clone()),
+            (pg_sys::INT8OID, DataType::Int64) => Self::Int8(array.as_primitive().clone()),
+            (pg_sys::INT8OID, DataType::Int32) => Self::Int4AsInt8(array.as_primitive().clone()),
+            (pg_sys::FLOAT4OID, DataType::Float32) => Self::Float4(array.as_primitive().clone()),
+            (pg_sys::FLOAT8OID, DataType::Float64) => Self::Float8(array.as_primitive().clone()),
+            (pg_sys::FLOAT8OID, DataType::Float32) => {
+                Self::Float4AsFloat8(array.as_primitive().clone())
+            }
+            (pg_sys::TEXTOID | pg_sys::VARCHAROID, DataType::Utf8) => {
+                Self::Text(array.as_string().clone())
+            }
+            (pg_sys::TEXTOID | pg_sys::VARCHAROID, DataType::LargeUtf8) => {
+                Self::LargeText(array.as_string().clone())
+            }
+            (pg_sys::DATEOID, DataType::Date32) => {
+                Self::Date(array.as_primitive().clone(), name.clone())
+            }
+            _ => Self::cell(array, oid, name),
+        }
+    }
+
+    /// Reads every column through get_cell, as before converters were compiled per batch
+    pub fn cell(array: &ArrayRef, oid: pg_sys::Oid, name: &Arc<str>) -> Self {
+        if postgis_type(oid).is_some() {
+            return Self::Postgis {
+                array: array.clone(),
+                oid,
+                name: name.clone(),
+            };
+        }
+
+        Self::Cell {
+            array: array.clone(),
+            oid,
+            name: name.clone(),
+            timezone: None,
+        }
+    }
+
+    /// Timestamps without a zone are read in timezone, see get_cell_in_timezone
+    pub fn in_timezone(self, timezone: Option<&Arc<str>>) -> Self {
+        match self {
+            Self::Cell {
+                array, oid, name, ..
+            } => Self::Cell {
+                array,
+                oid,
+                name,
+                timezone: timezone.cloned(),
+            },
+            converter => converter,
+        }
+    }
+
+    /// Returns the Datum of the value at index, or None if it is NULL
+    pub fn datum(&self, index: usize) -> Result<Option<pg_sys::Datum>> {
+        Ok(match self {
+            Self::Bool(array) => value(array, index, |array| array.value(index).into_datum()),
+            Self::Int2(array) => value(array, index, |array| array.value(index).into_datum()),
+            Self::Int4(array) => value(array, index, |array| array.value(index).into_datum()),
+            Self::Int8(array) => value(array, index, |array| array.value(index).into_datum()),
+            Self::Int4AsInt8(array) => value(array, index, |array| {
+                (array.value(index) as i64).into_datum()
+            }),
+            Self::Float4(array) => value(array, index, |array| array.value(index).into_datum()),
+            Self::Float8(array) => value(array, index, |array| array.value(index).into_datum()),
+            Self::Float4AsFloat8(array) => value(array, index, |array| {
+                (array.value(index) as f64).into_datum()
+            }),
+            Self::Text(array) => value(array, index, |array| array.value(index).into_datum()),
+            Self::LargeText(array) => value(array, index, |array| array.value(index).into_datum()),
+            // DateADT is a day count from the Postgres epoch, passed by value like int4
+            Self::Date(array, name) => match array.is_null(index) {
+                true => None,
+                false => {
+                    let days = array.value(index).saturating_sub(POSTGRES_EPOCH_DAYS);
+                    if !(MIN_DATE_DAYS..MAX_DATE_DAYS).contains(&days) {
+                        return Err(DataTypeError::ValueOutOfRange(
+                            name.to_string(),
+                            array
+                                .value_as_date(index)
+                                .map(|date| date.to_string())
+                                .unwrap_or_default(),
+                            PgOid::from(pg_sys::DATEOID),
+                        )
+                        .into());
+                    }
+                    days.into_datum()
+                }
+            },
+            Self::Postgis { .. } | Self::Cell { .. } => match self.cell_value(index)? {
+                Some(cell) => cell.into_datum(),
+                None => None,
+            },
+        })
+    }
+
+    /// Returns the value at index as a Cell for the foreign scan's Row.
+    /// Converted Datums are carried as Cell::Bytea, which passes any Datum through into_datum unchanged.
+    pub fn cell_value(&self, index: usize) -> Result<Option<Cell>> {
+        match self {
+            Self::Cell {
+                array,
+                oid,
+                name,
+                timezone,
+            } => array.get_cell_in_timezone(index, *oid, name, timezone.as_deref()),
+            Self::Postgis { array, oid, name } => array.get_postgis_value(index, *oid, name),
+            _ => Ok(self
+                .datum(index)?
+                .map(|datum| Cell::Bytea(datum.cast_mut_ptr()))),
+        }
+    }
+}
+
+#[inline]
+fn value<A: Array>(
+    array: &A,
+    index: usize,
+    to_datum: impl FnOnce(&A) -> Option<pg_sys::Datum>,
+) -> Option<pg_sys::Datum> {
+    match array.is_null(index) {
+        true => None,
+        false => to_datum(array),
+    }
+}
@@ -19,6 +19,7 @@ use chrono::{
     DateTime, Datelike, NaiveDate, NaiveDateTime, NaiveTime, TimeDelta, TimeZone, Timelike,
 };
 use pgrx::*;
+use std::ffi::CStr;
 use std::fmt::Debug;
 use std::panic::{RefUnwindSafe, UnwindSafe};
 use std::str::FromStr;
@@ -31,6 +32,10 @@ pub struct Date(pub NaiveDate);
 #[derive(Clone, Debug)]
 pub struct DateTimeNoTz(pub NaiveDateTime);
 
+/// A wall clock time without an offset, read in the named time zone
+#[derive(Clone, Debug)]
+pub struct DateTimeInZone(pub NaiveDateTime, pub String);
+
 #[derive(Clone, Debug)]
 pub struct Time(pub NaiveTime);
 
@@ -60,6 +65,15 @@ impl<Tz: TimeZone> DateTimeTz<Tz> {
     }
 }
 
+/// Returns the name of the session TimeZone, e.g. Europe/Berlin
+pub fn session_timezone() -> String {
+    unsafe {
+        CStr::from_ptr(pg_sys::pg_get_timezone_name(pg_sys::session_timezone))
+            .to_string_lossy()
+            .into_owned()
+    }
+}
+
 impl TryFrom<DateTimeNoTz> for datum::Timestamp {
     type Error = datum::datetime_support::DateTimeConversionError;
 
@@ -116,6 +130,42 @@ where
     }
 }
 
+impl TryFrom<DateTimeInZone> for datum::TimestampWithTimeZone {
+    type Error = datum::datetime_support::DateTimeConversionError;
+
+    // Postgres resolves the offset from its time zone database, so wall clock times
+    // skipped or repeated by a DST change follow the same rules as timestamptz input
+    fn try_from(datetime: DateTimeInZone) -> Result<Self, Self::Error> {
+        let DateTimeInZone(datetime, tz) = datetime;
+
+        datum::TimestampWithTimeZone::with_timezone(
+            datetime.year(),
+            datetime.month() as u8,
+            datetime.day() as u8,
+            datetime.hour() as u8,
+            datetime.minute() as u8,
+            datetime.second() as f64 + datetime.nanosecond() as f64 / NANOSECONDS_IN_SECOND as f64,
+            tz,
+        )
+    }
+}
+
+impl TryFrom<Time> for datum::TimeWithTimeZone {
+    type Error = datum::datetime_support::DateTimeConversionError;
+
+    // Arrow times carry no offset, so they are read as UTC
+    fn try_from(time: Time) -> Result<Self, Self::Error> {
+        let Time(time) = time;
+
+        datum::TimeWithTimeZone::with_timezone(
+            time.hour() as u8,
+            time.minute() as u8,
+            time.second() as f64 + time.nanosecond() as f64 / NANOSECONDS_IN_SECOND as f64,
+            "UTC",
+        )
+    }
+}
+
 impl TryFrom<Date> for datum::Date {
     type Error = datum::datetime_support::DateTimeConversionError;
 
@@ -0,0 +1,446 @@
+// Copyright (c) 2023-2025 Retake, Inc.
+//
+// This file is part of ParadeDB - Postgres for Search and Analytics
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <http://www.gnu.org/licenses/>.
+
+use anyhow::{anyhow, bail, Result};
+use std::iter::Peekable;
+use std::str::Chars;
+
+/// A DuckDB logical type, as printed by DESCRIBE
+#[derive(Clone, Debug, PartialEq)]
+pub enum DuckDbType {
+    Boolean,
+    TinyInt,
+    SmallInt,
+    Integer,
+    BigInt,
+    HugeInt,
+    UTinyInt,
+    USmallInt,
+    UInteger,
+    UBigInt,
+    UHugeInt,
+    Float,
+    Double,
+    Decimal(Option<(u32, u32)>),
+    Varchar,
+    Blob,
+    Bit,
+    Date,
+    Time,
+    TimeTz,
+    Timestamp,
+    TimestampTz,
+    Interval,
+    Uuid,
+    Json,
+    // DuckDB spatial types
+    Geometry,
+    WkbBlob,
+    Point,
+    Box,
+    Polygon,
+    LineString,
+    List(Box<DuckDbType>),
+    Array(Box<DuckDbType>, usize),
+    Struct(Vec<(String, DuckDbType)>),
+    Map(Box<DuckDbType>, Box<DuckDbType>),
+    Union(Vec<(String, DuckDbType)>),
+    Enum(Vec<String>),
+    Invalid,
+    // Types without a dedicated mapping keep their DuckDB name
+    Other(String),
+}
+
+impl DuckDbType {
+    pub fn parse(duckdb_type: &str) -> Result<Self> {
+        let mut parser = TypeParser {
+            chars: duckdb_type.trim().chars().peekable(),
+        };
+        let parsed = parser.parse_type()?;
+        parser.skip_whitespace();
+
+        match parser.chars.peek() {
+            None => Ok(parsed),
+            Some(_) => bail!(
+                "unexpected trailing characters in DuckDB type {}",
+                duckdb_type
+            ),
+        }
+    }
+
+    /// Returns the Postgres type that values of this type are read into
+    pub fn to_postgres(&self, column_name: &str) -> Result<String> {
+        Ok(match self {
+            Self::Boolean => "BOOLEAN".to_string(),
+            Self::TinyInt | Self::SmallInt => "SMALLINT".to_string(),
+            Self::UTinyInt | Self::USmallInt | Self::Integer => "INTEGER".to_string(),
+            Self::UInteger | Self::BigInt => "BIGINT".to_string(),
+            Self::UBigInt | Self::HugeInt | Self::UHugeInt => "NUMERIC".to_string(),
+            Self::Float => "FLOAT".to_string(),
+            Self::Double => "DOUBLE PRECISION".to_string(),
+            Self::Decimal(Some((precision, scale))) => format!("NUMERIC({precision},{scale})"),
+            Self::Decimal(None) => "NUMERIC".to_string(),
+            Self::Varchar => "VARCHAR".to_string(),
+            // Geometries are stored as blobs, and are read as their raw bytes unless PostGIS is installed
+            Self::Blob | Self::Geometry | Self::WkbBlob => "TEXT".to_string(),
+            Self::Date => "DATE".to_string(),
+            Self::Time => "TIME".to_string(),
+            Self::TimeTz => "TIME WITH TIME ZONE".to_string(),
+            Self::Timestamp => "TIMESTAMP".to_string(),
+            Self::TimestampTz => "TIMESTAMP WITH TIME ZONE".to_string(),
+            Self::Interval => "INTERVAL".to_string(),
+            Self::Uuid => "UUID".to_string(),
+            Self::Json => "JSON".to_string(),
+            Self::Point => "POINT".to_string(),
+            Self::Box => "BOX".to_string(),
+            Self::Polygon => "POLYGON".to_string(),
+            Self::LineString => "LINE".to_string(),
+            // Lists of structs are read as a single JSON document, like structs
+            Self::List(element) | Self::Array(element, _) => match element.innermost() {
+                Self::Struct(_) => "JSONB".to_string(),
+                _ => format!("{}[]", element.to_postgres(column_name)?),
+            },
+            Self::Struct(_) => "JSONB".to_string(),
+            Self::Map(_, _) => bail!(
+                "Column '{}' has type MAP, which is not supported",
+                column_name
+            ),
+            Self::Union(_) => bail!(
+                "Column '{}' has type UNION, which is not supported",
+                column_name
+            ),
+            Self::Enum(_) => bail!(
+                "Column '{}' has type ENUM, which is not supported",
+                column_name
+            ),
+            Self::Bit => bail!(
+                "Column '{}' has type BIT, which is not supported",
+                column_name
+            ),
+            Self::Invalid => bail!("Column '{}' has an invalid DuckDB type", column_name),
+            Self::Other(name) => name.clone(),
+        })
+    }
+
+    fn innermost(&self) -> &Self {
+        match self {
+            Self::List(element) | Self::Array(element, _) => element.innermost(),
+            other => other,
+        }
+    }
+
+    fn from_name(name: &str) -> Self {
+        match name.to_uppercase().as_str() {
+            "BOOLEAN" | "BOOL" | "LOGICAL" => Self::Boolean,
+            "TINYINT" | "INT1" => Self::TinyInt,
+            "SMALLINT" | "INT2" | "SHORT" => Self::SmallInt,
+            "INTEGER" | "INT" | "INT4" | "SIGNED" => Self::Integer,
+            "BIGINT" | "INT8" | "LONG" => Self::BigInt,
+            "HUGEINT" | "INT128" => Self::HugeInt,
+            "UTINYINT" => Self::UTinyInt,
+            "USMALLINT" => Self::USmallInt,
+            "UINTEGER" => Self::UInteger,
+            "UBIGINT" => Self::UBigInt,
+            "UHUGEINT" => Self::UHugeInt,
+            "FLOAT" | "FLOAT4" | "REAL" => Self::Float,
+            "DOUBLE" | "FLOAT8" | "DOUBLE PRECISION" => Self::Double,
+            "DECIMAL" | "NUMERIC" => Self::Decimal(None),
+            "VARCHAR" | "TEXT" | "STRING" | "CHAR" | "BPCHAR" => Self::Varchar,
+            "BLOB" | "BYTEA" | "BINARY" | "VARBINARY" => Self::Blob,
+            "BIT" | "BITSTRING" => Self::Bit,
+            "DATE" => Self::Date,
+            "TIME" => Self::Time,
+            "TIME WITH TIME ZONE" | "TIMETZ" => Self::TimeTz,
+            "TIMESTAMP" | "DATETIME" | "TIMESTAMP_S" | "TIMESTAMP_MS" | "TIMESTAMP_NS"
+            | "TIMESTAMP_US" => Self::Timestamp,
+            "TIMESTAMP WITH TIME ZONE" | "TIMESTAMPTZ" => Self::TimestampTz,
+            "INTERVAL" => Self::Interval,
+            "UUID" => Self::Uuid,
+            "JSON" => Self::Json,
+            "GEOMETRY" => Self::Geometry,
+            "WKB_BLOB" => Self::WkbBlob,
+            "POINT_2D" | "POINT_3D" | "POINT_4D" => Self::Point,
+            "BOX_2D" => Self::Box,
+            "POLYGON_2D" => Self::Polygon,
+            "LINESTRING_2D" => Self::LineString,
+            "INVALID" => Self::Invalid,
+            _ => Self::Other(name.to_string()),
+        }
+    }
+}
+
+struct TypeParser<'a> {
+    chars: Peekable<Chars<'a>>,
+}
+
+impl TypeParser<'_> {
+    fn parse_type(&mut self) -> Result<DuckDbType> {
+        self.skip_whitespace();
+        let name = self.parse_name()?;
+
+        self.skip_whitespace();
+        let mut parsed = match self.chars.peek() {
+            Some('(') => {
+                self.chars.next();
+                let parsed = self.parse_parameters(&name)?;
+                self.expect(')')?;
+                parsed
+            }
+            _ => DuckDbType::from_name(&name),
+        };
+
+        // INTEGER[] is a list and INTEGER[3] a fixed size array, and both can be nested
+        loop {
+            self.skip_whitespace();
+            if self.chars.peek() != Some(&'[') {
+                break;
+            }
+            self.chars.next();
+
+            let size = self.take_while(|c| c.is_ascii_digit());
+            self.expect(']')?;
+            parsed = match size.is_empty() {
+                true => DuckDbType::List(Box::new(parsed)),
+                false => DuckDbType::Array(Box::new(parsed), size.parse()?),
+            };
+        }
+
+        Ok(parsed)
+    }
+
+    // Names can span several words, e.g. TIMESTAMP WITH TIME ZONE or DOUBLE PRECISION
+    fn parse_name(&mut self) -> Result<String> {
+        let mut name = self.parse_word();
+        if name.is_empty() {
+            bail!("expected a DuckDB type name");
+        }
+
+        loop {
+            let mut lookahead = self.chars.clone();
+            while lookahead.peek().is_some_and(|c| c.is_whitespace()) {
+                lookahead.next();
+            }
+            let word: String = lookahead
+                .clone()
+                .take_while(|c| c.is_ascii_alphanumeric() || *c == '_')
+                .collect();
+
+            let continues = match (name.to_uppercase().as_str(), word.to_uppercase().as_str()) {
+                ("TIME" | "TIMESTAMP", "WITH") => true,
+                ("TIME WITH" | "TIMESTAMP WITH", "TIME") => true,
+                ("TIME WITH TIME" | "TIMESTAMP WITH TIME", "ZONE") => true,
+                ("DOUBLE", "PRECISION") => true,
+                _ => false,
+            };
+            if !continues {
+                break;
+            }
+
+            self.skip_whitespace();
+            name = format!("{name} {}", self.parse_word());
+        }
+
+        Ok(name)
+    }
+
+    fn parse_parameters(&mut self, name: &str) -> Result<DuckDbType> {
+        match name.to_uppercase().as_str() {
+            "STRUCT" => Ok(DuckDbType::Struct(self.parse_fields()?)),
+            "UNION" => Ok(DuckDbType::Union(self.parse_fields()?)),
+            "MAP" => {
+                let key = self.parse_type()?;
+                self.skip_whitespace();
+                self.expect(',')?;
+                let value = self.parse_type()?;
+                self.skip_whitespace();
+                Ok(DuckDbType::Map(Box::new(key), Box::new(value)))
+            }
+            "ENUM" => {
+                let mut values = vec![];
+                loop {
+                    self.skip_whitespace();
+                    values.push(self.parse_quoted('\'')?);
+                    self.skip_whitespace();
+                    if self.chars.peek() != Some(&',') {
+                        break;
+                    }
+                    self.chars.next();
+                }
+                Ok(DuckDbType::Enum(values))
+            }
+            "DECIMAL" | "NUMERIC" => {
+                self.skip_whitespace();
+                let precision = self.take_while(|c| c.is_ascii_digit()).parse::<u32>()?;
+                self.skip_whitespace();
+                let scale = match self.chars.peek() {
+                    Some(',') => {
+                        self.chars.next();
+                        self.skip_whitespace();
+                        self.take_while(|c| c.is_ascii_digit()).parse::<u32>()?
+                    }
+                    _ => 0,
+                };
+                self.skip_whitespace();
+                Ok(DuckDbType::Decimal(Some((precision, scale))))
+            }
+            // Modifiers such as VARCHAR(10) don't change how values are read
+            _ => {
+                self.take_while(|c| c != ')');
+                Ok(DuckDbType::from_name(name))
+            }
+        }
+    }
+
+    fn parse_fields(&mut self) -> Result<Vec<(String, DuckDbType)>> {
+        let mut fields = vec![];
+        loop {
+            self.skip_whitespace();
+            let field_name = match self.chars.peek() {
+                Some('"') => self.parse_quoted('"')?,
+                _ => self.parse_word(),
+            };
+            let field_type = self.parse_type()?;
+            fields.push((field_name, field_type));
+
+            self.skip_whitespace();
+            if self.chars.peek() != Some(&',') {
+                break;
+            }
+            self.chars.next();
+        }
+        Ok(fields)
+    }
+
+    // Quotes inside quoted names and values are escaped by doubling them
+    fn parse_quoted(&mut self, quote: char) -> Result<String> {
+        self.expect(quote)?;
+        let mut value = String::new();
+        loop {
+            match self.chars.next() {
+                Some(c) if c == quote => {
+                    if self.chars.peek() == Some(&quote) {
+                        self.chars.next();
+                        value.push(quote);
+                    } else {
+                        return Ok(value);
+                    }
+                }
+                Some(c) => value.push(c),
+                None => bail!("unterminated {} in DuckDB type", quote),
+            }
+        }
+    }
+
+    fn parse_word(&mut self) -> String {
+        self.take_while(|c| c.is_ascii_alphanumeric() || c == '_')
+    }
+
+    fn take_while(&mut self, predicate: impl Fn(char) -> bool) -> String {
+        let mut taken = String::new();
+        while let Some(&c) = self.chars.peek() {
+            if !predicate(c) {
+                break;
+            }
+            taken.push(c);
+            self.chars.next();
+        }
+        taken
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.chars.peek().is_some_and(|c| c.is_whitespace()) {
+            self.chars.next();
+        }
+    }
+
+    fn expect(&mut self, expected: char) -> Result<()> {
+        match self.chars.next() {
+            Some(c) if c == expected => Ok(()),
+            Some(c) => Err(anyhow!(
+                "expected '{}' but found '{}' in DuckDB type",
+                expected,
+                c
+            )),
+            None => Err(anyhow!("expected '{}' at the end of DuckDB type", expected)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn to_postgres(duckdb_type: &str) -> Result<String> {
+        DuckDbType::parse(duckdb_type)?.to_postgres("col")
+    }
+
+    #[test]
+    fn test_integer_types() {
+        assert_eq!(to_postgres("TINYINT").unwrap(), "SMALLINT");
+        assert_eq!(to_postgres("UTINYINT").unwrap(), "INTEGER");
+        assert_eq!(to_postgres("USMALLINT").unwrap(), "INTEGER");
+        assert_eq!(to_postgres("UINTEGER").unwrap(), "BIGINT");
+        assert_eq!(to_postgres("UBIGINT").unwrap(), "NUMERIC");
+        assert_eq!(to_postgres("UHUGEINT").unwrap(), "NUMERIC");
+    }
+
+    #[test]
+    fn test_multi_word_types() {
+        assert_eq!(
+            to_postgres("TIMESTAMP WITH TIME ZONE").unwrap(),
+            "TIMESTAMP WITH TIME ZONE"
+        );
+        assert_eq!(
+            to_postgres("TIME WITH TIME ZONE").unwrap(),
+            "TIME WITH TIME ZONE"
+        );
+        assert_eq!(to_postgres("TIMESTAMP_NS").unwrap(), "TIMESTAMP");
+        assert_eq!(to_postgres("DOUBLE").unwrap(), "DOUBLE PRECISION");
+    }
+
+    #[test]
+    fn test_nested_types() {
+        assert_eq!(to_postgres("DECIMAL(18,3)[]").unwrap(), "NUMERIC(18,3)[]");
+        assert_eq!(to_postgres("UTINYINT[][]").unwrap(), "INTEGER[][]");
+        assert_eq!(to_postgres("VARCHAR[3]").unwrap(), "VARCHAR[]");
+        assert_eq!(
+            to_postgres("STRUCT(\"a b\" DOUBLE, c DECIMAL(10, 2)[])").unwrap(),
+            "JSONB"
+        );
+        assert_eq!(to_postgres("STRUCT(a INTEGER)[]").unwrap(), "JSONB");
+        assert_eq!(
+            DuckDbType::parse("STRUCT(\"a b\" DOUBLE, c MAP(VARCHAR, INTEGER))").unwrap(),
+            DuckDbType::Struct(vec![
+                ("a b".to_string(), DuckDbType::Double),
+                (
+                    "c".to_string(),
+                    DuckDbType::Map(Box::new(DuckDbType::Varchar), Box::new(DuckDbType::Integer))
+                ),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_unsupported_types() {
+        assert!(to_postgres("MAP(VARCHAR, INTEGER)").is_err());
+        assert!(to_postgres("ENUM('a', 'it''s')").is_err());
+        assert!(to_postgres("UNION(a INTEGER, b VARCHAR)").is_err());
+        assert!(to_postgres("BIT").is_err());
+        assert!(to_postgres("INVALID").is_err());
+        assert!(DuckDbType::parse("STRUCT(a INTEGER").is_err());
+    }
+}
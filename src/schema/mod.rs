@@ -16,4 +16,7 @@
 // along with this program. If not, see <http://www.gnu.org/licenses/>.
 
 pub mod cell;
+pub mod converter;
 pub mod datetime;
+pub mod duckdb_type;
+pub mod postgis;
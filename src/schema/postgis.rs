@@ -0,0 +1,154 @@
+// Copyright (c) 2023-2025 Retake, Inc.
+//
+// This file is part of ParadeDB - Postgres for Search and Analytics
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <http://www.gnu.org/licenses/>.
+use anyhow::Result;
+use pgrx::*;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PostgisType {
+    Geometry,
+    Geography,
+}
+
+// Geometry types accepted in a PostGIS typmod, e.g. geometry(MultiPolygon, 4326)
+const POSTGIS_GEOMETRY_TYPES: [&str; 7] = [
+    "Point",
+    "LineString",
+    "Polygon",
+    "MultiPoint",
+    "MultiLineString",
+    "MultiPolygon",
+    "GeometryCollection",
+];
+
+/// PostGIS types don't have a fixed oid, so they are recognized by name
+pub fn postgis_type(oid: pg_sys::Oid) -> Option<PostgisType> {
+    // Built-in types are never PostGIS types, which skips the catalog lookup for them
+    if oid.as_u32() < pg_sys::FirstNormalObjectId {
+        return None;
+    }
+
+    let type_name = unsafe {
+        let type_tuple = pg_sys::SearchSysCache1(
+            pg_sys::SysCacheIdentifier::TYPEOID as i32,
+            oid.into_datum()?,
+        );
+        if type_tuple.is_null() {
+            return None;
+        }
+        let pg_type = pg_sys::GETSTRUCT(type_tuple) as pg_sys::Form_pg_type;
+        let type_name = name_data_to_str(&(*pg_type).typname).to_string();
+        pg_sys::ReleaseSysCache(type_tuple);
+        type_name
+    };
+
+    match type_name.as_str() {
+        "geometry" => Some(PostgisType::Geometry),
+        "geography" => Some(PostgisType::Geography),
+        _ => None,
+    }
+}
+
+pub fn postgis_installed() -> bool {
+    unsafe { pg_sys::get_extension_oid(c"postgis".as_ptr(), true) != pg_sys::InvalidOid }
+}
+
+/// Foreign scans read DuckDB geometries as WKB. The SRID of the column is prepended because WKB has none.
+pub fn postgis_scan_target(column_name: &str, typmod: i32) -> String {
+    match postgis_srid(typmod) {
+        Some(srid) => {
+            format!("'SRID={srid};' || ST_AsHEXWKB({column_name}) AS {column_name}")
+        }
+        None => format!("ST_AsWKB({column_name}) AS {column_name}"),
+    }
+}
+
+// Decodes the SRID the same way as the TYPMOD_GET_SRID macro of PostGIS
+fn postgis_srid(typmod: i32) -> Option<i32> {
+    if typmod < 0 {
+        return None;
+    }
+
+    match ((typmod & 0x0FFFFF00) - (typmod & 0x10000000)) >> 8 {
+        0 => None,
+        srid => Some(srid),
+    }
+}
+
+/// Maps a GDAL geometry type name such as "3D Multi Polygon" to a PostGIS column type
+pub fn postgis_column_type(geometry_type: &str, srid: Option<i32>) -> String {
+    let mut dimensions = String::new();
+    let mut geometry_type = geometry_type.trim();
+
+    if let Some(stripped) = geometry_type.strip_prefix("3D ") {
+        dimensions.push('Z');
+        geometry_type = stripped;
+    }
+    if let Some(stripped) = geometry_type.strip_prefix("Measured ") {
+        dimensions.push('M');
+        geometry_type = stripped;
+    }
+
+    let geometry_type = geometry_type.replace(' ', "");
+    let geometry_type = POSTGIS_GEOMETRY_TYPES
+        .iter()
+        .find(|postgis_type| postgis_type.eq_ignore_ascii_case(&geometry_type));
+
+    match (geometry_type, srid) {
+        (Some(geometry_type), Some(srid)) => {
+            format!("geometry({geometry_type}{dimensions}, {srid})")
+        }
+        (Some(geometry_type), None) => format!("geometry({geometry_type}{dimensions})"),
+        (None, Some(srid)) => format!("geometry(Geometry, {srid})"),
+        (None, None) => "geometry".to_string(),
+    }
+}
+
+pub fn wkb_to_hex(wkb: &[u8]) -> String {
+    wkb.iter().map(|byte| format!("{byte:02X}")).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_postgis_srid() {
+        // geometry(Point, 4326) and geometry(Point)
+        assert_eq!(postgis_srid(1107460), Some(4326));
+        assert_eq!(postgis_srid(4), None);
+        assert_eq!(postgis_srid(-1), None);
+    }
+
+    #[test]
+    fn test_postgis_column_type() {
+        assert_eq!(
+            postgis_column_type("Multi Polygon", Some(4326)),
+            "geometry(MultiPolygon, 4326)"
+        );
+        assert_eq!(postgis_column_type("3D Point", None), "geometry(PointZ)");
+        assert_eq!(
+            postgis_column_type("Unknown (any)", Some(3857)),
+            "geometry(Geometry, 3857)"
+        );
+        assert_eq!(postgis_column_type("None", None), "geometry");
+    }
+
+    #[test]
+    fn test_wkb_to_hex() {
+        assert_eq!(wkb_to_hex(&[1, 1, 0, 0, 0, 255]), "0101000000FF");
+    }
+}
@@ -0,0 +1,381 @@
+// Copyright (c) 2023-2025 Retake, Inc.
+//
+// This file is part of ParadeDB - Postgres for Search and Analytics
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <http://www.gnu.org/licenses/>.
+
+use duckdb::arrow::array::RecordBatch;
+use pgrx::*;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+// Fixed sizes are required because the store lives in shared memory
+const MAX_ENTRIES: usize = 1000;
+const MAX_QUERY_LEN: usize = 1024;
+const MAX_ERROR_LEN: usize = 512;
+// Twice MAX_ENTRIES, so that probe sequences of the index stay short
+const INDEX_SLOTS: usize = 2048;
+
+pub static QUERY_STATS: PgLwLock<QueryStatsStore> = PgLwLock::new();
+
+// Shared memory can only be requested while shared_preload_libraries is being processed.
+// If the extension was loaded later, statistics are silently not collected.
+static QUERY_STATS_ENABLED: AtomicBool = AtomicBool::new(false);
+
+#[derive(Copy, Clone)]
+pub struct QueryStatsEntry {
+    in_use: bool,
+    queryid: i64,
+    query: [u8; MAX_QUERY_LEN],
+    query_len: usize,
+    calls: i64,
+    pushdown_calls: i64,
+    fallback_calls: i64,
+    fdw_scan_calls: i64,
+    last_fallback_error: [u8; MAX_ERROR_LEN],
+    last_fallback_error_len: usize,
+    total_duckdb_time: f64,
+    rows: i64,
+    bytes_read: i64,
+}
+
+impl QueryStatsEntry {
+    const EMPTY: Self = Self {
+        in_use: false,
+        queryid: 0,
+        query: [0; MAX_QUERY_LEN],
+        query_len: 0,
+        calls: 0,
+        pushdown_calls: 0,
+        fallback_calls: 0,
+        fdw_scan_calls: 0,
+        last_fallback_error: [0; MAX_ERROR_LEN],
+        last_fallback_error_len: 0,
+        total_duckdb_time: 0.0,
+        rows: 0,
+        bytes_read: 0,
+    };
+
+    pub fn queryid(&self) -> i64 {
+        self.queryid
+    }
+
+    pub fn query(&self) -> String {
+        String::from_utf8_lossy(&self.query[..self.query_len]).into_owned()
+    }
+
+    pub fn calls(&self) -> i64 {
+        self.calls
+    }
+
+    pub fn pushdown_calls(&self) -> i64 {
+        self.pushdown_calls
+    }
+
+    pub fn fallback_calls(&self) -> i64 {
+        self.fallback_calls
+    }
+
+    pub fn fdw_scan_calls(&self) -> i64 {
+        self.fdw_scan_calls
+    }
+
+    pub fn last_fallback_error(&self) -> Option<String> {
+        match self.last_fallback_error_len {
+            0 => None,
+            len => Some(String::from_utf8_lossy(&self.last_fallback_error[..len]).into_owned()),
+        }
+    }
+
+    pub fn total_duckdb_time(&self) -> f64 {
+        self.total_duckdb_time
+    }
+
+    pub fn mean_duckdb_time(&self) -> f64 {
+        match self.pushdown_calls + self.fdw_scan_calls {
+            0 => 0.0,
+            timed_calls => self.total_duckdb_time / timed_calls as f64,
+        }
+    }
+
+    pub fn rows(&self) -> i64 {
+        self.rows
+    }
+
+    pub fn bytes_read(&self) -> i64 {
+        self.bytes_read
+    }
+}
+
+pub struct QueryStatsStore {
+    entries: [QueryStatsEntry; MAX_ENTRIES],
+    // Entries are filled in order and only freed all at once by reset
+    len: usize,
+    // Open addressing index from queryid to the position of its entry plus one, 0 marking a free slot.
+    // Recording a query is on the hot path of every backend, so it must not scan the entries.
+    index: [u16; INDEX_SLOTS],
+}
+
+unsafe impl PGRXSharedMemory for QueryStatsStore {}
+
+impl Default for QueryStatsStore {
+    fn default() -> Self {
+        Self {
+            entries: [QueryStatsEntry::EMPTY; MAX_ENTRIES],
+            len: 0,
+            index: [0; INDEX_SLOTS],
+        }
+    }
+}
+
+impl QueryStatsStore {
+    pub fn entries(&self) -> impl Iterator<Item = &QueryStatsEntry> {
+        self.entries[..self.len].iter().filter(|entry| entry.in_use)
+    }
+
+    pub fn reset(&mut self) {
+        self.entries = [QueryStatsEntry::EMPTY; MAX_ENTRIES];
+        self.len = 0;
+        self.index = [0; INDEX_SLOTS];
+    }
+
+    fn entry_mut(&mut self, queryid: i64, query: &str) -> &mut QueryStatsEntry {
+        let slot = self.slot(queryid);
+        if self.index[slot] != 0 {
+            return &mut self.entries[self.index[slot] as usize - 1];
+        }
+
+        // Take the next free entry, or evict the least used one once the store is full
+        let index = if self.len < MAX_ENTRIES {
+            self.len += 1;
+            self.len - 1
+        } else {
+            let index = self
+                .entries
+                .iter()
+                .enumerate()
+                .min_by_key(|(_, entry)| entry.calls)
+                .map(|(index, _)| index)
+                .unwrap_or(0);
+            self.remove_from_index(self.entries[index].queryid);
+            index
+        };
+
+        let mut entry = QueryStatsEntry::EMPTY;
+        entry.in_use = true;
+        entry.queryid = queryid;
+        entry.query_len = copy_truncated(query, &mut entry.query);
+        self.entries[index] = entry;
+
+        // Removing the evicted entry may have moved the free slot
+        let slot = self.slot(queryid);
+        self.index[slot] = (index + 1) as u16;
+        &mut self.entries[index]
+    }
+
+    /// The slot of the index that holds the entry of the query, or the free slot it would be put in
+    fn slot(&self, queryid: i64) -> usize {
+        let mut slot = home_slot(queryid);
+        // The index always has free slots, since it is larger than the store
+        while self.index[slot] != 0
+            && self.entries[self.index[slot] as usize - 1].queryid != queryid
+        {
+            slot = (slot + 1) % INDEX_SLOTS;
+        }
+        slot
+    }
+
+    fn remove_from_index(&mut self, queryid: i64) {
+        let mut hole = self.slot(queryid);
+        if self.index[hole] == 0 {
+            return;
+        }
+        self.index[hole] = 0;
+
+        // Shift the rest of the probe sequence back, so that lookups don't stop at the hole
+        let mut slot = (hole + 1) % INDEX_SLOTS;
+        while self.index[slot] != 0 {
+            let home = home_slot(self.entries[self.index[slot] as usize - 1].queryid);
+            let distance_to_hole = (hole + INDEX_SLOTS - home) % INDEX_SLOTS;
+            let distance_to_slot = (slot + INDEX_SLOTS - home) % INDEX_SLOTS;
+            if distance_to_hole < distance_to_slot {
+                self.index[hole] = self.index[slot];
+                self.index[slot] = 0;
+                hole = slot;
+            }
+            slot = (slot + 1) % INDEX_SLOTS;
+        }
+    }
+}
+
+// Query ids are hashes already, so their low bits are spread evenly
+fn home_slot(queryid: i64) -> usize {
+    queryid as u64 as usize % INDEX_SLOTS
+}
+
+pub fn init() {
+    if unsafe { pg_sys::process_shared_preload_libraries_in_progress } {
+        pg_shmem_init!(QUERY_STATS);
+        QUERY_STATS_ENABLED.store(true, Ordering::Relaxed);
+    }
+}
+
+pub fn is_enabled() -> bool {
+    QUERY_STATS_ENABLED.load(Ordering::Relaxed)
+}
+
+/// Uses the query id computed by Postgres when available (compute_query_id),
+/// and otherwise a hash of the whitespace-normalized query text.
+pub fn fingerprint(query_id: u64, query: &str) -> i64 {
+    if query_id != 0 {
+        return query_id as i64;
+    }
+
+    let mut hasher = DefaultHasher::new();
+    query
+        .split_whitespace()
+        .for_each(|token| token.hash(&mut hasher));
+    hasher.finish() as i64
+}
+
+pub fn record_pushdown(queryid: i64, query: &str, elapsed: Duration, rows: u64, bytes: u64) {
+    if !is_enabled() {
+        return;
+    }
+
+    let mut store = QUERY_STATS.exclusive();
+    let entry = store.entry_mut(queryid, query);
+    entry.calls += 1;
+    entry.pushdown_calls += 1;
+    entry.total_duckdb_time += elapsed.as_secs_f64() * 1000.0;
+    entry.rows += rows as i64;
+    entry.bytes_read += bytes as i64;
+}
+
+pub fn record_fallback(queryid: i64, query: &str, error: &str) {
+    if !is_enabled() {
+        return;
+    }
+
+    let mut store = QUERY_STATS.exclusive();
+    let entry = store.entry_mut(queryid, query);
+    entry.calls += 1;
+    entry.fallback_calls += 1;
+    entry.last_fallback_error_len = copy_truncated(error, &mut entry.last_fallback_error);
+}
+
+/// FDW scans are recorded under the DuckDB scan statement they execute.
+/// `started` is true for the call that opened the scan, and false for each batch read afterwards.
+pub fn record_fdw_scan(sql: &str, started: bool, elapsed: Duration, rows: u64, bytes: u64) {
+    if !is_enabled() {
+        return;
+    }
+
+    let mut store = QUERY_STATS.exclusive();
+    let entry = store.entry_mut(fingerprint(0, sql), sql);
+    if started {
+        entry.calls += 1;
+        entry.fdw_scan_calls += 1;
+    }
+    entry.total_duckdb_time += elapsed.as_secs_f64() * 1000.0;
+    entry.rows += rows as i64;
+    entry.bytes_read += bytes as i64;
+}
+
+/// Returns the number of rows and the Arrow memory size of the batches read from DuckDB
+pub fn batches_size<'a>(batches: impl IntoIterator<Item = &'a RecordBatch>) -> (u64, u64) {
+    batches.into_iter().fold((0, 0), |(rows, bytes), batch| {
+        (
+            rows + batch.num_rows() as u64,
+            bytes + batch.get_array_memory_size() as u64,
+        )
+    })
+}
+
+fn copy_truncated(source: &str, target: &mut [u8]) -> usize {
+    // Truncate on a char boundary so the stored text is always valid UTF-8
+    let mut len = source.len().min(target.len());
+    while !source.is_char_boundary(len) {
+        len -= 1;
+    }
+    target[..len].copy_from_slice(&source.as_bytes()[..len]);
+    len
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fingerprint_normalizes_whitespace() {
+        assert_eq!(
+            fingerprint(0, "SELECT COUNT(*)   FROM trips"),
+            fingerprint(0, "SELECT COUNT(*)\nFROM trips")
+        );
+        assert_ne!(
+            fingerprint(0, "SELECT COUNT(*) FROM trips"),
+            fingerprint(0, "SELECT COUNT(*) FROM other")
+        );
+        assert_eq!(fingerprint(42, "SELECT 1"), 42);
+    }
+
+    #[test]
+    fn test_store_evicts_and_finds_entries() {
+        // The store is too large for the stack of a test thread
+        std::thread::Builder::new()
+            .stack_size(64 << 20)
+            .spawn(|| {
+                let mut store = Box::<QueryStatsStore>::default();
+                for queryid in 0..MAX_ENTRIES as i64 {
+                    // Ids sharing their home slot make long probe sequences
+                    let queryid = queryid * INDEX_SLOTS as i64;
+                    store.entry_mut(queryid, "SELECT 1").calls += queryid + 1;
+                }
+                assert_eq!(store.entries().count(), MAX_ENTRIES);
+
+                // The least used entry makes room for a new query
+                store.entry_mut(-1, "SELECT 2").calls += 1;
+                assert_eq!(store.entries().count(), MAX_ENTRIES);
+                assert!(store.entries().all(|entry| entry.queryid != 0));
+
+                for entry in store.entries().copied().collect::<Vec<_>>() {
+                    let found = store.entry_mut(entry.queryid, "");
+                    assert_eq!(found.calls, entry.calls);
+                    assert_eq!(found.query(), entry.query());
+                }
+                assert_eq!(store.entries().count(), MAX_ENTRIES);
+
+                store.reset();
+                assert_eq!(store.entries().count(), 0);
+                assert_eq!(store.entry_mut(-1, "SELECT 2").calls, 0);
+            })
+            .unwrap()
+            .join()
+            .unwrap();
+    }
+
+    #[test]
+    fn test_copy_truncated_respects_char_boundary() {
+        let mut target = [0u8; 4];
+        let len = copy_truncated("ab\u{00e9}\u{00e9}", &mut target);
+        assert_eq!(len, 4);
+        assert_eq!(&target[..len], "ab\u{00e9}".as_bytes());
+
+        let mut target = [0u8; 3];
+        let len = copy_truncated("ab\u{00e9}", &mut target);
+        assert_eq!(&target[..len], b"ab");
+    }
+}
@@ -0,0 +1,86 @@
+// Copyright (c) 2023-2025 Retake, Inc.
+//
+// This file is part of ParadeDB - Postgres for Search and Analytics
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <http://www.gnu.org/licenses/>.
+
+mod fixtures;
+
+use crate::fixtures::arrow::setup_parquet_wrapper_and_server;
+use crate::fixtures::db::Query;
+use crate::fixtures::tables::nyc_trips::NycTripsTable;
+use crate::fixtures::{conn, tempdir};
+use anyhow::Result;
+use datafusion::arrow::datatypes::FieldRef;
+use datafusion::parquet::arrow::ArrowWriter;
+use rstest::*;
+use serde_arrow::schema::{SchemaLike, TracingOptions};
+use sqlx::PgConnection;
+use std::fs::File;
+use std::time::{Duration, Instant};
+use tempfile::TempDir;
+
+// The 100 nyc_trips rows are written this many times to get a scan worth timing
+const TRIPS_REPEAT: usize = 2_000;
+const RUNS: u32 = 5;
+
+fn time_scan(conn: &mut PgConnection) -> (Duration, Vec<NycTripsTable>) {
+    let mut elapsed = Duration::ZERO;
+    let mut rows = vec![];
+    for _ in 0..RUNS {
+        let start = Instant::now();
+        rows = "SELECT * FROM trips".fetch(conn);
+        elapsed += start.elapsed();
+    }
+    (elapsed / RUNS, rows)
+}
+
+#[rstest]
+#[ignore = "benchmark, run with --ignored in a release build"]
+async fn bench_write_batches_to_slots(mut conn: PgConnection, tempdir: TempDir) -> Result<()> {
+    NycTripsTable::setup().execute(&mut conn);
+    let rows: Vec<NycTripsTable> = "SELECT * FROM nyc_trips".fetch(&mut conn);
+
+    let fields = Vec::<FieldRef>::from_type::<NycTripsTable>(TracingOptions::default())?;
+    let batch = serde_arrow::to_record_batch(&fields, &rows)?;
+    let parquet_path = tempdir.path().join("trips.parquet");
+    let mut writer = ArrowWriter::try_new(File::create(&parquet_path)?, batch.schema(), None)?;
+    for _ in 0..TRIPS_REPEAT {
+        writer.write(&batch)?;
+    }
+    writer.close()?;
+
+    setup_parquet_wrapper_and_server().execute(&mut conn);
+    format!(
+        "CREATE FOREIGN TABLE trips () SERVER parquet_server OPTIONS (files '{}')",
+        parquet_path.to_str().unwrap()
+    )
+    .execute(&mut conn);
+
+    "SET thdb.disable_column_converters = true".execute(&mut conn);
+    let (before, cell_rows) = time_scan(&mut conn);
+
+    "SET thdb.disable_column_converters = false".execute(&mut conn);
+    let (after, converter_rows) = time_scan(&mut conn);
+
+    assert_eq!(cell_rows.len(), rows.len() * TRIPS_REPEAT);
+    assert_eq!(cell_rows, converter_rows);
+
+    println!(
+        "write_batches_to_slots over {} rows: get_cell {before:?}, column converters {after:?}",
+        converter_rows.len()
+    );
+
+    Ok(())
+}
@@ -23,10 +23,11 @@ use crate::fixtures::tables::duckdb_types::DuckdbTypesTable;
 use crate::fixtures::{conn, tempdir};
 use anyhow::Result;
 use rstest::*;
+use sqlx::postgres::types::PgTimeTz;
 use sqlx::PgConnection;
 use tempfile::TempDir;
-use time::macros::datetime;
-use time::PrimitiveDateTime;
+use time::macros::{datetime, time};
+use time::{PrimitiveDateTime, UtcOffset};
 
 #[rstest]
 async fn test_date_trunc(
@@ -58,3 +59,268 @@ async fn test_date_trunc(
 
     Ok(())
 }
+
+#[rstest]
+async fn test_session_settings_pushdown_matches_fdw(
+    mut conn: PgConnection,
+    tempdir: TempDir,
+    duckdb_conn: duckdb::Connection,
+) -> Result<()> {
+    let parquet_path = tempdir.path().join("test_arrow_types.parquet");
+
+    duckdb_conn
+        .execute(&DuckdbTypesTable::create_duckdb_table(), [])
+        .unwrap();
+
+    duckdb_conn
+        .execute(&DuckdbTypesTable::populate_duckdb_table(), [])
+        .unwrap();
+
+    duckdb_conn
+        .execute(
+            &DuckdbTypesTable::export_duckdb_table(parquet_path.to_str().unwrap()),
+            [],
+        )
+        .unwrap();
+
+    DuckdbTypesTable::create_foreign_table(parquet_path.to_str().unwrap()).execute(&mut conn);
+
+    let queries = [
+        "SELECT EXTRACT(EPOCH FROM date_trunc('day', timestamp_tz_col))::bigint::text FROM duckdb_types_test",
+        "SELECT EXTRACT(HOUR FROM timestamp_tz_col)::int::text FROM duckdb_types_test",
+        "SELECT (timestamp_tz_col::date)::text FROM duckdb_types_test",
+        "SELECT (date_trunc('day', now()) = date_trunc('day', current_timestamp))::text FROM duckdb_types_test",
+        "SELECT (current_date - timestamp_tz_col::date > 0)::text FROM duckdb_types_test",
+        "SELECT current_user::text FROM duckdb_types_test",
+    ];
+
+    for settings in [
+        "SET TimeZone = 'UTC'",
+        "SET TimeZone = 'Asia/Shanghai'",
+        "SET TimeZone = 'America/New_York'",
+        "SET TIME ZONE 8",
+        "SET DateStyle = 'SQL, DMY'",
+    ] {
+        settings.execute(&mut conn);
+
+        for query in queries {
+            "SET thdb.disable_executor = false".execute(&mut conn);
+            let pushdown: Vec<(String,)> = query.fetch(&mut conn);
+
+            "SET thdb.disable_executor = true".execute(&mut conn);
+            let fdw: Vec<(String,)> = query.fetch(&mut conn);
+
+            assert_eq!(pushdown, fdw, "{settings}: {query}");
+        }
+    }
+
+    "RESET thdb.disable_executor".execute(&mut conn);
+    "RESET TimeZone".execute(&mut conn);
+    "RESET DateStyle".execute(&mut conn);
+
+    Ok(())
+}
+
+#[rstest]
+async fn test_timestamptz_timezone_dst(
+    mut conn: PgConnection,
+    tempdir: TempDir,
+    duckdb_conn: duckdb::Connection,
+) -> Result<()> {
+    let parquet_path = tempdir.path().join("test_timestamptz_timezone.parquet");
+
+    // Wall clock times around the 2024 DST changes of New York, and one before standard time
+    duckdb_conn.execute_batch(&format!(
+        r#"
+        CREATE TABLE timestamps (id INTEGER, ts TIMESTAMP, ts_utc TIMESTAMPTZ);
+        INSERT INTO timestamps VALUES
+            (1, '2024-03-10 01:30:00', '2024-03-10 06:30:00+00'),
+            (2, '2024-03-10 03:30:00', '2024-03-10 07:30:00+00'),
+            (3, '2024-11-03 00:30:00', '2024-11-03 05:30:00+00'),
+            (4, '2024-11-03 03:00:00', '2024-11-03 06:30:00+00'),
+            (5, '1883-11-18 12:00:00', '1883-11-18 12:00:00+00');
+        COPY timestamps TO '{}' (FORMAT PARQUET);
+        "#,
+        parquet_path.to_str().unwrap()
+    ))?;
+
+    format!(
+        r#"
+        CREATE FOREIGN DATA WRAPPER parquet_wrapper HANDLER parquet_fdw_handler VALIDATOR parquet_fdw_validator;
+        CREATE SERVER parquet_server FOREIGN DATA WRAPPER parquet_wrapper;
+        CREATE FOREIGN TABLE timestamps_ny (id INTEGER, ts TIMESTAMPTZ, ts_utc TIMESTAMPTZ)
+            SERVER parquet_server OPTIONS (files '{path}', timezone 'America/New_York');
+        CREATE FOREIGN TABLE timestamps_session (id INTEGER, ts TIMESTAMPTZ, ts_utc TIMESTAMPTZ)
+            SERVER parquet_server OPTIONS (files '{path}');
+        "#,
+        path = parquet_path.to_str().unwrap()
+    )
+    .execute(&mut conn);
+
+    "SET TimeZone = 'UTC'".execute(&mut conn);
+    let rows: Vec<(i32, String, String)> =
+        "SELECT id, ts::text, ts_utc::text FROM timestamps_ny ORDER BY id".fetch(&mut conn);
+    assert_eq!(
+        rows,
+        vec![
+            (
+                1,
+                "2024-03-10 06:30:00+00".into(),
+                "2024-03-10 06:30:00+00".into()
+            ),
+            (
+                2,
+                "2024-03-10 07:30:00+00".into(),
+                "2024-03-10 07:30:00+00".into()
+            ),
+            (
+                3,
+                "2024-11-03 04:30:00+00".into(),
+                "2024-11-03 05:30:00+00".into()
+            ),
+            (
+                4,
+                "2024-11-03 08:00:00+00".into(),
+                "2024-11-03 06:30:00+00".into()
+            ),
+            // New York used local mean time, 4:56:02 behind UTC, until November 18 1883 at noon
+            (
+                5,
+                "1883-11-18 16:56:02+00".into(),
+                "1883-11-18 12:00:00+00".into()
+            ),
+        ]
+    );
+
+    // Zoned timestamps keep their instant whatever the session TimeZone is, while
+    // timestamps without a zone follow it
+    // The foreign scan reads the table, rather than DuckDB running the cast to text
+    "SET thdb.disable_executor = true".execute(&mut conn);
+    "SET TimeZone = 'Europe/Berlin'".execute(&mut conn);
+    let rows: Vec<(i32, String, String)> =
+        "SELECT id, ts::text, ts_utc::text FROM timestamps_session WHERE id IN (3, 4) ORDER BY id"
+            .fetch(&mut conn);
+    assert_eq!(
+        rows,
+        vec![
+            (
+                3,
+                "2024-11-03 00:30:00+01".into(),
+                "2024-11-03 06:30:00+01".into()
+            ),
+            (
+                4,
+                "2024-11-03 03:00:00+01".into(),
+                "2024-11-03 07:30:00+01".into()
+            ),
+        ]
+    );
+
+    "RESET thdb.disable_executor".execute(&mut conn);
+    "RESET TimeZone".execute(&mut conn);
+
+    Ok(())
+}
+
+#[rstest]
+async fn test_timetz_offset(
+    mut conn: PgConnection,
+    tempdir: TempDir,
+    duckdb_conn: duckdb::Connection,
+) -> Result<()> {
+    let csv_path = tempdir.path().join("test_timetz.csv");
+
+    duckdb_conn.execute_batch(&format!(
+        r#"
+        CREATE TABLE times (id INTEGER, t TIMETZ);
+        INSERT INTO times VALUES (1, '12:34:56+02'), (2, '23:00:00-05:30');
+        COPY times TO '{}' (FORMAT CSV, HEADER);
+        "#,
+        csv_path.to_str().unwrap()
+    ))?;
+
+    format!(
+        r#"
+        CREATE FOREIGN DATA WRAPPER csv_wrapper HANDLER csv_fdw_handler VALIDATOR csv_fdw_validator;
+        CREATE SERVER csv_server FOREIGN DATA WRAPPER csv_wrapper;
+        CREATE FOREIGN TABLE times () SERVER csv_server
+            OPTIONS (files '{}', columns '{{''id'': ''INTEGER'', ''t'': ''TIMETZ''}}');
+        "#,
+        csv_path.to_str().unwrap()
+    )
+    .execute(&mut conn);
+
+    let (column_type,): (String,) =
+        "SELECT format_type(atttypid, atttypmod) FROM pg_attribute WHERE attrelid = 'times'::regclass AND attname = 't'"
+            .fetch_one(&mut conn);
+    assert_eq!(column_type, "time with time zone");
+
+    "SET thdb.disable_executor = true".execute(&mut conn);
+    let rows: Vec<(i32, String)> = "SELECT id, t::text FROM times ORDER BY id".fetch(&mut conn);
+    assert_eq!(
+        rows,
+        vec![(1, "12:34:56+02".into()), (2, "23:00:00-05:30".into())]
+    );
+    "RESET thdb.disable_executor".execute(&mut conn);
+
+    Ok(())
+}
+
+#[rstest]
+async fn test_time_read_as_timetz(
+    mut conn: PgConnection,
+    tempdir: TempDir,
+    duckdb_conn: duckdb::Connection,
+) -> Result<()> {
+    let csv_path = tempdir.path().join("test_time_as_timetz.csv");
+
+    duckdb_conn.execute_batch(&format!(
+        r#"
+        CREATE TABLE times (id INTEGER, t TIME);
+        INSERT INTO times VALUES (1, '12:34:56'), (2, '23:00:00');
+        COPY times TO '{}' (FORMAT CSV, HEADER);
+        "#,
+        csv_path.to_str().unwrap()
+    ))?;
+
+    format!(
+        r#"
+        CREATE FOREIGN DATA WRAPPER csv_wrapper HANDLER csv_fdw_handler VALIDATOR csv_fdw_validator;
+        CREATE SERVER csv_server FOREIGN DATA WRAPPER csv_wrapper;
+        CREATE FOREIGN TABLE times (id INTEGER, t TIMETZ) SERVER csv_server
+            OPTIONS (files '{}', columns '{{''id'': ''INTEGER'', ''t'': ''TIME''}}');
+        "#,
+        csv_path.to_str().unwrap()
+    )
+    .execute(&mut conn);
+
+    // Times without an offset are read as UTC whatever the session zone, by the foreign scan and by pushdown
+    "SET TimeZone = 'Asia/Kolkata'".execute(&mut conn);
+    let expected = vec![
+        (
+            1,
+            PgTimeTz {
+                time: time!(12:34:56),
+                offset: UtcOffset::UTC,
+            },
+        ),
+        (
+            2,
+            PgTimeTz {
+                time: time!(23:00:00),
+                offset: UtcOffset::UTC,
+            },
+        ),
+    ];
+
+    let pushed_down: Vec<(i32, PgTimeTz)> = "SELECT id, t FROM times ORDER BY id".fetch(&mut conn);
+    assert_eq!(pushed_down, expected);
+
+    "SET thdb.disable_executor = true".execute(&mut conn);
+    let scanned: Vec<(i32, PgTimeTz)> = "SELECT id, t FROM times ORDER BY id".fetch(&mut conn);
+    assert_eq!(scanned, expected);
+    "RESET thdb.disable_executor".execute(&mut conn);
+    "RESET TimeZone".execute(&mut conn);
+
+    Ok(())
+}
@@ -187,8 +187,58 @@ async fn test_explain_foreign_table(#[future(awt)] s3: S3, mut conn: PgConnectio
     let explain: Vec<(String,)> = "EXPLAIN SELECT COUNT(*) FROM trips".fetch(&mut conn);
     assert_eq!(explain[0].0, "DuckDB Scan: SELECT COUNT(*) FROM trips");
 
+    let explain: Vec<(String,)> = "EXPLAIN verbose SELECT COUNT(*) FROM trips".fetch(&mut conn);
+    assert_eq!(explain[0].0, "DuckDB Scan: SELECT COUNT(*) FROM trips");
+    assert_eq!(explain[1].0, "  Output: count");
+
+    let explain: Result<Vec<(String,)>, sqlx::Error> =
+        "EXPLAIN (costs off, buffers, timing off) SELECT COUNT(*) FROM trips"
+            .fetch_result(&mut conn);
+    assert!(explain.is_ok());
+
+    let explain: Result<Vec<(String,)>, sqlx::Error> =
+        "EXPLAIN (unknown_option) SELECT COUNT(*) FROM trips".fetch_result(&mut conn);
+    assert!(explain.is_err());
+
+    Ok(())
+}
+
+#[rstest]
+async fn test_explain_foreign_table_structured_format(
+    #[future(awt)] s3: S3,
+    mut conn: PgConnection,
+) -> Result<()> {
+    NycTripsTable::setup().execute(&mut conn);
+
+    let rows: Vec<NycTripsTable> = "SELECT * FROM nyc_trips".fetch(&mut conn);
+    s3.client.create_bucket().bucket(S3_BUCKET).send().await?;
+    s3.create_bucket(S3_BUCKET).await?;
+    s3.put_rows(S3_BUCKET, S3_KEY, &rows).await?;
+
+    NycTripsTable::setup_s3_listing_fdw(&s3.url.clone(), &format!("s3://{S3_BUCKET}/{S3_KEY}"))
+        .execute(&mut conn);
+
+    let (explain,): (serde_json::Value,) =
+        "EXPLAIN (FORMAT JSON, VERBOSE, BUFFERS) SELECT COUNT(*) FROM trips".fetch_one(&mut conn);
+    let plan = &explain[0]["Plan"];
+    assert_eq!(plan["Node Type"], "DuckDB Scan");
+    assert_eq!(plan["Query"], "SELECT COUNT(*) FROM trips");
+    assert_eq!(plan["Output"], serde_json::json!(["count"]));
+    assert!(!plan["DuckDB Plan"].is_null());
+
+    let (explain,): (serde_json::Value,) =
+        "EXPLAIN (ANALYZE, FORMAT JSON) SELECT COUNT(*) FROM trips".fetch_one(&mut conn);
+    assert_eq!(explain[0]["Plan"]["Node Type"], "DuckDB Scan");
+    assert!(explain[0]["Execution Time"].is_number());
+
+    let explain: Vec<(String,)> =
+        "EXPLAIN (FORMAT YAML) SELECT COUNT(*) FROM trips".fetch(&mut conn);
+    assert_eq!(explain.len(), 1);
+    assert!(explain[0].0.starts_with("- Plan: "));
+    assert!(explain[0].0.contains("Node Type: \"DuckDB Scan\""));
+
     let explain: Result<Vec<(String,)>, sqlx::Error> =
-        "EXPLAIN verbose SELECT COUNT(*) FROM trips".fetch_result(&mut conn);
+        "EXPLAIN (style duckdb, FORMAT JSON) SELECT COUNT(*) FROM trips".fetch_result(&mut conn);
     assert!(explain.is_err());
 
     Ok(())
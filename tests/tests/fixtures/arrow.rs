@@ -17,6 +17,8 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program. If not, see <http://www.gnu.org/licenses/>.
 
+use std::fs::File;
+use std::path::Path;
 use std::sync::Arc;
 
 use anyhow::{bail, Result};
@@ -26,6 +28,7 @@ use datafusion::arrow::array::*;
 use datafusion::arrow::buffer::Buffer;
 use datafusion::arrow::datatypes::{DataType, Field, Schema, SchemaRef, TimeUnit};
 use datafusion::arrow::record_batch::RecordBatch;
+use datafusion::parquet::arrow::ArrowWriter;
 use pgrx::pg_sys::InvalidOid;
 use pgrx::PgBuiltInOids;
 use sqlx::postgres::PgRow;
@@ -467,6 +470,14 @@ pub fn primitive_setup_fdw_local_file_listing(local_file_path: &str, table: &str
     setup_fdw_local_parquet_file_listing(local_file_path, table, &primitive_table_columns())
 }
 
+/// Writes the batch to a new parquet file at path
+pub fn write_parquet(path: &Path, batch: &RecordBatch) -> Result<()> {
+    let mut writer = ArrowWriter::try_new(File::create(path)?, batch.schema(), None)?;
+    writer.write(batch)?;
+    writer.close()?;
+    Ok(())
+}
+
 pub fn primitive_setup_fdw_local_file_delta(local_file_path: &str, table: &str) -> String {
     let create_foreign_data_wrapper = primitive_create_foreign_data_wrapper(
         "delta_wrapper",
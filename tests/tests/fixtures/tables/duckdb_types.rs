@@ -17,11 +17,11 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program. If not, see <http://www.gnu.org/licenses/>.
 
-use sqlx::postgres::types::PgInterval;
+use sqlx::postgres::types::{PgInterval, PgTimeTz};
 use sqlx::types::{BigDecimal, Json, Uuid};
 use sqlx::FromRow;
 use std::collections::HashMap;
-use time::{Date, OffsetDateTime, PrimitiveDateTime, Time};
+use time::{Date, OffsetDateTime, PrimitiveDateTime, Time, UtcOffset};
 
 #[derive(Debug, PartialEq, FromRow)]
 pub struct DuckdbTypesTable {
@@ -52,7 +52,7 @@ pub struct DuckdbTypesTable {
     pub struct_col: Json<HashMap<String, String>>,
     pub array_col: [i32; 3],
     pub uuid_col: Uuid,
-    pub time_tz_col: Time,
+    pub time_tz_col: PgTimeTz<Time, UtcOffset>,
     pub timestamp_tz_col: OffsetDateTime,
 }
 
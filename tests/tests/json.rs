@@ -391,3 +391,57 @@ fn test_json_cast_from_struct_list(mut conn: PgConnection, tempdir: TempDir) ->
 
     Ok(())
 }
+
+#[rstest]
+async fn test_json_rejects(mut conn: PgConnection, tempdir: TempDir) -> Result<()> {
+    let file_path = tempdir.path().join("rejects.json");
+    std::fs::write(&file_path, "{\"id\": 1}\n{\"id\": 2\n{\"id\": 3}\n")?;
+    let file_path = file_path.to_string_lossy();
+
+    format!(
+        r#"
+        {};
+        {};
+        CREATE FOREIGN TABLE json_rejected (id INT) SERVER json_server
+        OPTIONS (files '{file_path}', format 'newline_delimited', store_rejects 'true');
+        "#,
+        primitive_create_foreign_data_wrapper(
+            "json_wrapper",
+            "json_fdw_handler",
+            "json_fdw_validator"
+        ),
+        primitive_create_server("json_server", "json_wrapper"),
+    )
+    .execute(&mut conn);
+
+    let ids: Vec<(i32,)> = "SELECT id FROM json_rejected ORDER BY id".fetch(&mut conn);
+    assert_eq!(ids, vec![(1,), (3,)]);
+
+    let rejects: Vec<(String, i64, String, String)> =
+        "SELECT file_path, line, error_type, raw_line FROM csv_rejects('json_rejected')"
+            .fetch(&mut conn);
+    assert_eq!(
+        rejects,
+        vec![(
+            file_path.to_string(),
+            2,
+            "INVALID JSON".to_string(),
+            "{\"id\": 2".to_string()
+        )]
+    );
+
+    // Records that are valid JSON but don't fit the given columns can't be reported
+    format!(
+        "CREATE FOREIGN TABLE json_rejected_columns (id INT) SERVER json_server \
+         OPTIONS (files '{file_path}', format 'newline_delimited', columns '{{''id'': ''INTEGER''}}', store_rejects 'true')"
+    )
+    .execute(&mut conn);
+    let result = "SELECT id FROM json_rejected_columns".execute_result(&mut conn);
+    assert!(
+        format!("{:?}", result).contains("store_rejects can't be combined with columns"),
+        "{:?}",
+        result
+    );
+
+    Ok(())
+}
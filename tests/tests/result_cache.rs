@@ -0,0 +1,104 @@
+// Copyright (c) 2023-2025 Retake, Inc.
+//
+// This file is part of ParadeDB - Postgres for Search and Analytics
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <http://www.gnu.org/licenses/>.
+mod fixtures;
+
+use crate::fixtures::arrow::setup_parquet_wrapper_and_server;
+use crate::fixtures::db::Query;
+use crate::fixtures::{conn, duckdb_conn, tempdir};
+use anyhow::Result;
+use rstest::*;
+use sqlx::PgConnection;
+use tempfile::TempDir;
+
+const QUERY: &str = "SELECT COUNT(*) AS result_cache_count FROM cached";
+
+fn write_rows(duckdb_conn: &duckdb::Connection, path: &str, rows: i64) -> Result<()> {
+    duckdb_conn.execute(
+        &format!("COPY (SELECT range AS id FROM range({rows})) TO '{path}' (FORMAT PARQUET)"),
+        [],
+    )?;
+    Ok(())
+}
+
+#[rstest]
+async fn test_result_cache(
+    mut conn: PgConnection,
+    tempdir: TempDir,
+    duckdb_conn: duckdb::Connection,
+) -> Result<()> {
+    let parquet_path = tempdir.path().join("cached.parquet");
+    let parquet_path = parquet_path.to_str().unwrap();
+    write_rows(&duckdb_conn, parquet_path, 100)?;
+
+    format!(
+        "{}; CREATE FOREIGN TABLE cached () SERVER parquet_server OPTIONS (files '{parquet_path}')",
+        setup_parquet_wrapper_and_server()
+    )
+    .execute(&mut conn);
+    format!("SELECT th_dbdm_result_cache_invalidate('{QUERY}')").execute(&mut conn);
+    "SET thdb.result_cache = true".execute(&mut conn);
+
+    let entries = format!(
+        "SELECT rows, extract(epoch FROM created)::float8 FROM th_dbdm_result_cache WHERE query = '{QUERY}'"
+    );
+
+    // The second run reads the result stored by the first one
+    assert_eq!(QUERY.fetch_one::<(i64,)>(&mut conn), (100,));
+    let stored: Vec<(i64, f64)> = entries.as_str().fetch(&mut conn);
+    assert_eq!(stored.len(), 1);
+    assert_eq!(stored[0].0, 1);
+    assert_eq!(QUERY.fetch_one::<(i64,)>(&mut conn), (100,));
+    assert_eq!(entries.as_str().fetch::<(i64, f64)>(&mut conn), stored);
+
+    // Rewriting the file changes its version, so the query runs again
+    write_rows(&duckdb_conn, parquet_path, 200)?;
+    assert_eq!(QUERY.fetch_one::<(i64,)>(&mut conn), (200,));
+    assert_eq!(entries.as_str().fetch::<(i64, f64)>(&mut conn).len(), 2);
+
+    // Volatile queries are never stored
+    "SELECT COUNT(*) AS result_cache_random FROM cached WHERE random() >= 0"
+        .fetch_one::<(i64,)>(&mut conn);
+    let volatile: (i64,) =
+        "SELECT COUNT(*) FROM th_dbdm_result_cache WHERE query LIKE '%result_cache_random%'"
+            .fetch_one(&mut conn);
+    assert_eq!(volatile, (0,));
+
+    let removed: (i64,) =
+        format!("SELECT th_dbdm_result_cache_invalidate('{QUERY}')").fetch_one(&mut conn);
+    assert_eq!(removed, (2,));
+    assert!(entries.as_str().fetch::<(i64, f64)>(&mut conn).is_empty());
+
+    // Cached results belong to every role, so only superusers can list or remove them
+    "CREATE ROLE cache_reader; GRANT SELECT ON th_dbdm_result_cache TO cache_reader; SET ROLE cache_reader"
+        .execute(&mut conn);
+    for query in [
+        "SELECT * FROM th_dbdm_result_cache",
+        "SELECT * FROM th_dbdm_result_cache_internal()",
+        "SELECT th_dbdm_result_cache_invalidate()",
+    ] {
+        let result = query.execute_result(&mut conn);
+        assert!(result
+            .unwrap_err()
+            .to_string()
+            .contains("only superusers can access the result cache"));
+    }
+    "RESET ROLE".execute(&mut conn);
+
+    "RESET thdb.result_cache".execute(&mut conn);
+
+    Ok(())
+}
@@ -22,23 +22,22 @@ use crate::fixtures::arrow::{
     primitive_create_table, primitive_create_user_mapping_options, primitive_record_batch,
     primitive_record_batch_single, primitive_setup_fdw_local_file_delta,
     primitive_setup_fdw_local_file_listing, primitive_setup_fdw_s3_delta,
-    primitive_setup_fdw_s3_listing, setup_parquet_wrapper_and_server,
+    primitive_setup_fdw_s3_listing, setup_parquet_wrapper_and_server, write_parquet,
 };
 use crate::fixtures::db::Query;
 use crate::fixtures::{conn, duckdb_conn, s3, tempdir, S3};
 use anyhow::Result;
-use datafusion::parquet::arrow::ArrowWriter;
 use deltalake::operations::create::CreateBuilder;
 use deltalake::writer::{DeltaWriter, RecordBatchWriter};
 use rstest::*;
-use sqlx::postgres::types::PgInterval;
+use sqlx::postgres::types::{PgInterval, PgTimeTz};
 use sqlx::types::{BigDecimal, Json, Uuid};
 use sqlx::PgConnection;
 use std::collections::HashMap;
-use std::fs::File;
 use std::str::FromStr;
 use tempfile::TempDir;
 use time::macros::{date, datetime, time};
+use time::UtcOffset;
 
 use crate::fixtures::tables::duckdb_types::DuckdbTypesTable;
 use crate::fixtures::tables::nyc_trips::NycTripsTable;
@@ -187,11 +186,7 @@ async fn test_arrow_types_local_file_listing(
 ) -> Result<()> {
     let stored_batch = primitive_record_batch()?;
     let parquet_path = tempdir.path().join("test_arrow_types.parquet");
-    let parquet_file = File::create(&parquet_path)?;
-
-    let mut writer = ArrowWriter::try_new(parquet_file, stored_batch.schema(), None).unwrap();
-    writer.write(&stored_batch)?;
-    writer.close()?;
+    write_parquet(&parquet_path, &stored_batch)?;
 
     primitive_setup_fdw_local_file_listing(parquet_path.as_path().to_str().unwrap(), "primitive")
         .execute(&mut conn);
@@ -210,6 +205,39 @@ async fn test_arrow_types_local_file_listing(
     Ok(())
 }
 
+#[rstest]
+async fn test_arrow_types_local_file_listing_fdw_scan(
+    mut conn: PgConnection,
+    tempdir: TempDir,
+) -> Result<()> {
+    let stored_batch = primitive_record_batch()?;
+    let parquet_path = tempdir.path().join("test_arrow_types.parquet");
+    write_parquet(&parquet_path, &stored_batch)?;
+
+    primitive_setup_fdw_local_file_listing(parquet_path.as_path().to_str().unwrap(), "primitive")
+        .execute(&mut conn);
+
+    // The foreign scan reads through the column converters, or through get_cell when they are disabled
+    "SET thdb.disable_executor = true".execute(&mut conn);
+    for disable_converters in ["false", "true"] {
+        format!("SET thdb.disable_column_converters = {disable_converters}").execute(&mut conn);
+        let retrieved_batch =
+            "SELECT * FROM primitive".fetch_recordbatch(&mut conn, &stored_batch.schema());
+
+        assert_eq!(stored_batch.num_columns(), retrieved_batch.num_columns());
+        for field in stored_batch.schema().fields() {
+            assert_eq!(
+                stored_batch.column_by_name(field.name()),
+                retrieved_batch.column_by_name(field.name())
+            )
+        }
+    }
+    "RESET thdb.disable_column_converters".execute(&mut conn);
+    "RESET thdb.disable_executor".execute(&mut conn);
+
+    Ok(())
+}
+
 #[rstest]
 async fn test_arrow_types_local_file_delta(mut conn: PgConnection, tempdir: TempDir) -> Result<()> {
     let temp_path = tempdir.path();
@@ -303,7 +331,10 @@ async fn test_duckdb_types_parquet_local(
             ])),
             array_col: [1, 2, 3],
             uuid_col: Uuid::parse_str("550e8400-e29b-41d4-a716-446655440000").unwrap(),
-            time_tz_col: time!(12:34:56),
+            time_tz_col: PgTimeTz {
+                time: time!(12:34:56),
+                offset: UtcOffset::UTC
+            },
             timestamp_tz_col: datetime!(2023-06-27 10:34:56 +00:00:00),
         }]
     );
@@ -315,11 +346,7 @@ async fn test_duckdb_types_parquet_local(
 async fn test_create_heap_from_parquet(mut conn: PgConnection, tempdir: TempDir) -> Result<()> {
     let stored_batch = primitive_record_batch()?;
     let parquet_path = tempdir.path().join("test_arrow_types.parquet");
-    let parquet_file = File::create(&parquet_path)?;
-
-    let mut writer = ArrowWriter::try_new(parquet_file, stored_batch.schema(), None).unwrap();
-    writer.write(&stored_batch)?;
-    writer.close()?;
+    write_parquet(&parquet_path, &stored_batch)?;
 
     primitive_setup_fdw_local_file_listing(parquet_path.as_path().to_str().unwrap(), "primitive")
         .execute(&mut conn);
@@ -336,11 +363,7 @@ async fn test_create_heap_from_parquet(mut conn: PgConnection, tempdir: TempDir)
 async fn test_quals_pushdown(mut conn: PgConnection, tempdir: TempDir) -> Result<()> {
     let stored_batch = primitive_record_batch()?;
     let parquet_path = tempdir.path().join("test_arrow_types.parquet");
-    let parquet_file = File::create(&parquet_path)?;
-
-    let mut writer = ArrowWriter::try_new(parquet_file, stored_batch.schema(), None).unwrap();
-    writer.write(&stored_batch)?;
-    writer.close()?;
+    write_parquet(&parquet_path, &stored_batch)?;
 
     primitive_setup_fdw_local_file_listing(parquet_path.as_path().to_str().unwrap(), "primitive")
         .execute(&mut conn);
@@ -423,11 +446,7 @@ async fn test_quals_pushdown(mut conn: PgConnection, tempdir: TempDir) -> Result
 async fn test_complex_quals_pushdown(mut conn: PgConnection, tempdir: TempDir) -> Result<()> {
     let stored_batch = primitive_record_batch()?;
     let parquet_path = tempdir.path().join("test_arrow_types.parquet");
-    let parquet_file = File::create(&parquet_path)?;
-
-    let mut writer = ArrowWriter::try_new(parquet_file, stored_batch.schema(), None).unwrap();
-    writer.write(&stored_batch)?;
-    writer.close()?;
+    write_parquet(&parquet_path, &stored_batch)?;
 
     primitive_setup_fdw_local_file_listing(parquet_path.as_path().to_str().unwrap(), "primitive")
         .execute(&mut conn);
@@ -504,11 +523,7 @@ async fn test_complex_quals_pushdown(mut conn: PgConnection, tempdir: TempDir) -
 async fn test_executor_hook_search_path(mut conn: PgConnection, tempdir: TempDir) -> Result<()> {
     let stored_batch = primitive_record_batch()?;
     let parquet_path = tempdir.path().join("test_arrow_types.parquet");
-    let parquet_file = File::create(&parquet_path)?;
-
-    let mut writer = ArrowWriter::try_new(parquet_file, stored_batch.schema(), None).unwrap();
-    writer.write(&stored_batch)?;
-    writer.close()?;
+    write_parquet(&parquet_path, &stored_batch)?;
 
     "CREATE SCHEMA tpch1".execute(&mut conn);
     "CREATE SCHEMA tpch2".execute(&mut conn);
@@ -601,20 +616,11 @@ async fn test_prepare_stmt_execute(#[future(awt)] s3: S3, mut conn: PgConnection
 async fn test_prepare_search_path(mut conn: PgConnection, tempdir: TempDir) -> Result<()> {
     let stored_batch = primitive_record_batch()?;
     let parquet_path = tempdir.path().join("test_arrow_types.parquet");
-    let parquet_file = File::create(&parquet_path)?;
-
-    let mut writer = ArrowWriter::try_new(parquet_file, stored_batch.schema(), None).unwrap();
-    writer.write(&stored_batch)?;
-    writer.close()?;
+    write_parquet(&parquet_path, &stored_batch)?;
 
     let stored_batch_less = primitive_record_batch_single()?;
     let less_parquet_path = tempdir.path().join("test_arrow_types_less.parquet");
-    let less_parquet_file = File::create(&less_parquet_path)?;
-
-    let mut writer =
-        ArrowWriter::try_new(less_parquet_file, stored_batch_less.schema(), None).unwrap();
-    writer.write(&stored_batch_less)?;
-    writer.close()?;
+    write_parquet(&less_parquet_path, &stored_batch_less)?;
 
     // In this example, we create two tables with identical structures and names, but in different schemas.
     // We expect that when the search path is changed, the correct table (the one in the current schema) will be referenced in DuckDB.
@@ -654,11 +660,7 @@ async fn test_prepare_search_path(mut conn: PgConnection, tempdir: TempDir) -> R
 async fn test_view_foreign_table(mut conn: PgConnection, tempdir: TempDir) -> Result<()> {
     let stored_batch = primitive_record_batch()?;
     let parquet_path = tempdir.path().join("test_arrow_types.parquet");
-    let parquet_file = File::create(&parquet_path)?;
-
-    let mut writer = ArrowWriter::try_new(parquet_file, stored_batch.schema(), None).unwrap();
-    writer.write(&stored_batch)?;
-    writer.close()?;
+    write_parquet(&parquet_path, &stored_batch)?;
 
     primitive_setup_fdw_local_file_listing(parquet_path.as_path().to_str().unwrap(), "primitive")
         .execute(&mut conn);
@@ -702,3 +704,1142 @@ async fn test_doesnt_break_prepare(mut conn: PgConnection) -> Result<()> {
     assert!(ret.is_ok());
     Ok(())
 }
+
+#[rstest]
+async fn test_fallback_policy(mut conn: PgConnection, tempdir: TempDir) -> Result<()> {
+    let stored_batch = primitive_record_batch()?;
+    let parquet_path = tempdir.path().join("test_arrow_types.parquet");
+    write_parquet(&parquet_path, &stored_batch)?;
+
+    primitive_setup_fdw_local_file_listing(parquet_path.as_path().to_str().unwrap(), "primitive")
+        .execute(&mut conn);
+
+    // pg_backend_pid() has no DuckDB translation, so this query always falls back
+    let query = "SELECT COUNT(*) FROM primitive WHERE pg_backend_pid() > 0";
+
+    let ret = query.execute_result(&mut conn);
+    assert!(ret.is_ok(), "{:?}", ret);
+
+    "SET thdb.fallback_policy = 'error'".execute(&mut conn);
+    let ret = query.execute_result(&mut conn);
+    assert!(ret.is_err(), "{:?}", ret);
+    assert!(format!("{:?}", ret).contains("untranslatable"), "{:?}", ret);
+
+    // A comment hint overrides the session setting
+    let ret = format!("/* thdb.fallback_policy=silent */ {query}").execute_result(&mut conn);
+    assert!(ret.is_ok(), "{:?}", ret);
+
+    "BEGIN".execute(&mut conn);
+    "SET LOCAL thdb.fallback_policy = 'log'".execute(&mut conn);
+    let ret = query.execute_result(&mut conn);
+    assert!(ret.is_ok(), "{:?}", ret);
+    "COMMIT".execute(&mut conn);
+
+    let ret = query.execute_result(&mut conn);
+    assert!(ret.is_err(), "{:?}", ret);
+
+    "RESET thdb.fallback_policy".execute(&mut conn);
+    Ok(())
+}
+
+#[rstest]
+async fn test_postgres_dialect_translation(mut conn: PgConnection, tempdir: TempDir) -> Result<()> {
+    let stored_batch = primitive_record_batch()?;
+    let parquet_path = tempdir.path().join("test_arrow_types.parquet");
+    write_parquet(&parquet_path, &stored_batch)?;
+
+    primitive_setup_fdw_local_file_listing(parquet_path.as_path().to_str().unwrap(), "primitive")
+        .execute(&mut conn);
+
+    // Fail instead of falling back, so every query below must be pushed down
+    "SET thdb.fallback_policy = 'error'".execute(&mut conn);
+
+    let rows: Vec<(String,)> =
+        "SELECT to_char(date32_col, 'YYYY-MM-DD') FROM primitive ORDER BY date32_col"
+            .fetch(&mut conn);
+    assert_eq!(
+        rows,
+        vec![
+            ("2020-01-01".to_string(),),
+            ("2020-01-02".to_string(),),
+            ("2020-01-03".to_string(),)
+        ]
+    );
+
+    // Postgres regex operators match anywhere in the string
+    let count: (i64,) = "SELECT COUNT(*) FROM primitive WHERE utf8_col ~ 'or'".fetch_one(&mut conn);
+    assert_eq!(count.0, 1);
+
+    let count: (i64,) =
+        "SELECT COUNT(*) FROM primitive WHERE utf8_col ILIKE ANY (ARRAY['h%', 'w%'])"
+            .fetch_one(&mut conn);
+    assert_eq!(count.0, 2);
+
+    let ret = "SELECT COUNT(*) FROM primitive WHERE 'primitive'::regclass::oid > 0"
+        .execute_result(&mut conn);
+    assert!(format!("{:?}", ret).contains("untranslatable"), "{:?}", ret);
+
+    "RESET thdb.fallback_policy".execute(&mut conn);
+    Ok(())
+}
+
+#[rstest]
+async fn test_insert_select_and_create_table_as(
+    mut conn: PgConnection,
+    tempdir: TempDir,
+) -> Result<()> {
+    let stored_batch = primitive_record_batch()?;
+    let parquet_path = tempdir.path().join("test_arrow_types.parquet");
+    write_parquet(&parquet_path, &stored_batch)?;
+
+    primitive_setup_fdw_local_file_listing(parquet_path.as_path().to_str().unwrap(), "primitive")
+        .execute(&mut conn);
+
+    "CREATE TABLE primitive_summary AS SELECT boolean_col, COUNT(*) AS n FROM primitive GROUP BY boolean_col"
+        .execute(&mut conn);
+    let rows: Vec<(bool, i64)> =
+        "SELECT boolean_col, n FROM primitive_summary ORDER BY boolean_col".fetch(&mut conn);
+    assert_eq!(rows, vec![(false, 1), (true, 2)]);
+
+    "SELECT utf8_col INTO primitive_strings FROM primitive WHERE int32_col >= 0".execute(&mut conn);
+    let rows: Vec<(String,)> =
+        "SELECT utf8_col FROM primitive_strings ORDER BY utf8_col".fetch(&mut conn);
+    assert_eq!(rows, vec![("Hello".to_string(),), ("World".to_string(),)]);
+
+    r#"
+    CREATE TABLE primitive_copy (
+        id SERIAL,
+        int64_col BIGINT PRIMARY KEY,
+        utf8_col TEXT NOT NULL,
+        note TEXT
+    )
+    "#
+    .execute(&mut conn);
+
+    // DuckDB errors must surface instead of silently running the INSERT in Postgres
+    "SET thdb.fallback_policy = 'error'".execute(&mut conn);
+    let ret = "INSERT INTO primitive_copy (int64_col, utf8_col) SELECT int64_col, utf8_col FROM primitive WHERE pg_backend_pid() > 0"
+        .execute_result(&mut conn);
+    assert!(ret.is_err(), "{:?}", ret);
+    "RESET thdb.fallback_policy".execute(&mut conn);
+
+    // Omitted columns with defaults are evaluated by Postgres
+    "INSERT INTO primitive_copy (int64_col, utf8_col) SELECT int64_col, utf8_col FROM primitive"
+        .execute(&mut conn);
+
+    "CREATE TABLE primitive_bulk (int64_col BIGINT PRIMARY KEY, utf8_col TEXT NOT NULL, note TEXT)"
+        .execute(&mut conn);
+    "INSERT INTO primitive_bulk SELECT int64_col, upper(utf8_col) FROM primitive ORDER BY int64_col"
+        .execute(&mut conn);
+
+    let rows: Vec<(i64, String, Option<String>)> =
+        "SELECT int64_col, utf8_col, note FROM primitive_bulk ORDER BY int64_col".fetch(&mut conn);
+    assert_eq!(
+        rows,
+        vec![
+            (-1, "THERE".to_string(), None),
+            (0, "WORLD".to_string(), None),
+            (1, "HELLO".to_string(), None)
+        ]
+    );
+
+    // Indexes are maintained by the bulk insert
+    let ret = "INSERT INTO primitive_bulk SELECT int64_col, utf8_col FROM primitive"
+        .execute_result(&mut conn);
+    assert!(ret.is_err(), "{:?}", ret);
+
+    let count: (i64,) = "SELECT COUNT(*) FROM primitive_copy".fetch_one(&mut conn);
+    assert_eq!(count.0, 3);
+
+    // Typmods, domains and constraints of the target apply as they do to a Postgres INSERT
+    "CREATE TABLE primitive_short (utf8_col VARCHAR(3))".execute(&mut conn);
+    let ret =
+        "INSERT INTO primitive_short SELECT utf8_col FROM primitive".execute_result(&mut conn);
+    assert!(format!("{:?}", ret).contains("too long"), "{:?}", ret);
+
+    "CREATE TABLE primitive_numeric (n NUMERIC(10, 2))".execute(&mut conn);
+    "INSERT INTO primitive_numeric SELECT float64_col / 3 FROM primitive".execute(&mut conn);
+    let rows: Vec<(String,)> = "SELECT n::text FROM primitive_numeric ORDER BY n".fetch(&mut conn);
+    assert_eq!(
+        rows,
+        vec![
+            ("-0.33".to_string(),),
+            ("0.00".to_string(),),
+            ("0.33".to_string(),)
+        ]
+    );
+
+    "CREATE DOMAIN positive_bigint AS BIGINT CHECK (VALUE > 0)".execute(&mut conn);
+    "CREATE TABLE primitive_domain (int64_col positive_bigint)".execute(&mut conn);
+    let ret =
+        "INSERT INTO primitive_domain SELECT int64_col FROM primitive".execute_result(&mut conn);
+    assert!(
+        format!("{:?}", ret).contains("positive_bigint"),
+        "{:?}",
+        ret
+    );
+
+    "CREATE TABLE primitive_not_null (utf8_col TEXT NOT NULL)".execute(&mut conn);
+    let ret = "INSERT INTO primitive_not_null SELECT CASE WHEN int64_col > 0 THEN utf8_col END FROM primitive"
+        .execute_result(&mut conn);
+    assert!(format!("{:?}", ret).contains("not-null"), "{:?}", ret);
+
+    "CREATE TABLE primitive_check (int64_col BIGINT CHECK (int64_col >= 0))".execute(&mut conn);
+    let ret =
+        "INSERT INTO primitive_check SELECT int64_col FROM primitive".execute_result(&mut conn);
+    assert!(
+        format!("{:?}", ret).contains("primitive_check"),
+        "{:?}",
+        ret
+    );
+
+    let count: (i64,) = r#"
+        SELECT (SELECT COUNT(*) FROM primitive_short) + (SELECT COUNT(*) FROM primitive_domain)
+            + (SELECT COUNT(*) FROM primitive_not_null) + (SELECT COUNT(*) FROM primitive_check)
+    "#
+    .fetch_one(&mut conn);
+    assert_eq!(count.0, 0);
+
+    Ok(())
+}
+
+#[rstest]
+async fn test_import_foreign_schema(mut conn: PgConnection, tempdir: TempDir) -> Result<()> {
+    let stored_batch = primitive_record_batch()?;
+    let write_file = |path: std::path::PathBuf| -> Result<()> {
+        std::fs::create_dir_all(path.parent().unwrap())?;
+        write_parquet(&path, &stored_batch)
+    };
+
+    // A single file, a directory of files and a hive partitioned directory
+    let root = tempdir.path();
+    write_file(root.join("Single-File.parquet"))?;
+    write_file(root.join("events").join("part_0.parquet"))?;
+    write_file(root.join("events").join("part_1.parquet"))?;
+    write_file(root.join("sales").join("year=2023").join("data.parquet"))?;
+    write_file(root.join("sales").join("year=2024").join("data.parquet"))?;
+
+    setup_parquet_wrapper_and_server().execute(&mut conn);
+    "CREATE SCHEMA imported".execute(&mut conn);
+    "CREATE SCHEMA limited".execute(&mut conn);
+
+    format!(
+        "IMPORT FOREIGN SCHEMA \"{}\" FROM SERVER parquet_server INTO imported",
+        root.to_str().unwrap()
+    )
+    .execute(&mut conn);
+
+    let tables: Vec<(String,)> = "SELECT foreign_table_name::text FROM information_schema.foreign_tables WHERE foreign_table_schema = 'imported' ORDER BY 1"
+        .fetch(&mut conn);
+    assert_eq!(
+        tables,
+        vec![
+            ("events".to_string(),),
+            ("sales".to_string(),),
+            ("single_file".to_string(),)
+        ]
+    );
+
+    let count: (i64,) = "SELECT COUNT(*) FROM imported.single_file".fetch_one(&mut conn);
+    assert_eq!(count.0, 3);
+    let count: (i64,) = "SELECT COUNT(*) FROM imported.events".fetch_one(&mut conn);
+    assert_eq!(count.0, 6);
+
+    // Hive partition columns are part of the imported schema
+    let rows: Vec<(i64, i64)> =
+        "SELECT year::bigint, COUNT(*) FROM imported.sales GROUP BY year ORDER BY year"
+            .fetch(&mut conn);
+    assert_eq!(rows, vec![(2023, 3), (2024, 3)]);
+
+    format!(
+        "IMPORT FOREIGN SCHEMA \"{}\" LIMIT TO (events, sales) FROM SERVER parquet_server INTO limited",
+        root.to_str().unwrap()
+    )
+    .execute(&mut conn);
+    format!(
+        "IMPORT FOREIGN SCHEMA \"{}\" EXCEPT (events, sales) FROM SERVER parquet_server INTO public",
+        root.to_str().unwrap()
+    )
+    .execute(&mut conn);
+
+    let tables: Vec<(String, String)> = "SELECT foreign_table_schema::text, foreign_table_name::text FROM information_schema.foreign_tables WHERE foreign_table_schema IN ('limited', 'public') ORDER BY 1, 2"
+        .fetch(&mut conn);
+    assert_eq!(
+        tables,
+        vec![
+            ("limited".to_string(), "events".to_string()),
+            ("limited".to_string(), "sales".to_string()),
+            ("public".to_string(), "single_file".to_string())
+        ]
+    );
+
+    // A conflicting import fails without touching the DuckDB view of the existing table
+    "CREATE SCHEMA conflict".execute(&mut conn);
+    format!(
+        "CREATE FOREIGN TABLE conflict.events () SERVER parquet_server OPTIONS (files '{}')",
+        root.join("Single-File.parquet").to_str().unwrap()
+    )
+    .execute(&mut conn);
+    let count: (i64,) = "SELECT COUNT(*) FROM conflict.events".fetch_one(&mut conn);
+    assert_eq!(count.0, 3);
+
+    let ret = format!(
+        "IMPORT FOREIGN SCHEMA \"{}\" FROM SERVER parquet_server INTO conflict",
+        root.to_str().unwrap()
+    )
+    .execute_result(&mut conn);
+    assert!(format!("{:?}", ret).contains("already exists"), "{:?}", ret);
+    let count: (i64,) = "SELECT COUNT(*) FROM conflict.events".fetch_one(&mut conn);
+    assert_eq!(count.0, 3);
+
+    // Files whose names turn into the same table name are not silently merged
+    let colliding = root.join("colliding");
+    write_file(colliding.join("a-b.parquet"))?;
+    write_file(colliding.join("a_b.parquet"))?;
+    let ret = format!(
+        "IMPORT FOREIGN SCHEMA \"{}\" FROM SERVER parquet_server INTO conflict",
+        colliding.to_str().unwrap()
+    )
+    .execute_result(&mut conn);
+    assert!(
+        format!("{:?}", ret).contains("would both be imported"),
+        "{:?}",
+        ret
+    );
+
+    Ok(())
+}
+
+#[rstest]
+async fn test_local_file_access(mut conn: PgConnection, tempdir: TempDir) -> Result<()> {
+    let stored_batch = primitive_record_batch()?;
+    let parquet_path = tempdir.path().join("test_arrow_types.parquet");
+    write_parquet(&parquet_path, &stored_batch)?;
+
+    let parquet_path = parquet_path.as_path().to_str().unwrap();
+    let allowed_directory = tempdir.path().to_str().unwrap();
+
+    // Confine DuckDB before its connection is opened in this session
+    format!("SET thdb.allowed_directories = '{allowed_directory}'").execute(&mut conn);
+    setup_parquet_wrapper_and_server().execute(&mut conn);
+    "CREATE ROLE file_reader; GRANT USAGE ON FOREIGN SERVER parquet_server TO file_reader; GRANT CREATE ON SCHEMA public TO file_reader"
+        .execute(&mut conn);
+
+    // Roles without pg_read_server_files can't point foreign tables at local files
+    "SET ROLE file_reader".execute(&mut conn);
+    let ret = format!(
+        "CREATE FOREIGN TABLE passwd () SERVER parquet_server OPTIONS (files '{parquet_path}')"
+    )
+    .execute_result(&mut conn);
+    assert!(ret
+        .unwrap_err()
+        .to_string()
+        .contains("pg_read_server_files"),);
+    "RESET ROLE".execute(&mut conn);
+
+    "GRANT pg_read_server_files TO file_reader".execute(&mut conn);
+    "SET ROLE file_reader".execute(&mut conn);
+    format!(
+        "CREATE FOREIGN TABLE primitive () SERVER parquet_server OPTIONS (files '{parquet_path}')"
+    )
+    .execute(&mut conn);
+    let count: (i64,) = "SELECT COUNT(*) FROM primitive".fetch_one(&mut conn);
+    assert_eq!(count.0, 3);
+
+    // Paths outside of the allowed directories are rejected, even through ..
+    let ret = format!(
+        "CREATE FOREIGN TABLE passwd () SERVER parquet_server OPTIONS (files '{allowed_directory}/../../etc/passwd')"
+    )
+    .execute_result(&mut conn);
+    assert!(ret
+        .unwrap_err()
+        .to_string()
+        .contains("outside of thdb.allowed_directories"));
+    "RESET ROLE".execute(&mut conn);
+
+    // Revoking the role takes effect when the view is registered again
+    "REVOKE pg_read_server_files FROM file_reader".execute(&mut conn);
+    "SET thdb.allowed_directories = '/nonexistent'".execute(&mut conn);
+    let ret = "SELECT COUNT(*) FROM primitive".execute_result(&mut conn);
+    assert!(ret.is_err());
+
+    Ok(())
+}
+
+#[rstest]
+async fn test_confined_session(mut conn: PgConnection, tempdir: TempDir) -> Result<()> {
+    let csv_path = tempdir.path().join("secret.csv");
+    std::fs::write(&csv_path, "a,b\n1,2\n")?;
+    let csv_path = csv_path.to_str().unwrap();
+
+    "CREATE ROLE confined_session".execute(&mut conn);
+
+    // Sniffing a file is as sensitive as reading it
+    "SET ROLE confined_session".execute(&mut conn);
+    let ret = format!("SELECT * FROM sniff_csv('{csv_path}')").execute_result(&mut conn);
+    assert!(
+        format!("{:?}", ret).contains("pg_read_server_files"),
+        "{:?}",
+        ret
+    );
+    "RESET ROLE".execute(&mut conn);
+
+    // Local files are checked for each table and call, so DuckDB keeps its external access
+    // and the role can still read remote files
+    "SET SESSION AUTHORIZATION confined_session".execute(&mut conn);
+    let value: (Option<String>,) =
+        "SELECT value FROM duckdb_settings() WHERE name = 'enable_external_access'"
+            .fetch_one(&mut conn);
+    assert_eq!(value.0, Some("true".to_string()));
+    "RESET SESSION AUTHORIZATION".execute(&mut conn);
+
+    Ok(())
+}
+
+#[rstest]
+async fn test_read_functions(mut conn: PgConnection, tempdir: TempDir) -> Result<()> {
+    let stored_batch = primitive_record_batch()?;
+    let parquet_path = tempdir.path().join("test_arrow_types.parquet");
+    write_parquet(&parquet_path, &stored_batch)?;
+
+    let parquet_path = parquet_path.as_path().to_str().unwrap();
+
+    // Queries that only read from the table functions are pushed down
+    "SET thdb.fallback_policy = 'error'".execute(&mut conn);
+    let rows: Vec<(String, i64)> = format!(
+        r#"
+        SELECT utf8_col, int64_col
+        FROM th_dbdm.read_parquet('{parquet_path}', '{{"select": "utf8_col, int64_col"}}')
+        AS t(utf8_col text, int64_col bigint)
+        ORDER BY int64_col
+        "#
+    )
+    .fetch(&mut conn);
+    assert_eq!(
+        rows,
+        vec![
+            ("There".to_string(), -1),
+            ("World".to_string(), 0),
+            ("Hello".to_string(), 1)
+        ]
+    );
+    "RESET thdb.fallback_policy".execute(&mut conn);
+
+    // Joined with a Postgres table, the function itself runs the DuckDB query
+    "CREATE TABLE greetings (greeting TEXT)".execute(&mut conn);
+    "INSERT INTO greetings VALUES ('Hello'), ('Goodbye')".execute(&mut conn);
+    let rows: Vec<(String, bool)> = format!(
+        r#"
+        SELECT g.greeting, t.boolean_col
+        FROM greetings g
+        JOIN th_dbdm.read_parquet('{parquet_path}', '{{"select": "utf8_col, boolean_col"}}') AS t(utf8_col text, boolean_col boolean)
+        ON t.utf8_col = g.greeting
+        "#
+    )
+    .fetch(&mut conn);
+    assert_eq!(rows, vec![("Hello".to_string(), true)]);
+
+    // Options are validated like foreign table options
+    let ret = format!(
+        "SELECT * FROM th_dbdm.read_parquet('{parquet_path}', '{{\"delim\": \",\"}}') AS t(a text)"
+    )
+    .execute_result(&mut conn);
+    assert!(ret.is_err());
+
+    // Option values can't smuggle SQL into the DuckDB query, whichever path runs it
+    for options in [
+        r#"{"select": "* FROM read_csv('/etc/passwd') --"}"#,
+        r#"{"binary_as_string": "(SELECT 1)"}"#,
+    ] {
+        let ret = format!(
+            "SELECT * FROM th_dbdm.read_parquet('{parquet_path}', $${options}$$) AS t(a text)"
+        )
+        .execute_result(&mut conn);
+        assert!(ret.is_err(), "{:?}", ret);
+
+        let ret = format!(
+            "SELECT g.greeting FROM greetings g, th_dbdm.read_parquet('{parquet_path}', $${options}$$) AS t(a text)"
+        )
+        .execute_result(&mut conn);
+        assert!(ret.is_err(), "{:?}", ret);
+    }
+    let ret = "SELECT * FROM th_dbdm.read_csv($$/tmp/x.csv') UNION SELECT * FROM read_text('/etc/passwd$$) AS t(a text)"
+        .execute_result(&mut conn);
+    assert!(format!("{:?}", ret).contains("single quotes"), "{:?}", ret);
+
+    // Local files need the same privileges as foreign tables
+    "CREATE ROLE file_peeker".execute(&mut conn);
+    "SET ROLE file_peeker".execute(&mut conn);
+    let ret = format!("SELECT COUNT(*) FROM th_dbdm.read_parquet('{parquet_path}') AS t(a text)")
+        .execute_result(&mut conn);
+    assert!(ret.is_err());
+    "RESET ROLE".execute(&mut conn);
+
+    Ok(())
+}
+
+#[rstest]
+async fn test_csv_rejects(mut conn: PgConnection, tempdir: TempDir) -> Result<()> {
+    let file_path = tempdir.path().join("rejects.csv");
+    std::fs::write(&file_path, "id,name\n1,one\ntwo,two\n3,three\n")?;
+    let file_path = file_path.to_string_lossy();
+
+    format!(
+        r#"
+        {};
+        {};
+        CREATE FOREIGN TABLE csv_rejected (id INT, name TEXT) SERVER csv_server
+        OPTIONS (files '{file_path}', columns '{{''id'': ''INTEGER'', ''name'': ''VARCHAR''}}', header 'true', store_rejects 'true');
+        CREATE FOREIGN TABLE csv_unchecked (id INT, name TEXT) SERVER csv_server
+        OPTIONS (files '{file_path}', header 'true', ignore_errors 'true');
+        "#,
+        primitive_create_foreign_data_wrapper("csv_wrapper", "csv_fdw_handler", "csv_fdw_validator"),
+        primitive_create_server("csv_server", "csv_wrapper"),
+    )
+    .execute(&mut conn);
+
+    // Nothing has been scanned yet
+    let rejects: Vec<(Option<String>,)> =
+        "SELECT file_path FROM csv_rejects('csv_rejected')".fetch(&mut conn);
+    assert!(rejects.is_empty());
+
+    let ids: Vec<(i32,)> = "SELECT id FROM csv_rejected ORDER BY id".fetch(&mut conn);
+    assert_eq!(ids, vec![(1,), (3,)]);
+
+    let rejects: Vec<(String, i64, Option<String>, String, String)> =
+        "SELECT file_path, line, column_name, error_type, raw_line FROM csv_rejects('csv_rejected')"
+            .fetch(&mut conn);
+    assert_eq!(rejects.len(), 1);
+    assert_eq!(rejects[0].0, file_path);
+    assert_eq!(rejects[0].1, 3);
+    assert_eq!(rejects[0].2.as_deref(), Some("id"));
+    assert_eq!(rejects[0].3, "CAST");
+    assert_eq!(rejects[0].4, "two,two");
+
+    let result = "SELECT * FROM csv_rejects('csv_unchecked')".execute_result(&mut conn);
+    assert!(result
+        .unwrap_err()
+        .to_string()
+        .contains("does not have the store_rejects option set"));
+
+    // Both tables use the default rejects tables, but only see the rejects of their own files
+    let other_path = tempdir.path().join("other_rejects.csv");
+    std::fs::write(
+        &other_path,
+        "id,name
+four,four
+5,five
+",
+    )?;
+    let other_path = other_path.to_string_lossy();
+
+    format!(
+        r#"
+        CREATE FOREIGN TABLE csv_other_rejected (id INT, name TEXT) SERVER csv_server
+        OPTIONS (files '{other_path}', columns '{{''id'': ''INTEGER'', ''name'': ''VARCHAR''}}', header 'true', store_rejects 'true');
+        "#
+    )
+    .execute(&mut conn);
+
+    let ids: Vec<(i32,)> = "SELECT id FROM csv_other_rejected ORDER BY id".fetch(&mut conn);
+    assert_eq!(ids, vec![(5,)]);
+
+    let rejects: Vec<(String, String)> =
+        "SELECT file_path, raw_line FROM csv_rejects('csv_other_rejected')".fetch(&mut conn);
+    assert_eq!(
+        rejects,
+        vec![(other_path.to_string(), "four,four".to_string())]
+    );
+
+    let rejects: Vec<(String,)> =
+        "SELECT file_path FROM csv_rejects('csv_rejected')".fetch(&mut conn);
+    assert!(rejects.iter().all(|(path,)| *path == file_path));
+
+    Ok(())
+}
+
+#[rstest]
+async fn test_create_csv_foreign_table(mut conn: PgConnection, tempdir: TempDir) -> Result<()> {
+    let file_path = tempdir.path().join("sniffed.csv");
+    std::fs::write(
+        &file_path,
+        "id;name;created\n1;one;2024-01-02\n2;two;2024-02-03\n",
+    )?;
+    let file_path = file_path.to_string_lossy();
+
+    format!(
+        "{}; {}",
+        primitive_create_foreign_data_wrapper(
+            "csv_wrapper",
+            "csv_fdw_handler",
+            "csv_fdw_validator"
+        ),
+        primitive_create_server("csv_server", "csv_wrapper"),
+    )
+    .execute(&mut conn);
+
+    let (ddl,): (String,) =
+        format!("SELECT create_csv_foreign_table('public.sniffed', 'csv_server', '{file_path}')")
+            .fetch_one(&mut conn);
+    assert!(ddl.starts_with(
+        "CREATE FOREIGN TABLE public.sniffed (id BIGINT, name VARCHAR, created DATE)"
+    ));
+    assert!(ddl.contains("auto_detect 'false'"));
+    assert!(ddl.contains("delim ';'"));
+    assert!(ddl.contains("preserve_casing 'true'"));
+
+    let rows: Vec<(i64, String, time::Date)> =
+        "SELECT id, name, created FROM sniffed ORDER BY id".fetch(&mut conn);
+    assert_eq!(
+        rows,
+        vec![
+            (1, "one".to_string(), date!(2024 - 01 - 02)),
+            (2, "two".to_string(), date!(2024 - 02 - 03))
+        ]
+    );
+
+    // Sniffed column names keep their casing, and the table reads them with the same casing
+    let mixed_case_path = tempdir.path().join("mixed_case.csv");
+    std::fs::write(&mixed_case_path, "Id,ItemName\n1,one\n")?;
+    let mixed_case_path = mixed_case_path.to_string_lossy();
+
+    format!("SELECT create_csv_foreign_table('mixed_case', 'csv_server', '{mixed_case_path}')")
+        .execute(&mut conn);
+    let rows: Vec<(i64, String)> = r#"SELECT "Id", "ItemName" FROM mixed_case"#.fetch(&mut conn);
+    assert_eq!(rows, vec![(1, "one".to_string())]);
+
+    Ok(())
+}
+
+#[rstest]
+async fn test_foreign_table_schema_drift(mut conn: PgConnection, tempdir: TempDir) -> Result<()> {
+    use datafusion::arrow::array::{
+        Int32Array, Int64Array, StringArray, TimestampMicrosecondArray,
+    };
+    use datafusion::arrow::datatypes::{DataType, Field, Schema, TimeUnit};
+    use datafusion::arrow::record_batch::RecordBatch;
+    use std::sync::Arc;
+
+    let parquet_path = tempdir.path().join("drift.parquet");
+    write_parquet(
+        &parquet_path,
+        &RecordBatch::try_new(
+            Arc::new(Schema::new(vec![
+                Field::new("id", DataType::Int32, false),
+                Field::new("name", DataType::Utf8, false),
+            ])),
+            vec![
+                Arc::new(Int32Array::from(vec![1])),
+                Arc::new(StringArray::from(vec!["one"])),
+            ],
+        )?,
+    )?;
+
+    setup_parquet_wrapper_and_server().execute(&mut conn);
+    format!(
+        "CREATE FOREIGN TABLE drift () SERVER parquet_server OPTIONS (files '{}')",
+        parquet_path.to_string_lossy()
+    )
+    .execute(&mut conn);
+
+    let diff: Vec<(String, String)> =
+        "SELECT column_name, status FROM foreign_table_schema_diff('drift')".fetch(&mut conn);
+    assert!(diff.is_empty());
+
+    // name is dropped, id is widened and score is added
+    write_parquet(
+        &parquet_path,
+        &RecordBatch::try_new(
+            Arc::new(Schema::new(vec![
+                Field::new("id", DataType::Int64, false),
+                Field::new("score", DataType::Int64, false),
+            ])),
+            vec![
+                Arc::new(Int64Array::from(vec![1])),
+                Arc::new(Int64Array::from(vec![10])),
+            ],
+        )?,
+    )?;
+
+    let diff: Vec<(String, String)> =
+        "SELECT column_name, status FROM foreign_table_schema_diff('drift') ORDER BY column_name"
+            .fetch(&mut conn);
+    assert_eq!(
+        diff,
+        vec![
+            ("id".to_string(), "type_changed".to_string()),
+            ("name".to_string(), "dropped".to_string()),
+            ("score".to_string(), "added".to_string()),
+        ]
+    );
+
+    "SET thdb.schema_drift_check = true".execute(&mut conn);
+    let result = "SELECT * FROM drift".execute_result(&mut conn);
+    assert!(result
+        .unwrap_err()
+        .to_string()
+        .contains("column name is no longer in the files"));
+
+    "SELECT refresh_foreign_table_schema('drift')".execute(&mut conn);
+    let columns: Vec<(String,)> =
+        "SELECT attname::text FROM pg_attribute WHERE attrelid = 'drift'::regclass AND attnum > 0 AND NOT attisdropped ORDER BY attnum"
+            .fetch(&mut conn);
+    assert_eq!(
+        columns,
+        vec![("id".into(),), ("name".into(),), ("score".into(),)]
+    );
+
+    "SELECT refresh_foreign_table_schema('drift', 'all')".execute(&mut conn);
+    let rows: Vec<(i64, i64)> = "SELECT id, score FROM drift".fetch(&mut conn);
+    assert_eq!(rows, vec![(1, 10)]);
+
+    // Aggregates are pushed down to DuckDB, and are checked for drift as well
+    write_parquet(
+        &parquet_path,
+        &RecordBatch::try_new(
+            Arc::new(Schema::new(vec![Field::new("id", DataType::Int64, false)])),
+            vec![Arc::new(Int64Array::from(vec![1]))],
+        )?,
+    )?;
+    let result = "SELECT COUNT(*) FROM drift".execute_result(&mut conn);
+    assert!(result
+        .unwrap_err()
+        .to_string()
+        .contains("column score is no longer in the files"));
+    "RESET thdb.schema_drift_check".execute(&mut conn);
+
+    // Columns declared with a type that can hold the values of the files haven't drifted
+    let widened_path = tempdir.path().join("widened.parquet");
+    let batch = RecordBatch::try_new(
+        Arc::new(Schema::new(vec![
+            Field::new("id", DataType::Int32, false),
+            Field::new("amount", DataType::Int32, false),
+            Field::new(
+                "created",
+                DataType::Timestamp(TimeUnit::Microsecond, None),
+                false,
+            ),
+        ])),
+        vec![
+            Arc::new(Int32Array::from(vec![1])),
+            Arc::new(Int32Array::from(vec![5])),
+            Arc::new(TimestampMicrosecondArray::from(vec![0])),
+        ],
+    )?;
+    write_parquet(&widened_path, &batch)?;
+
+    format!(
+        "CREATE FOREIGN TABLE widened (id BIGINT, amount NUMERIC, created TIMESTAMPTZ) SERVER parquet_server OPTIONS (files '{}')",
+        widened_path.to_string_lossy()
+    )
+    .execute(&mut conn);
+    let diff: Vec<(String, String)> =
+        "SELECT column_name, status FROM foreign_table_schema_diff('widened')".fetch(&mut conn);
+    assert!(diff.is_empty());
+
+    // Narrower types can't hold every value
+    format!(
+        "CREATE FOREIGN TABLE narrowed (id SMALLINT) SERVER parquet_server OPTIONS (files '{}')",
+        widened_path.to_string_lossy()
+    )
+    .execute(&mut conn);
+    let diff: Vec<(String, String)> =
+        "SELECT column_name, status FROM foreign_table_schema_diff('narrowed') WHERE column_name = 'id'"
+            .fetch(&mut conn);
+    assert_eq!(diff, vec![("id".to_string(), "type_changed".to_string())]);
+
+    // Diffing reads the files, so it needs SELECT on the table
+    "CREATE ROLE drift_watcher".execute(&mut conn);
+    "SET ROLE drift_watcher".execute(&mut conn);
+    let result = "SELECT * FROM foreign_table_schema_diff('widened')".execute_result(&mut conn);
+    assert!(result
+        .unwrap_err()
+        .to_string()
+        .contains("permission denied for table widened"));
+    let result = "SELECT refresh_foreign_table_schema('widened')".execute_result(&mut conn);
+    assert!(result
+        .unwrap_err()
+        .to_string()
+        .contains("permission denied for table widened"));
+    "RESET ROLE".execute(&mut conn);
+
+    Ok(())
+}
+
+#[rstest]
+async fn test_type_overrides(mut conn: PgConnection, tempdir: TempDir) -> Result<()> {
+    use datafusion::arrow::array::{Int32Array, StringArray};
+    use datafusion::arrow::datatypes::{DataType, Field, Schema};
+    use datafusion::arrow::record_batch::RecordBatch;
+    use std::sync::Arc;
+
+    let parquet_path = tempdir.path().join("overrides.parquet");
+    let batch = RecordBatch::try_new(
+        Arc::new(Schema::new(vec![
+            Field::new("id", DataType::Utf8, false),
+            Field::new("payload", DataType::Utf8, false),
+            Field::new("amount", DataType::Utf8, false),
+            Field::new("addr", DataType::Utf8, false),
+            Field::new("count", DataType::Int32, false),
+        ])),
+        vec![
+            Arc::new(StringArray::from(vec![
+                "a0eebc99-9c0b-4ef8-bb6d-6bb9bd380a11",
+            ])),
+            Arc::new(StringArray::from(vec![r#"{"a": 1}"#])),
+            Arc::new(StringArray::from(vec!["12.50"])),
+            Arc::new(StringArray::from(vec!["10.0.0.1"])),
+            Arc::new(Int32Array::from(vec![3])),
+        ],
+    )?;
+    write_parquet(&parquet_path, &batch)?;
+
+    setup_parquet_wrapper_and_server().execute(&mut conn);
+    format!(
+        r#"CREATE FOREIGN TABLE overrides () SERVER parquet_server OPTIONS (files '{}', type_overrides '{{"id": "uuid", "payload": "jsonb", "amount": "numeric(10,2)", "addr": "inet"}}')"#,
+        parquet_path.to_string_lossy()
+    )
+    .execute(&mut conn);
+
+    let columns: Vec<(String, String)> =
+        "SELECT attname::text, format_type(atttypid, atttypmod) FROM pg_attribute WHERE attrelid = 'overrides'::regclass AND attnum > 0 ORDER BY attnum"
+            .fetch(&mut conn);
+    assert_eq!(
+        columns,
+        vec![
+            ("id".into(), "uuid".into()),
+            ("payload".into(), "jsonb".into()),
+            ("amount".into(), "numeric(10,2)".into()),
+            ("addr".into(), "inet".into()),
+            ("count".into(), "integer".into()),
+        ]
+    );
+
+    let row: (String, String, String, String, i32) =
+        "SELECT id::text, payload->>'a', amount::text, host(addr), count FROM overrides"
+            .fetch_one(&mut conn);
+    assert_eq!(
+        row,
+        (
+            "a0eebc99-9c0b-4ef8-bb6d-6bb9bd380a11".into(),
+            "1".into(),
+            "12.50".into(),
+            "10.0.0.1".into(),
+            3
+        )
+    );
+
+    // Overridden columns don't count as drift
+    let diff: Vec<(String, String)> =
+        "SELECT column_name, status FROM foreign_table_schema_diff('overrides')".fetch(&mut conn);
+    assert!(diff.is_empty());
+
+    // Overrides are parsed as type names, and written as Postgres formats them
+    format!(
+        r#"CREATE FOREIGN TABLE overrides_alias () SERVER parquet_server OPTIONS (files '{}', type_overrides '{{"amount": "DECIMAL(10, 2)", "count": "int8"}}')"#,
+        parquet_path.to_string_lossy()
+    )
+    .execute(&mut conn);
+    let columns: Vec<(String, String)> =
+        "SELECT attname::text, format_type(atttypid, atttypmod) FROM pg_attribute WHERE attrelid = 'overrides_alias'::regclass AND attname IN ('amount', 'count') ORDER BY attnum"
+            .fetch(&mut conn);
+    assert_eq!(
+        columns,
+        vec![
+            ("amount".into(), "numeric(10,2)".into()),
+            ("count".into(), "bigint".into()),
+        ]
+    );
+
+    let result = format!(
+        r#"CREATE FOREIGN TABLE overrides_invalid () SERVER parquet_server OPTIONS (files '{}', type_overrides '{{"count": "int, ADD COLUMN injected text"}}')"#,
+        parquet_path.to_string_lossy()
+    )
+    .execute_result(&mut conn);
+    assert!(result.is_err(), "{:?}", result);
+
+    Ok(())
+}
+
+#[rstest]
+async fn test_wide_numeric(mut conn: PgConnection, tempdir: TempDir) -> Result<()> {
+    let file_path = tempdir.path().join("wide.csv");
+    std::fs::write(
+        &file_path,
+        "h,uh,ub\n-12345678901234567890123456789012345678,98765432109876543210987654321098765432,18446744073709551615\n",
+    )?;
+    let file_path = file_path.to_string_lossy();
+    let columns = "columns '{''h'': ''HUGEINT'', ''uh'': ''UHUGEINT'', ''ub'': ''UBIGINT''}'";
+
+    format!(
+        r#"
+        {};
+        {};
+        CREATE FOREIGN TABLE wide () SERVER csv_server OPTIONS (files '{file_path}', {columns});
+        CREATE FOREIGN TABLE wide_text (h TEXT, uh TEXT, ub TEXT) SERVER csv_server
+        OPTIONS (files '{file_path}', {columns});
+        CREATE FOREIGN TABLE wide_bigint (ub BIGINT) SERVER csv_server
+        OPTIONS (files '{file_path}', {columns});
+        "#,
+        primitive_create_foreign_data_wrapper(
+            "csv_wrapper",
+            "csv_fdw_handler",
+            "csv_fdw_validator"
+        ),
+        primitive_create_server("csv_server", "csv_wrapper"),
+    )
+    .execute(&mut conn);
+
+    let expected = (
+        BigDecimal::from_str("-12345678901234567890123456789012345678")?,
+        BigDecimal::from_str("98765432109876543210987654321098765432")?,
+        BigDecimal::from_str("18446744073709551615")?,
+    );
+    let row: (BigDecimal, BigDecimal, BigDecimal) =
+        "SELECT h, uh, ub FROM wide".fetch_one(&mut conn);
+    assert_eq!(row, expected);
+
+    let row: (String, String, String) = "SELECT h, uh, ub FROM wide_text".fetch_one(&mut conn);
+    assert_eq!(
+        row,
+        (
+            "-12345678901234567890123456789012345678".to_string(),
+            "98765432109876543210987654321098765432".to_string(),
+            "18446744073709551615".to_string()
+        )
+    );
+
+    let result = "SELECT ub FROM wide_bigint".execute_result(&mut conn);
+    assert!(result
+        .unwrap_err()
+        .to_string()
+        .contains("Column ub has value 18446744073709551615, which is out of range"));
+
+    Ok(())
+}
+
+fn find_plan_node<'a>(
+    plan: &'a serde_json::Value,
+    node_type: &str,
+) -> Option<&'a serde_json::Value> {
+    if plan["Node Type"] == node_type {
+        return Some(plan);
+    }
+    plan["Plans"]
+        .as_array()?
+        .iter()
+        .find_map(|child| find_plan_node(child, node_type))
+}
+
+#[rstest]
+async fn test_parallel_foreign_scan(
+    mut conn: PgConnection,
+    tempdir: TempDir,
+    duckdb_conn: duckdb::Connection,
+) -> Result<()> {
+    for part in 0..4 {
+        let parquet_path = tempdir.path().join(format!("part_{part}.parquet"));
+        duckdb_conn.execute(
+            &format!(
+                "COPY (SELECT range AS id, 'row ' || range AS name FROM range({}, {})) TO '{}' (FORMAT PARQUET)",
+                part * 1000,
+                (part + 1) * 1000,
+                parquet_path.to_str().unwrap()
+            ),
+            [],
+        )?;
+    }
+
+    format!(
+        "{}; CREATE FOREIGN TABLE parts () SERVER parquet_server OPTIONS (files '{}/part_*.parquet')",
+        setup_parquet_wrapper_and_server(),
+        tempdir.path().to_str().unwrap()
+    )
+    .execute(&mut conn);
+
+    // The foreign scan is split among workers as soon as the planner considers a Gather
+    r#"
+    SET thdb.disable_executor = true;
+    SET parallel_setup_cost = 0;
+    SET parallel_tuple_cost = 0;
+    SET max_parallel_workers_per_gather = 2;
+    "#
+    .execute(&mut conn);
+
+    let expected = (4000_i64, 4000_i64, BigDecimal::from(7998000));
+    let query = "SELECT COUNT(*), COUNT(DISTINCT name), SUM(id) FROM parts";
+    let parallel: (i64, i64, BigDecimal) = query.fetch_one(&mut conn);
+    assert_eq!(parallel, expected);
+
+    // Queries on foreign tables alone are explained by DuckDB, so the plan joins a local table.
+    // Without the leader, every row is read by a worker, and each worker reads whole files.
+    r#"
+    CREATE TABLE parts_filter (keep BOOLEAN);
+    INSERT INTO parts_filter VALUES (true);
+    SET parallel_leader_participation = off;
+    "#
+    .execute(&mut conn);
+    let (explain,): (serde_json::Value,) =
+        "EXPLAIN (ANALYZE, VERBOSE, FORMAT JSON) SELECT parts.id FROM parts, parts_filter WHERE parts_filter.keep"
+            .fetch_one(&mut conn);
+    let gather = find_plan_node(&explain[0]["Plan"], "Gather").expect("plan has no Gather");
+    assert!(gather["Workers Launched"].as_i64().unwrap_or_default() > 0);
+    let scan = find_plan_node(gather, "Foreign Scan").expect("Gather has no foreign scan");
+    assert_eq!(scan["Parallel Aware"], serde_json::json!(true));
+    let worker_rows = scan["Workers"]
+        .as_array()
+        .expect("foreign scan has no workers")
+        .iter()
+        .map(|worker| worker["Actual Rows"].as_i64().unwrap_or_default())
+        .collect::<Vec<i64>>();
+    assert_eq!(worker_rows.iter().sum::<i64>(), 4000);
+    assert!(worker_rows.iter().all(|rows| rows % 1000 == 0));
+    "RESET parallel_leader_participation".execute(&mut conn);
+
+    "SET thdb.enable_parallel_foreign_scan = false".execute(&mut conn);
+    let serial: (i64, i64, BigDecimal) = query.fetch_one(&mut conn);
+    assert_eq!(serial, expected);
+
+    r#"
+    RESET thdb.enable_parallel_foreign_scan;
+    RESET max_parallel_workers_per_gather;
+    RESET parallel_tuple_cost;
+    RESET parallel_setup_cost;
+    RESET thdb.disable_executor;
+    "#
+    .execute(&mut conn);
+
+    Ok(())
+}
+
+#[rstest]
+async fn test_async_foreign_scan(
+    mut conn: PgConnection,
+    tempdir: TempDir,
+    duckdb_conn: duckdb::Connection,
+) -> Result<()> {
+    setup_parquet_wrapper_and_server().execute(&mut conn);
+    for part in 0..3 {
+        let parquet_path = tempdir.path().join(format!("part_{part}.parquet"));
+        duckdb_conn.execute(
+            &format!(
+                "COPY (SELECT range AS id, 'row ' || range AS name FROM range({}, {})) TO '{}' (FORMAT PARQUET)",
+                part * 1000,
+                (part + 1) * 1000,
+                parquet_path.to_str().unwrap()
+            ),
+            [],
+        )?;
+        format!(
+            "CREATE FOREIGN TABLE part_{part} () SERVER parquet_server OPTIONS (files '{}')",
+            parquet_path.to_str().unwrap()
+        )
+        .execute(&mut conn);
+    }
+
+    r#"
+    SET thdb.disable_executor = true;
+    SET max_parallel_workers_per_gather = 0;
+    "#
+    .execute(&mut conn);
+
+    let query = r#"
+    SELECT COUNT(*), COUNT(DISTINCT name), SUM(id) FROM (
+        SELECT id, name FROM part_0
+        UNION ALL SELECT id, name FROM part_1
+        UNION ALL SELECT id, name FROM part_2
+    ) parts
+    "#;
+    let explain: Vec<(String,)> = format!("EXPLAIN {query}").fetch(&mut conn);
+    assert_eq!(
+        explain
+            .iter()
+            .filter(|(line,)| line.contains("Async Foreign Scan"))
+            .count(),
+        3
+    );
+
+    let expected = (3000_i64, 3000_i64, BigDecimal::from(4498500));
+    let concurrent: (i64, i64, BigDecimal) = query.fetch_one(&mut conn);
+    assert_eq!(concurrent, expected);
+
+    // A LIMIT stops the Append before every statement is done
+    let limited: Vec<(i64,)> =
+        "SELECT id FROM part_0 UNION ALL SELECT id FROM part_1 LIMIT 10".fetch(&mut conn);
+    assert_eq!(limited.len(), 10);
+
+    // Statements of a failed UNION ALL are stopped with the subtransaction that started them
+    format!(
+        "CREATE FOREIGN TABLE part_missing (id BIGINT, name TEXT) SERVER parquet_server OPTIONS (files '{}')",
+        tempdir.path().join("missing.parquet").to_str().unwrap()
+    )
+    .execute(&mut conn);
+    "BEGIN; SAVEPOINT before_missing".execute(&mut conn);
+    let result =
+        "SELECT id FROM part_0 UNION ALL SELECT id FROM part_missing".execute_result(&mut conn);
+    assert!(result.is_err());
+    "ROLLBACK TO SAVEPOINT before_missing".execute(&mut conn);
+    let retried: (i64, i64, BigDecimal) = query.fetch_one(&mut conn);
+    assert_eq!(retried, expected);
+    "COMMIT".execute(&mut conn);
+
+    "SET thdb.enable_async_foreign_scan = false".execute(&mut conn);
+    let explain: Vec<(String,)> = format!("EXPLAIN {query}").fetch(&mut conn);
+    assert!(!explain
+        .iter()
+        .any(|(line,)| line.contains("Async Foreign Scan")));
+    let sequential: (i64, i64, BigDecimal) = query.fetch_one(&mut conn);
+    assert_eq!(sequential, expected);
+
+    r#"
+    RESET thdb.enable_async_foreign_scan;
+    RESET max_parallel_workers_per_gather;
+    RESET thdb.disable_executor;
+    "#
+    .execute(&mut conn);
+
+    Ok(())
+}
+
+#[rstest]
+async fn test_pinned_delta_snapshot(mut conn: PgConnection, tempdir: TempDir) -> Result<()> {
+    let temp_path = tempdir.path();
+    let batch = delta_primitive_record_batch()?;
+    let delta_schema = deltalake::kernel::Schema::try_from(batch.schema().as_ref())?;
+    let mut table = CreateBuilder::new()
+        .with_location(temp_path.to_string_lossy().as_ref())
+        .with_columns(delta_schema.fields().to_vec())
+        .await?;
+    let mut writer = RecordBatchWriter::for_table(&table)?;
+    writer.write(batch.clone()).await?;
+    writer.flush_and_commit(&mut table).await?;
+
+    primitive_setup_fdw_local_file_delta(&temp_path.to_string_lossy(), "delta_primitive")
+        .execute(&mut conn);
+
+    let rows = batch.num_rows() as i64;
+    let count = "SELECT COUNT(*) FROM delta_primitive";
+
+    // A version committed during the transaction is only seen once it is over
+    "SET thdb.pin_lake_snapshots = true".execute(&mut conn);
+    "BEGIN ISOLATION LEVEL REPEATABLE READ".execute(&mut conn);
+    assert_eq!(count.fetch_one::<(i64,)>(&mut conn), (rows,));
+    writer.write(batch.clone()).await?;
+    writer.flush_and_commit(&mut table).await?;
+    assert_eq!(count.fetch_one::<(i64,)>(&mut conn), (rows,));
+    "SET LOCAL thdb.disable_executor = true".execute(&mut conn);
+    assert_eq!(count.fetch_one::<(i64,)>(&mut conn), (rows,));
+    "COMMIT".execute(&mut conn);
+    assert_eq!(count.fetch_one::<(i64,)>(&mut conn), (rows * 2,));
+
+    "SET thdb.pin_lake_snapshots = false".execute(&mut conn);
+    "BEGIN ISOLATION LEVEL REPEATABLE READ".execute(&mut conn);
+    assert_eq!(count.fetch_one::<(i64,)>(&mut conn), (rows * 2,));
+    writer.write(batch.clone()).await?;
+    writer.flush_and_commit(&mut table).await?;
+    assert_eq!(count.fetch_one::<(i64,)>(&mut conn), (rows * 3,));
+    "COMMIT".execute(&mut conn);
+    "RESET thdb.pin_lake_snapshots".execute(&mut conn);
+
+    Ok(())
+}
@@ -1,10 +1,11 @@
 mod fixtures;
 
-use crate::fixtures::conn;
 use crate::fixtures::db::Query;
+use crate::fixtures::{conn, tempdir};
 use anyhow::Result;
 use rstest::*;
 use sqlx::PgConnection;
+use tempfile::TempDir;
 
 #[rstest]
 async fn test_duckdb_settings(mut conn: PgConnection) -> Result<()> {
@@ -25,3 +26,79 @@ async fn test_duckdb_extensions(mut conn: PgConnection) -> Result<()> {
 
     Ok(())
 }
+
+#[rstest]
+async fn test_duckdb_install_extension(mut conn: PgConnection, tempdir: TempDir) -> Result<()> {
+    let repository = tempdir.path().to_str().unwrap();
+
+    // Extensions missing from the repository fail with an error instead of bringing the backend down
+    let ret = format!("SELECT duckdb_install_extension('missing_extension', '{repository}')")
+        .execute_result(&mut conn);
+    assert!(ret.unwrap_err().to_string().contains("missing_extension"));
+    let one: (i32,) = "SELECT 1".fetch_one(&mut conn);
+    assert_eq!(one.0, 1);
+
+    let ret = format!("SELECT duckdb_install_extension('bad; name', '{repository}')")
+        .execute_result(&mut conn);
+    assert!(ret
+        .unwrap_err()
+        .to_string()
+        .contains("invalid duckdb extension name"));
+
+    // Only superusers can install extensions
+    "CREATE ROLE extension_installer".execute(&mut conn);
+    "SET ROLE extension_installer".execute(&mut conn);
+    let ret = format!("SELECT duckdb_install_extension('json', '{repository}')")
+        .execute_result(&mut conn);
+    assert!(ret.unwrap_err().to_string().contains("permission denied"));
+    "RESET ROLE".execute(&mut conn);
+
+    Ok(())
+}
+
+#[rstest]
+async fn test_duckdb_query(mut conn: PgConnection) -> Result<()> {
+    let rows: Vec<(i32, String)> = r#"
+        SELECT * FROM duckdb_query($$SELECT * FROM (VALUES (1, 'one'), (2, 'two')) t(n, word) ORDER BY n$$)
+        AS t(n int, word text)
+    "#
+    .fetch(&mut conn);
+    assert_eq!(rows, vec![(1, "one".to_string()), (2, "two".to_string())]);
+
+    let rows: Vec<(String,)> = r#"
+        SELECT duckdb_query_json::text FROM duckdb_query_json($$SELECT 1 AS n, 'one' AS word;$$)
+    "#
+    .fetch(&mut conn);
+    assert_eq!(rows, vec![(r#"{"n": 1, "word": "one"}"#.to_string(),)]);
+
+    // Output types come from the column definition list
+    let ret = "SELECT * FROM duckdb_query('SELECT 1')".execute_result(&mut conn);
+    assert!(ret.is_err());
+
+    // Only superusers and members of thdb.duckdb_query_role can run DuckDB queries
+    "CREATE ROLE duckdb_analyst".execute(&mut conn);
+    "CREATE ROLE duckdb_reader".execute(&mut conn);
+    "GRANT duckdb_analyst TO duckdb_reader".execute(&mut conn);
+
+    "SET ROLE duckdb_reader".execute(&mut conn);
+    let ret = "SELECT * FROM duckdb_query_json('SELECT 1 AS n')".execute_result(&mut conn);
+    assert!(ret.is_err());
+    let ret = "SELECT duckdb_execute($$SET memory_limit='1GiB'$$)".execute_result(&mut conn);
+    assert!(
+        format!("{:?}", ret).contains("permission denied"),
+        "{:?}",
+        ret
+    );
+    "RESET ROLE".execute(&mut conn);
+
+    "SET thdb.duckdb_query_role = 'duckdb_analyst'".execute(&mut conn);
+    "SET ROLE duckdb_reader".execute(&mut conn);
+    let count: (i64,) =
+        "SELECT COUNT(*) FROM duckdb_query('SELECT * FROM range(10)') AS t(n bigint)"
+            .fetch_one(&mut conn);
+    assert_eq!(count.0, 10);
+    "SELECT duckdb_execute($$SET memory_limit='1GiB'$$)".execute(&mut conn);
+    "RESET ROLE".execute(&mut conn);
+
+    Ok(())
+}
@@ -75,3 +75,127 @@ async fn test_arrow_types_local_file_spatial(
 
     Ok(())
 }
+
+#[rstest]
+async fn test_postgis_geometry_local_file_spatial(
+    mut conn: PgConnection,
+    tempdir: TempDir,
+) -> Result<()> {
+    let (postgis_available,): (bool,) =
+        "SELECT EXISTS (SELECT 1 FROM pg_available_extensions WHERE name = 'postgis')"
+            .fetch_one(&mut conn);
+    if !postgis_available {
+        return Ok(());
+    }
+    "CREATE EXTENSION IF NOT EXISTS postgis".execute(&mut conn);
+
+    let temp_path = tempdir.path().join("test_postgis.geojson");
+    let geojson = r#"{
+        "type": "FeatureCollection",
+        "crs": { "type": "name", "properties": { "name": "urn:ogc:def:crs:EPSG::4326" } },
+        "features": [
+            { "type": "Feature", "properties": { "name": "a" }, "geometry": { "type": "Point", "coordinates": [-120.66029, 35.2812] } }
+        ]
+    }"#;
+    std::fs::write(&temp_path, geojson)?;
+
+    format!(
+        r#"
+        CREATE FOREIGN DATA WRAPPER spatial_wrapper HANDLER spatial_fdw_handler VALIDATOR spatial_fdw_validator;
+        CREATE SERVER spatial_server FOREIGN DATA WRAPPER spatial_wrapper;
+        CREATE FOREIGN TABLE spatial_postgis () SERVER spatial_server OPTIONS (files '{}');
+        "#,
+        temp_path.to_string_lossy()
+    )
+    .execute(&mut conn);
+
+    // Auto schema creation uses the geometry type and SRID of the layer
+    let (column_type,): (String,) = "SELECT format_type(atttypid, atttypmod) FROM pg_attribute WHERE attrelid = 'spatial_postgis'::regclass AND attname = 'geom'"
+        .fetch_one(&mut conn);
+    assert_eq!(column_type, "geometry(Point,4326)");
+
+    let (wkt, srid): (String, i32) =
+        "SELECT ST_AsText(geom), ST_SRID(geom) FROM spatial_postgis".fetch_one(&mut conn);
+    assert_eq!(wkt, "POINT(-120.66029 35.2812)");
+    assert_eq!(srid, 4326);
+
+    // Queries returning geometries are read through the foreign scan rather than pushed down
+    let (distance,): (f64,) =
+        "SELECT ST_Distance(geom, 'SRID=4326;POINT(-120.66029 35.2812)'::geometry) FROM spatial_postgis"
+            .fetch_one(&mut conn);
+    assert_eq!(distance, 0.0);
+
+    // Only queries that read the geometry column are kept from DuckDB. pg_backend_pid() can't be
+    // pushed down, so the fallback policy makes the pushed down query fail.
+    "SET thdb.fallback_policy = 'error'".execute(&mut conn);
+    let ret =
+        "SELECT name FROM spatial_postgis WHERE pg_backend_pid() > 0".execute_result(&mut conn);
+    assert!(format!("{:?}", ret).contains("untranslatable"), "{:?}", ret);
+    let ret =
+        "SELECT name FROM spatial_postgis WHERE pg_backend_pid() > 0 AND NOT ST_IsEmpty(geom)"
+            .execute_result(&mut conn);
+    assert!(ret.is_ok(), "{:?}", ret);
+    "RESET thdb.fallback_policy".execute(&mut conn);
+
+    Ok(())
+}
+
+#[rstest]
+async fn test_spatial_filter_pushdown(mut conn: PgConnection, tempdir: TempDir) -> Result<()> {
+    let (postgis_available,): (bool,) =
+        "SELECT EXISTS (SELECT 1 FROM pg_available_extensions WHERE name = 'postgis')"
+            .fetch_one(&mut conn);
+    if !postgis_available {
+        return Ok(());
+    }
+    "CREATE EXTENSION IF NOT EXISTS postgis".execute(&mut conn);
+
+    let temp_path = tempdir.path().join("test_filter.geojson");
+    let geojson = r#"{
+        "type": "FeatureCollection",
+        "features": [
+            { "type": "Feature", "properties": { "name": "a" }, "geometry": { "type": "Point", "coordinates": [1, 1] } },
+            { "type": "Feature", "properties": { "name": "b" }, "geometry": { "type": "Point", "coordinates": [5, 5] } },
+            { "type": "Feature", "properties": { "name": "c" }, "geometry": { "type": "Point", "coordinates": [9, 9] } }
+        ]
+    }"#;
+    std::fs::write(&temp_path, geojson)?;
+
+    format!(
+        r#"
+        CREATE FOREIGN DATA WRAPPER spatial_wrapper HANDLER spatial_fdw_handler VALIDATOR spatial_fdw_validator;
+        CREATE SERVER spatial_server FOREIGN DATA WRAPPER spatial_wrapper;
+        CREATE FOREIGN TABLE spatial_filtered (name text, geom geometry) SERVER spatial_server OPTIONS (files '{}');
+        "#,
+        temp_path.to_string_lossy()
+    )
+    .execute(&mut conn);
+
+    let names: Vec<(String,)> =
+        "SELECT name FROM spatial_filtered WHERE geom && ST_MakeEnvelope(0, 0, 6, 6) ORDER BY name"
+            .fetch(&mut conn);
+    assert_eq!(names, vec![("a".to_string(),), ("b".to_string(),)]);
+
+    // The bounding box is passed to st_read
+    let (view_sql,): (String,) = "SELECT sql FROM duckdb_query($$SELECT sql FROM duckdb_views() WHERE view_name = 'spatial_filtered'$$) AS (sql text)"
+        .fetch_one(&mut conn);
+    assert!(view_sql.contains("spatial_filter_box"));
+
+    let names: Vec<(String,)> =
+        "SELECT name FROM spatial_filtered WHERE ST_Intersects(geom, ST_MakeEnvelope(4, 4, 10, 10)) ORDER BY name"
+            .fetch(&mut conn);
+    assert_eq!(names, vec![("b".to_string(),), ("c".to_string(),)]);
+
+    // Parameters are known when the executor starts
+    "PREPARE spatial_query(geometry) AS SELECT name FROM spatial_filtered WHERE ST_Intersects(geom, $1) ORDER BY name"
+        .execute(&mut conn);
+    let names: Vec<(String,)> =
+        "EXECUTE spatial_query('POLYGON((8 8, 8 10, 10 10, 10 8, 8 8))')".fetch(&mut conn);
+    assert_eq!(names, vec![("c".to_string(),)]);
+
+    // Without a spatial predicate the view is read unfiltered again
+    let (count,): (i64,) = "SELECT COUNT(*) FROM spatial_filtered".fetch_one(&mut conn);
+    assert_eq!(count, 3);
+
+    Ok(())
+}
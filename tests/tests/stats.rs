@@ -0,0 +1,103 @@
+// Copyright (c) 2023-2025 Retake, Inc.
+//
+// This file is part of ParadeDB - Postgres for Search and Analytics
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <http://www.gnu.org/licenses/>.
+mod fixtures;
+
+use crate::fixtures::db::Query;
+use crate::fixtures::{conn, s3, S3};
+use anyhow::Result;
+use rstest::*;
+use sqlx::PgConnection;
+
+use crate::fixtures::tables::nyc_trips::NycTripsTable;
+
+const S3_BUCKET: &str = "test-stat-queries";
+const S3_KEY: &str = "test_stat_queries.parquet";
+
+#[rstest]
+async fn test_stat_queries(#[future(awt)] s3: S3, mut conn: PgConnection) -> Result<()> {
+    NycTripsTable::setup().execute(&mut conn);
+    let rows: Vec<NycTripsTable> = "SELECT * FROM nyc_trips".fetch(&mut conn);
+    s3.client.create_bucket().bucket(S3_BUCKET).send().await?;
+    s3.create_bucket(S3_BUCKET).await?;
+    s3.put_rows(S3_BUCKET, S3_KEY, &rows).await?;
+
+    NycTripsTable::setup_s3_listing_fdw(&s3.url.clone(), &format!("s3://{S3_BUCKET}/{S3_KEY}"))
+        .execute(&mut conn);
+
+    "SELECT th_dbdm_stat_reset()".execute(&mut conn);
+
+    for _ in 0..3 {
+        let count: (i64,) =
+            "SELECT COUNT(*) AS stat_pushdown_count FROM trips".fetch_one(&mut conn);
+        assert_eq!(count.0, 100);
+    }
+
+    let stats: (i64, i64, i64, i64) = r#"
+        SELECT calls, pushdown_calls, fallback_calls, rows
+        FROM th_dbdm_stat_queries
+        WHERE query LIKE '%stat_pushdown_count%'
+    "#
+    .fetch_one(&mut conn);
+    assert_eq!(stats, (3, 3, 0, 3));
+
+    // Postgres-only functions cannot run in DuckDB and fall back to the FDW scan
+    "SELECT COUNT(*) AS stat_fallback_count FROM trips WHERE pg_backend_pid() > 0"
+        .fetch_one::<(i64,)>(&mut conn);
+
+    let stats: (i64, Option<String>) = r#"
+        SELECT fallback_calls, last_fallback_error
+        FROM th_dbdm_stat_queries
+        WHERE query LIKE '%stat_fallback_count%'
+    "#
+    .fetch_one(&mut conn);
+    assert_eq!(stats.0, 1);
+    assert!(stats.1.is_some());
+
+    let fdw_scans: (i64,) =
+        "SELECT COALESCE(SUM(fdw_scan_calls), 0)::bigint FROM th_dbdm_stat_queries"
+            .fetch_one(&mut conn);
+    assert!(fdw_scans.0 >= 1);
+
+    "SELECT th_dbdm_stat_reset()".execute(&mut conn);
+    let remaining: (i64,) =
+        "SELECT COUNT(*) FROM th_dbdm_stat_queries WHERE query LIKE '%stat_pushdown_count%'"
+            .fetch_one(&mut conn);
+    assert_eq!(remaining.0, 0);
+
+    // Statistics hold the query text of every role, so only monitoring roles can read them
+    "CREATE ROLE stat_reader; SET ROLE stat_reader".execute(&mut conn);
+    for query in [
+        "SELECT * FROM th_dbdm_stat_queries",
+        "SELECT th_dbdm_stat_reset()",
+    ] {
+        let result = query.execute_result(&mut conn);
+        assert!(result
+            .unwrap_err()
+            .to_string()
+            .contains("permission denied"));
+    }
+    "RESET ROLE; GRANT pg_read_all_stats TO stat_reader; SET ROLE stat_reader".execute(&mut conn);
+    assert!("SELECT * FROM th_dbdm_stat_queries"
+        .execute_result(&mut conn)
+        .is_ok());
+    assert!("SELECT th_dbdm_stat_reset()"
+        .execute_result(&mut conn)
+        .is_err());
+    "RESET ROLE".execute(&mut conn);
+
+    Ok(())
+}